@@ -0,0 +1,766 @@
+// Declarative chain definitions for CodeUChain-based RustDesk
+//
+// Chains can be described in TOML or JSON instead of being hard-coded in the
+// chain factories:
+//
+//     [[links]]
+//     name = "connection"
+//     type = "connection"
+//     params = { version = "1.4.2", timeout_ms = 5000 }
+//
+//     [[links]]
+//     name = "video"
+//     type = "video"
+//     params = { fps = 60 }
+//
+//     [[edges]]
+//     from = "connection"
+//     to = "video"
+//     when = "connected"
+//
+//     [[middleware]]
+//     type = "rate_limit"
+//     params = { requests_per_second = 50, max_wait_ms = 200 }
+//
+// A `LinkRegistry` maps the `type` names to factories and the `when` names to
+// predicates. Every problem found while building is reported with the entry it
+// was found in, e.g. `links[1] 'video': unknown parameter 'fsp'`.
+
+use crate::core::{Chain, ConnectError, Context, Link, Middleware};
+use crate::ipc_links::*;
+use crate::links::*;
+use crate::middleware::*;
+use crate::types::{CodeUChainError, ContextHelpers, RustDeskContext};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Declarative description of a chain
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainDefinition {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub links: Vec<LinkDefinition>,
+    #[serde(default)]
+    pub edges: Vec<EdgeDefinition>,
+    #[serde(default)]
+    pub middleware: Vec<MiddlewareDefinition>,
+}
+
+/// A link entry: the name it gets in the chain and the registered type building it
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LinkDefinition {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+}
+
+/// An edge between two links, guarded by a named predicate
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EdgeDefinition {
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_predicate")]
+    pub when: String,
+}
+
+fn default_predicate() -> String {
+    "always".to_string()
+}
+
+/// A middleware entry, applied in file order (first entry is outermost)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MiddlewareDefinition {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub params: Map<String, Value>,
+}
+
+impl ChainDefinition {
+    pub fn from_toml(text: &str) -> Result<Self, ChainConfigError> {
+        toml::from_str(text).map_err(|e| ChainConfigError::single("<toml>", e.to_string()))
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ChainConfigError> {
+        serde_json::from_str(text).map_err(|e| ChainConfigError::single("<json>", e.to_string()))
+    }
+
+    /// Read a definition, picking the format from the file extension
+    pub fn from_file(path: &Path) -> Result<Self, ChainConfigError> {
+        let location = path.display().to_string();
+        let text = std::fs::read_to_string(path).map_err(|e| ChainConfigError::single(&location, e.to_string()))?;
+        let parsed = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            other => {
+                return Err(ChainConfigError::single(
+                    &location,
+                    format!("unsupported chain definition format {:?}, expected .toml or .json", other.unwrap_or("")),
+                ))
+            }
+        };
+        parsed.map_err(|mut err| {
+            for issue in &mut err.issues {
+                issue.entry = location.clone();
+            }
+            err
+        })
+    }
+}
+
+/// A single problem in a chain definition
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigIssue {
+    /// The offending entry, e.g. `links[2] 'video'` or `edges[0] 'a' -> 'b'`
+    pub entry: String,
+    pub message: String,
+}
+
+/// All problems found while loading a chain definition
+#[derive(Debug, Clone)]
+pub struct ChainConfigError {
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ChainConfigError {
+    fn single(entry: &str, message: String) -> Self {
+        Self {
+            issues: vec![ConfigIssue {
+                entry: entry.to_string(),
+                message,
+            }],
+        }
+    }
+}
+
+impl std::fmt::Display for ChainConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid chain definition:")?;
+        for issue in &self.issues {
+            write!(f, "\n  {}: {}", issue.entry, issue.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ChainConfigError {}
+
+impl From<ChainConfigError> for CodeUChainError {
+    fn from(err: ChainConfigError) -> Self {
+        CodeUChainError::ConfigurationError(err.to_string())
+    }
+}
+
+/// Parameters of a link or middleware entry
+///
+/// Factories read the parameters they understand; anything left unread is
+/// reported as an unknown parameter of the entry. Invalid values are recorded
+/// rather than returned, so a factory always reads all of its parameters and
+/// one bad value doesn't make the following ones look unknown.
+pub struct Params<'a> {
+    values: &'a Map<String, Value>,
+    read: RefCell<HashSet<&'a str>>,
+    errors: RefCell<Vec<String>>,
+}
+
+impl<'a> Params<'a> {
+    fn new(values: &'a Map<String, Value>) -> Self {
+        Self {
+            values,
+            read: RefCell::new(HashSet::new()),
+            errors: RefCell::new(Vec::new()),
+        }
+    }
+
+    fn get(&self, key: &'a str) -> Option<&'a Value> {
+        self.read.borrow_mut().insert(key);
+        self.values.get(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    /// Record a problem with the parameters, reported with the entry
+    pub fn error(&self, message: String) {
+        self.errors.borrow_mut().push(message);
+    }
+
+    fn expect<T>(&self, key: &str, value: Option<T>, expected: &str) -> Option<T> {
+        if value.is_none() {
+            self.error(format!("parameter '{}' must be {}", key, expected));
+        }
+        value
+    }
+
+    pub fn u64(&self, key: &'a str) -> Option<u64> {
+        let value = self.get(key)?;
+        self.expect(key, value.as_u64(), "a non-negative integer")
+    }
+
+    /// An integer parameter that must fit in `T`
+    pub fn int<T: TryFrom<u64>>(&self, key: &'a str) -> Option<T> {
+        let value = self.u64(key)?;
+        let value = T::try_from(value).ok();
+        self.expect(key, value, &format!("an integer fitting in {}", std::any::type_name::<T>()))
+    }
+
+    pub fn bool(&self, key: &'a str) -> Option<bool> {
+        let value = self.get(key)?;
+        self.expect(key, value.as_bool(), "a boolean")
+    }
+
+    pub fn string(&self, key: &'a str) -> Option<String> {
+        let value = self.get(key)?;
+        self.expect(key, value.as_str().map(str::to_string), "a string")
+    }
+
+    pub fn strings(&self, key: &'a str) -> Option<Vec<String>> {
+        let strings = match self.get(key)? {
+            Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>(),
+            _ => None,
+        };
+        self.expect(key, strings, "a list of strings")
+    }
+
+    fn unread(&self) -> Vec<&'a str> {
+        let read = self.read.borrow();
+        let mut unread: Vec<&str> = self
+            .values
+            .keys()
+            .map(String::as_str)
+            .filter(|key| !read.contains(key))
+            .collect();
+        unread.sort();
+        unread
+    }
+}
+
+/// Builds a link from its parameters
+pub type LinkFactory = Box<dyn Fn(&Params) -> Result<Box<dyn Link>, String> + Send + Sync>;
+
+/// Builds a middleware from its parameters
+pub type MiddlewareFactory = Box<dyn Fn(&Params) -> Result<Box<dyn Middleware>, String> + Send + Sync>;
+
+/// Named edge predicate usable in `when`
+pub type NamedPredicate = Arc<dyn Fn(&Context) -> bool + Send + Sync>;
+
+/// Registry of the link types, middleware types and predicates a definition may use
+pub struct LinkRegistry {
+    links: HashMap<String, LinkFactory>,
+    middleware: HashMap<String, MiddlewareFactory>,
+    predicates: HashMap<String, NamedPredicate>,
+}
+
+impl LinkRegistry {
+    /// Registry knowing only the `always` predicate
+    pub fn new() -> Self {
+        let mut registry = Self {
+            links: HashMap::new(),
+            middleware: HashMap::new(),
+            predicates: HashMap::new(),
+        };
+        registry.register_predicate("always", |_| true);
+        registry
+    }
+
+    /// Registry with the built-in links, middleware and session predicates
+    pub fn with_defaults() -> Self {
+        let mut registry = Self::new();
+
+        registry.register_link("connection", |p| {
+            let version = p.string("version");
+            if !p.contains("version") {
+                return Err("missing parameter 'version'".to_string());
+            }
+            // An invalid value is already recorded and fails the entry
+            let mut link = ConnectionLink::new(&version.unwrap_or_default());
+            if let Some(timeout_ms) = p.u64("timeout_ms") {
+                link = link.with_timeout(timeout_ms);
+            }
+            if let Some(retries) = p.int("max_retries") {
+                link = link.with_retries(retries);
+            }
+            if let Some(udp_punch) = p.bool("udp_punch") {
+                link = link.with_udp_punch(udp_punch);
+            }
+            if let Some(force_relay) = p.bool("force_relay") {
+                link = link.with_force_relay(force_relay);
+            }
+            if let Some(servers) = p.strings("rendezvous_servers") {
+                link = link.with_rendezvous_servers(servers);
+            }
+            if let Some(key) = p.string("licence_key") {
+                link = link.with_licence_key(&key);
+            }
+            Ok(Box::new(link))
+        });
+        registry.register_link("video", |p| {
+            let mut link = VideoLink::new();
+            match p.int("fps") {
+                Some(0) => p.error("parameter 'fps' must be greater than zero".to_string()),
+                Some(fps) => link = link.with_fps(fps),
+                None => {}
+            }
+            if let Some(codec) = p.string("codec") {
+                link = link.with_codec(codec);
+            }
+            if let Some(hw_accel) = p.bool("hw_accel") {
+                link = link.with_hw_accel(hw_accel);
+            }
+            Ok(Box::new(link))
+        });
+        registry.register_link("audio", |p| {
+            let mut link = AudioLink::new();
+            if let Some(rate) = p.int("sample_rate") {
+                link = link.with_sample_rate(rate);
+            }
+            if let Some(channels) = p.int("channels") {
+                link = link.with_channels(channels);
+            }
+            Ok(Box::new(link))
+        });
+        registry.register_link("clipboard", |p| {
+            let mut link = ClipboardLink::new();
+            if let Some(size) = p.int("max_size") {
+                link = link.with_max_size(size);
+            }
+            Ok(Box::new(link))
+        });
+        registry.register_link("input", |p| {
+            let mut link = InputLink::new();
+            if let Some(size) = p.int("buffer_size") {
+                link = link.with_buffer_size(size);
+            }
+            Ok(Box::new(link))
+        });
+        registry.register_link("config_validator", |_| Ok(Box::new(ConfigValidatorLink::new())));
+        registry.register_link("config_processor", |_| Ok(Box::new(ConfigProcessorLink::new())));
+        registry.register_link("message_validator", |_| Ok(Box::new(MessageValidatorLink::new())));
+        registry.register_link("message_processor", |_| Ok(Box::new(MessageProcessorLink::new())));
+        registry.register_link("system_info", |_| Ok(Box::new(SystemInfoLink::new())));
+
+        registry.register_middleware("logging", |p| {
+            let mut middleware = LoggingMiddleware::new();
+            if let Some(level) = p.string("level") {
+                middleware = middleware.with_level(&level);
+            }
+            Ok(Box::new(middleware))
+        });
+        registry.register_middleware("performance", |_| Ok(Box::new(PerformanceMiddleware::new())));
+        registry.register_middleware("metrics", |_| Ok(Box::new(MetricsMiddleware::new())));
+        registry.register_middleware("error_handling", |p| {
+            let mut middleware = ErrorHandlingMiddleware::new();
+            if let Some(retries) = p.int("max_retries") {
+                middleware = middleware.with_retries(retries);
+            }
+            if let Some(delay_ms) = p.u64("retry_delay_ms") {
+                middleware = middleware.with_retry_delay(delay_ms);
+            }
+            Ok(Box::new(middleware))
+        });
+        registry.register_middleware("security", |p| {
+            let mut middleware = SecurityMiddleware::new();
+            if let Some(encryption) = p.bool("encryption") {
+                middleware = middleware.with_encryption(encryption);
+            }
+            for peer in p.strings("allowed_peers").unwrap_or_default() {
+                middleware = middleware.allow_peer(&peer);
+            }
+            Ok(Box::new(middleware))
+        });
+        registry.register_middleware("circuit_breaker", |p| {
+            let mut threshold = 5;
+            match p.int("failure_threshold") {
+                Some(0) => p.error("parameter 'failure_threshold' must be greater than zero".to_string()),
+                Some(failure_threshold) => threshold = failure_threshold,
+                None => {}
+            }
+            let recovery_ms = p.u64("recovery_timeout_ms").unwrap_or(30_000);
+            let middleware = CircuitBreakerMiddleware::new(threshold, recovery_ms)
+                .with_cached_results(p.bool("cache_results").unwrap_or(false));
+            Ok(Box::new(middleware))
+        });
+        registry.register_middleware("rate_limit", |p| {
+            let rps = p.int("requests_per_second");
+            let max_wait_ms = p.u64("max_wait_ms");
+            if !p.contains("requests_per_second") {
+                return Err("missing parameter 'requests_per_second'".to_string());
+            }
+            if rps == Some(0) {
+                p.error("parameter 'requests_per_second' must be greater than zero".to_string());
+            }
+            // An invalid value is already recorded and fails the entry
+            let mut middleware = RateLimitMiddleware::new(rps.unwrap_or_default());
+            if let Some(max_wait_ms) = max_wait_ms {
+                middleware = middleware.with_queueing(max_wait_ms);
+            }
+            Ok(Box::new(middleware))
+        });
+
+        registry.register_predicate("connected", |ctx| {
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Connected(_)))
+        });
+        registry.register_predicate("streaming", |ctx| {
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        });
+        registry.register_predicate("error", |ctx| {
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Error { .. }))
+        });
+        registry.register_predicate("not_error", |ctx| {
+            !matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Error { .. }))
+        });
+
+        registry
+    }
+
+    pub fn register_link<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(&Params) -> Result<Box<dyn Link>, String> + Send + Sync + 'static,
+    {
+        self.links.insert(kind.to_string(), Box::new(factory));
+    }
+
+    pub fn register_middleware<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(&Params) -> Result<Box<dyn Middleware>, String> + Send + Sync + 'static,
+    {
+        self.middleware.insert(kind.to_string(), Box::new(factory));
+    }
+
+    pub fn register_predicate<F>(&mut self, name: &str, predicate: F)
+    where
+        F: Fn(&Context) -> bool + Send + Sync + 'static,
+    {
+        self.predicates.insert(name.to_string(), Arc::new(predicate));
+    }
+
+    pub fn load_toml(&self, text: &str) -> Result<Chain, ChainConfigError> {
+        self.build(&ChainDefinition::from_toml(text)?)
+    }
+
+    pub fn load_json(&self, text: &str) -> Result<Chain, ChainConfigError> {
+        self.build(&ChainDefinition::from_json(text)?)
+    }
+
+    pub fn load_file(&self, path: &Path) -> Result<Chain, ChainConfigError> {
+        self.build(&ChainDefinition::from_file(path)?)
+    }
+
+    /// Build a chain, reporting every invalid entry rather than just the first
+    pub fn build(&self, definition: &ChainDefinition) -> Result<Chain, ChainConfigError> {
+        let mut chain = Chain::new();
+        let mut issues = Vec::new();
+        let mut names = HashSet::new();
+
+        for (i, link) in definition.links.iter().enumerate() {
+            let entry = format!("links[{}] '{}'", i, link.name);
+            if !names.insert(link.name.as_str()) {
+                issues.push(issue(&entry, "duplicate link name".to_string()));
+                continue;
+            }
+            let factory = match self.links.get(&link.kind) {
+                Some(factory) => factory,
+                None => {
+                    issues.push(issue(&entry, format!("unknown link type '{}'", link.kind)));
+                    continue;
+                }
+            };
+            match build_with(factory.as_ref(), &link.params, &entry, &mut issues) {
+                Some(built) => chain.add_link(link.name.clone(), built),
+                None => continue,
+            }
+        }
+
+        for (i, edge) in definition.edges.iter().enumerate() {
+            let entry = format!("edges[{}] '{}' -> '{}'", i, edge.from, edge.to);
+            let mut valid = true;
+            for endpoint in [&edge.from, &edge.to] {
+                if !names.contains(endpoint.as_str()) {
+                    issues.push(issue(&entry, format!("unknown link '{}'", endpoint)));
+                    valid = false;
+                }
+            }
+            let predicate = match self.predicates.get(&edge.when) {
+                Some(predicate) => predicate.clone(),
+                None => {
+                    issues.push(issue(&entry, format!("unknown predicate '{}'", edge.when)));
+                    continue;
+                }
+            };
+            if !valid {
+                continue;
+            }
+            match chain.connect(edge.from.clone(), edge.to.clone(), move |ctx| predicate(ctx)) {
+                // Links that failed to build are missing from the chain and already reported
+                Ok(()) | Err(ConnectError::UnknownLink(_)) => {}
+                Err(e) => issues.push(issue(&entry, e.to_string())),
+            }
+        }
+
+        for (i, middleware) in definition.middleware.iter().enumerate() {
+            let entry = format!("middleware[{}] '{}'", i, middleware.kind);
+            let factory = match self.middleware.get(&middleware.kind) {
+                Some(factory) => factory,
+                None => {
+                    issues.push(issue(&entry, format!("unknown middleware type '{}'", middleware.kind)));
+                    continue;
+                }
+            };
+            if let Some(built) = build_with(factory.as_ref(), &middleware.params, &entry, &mut issues) {
+                chain.use_middleware(built);
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(chain)
+        } else {
+            Err(ChainConfigError { issues })
+        }
+    }
+}
+
+fn issue(entry: &str, message: String) -> ConfigIssue {
+    ConfigIssue {
+        entry: entry.to_string(),
+        message,
+    }
+}
+
+/// Run a factory, recording its errors and any parameters it did not read
+fn build_with<T>(
+    factory: &(dyn Fn(&Params) -> Result<T, String> + Send + Sync),
+    values: &Map<String, Value>,
+    entry: &str,
+    issues: &mut Vec<ConfigIssue>,
+) -> Option<T> {
+    let params = Params::new(values);
+    let built = factory(&params);
+    let unknown = params.unread();
+    let mut messages = params.errors.into_inner();
+    if let Err(message) = &built {
+        messages.push(message.clone());
+    }
+    messages.extend(unknown.iter().map(|key| format!("unknown parameter '{}'", key)));
+    if messages.is_empty() {
+        built.ok()
+    } else {
+        issues.extend(messages.into_iter().map(|message| issue(entry, message)));
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contexts::RustDeskChainContext;
+    use crate::types::ConnType;
+
+    const CLIENT_TOML: &str = r#"
+name = "client"
+
+[[links]]
+name = "connection"
+type = "connection"
+params = { version = "1.4.2", timeout_ms = 2000, max_retries = 1 }
+
+[[links]]
+name = "video"
+type = "video"
+params = { fps = 60, codec = "vp9" }
+
+[[edges]]
+from = "connection"
+to = "video"
+when = "connected"
+
+[[middleware]]
+type = "error_handling"
+params = { max_retries = 0 }
+
+[[middleware]]
+type = "rate_limit"
+params = { requests_per_second = 100, max_wait_ms = 50 }
+"#;
+
+    #[test]
+    fn test_load_toml_builds_chain() {
+        let chain = LinkRegistry::with_defaults().load_toml(CLIENT_TOML).unwrap();
+        assert_eq!(chain.execution_order(), vec!["connection", "video"]);
+    }
+
+    // Test peers are only mocked without `full`, which asks the rendezvous servers
+    #[cfg(not(feature = "full"))]
+    #[tokio::test]
+    async fn test_loaded_chain_runs() {
+        let chain = LinkRegistry::with_defaults().load_toml(CLIENT_TOML).unwrap();
+        let ctx = RustDeskChainContext::new("test-peer-1".to_string(), ConnType::DEFAULT_CONN, None);
+
+        let result = chain.run(ctx.into_inner()).await.unwrap();
+        assert!(matches!(
+            result.rustdesk_state().as_deref(),
+            Some(RustDeskContext::Streaming { .. })
+        ));
+    }
+
+    #[test]
+    fn test_load_json_matches_toml() {
+        let json = r#"{
+            "links": [
+                {"name": "validate", "type": "config_validator"},
+                {"name": "process", "type": "config_processor"}
+            ],
+            "edges": [{"from": "validate", "to": "process"}],
+            "middleware": [{"type": "logging", "params": {"level": "debug"}}]
+        }"#;
+        let chain = LinkRegistry::with_defaults().load_json(json).unwrap();
+        assert_eq!(chain.execution_order(), vec!["validate", "process"]);
+    }
+
+    #[test]
+    fn test_validation_points_at_offending_entries() {
+        let toml = r#"
+[[links]]
+name = "video"
+type = "video"
+params = { fsp = 30 }
+
+[[links]]
+name = "audio"
+type = "speaker"
+
+[[links]]
+name = "video"
+type = "video"
+
+[[edges]]
+from = "video"
+to = "clipboard"
+when = "connected"
+
+[[edges]]
+from = "video"
+to = "video"
+when = "sometimes"
+
+[[middleware]]
+type = "rate_limit"
+"#;
+        let err = LinkRegistry::with_defaults().load_toml(toml).err().expect("definition should be rejected");
+        let issues: Vec<(&str, &str)> = err
+            .issues
+            .iter()
+            .map(|i| (i.entry.as_str(), i.message.as_str()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("links[0] 'video'", "unknown parameter 'fsp'"),
+                ("links[1] 'audio'", "unknown link type 'speaker'"),
+                ("links[2] 'video'", "duplicate link name"),
+                ("edges[0] 'video' -> 'clipboard'", "unknown link 'clipboard'"),
+                ("edges[1] 'video' -> 'video'", "unknown predicate 'sometimes'"),
+                ("middleware[0] 'rate_limit'", "missing parameter 'requests_per_second'"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cycles_and_bad_parameter_types_are_reported() {
+        let json = r#"{
+            "links": [
+                {"name": "a", "type": "input", "params": {"buffer_size": "large"}},
+                {"name": "b", "type": "clipboard"},
+                {"name": "c", "type": "clipboard"}
+            ],
+            "edges": [
+                {"from": "b", "to": "c"},
+                {"from": "c", "to": "b"}
+            ]
+        }"#;
+        let err = LinkRegistry::with_defaults().load_json(json).err().expect("definition should be rejected");
+        assert_eq!(err.issues.len(), 2);
+        assert_eq!(err.issues[0].entry, "links[0] 'a'");
+        assert!(err.issues[0].message.contains("non-negative integer"));
+        assert_eq!(err.issues[1].entry, "edges[1] 'c' -> 'b'");
+        assert!(err.issues[1].message.contains("cycle"));
+    }
+
+    #[test]
+    fn test_every_parameter_is_checked() {
+        let json = r#"{
+            "links": [
+                {"name": "v", "type": "video", "params": {"fps": 0, "codec": "av1"}},
+                {"name": "a", "type": "audio", "params": {"channels": 70000, "sample_rate": true}}
+            ],
+            "middleware": [{"type": "rate_limit", "params": {"requests_per_second": 5000000000, "max_wait_ms": 10}}]
+        }"#;
+        let err = LinkRegistry::with_defaults().load_json(json).err().expect("definition should be rejected");
+        let issues: Vec<(&str, &str)> = err
+            .issues
+            .iter()
+            .map(|i| (i.entry.as_str(), i.message.as_str()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("links[0] 'v'", "parameter 'fps' must be greater than zero"),
+                ("links[1] 'a'", "parameter 'sample_rate' must be a non-negative integer"),
+                ("links[1] 'a'", "parameter 'channels' must be an integer fitting in u16"),
+                ("middleware[0] 'rate_limit'", "parameter 'requests_per_second' must be an integer fitting in u32"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_zero_rates_are_rejected() {
+        let json = r#"{
+            "links": [{"name": "v", "type": "video"}],
+            "middleware": [
+                {"type": "circuit_breaker", "params": {"failure_threshold": 0}},
+                {"type": "rate_limit", "params": {"requests_per_second": 0}}
+            ]
+        }"#;
+        let err = LinkRegistry::with_defaults().load_json(json).err().expect("definition should be rejected");
+        let issues: Vec<(&str, &str)> = err
+            .issues
+            .iter()
+            .map(|i| (i.entry.as_str(), i.message.as_str()))
+            .collect();
+        assert_eq!(
+            issues,
+            vec![
+                ("middleware[0] 'circuit_breaker'", "parameter 'failure_threshold' must be greater than zero"),
+                ("middleware[1] 'rate_limit'", "parameter 'requests_per_second' must be greater than zero"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_registrations() {
+        let mut registry = LinkRegistry::new();
+        registry.register_link("noop", |_| Ok(Box::new(ConfigValidatorLink::new())));
+        registry.register_predicate("flagged", |ctx| ctx.get("flag").is_some());
+
+        let json = r#"{
+            "links": [{"name": "x", "type": "noop"}, {"name": "y", "type": "noop"}],
+            "edges": [{"from": "x", "to": "y", "when": "flagged"}]
+        }"#;
+        assert!(registry.load_json(json).is_ok());
+        assert!(LinkRegistry::new().load_json(json).is_err());
+    }
+}
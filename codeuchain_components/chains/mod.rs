@@ -4,6 +4,7 @@ use crate::types::*;
 use crate::contexts::*;
 use crate::links::*;
 use crate::core::{Chain, Context, Link};
+use crate::streaming::{StreamHandle, StreamLink, StreamingChain};
 use std::sync::Arc;
 
 /// Client-side processing chain
@@ -12,11 +13,12 @@ pub struct ClientChain {
 }
 
 impl ClientChain {
-    pub fn new() -> Self {
+    /// `version` is the RustDesk version announced when connecting
+    pub fn new(version: &str) -> Self {
         let mut chain = Chain::new();
 
         // Add links in processing order
-        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new()));
+        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new(version)));
         chain.add_link("video".to_string(), Box::new(VideoLink::new()));
         chain.add_link("audio".to_string(), Box::new(AudioLink::new()));
         chain.add_link("clipboard".to_string(), Box::new(ClipboardLink::new()));
@@ -24,52 +26,20 @@ impl ClientChain {
 
         // Set up predicates for conditional processing
         chain.connect("connection".to_string(), "video".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Connected(_))
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Connected(_)))
+        }).expect("static chain topology");
 
         chain.connect("video".to_string(), "audio".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("audio".to_string(), "clipboard".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("clipboard".to_string(), "input".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         Self { chain }
     }
@@ -95,11 +65,12 @@ pub struct ServerChain {
 }
 
 impl ServerChain {
-    pub fn new() -> Self {
+    /// `version` is the RustDesk version announced when connecting
+    pub fn new(version: &str) -> Self {
         let mut chain = Chain::new();
 
         // Add links in processing order
-        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new()));
+        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new(version)));
         chain.add_link("input".to_string(), Box::new(InputLink::new()));
         chain.add_link("video_capture".to_string(), Box::new(VideoLink::new()));
         chain.add_link("audio_capture".to_string(), Box::new(AudioLink::new()));
@@ -107,52 +78,20 @@ impl ServerChain {
 
         // Set up predicates for conditional processing
         chain.connect("connection".to_string(), "input".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Connected(_))
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Connected(_)))
+        }).expect("static chain topology");
 
         chain.connect("input".to_string(), "video_capture".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("video_capture".to_string(), "audio_capture".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("audio_capture".to_string(), "clipboard_sync".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         Self { chain }
     }
@@ -177,11 +116,12 @@ pub struct RemoteDesktopChain {
 }
 
 impl RemoteDesktopChain {
-    pub fn new() -> Self {
+    /// `version` is the RustDesk version announced when connecting
+    pub fn new(version: &str) -> Self {
         let mut chain = Chain::new();
 
         // Add all links for a complete remote desktop session
-        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new()));
+        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new(version)));
         chain.add_link("video".to_string(), Box::new(VideoLink::new()));
         chain.add_link("audio".to_string(), Box::new(AudioLink::new()));
         chain.add_link("clipboard".to_string(), Box::new(ClipboardLink::new()));
@@ -189,52 +129,20 @@ impl RemoteDesktopChain {
 
         // Set up processing flow with predicates
         chain.connect("connection".to_string(), "video".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Connected(_))
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Connected(_)))
+        }).expect("static chain topology");
 
         chain.connect("video".to_string(), "audio".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("audio".to_string(), "clipboard".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         chain.connect("clipboard".to_string(), "input".to_string(), |ctx| {
-            if let Some(rustdesk_ctx_json) = ctx.data().get("rustdesk_context") {
-                if let Ok(rustdesk_ctx) = serde_json::from_value::<RustDeskContext>(rustdesk_ctx_json.clone()) {
-                    matches!(rustdesk_ctx, RustDeskContext::Streaming { .. })
-                } else {
-                    false
-                }
-            } else {
-                false
-            }
-        });
+            matches!(ctx.rustdesk_state().as_deref(), Some(RustDeskContext::Streaming { .. }))
+        }).expect("static chain topology");
 
         Self { chain }
     }
@@ -253,23 +161,93 @@ impl RemoteDesktopChain {
     }
 }
 
+/// Long-lived video and audio pipelines for a connected session
+///
+/// Unlike [`RemoteDesktopChain`], which captures one frame per `process` call,
+/// the media chains keep capturing until the session is cancelled.
+pub struct MediaStreamChain {
+    video: StreamingChain,
+    audio: StreamingChain,
+}
+
+impl MediaStreamChain {
+    pub fn new() -> Self {
+        Self::with_links(VideoLink::new(), AudioLink::new())
+    }
+
+    pub fn with_links(video_link: VideoLink, audio_link: AudioLink) -> Self {
+        let mut video = StreamingChain::new();
+        video.add_stream_link("video".to_string(), Arc::new(video_link));
+
+        let mut audio = StreamingChain::new();
+        audio.add_stream_link("audio".to_string(), Arc::new(audio_link));
+
+        Self { video, audio }
+    }
+
+    /// Append a stage after video capture, e.g. an encoder or a renderer
+    pub fn with_video_stage(mut self, name: &str, link: Arc<dyn StreamLink>) -> Self {
+        self.video.add_stream_link(name.to_string(), link);
+        self
+    }
+
+    /// Append a stage after audio capture
+    pub fn with_audio_stage(mut self, name: &str, link: Arc<dyn StreamLink>) -> Self {
+        self.audio.add_stream_link(name.to_string(), link);
+        self
+    }
+
+    /// Start both pipelines from a connected session context
+    pub async fn start(&self, session: Context) -> std::result::Result<MediaStreams, Box<dyn std::error::Error + Send + Sync>> {
+        let streams = MediaStreams {
+            video: self.video.spawn(),
+            audio: self.audio.spawn(),
+        };
+        streams.video.send(session.clone()).await?;
+        streams.audio.send(session).await?;
+        Ok(streams)
+    }
+}
+
+/// Running media pipelines of a session
+pub struct MediaStreams {
+    pub video: StreamHandle,
+    pub audio: StreamHandle,
+}
+
+impl MediaStreams {
+    /// Forward updated session state (e.g. after a settings change) to both pipelines
+    pub async fn update(&self, session: Context) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.video.send(session.clone()).await?;
+        self.audio.send(session).await
+    }
+
+    /// Stop both pipelines and wait for them to finish
+    pub async fn stop(self) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.video.cancel();
+        self.audio.cancel();
+        self.video.join().await?;
+        self.audio.join().await
+    }
+}
+
 /// Helper functions for chain management
 pub mod helpers {
     use super::*;
 
     /// Create a client chain with default configuration
-    pub fn create_default_client_chain() -> ClientChain {
-        ClientChain::new()
+    pub fn create_default_client_chain(version: &str) -> ClientChain {
+        ClientChain::new(version)
     }
 
     /// Create a server chain with default configuration
-    pub fn create_default_server_chain() -> ServerChain {
-        ServerChain::new()
+    pub fn create_default_server_chain(version: &str) -> ServerChain {
+        ServerChain::new(version)
     }
 
     /// Create a full remote desktop chain
-    pub fn create_remote_desktop_chain() -> RemoteDesktopChain {
-        RemoteDesktopChain::new()
+    pub fn create_remote_desktop_chain(version: &str) -> RemoteDesktopChain {
+        RemoteDesktopChain::new(version)
     }
 
     /// Create a context for testing
@@ -286,6 +264,8 @@ mod tests {
     use std::collections::HashMap;
     use crate::middleware::{LoggingMiddleware, PerformanceMiddleware, ErrorHandlingMiddleware, SecurityMiddleware};
 
+    const VERSION: &str = "1.4.2";
+
     fn json_to_hashmap(value: Value) -> HashMap<String, Value> {
         if let Value::Object(map) = value {
             map.into_iter().collect()
@@ -298,7 +278,7 @@ mod tests {
     async fn test_client_chain_full_flow() {
         println!("🧪 Testing ClientChain full flow...");
 
-        let client_chain = ClientChain::new()
+        let client_chain = ClientChain::new(VERSION)
             .with_middleware(LoggingMiddleware::new())
             .with_middleware(PerformanceMiddleware::new());
 
@@ -337,7 +317,7 @@ mod tests {
     async fn test_server_chain_with_middleware() {
         println!("🧪 Testing ServerChain with middleware stack...");
 
-        let mut server_chain = ServerChain::new();
+        let mut server_chain = ServerChain::new(VERSION);
         server_chain.add_middleware(LoggingMiddleware::new());
         server_chain.add_middleware(PerformanceMiddleware::new());
         server_chain.add_middleware(ErrorHandlingMiddleware::new());
@@ -374,7 +354,7 @@ mod tests {
     async fn test_remote_desktop_chain_comprehensive() {
         println!("🧪 Testing RemoteDesktopChain comprehensive flow...");
 
-        let mut rd_chain = RemoteDesktopChain::new();
+        let mut rd_chain = RemoteDesktopChain::new(VERSION);
         rd_chain.add_middleware(LoggingMiddleware::new());
         rd_chain.add_middleware(PerformanceMiddleware::new());
         rd_chain.add_middleware(SecurityMiddleware::new());
@@ -430,7 +410,7 @@ mod tests {
     async fn test_chain_predicate_logic() {
         println!("🧪 Testing chain predicate logic...");
 
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new(VERSION);
 
         // Test context that should trigger video->audio connection
         let streaming_data = json_to_hashmap(json!({
@@ -479,7 +459,7 @@ mod tests {
     async fn test_chain_error_propagation() {
         println!("🧪 Testing chain error propagation with middleware...");
 
-        let mut chain = RemoteDesktopChain::new();
+        let mut chain = RemoteDesktopChain::new(VERSION);
         chain.add_middleware(ErrorHandlingMiddleware::new());
         chain.add_middleware(LoggingMiddleware::new());
 
@@ -512,15 +492,15 @@ mod tests {
         println!("🧪 Testing chain helper functions...");
 
         // Test client chain creation
-        let client_chain = helpers::create_default_client_chain();
+        let client_chain = helpers::create_default_client_chain(VERSION);
         println!("✅ Created default client chain");
 
         // Test server chain creation
-        let server_chain = helpers::create_default_server_chain();
+        let server_chain = helpers::create_default_server_chain(VERSION);
         println!("✅ Created default server chain");
 
         // Test remote desktop chain creation
-        let rd_chain = helpers::create_remote_desktop_chain();
+        let rd_chain = helpers::create_remote_desktop_chain(VERSION);
         println!("✅ Created remote desktop chain");
 
         // Test context creation
@@ -537,7 +517,7 @@ mod tests {
     async fn test_middleware_integration_in_chains() {
         println!("🧪 Testing middleware integration in chains...");
 
-        let chain = ClientChain::new()
+        let chain = ClientChain::new(VERSION)
             .with_middleware(LoggingMiddleware::new())
             .with_middleware(PerformanceMiddleware::new())
             .with_middleware(ErrorHandlingMiddleware::new())
@@ -102,7 +102,7 @@ impl ClientChainFactory {
         chain.add_link("media".to_string(), Box::new(media_link));
         chain.add_link("quality".to_string(), Box::new(quality_link));
 
-        chain.connect("media".to_string(), "quality".to_string(), |_| true);
+        chain.connect("media".to_string(), "quality".to_string(), |_| true).expect("static chain topology");
 
         chain.use_middleware(Box::new(PerformanceMiddleware::new()));
         chain.use_middleware(Box::new(LoggingMiddleware::new().with_level("info")));
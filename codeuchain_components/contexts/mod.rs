@@ -2,8 +2,6 @@
 
 pub use crate::types::*;
 use crate::core::Context;
-use std::collections::HashMap;
-use serde_json;
 
 /// Enhanced context wrapper with metadata and error handling
 #[derive(Clone)]
@@ -39,17 +37,14 @@ impl RustDeskChainContext {
 
         let context_data = RustDeskContext::Initial(connection_info);
 
-        let mut data = HashMap::new();
-        data.insert("rustdesk_context".to_string(), serde_json::to_value(context_data).unwrap());
-
         Self {
-            inner: Context::new(data),
+            inner: Context::empty().with_rustdesk_context(context_data),
             metadata,
         }
     }
 
     /// Create from existing CodeUChain context
-    pub fn from_context(context: Context) -> Result<Self> {
+    pub fn from_context(context: Context) -> CodeUChainResult<Self> {
         let metadata = ContextMetadata {
             session_id: format!("session_{}", rand::random::<u64>()),
             request_id: format!("req_{}", rand::random::<u64>()),
@@ -71,24 +66,23 @@ impl RustDeskChainContext {
     }
 
     /// Get the current RustDesk context data with error handling
-    pub fn data(&self) -> Result<RustDeskContext> {
-        let json_value = self.inner.data().get("rustdesk_context")
-            .ok_or(CodeUChainError::ValidationError("Missing rustdesk_context".to_string()))?;
-        serde_json::from_value(json_value.clone())
-            .map_err(|e| CodeUChainError::ValidationError(format!("Failed to deserialize context: {}", e)))
+    pub fn data(&self) -> CodeUChainResult<RustDeskContext> {
+        self.inner.clone().into_rustdesk_context()
+            .ok_or(CodeUChainError::ValidationError("Missing rustdesk_context".to_string()))
+    }
+
+    /// Borrow the typed RustDesk context without cloning it
+    pub fn state(&self) -> Option<&RustDeskContext> {
+        self.inner.as_rustdesk_context()
     }
 
     /// Insert new RustDesk context data with validation
-    pub fn insert(self, rustdesk_data: RustDeskContext) -> Result<RustDeskChainContext> {
+    pub fn insert(self, rustdesk_data: RustDeskContext) -> CodeUChainResult<RustDeskChainContext> {
         // Validate context transition
         self.validate_transition(&rustdesk_data)?;
 
-        let mut new_data = self.inner.data().clone();
-        new_data.insert("rustdesk_context".to_string(), serde_json::to_value(&rustdesk_data)
-            .map_err(|e| CodeUChainError::ValidationError(format!("Failed to serialize context: {}", e)))?);
-
         Ok(RustDeskChainContext {
-            inner: Context::new(new_data),
+            inner: self.inner.with_rustdesk_context(rustdesk_data),
             metadata: self.metadata,
         })
     }
@@ -106,7 +100,10 @@ impl RustDeskChainContext {
 
     /// Check if context is in error state
     pub fn is_error(&self) -> bool {
-        matches!(self.data(), Ok(RustDeskContext::Error { .. }))
+        match self.state() {
+            Some(state) => state.is_error(),
+            None => matches!(self.data(), Ok(RustDeskContext::Error { .. })),
+        }
     }
 
     /// Get error information if in error state
@@ -119,10 +116,18 @@ impl RustDeskChainContext {
     }
 
     /// Validate context state transitions
-    fn validate_transition(&self, new_context: &RustDeskContext) -> Result<()> {
-        let current = self.data()?;
+    fn validate_transition(&self, new_context: &RustDeskContext) -> CodeUChainResult<()> {
+        // Borrow the typed state when present so streaming frames are not copied
+        let decoded;
+        let current = match self.state() {
+            Some(state) => state,
+            None => {
+                decoded = self.data()?;
+                &decoded
+            }
+        };
 
-        let valid = match (&current, new_context) {
+        let valid = match (current, new_context) {
             (RustDeskContext::Initial(_), RustDeskContext::Connected(_)) => true,
             (RustDeskContext::Initial(_), RustDeskContext::Error { .. }) => true,
             (RustDeskContext::Connected(_), RustDeskContext::Streaming { .. }) => true,
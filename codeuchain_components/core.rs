@@ -7,17 +7,103 @@
 //! - Middleware: Cross-cutting concerns
 
 use async_trait::async_trait;
+use serde::Serialize;
 use serde_json::{Value, Map};
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Result type for CodeUChain operations
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// Why [`Chain::connect`] refused an edge
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectError {
+    UnknownLink(String),
+    Cycle { from: String, to: String },
+}
+
+impl std::fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownLink(name) => write!(f, "Cannot connect unknown link '{}'", name),
+            Self::Cycle { from, to } => write!(f, "Connecting '{}' -> '{}' would create a cycle", from, to),
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+/// Compile-time declaration of a typed context slot
+///
+/// Keys are zero-sized marker types, so two slots can never collide even if they
+/// share a `NAME`; the name is only used when the context is rendered as JSON.
+/// Declare keys with [`context_key!`](crate::context_key).
+pub trait ContextKey: 'static {
+    type Value: Clone + Serialize + Send + Sync + 'static;
+    const NAME: &'static str;
+}
+
+/// Declare a [`ContextKey`] marker type
+///
+/// ```ignore
+/// context_key!(pub SessionKey: SessionContext = "session");
+/// ```
+#[macro_export]
+macro_rules! context_key {
+    ($(#[$meta:meta])* $vis:vis $key:ident: $value:ty = $name:expr) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy)]
+        $vis struct $key;
+
+        impl $crate::core::ContextKey for $key {
+            type Value = $value;
+            const NAME: &'static str = $name;
+        }
+    };
+}
+
+/// A typed value stored alongside its JSON renderer
+#[derive(Clone)]
+struct TypedEntry {
+    name: &'static str,
+    value: Arc<dyn Any + Send + Sync>,
+    to_json: fn(&(dyn Any + Send + Sync)) -> Value,
+}
+
+impl TypedEntry {
+    fn new<K: ContextKey>(value: K::Value) -> Self {
+        Self {
+            name: K::NAME,
+            value: Arc::new(value),
+            to_json: |any| {
+                any.downcast_ref::<<K as ContextKey>::Value>()
+                    .and_then(|value| serde_json::to_value(value).ok())
+                    .unwrap_or(Value::Null)
+            },
+        }
+    }
+
+    fn json(&self) -> Value {
+        (self.to_json)(self.value.as_ref())
+    }
+}
+
+impl std::fmt::Debug for TypedEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.json())
+    }
+}
+
 /// Context represents immutable state flowing through processing chains
+///
+/// Besides the untyped JSON map, a context carries typed slots declared with
+/// [`ContextKey`]. Typed values are shared by `Arc` and never re-encoded while
+/// they move between links; they are only serialized by [`Context::to_json`].
 #[derive(Clone, Debug)]
 pub struct Context {
     data: Arc<HashMap<String, Value>>,
+    typed: Arc<HashMap<TypeId, TypedEntry>>,
 }
 
 impl Context {
@@ -25,6 +111,7 @@ impl Context {
     pub fn new(data: HashMap<String, Value>) -> Self {
         Self {
             data: Arc::new(data),
+            typed: Arc::new(HashMap::new()),
         }
     }
 
@@ -42,7 +129,53 @@ impl Context {
     pub fn insert(&self, key: String, value: Value) -> Self {
         let mut new_data = (*self.data).clone();
         new_data.insert(key, value);
-        Self::new(new_data)
+        Self {
+            data: Arc::new(new_data),
+            typed: self.typed.clone(),
+        }
+    }
+
+    /// Remove an untyped value, returning a new context
+    pub fn remove(&self, key: &str) -> Self {
+        if !self.data.contains_key(key) {
+            return self.clone();
+        }
+        let mut new_data = (*self.data).clone();
+        new_data.remove(key);
+        Self {
+            data: Arc::new(new_data),
+            typed: self.typed.clone(),
+        }
+    }
+
+    /// Replace the untyped data while keeping every typed slot
+    pub fn with_data(&self, data: HashMap<String, Value>) -> Self {
+        Self {
+            data: Arc::new(data),
+            typed: self.typed.clone(),
+        }
+    }
+
+    /// Get a typed value from the context
+    pub fn get_typed<K: ContextKey>(&self) -> Option<&K::Value> {
+        self.typed
+            .get(&TypeId::of::<K>())
+            .and_then(|entry| entry.value.downcast_ref::<K::Value>())
+    }
+
+    /// Insert a typed value, returning a new context
+    pub fn insert_typed<K: ContextKey>(&self, value: K::Value) -> Self {
+        let mut typed = (*self.typed).clone();
+        typed.insert(TypeId::of::<K>(), TypedEntry::new::<K>(value));
+        Self {
+            data: self.data.clone(),
+            typed: Arc::new(typed),
+        }
+    }
+
+    /// Check whether a typed slot is set
+    pub fn contains_typed<K: ContextKey>(&self) -> bool {
+        self.typed.contains_key(&TypeId::of::<K>())
     }
 
     /// Get the underlying data
@@ -51,18 +184,39 @@ impl Context {
     }
 
     /// Convert to a Map for JSON serialization
+    ///
+    /// Typed slots are rendered under their key names and win over untyped
+    /// values with the same name.
     pub fn to_map(&self) -> Map<String, Value> {
-        self.data.iter()
+        let mut map: Map<String, Value> = self.data.iter()
             .map(|(k, v)| (k.clone(), v.clone()))
-            .collect()
+            .collect();
+        for entry in self.typed.values() {
+            map.insert(entry.name.to_string(), entry.json());
+        }
+        map
+    }
+
+    /// Render the whole context, typed slots included, as a JSON object
+    pub fn to_json(&self) -> Value {
+        Value::Object(self.to_map())
     }
 }
 
+/// Edge predicate deciding whether control flows from one link to the next
+pub type Predicate = Box<dyn Fn(&Context) -> bool + Send + Sync>;
+
 /// Chain represents a processing pipeline of links
+///
+/// Links form a directed acyclic graph. Links without incoming connections are
+/// entry points and always run; every other link runs only when at least one of
+/// its incoming predicates held for the context produced by its source link.
+/// Links are visited in topological order, ties broken by insertion order, so a
+/// join waits for every upstream link to either run or be skipped.
 #[derive(Default)]
 pub struct Chain {
-    links: HashMap<String, Box<dyn Link>>,
-    connections: Vec<(String, String, Box<dyn Fn(&Context) -> bool + Send + Sync>)>,
+    links: Vec<(String, Box<dyn Link>)>,
+    connections: Vec<(String, String, Predicate)>,
     middleware: Vec<Box<dyn Middleware>>,
 }
 
@@ -70,50 +224,143 @@ impl Chain {
     /// Create a new empty chain
     pub fn new() -> Self {
         Self {
-            links: HashMap::new(),
+            links: Vec::new(),
             connections: Vec::new(),
             middleware: Vec::new(),
         }
     }
 
     /// Add a link to the chain
+    ///
+    /// Re-adding an existing name replaces the link but keeps its original position.
     pub fn add_link(&mut self, name: String, link: Box<dyn Link>) {
-        self.links.insert(name, link);
+        match self.links.iter_mut().find(|(existing, _)| *existing == name) {
+            Some(entry) => entry.1 = link,
+            None => self.links.push((name, link)),
+        }
     }
 
     /// Connect two links with a predicate
-    pub fn connect<F>(&mut self, from: String, to: String, predicate: F)
+    ///
+    /// Fails if either link has not been added yet or if the edge would close a cycle.
+    pub fn connect<F>(&mut self, from: String, to: String, predicate: F) -> std::result::Result<(), ConnectError>
     where
         F: Fn(&Context) -> bool + Send + Sync + 'static,
     {
+        for name in [&from, &to] {
+            if self.index_of(name).is_none() {
+                return Err(ConnectError::UnknownLink(name.clone()));
+            }
+        }
+        if from == to || self.reachable(&to, &from) {
+            return Err(ConnectError::Cycle { from, to });
+        }
+
         self.connections.push((from, to, Box::new(predicate)));
+        Ok(())
     }
 
     /// Add middleware to the chain
+    ///
+    /// Middleware added first is the outermost layer around every link call.
     pub fn use_middleware(&mut self, middleware: Box<dyn Middleware>) {
         self.middleware.push(middleware);
     }
 
+    /// Look up a link by name
+    pub fn link(&self, name: &str) -> Option<&dyn Link> {
+        self.index_of(name).map(|index| self.links[index].1.as_ref())
+    }
+
+    /// Names of the links in the order they will be considered for execution
+    pub fn execution_order(&self) -> Vec<&str> {
+        self.topological_order()
+            .into_iter()
+            .map(|index| self.links[index].0.as_str())
+            .collect()
+    }
+
     /// Run the chain with the given context
     pub async fn run(&self, mut ctx: Context) -> Result<Context> {
-        // For now, run all links in sequence
-        // TODO: Implement proper graph execution with predicates
-        for (name, link) in &self.links {
-            // Run middleware before
-            for middleware in &self.middleware {
-                middleware.before(name, &ctx).await?;
+        let order = self.topological_order();
+        // Links with no incoming edges are entry points and are always active
+        let mut active: Vec<bool> = (0..self.links.len())
+            .map(|index| !self.connections.iter().any(|(_, to, _)| *to == self.links[index].0))
+            .collect();
+
+        for index in order {
+            if !active[index] {
+                continue;
+            }
+            let (name, link) = &self.links[index];
+
+            let next = Next {
+                name,
+                link: link.as_ref(),
+                middleware: &self.middleware,
+            };
+            ctx = next.run(ctx).await?;
+
+            for (from, to, predicate) in &self.connections {
+                if from == name && predicate(&ctx) {
+                    if let Some(target) = self.index_of(to) {
+                        active[target] = true;
+                    }
+                }
             }
+        }
 
-            // Run the link
-            ctx = link.call(ctx).await?;
+        Ok(ctx)
+    }
 
-            // Run middleware after
-            for middleware in &self.middleware {
-                middleware.after(name, &ctx).await?;
+    fn index_of(&self, name: &str) -> Option<usize> {
+        self.links.iter().position(|(existing, _)| existing == name)
+    }
+
+    /// Whether `to` can be reached from `from` following existing connections
+    fn reachable(&self, from: &str, to: &str) -> bool {
+        let mut stack = vec![from];
+        let mut seen = std::collections::HashSet::new();
+        while let Some(current) = stack.pop() {
+            if current == to {
+                return true;
+            }
+            if !seen.insert(current) {
+                continue;
             }
+            stack.extend(
+                self.connections
+                    .iter()
+                    .filter(|(source, _, _)| source == current)
+                    .map(|(_, target, _)| target.as_str()),
+            );
         }
+        false
+    }
 
-        Ok(ctx)
+    /// Kahn's algorithm, always picking the earliest inserted ready link
+    fn topological_order(&self) -> Vec<usize> {
+        let mut in_degree = vec![0usize; self.links.len()];
+        for (_, to, _) in &self.connections {
+            if let Some(target) = self.index_of(to) {
+                in_degree[target] += 1;
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.links.len());
+        let mut done = vec![false; self.links.len()];
+        while let Some(next) = (0..self.links.len()).find(|&i| !done[i] && in_degree[i] == 0) {
+            done[next] = true;
+            order.push(next);
+            for (from, to, _) in &self.connections {
+                if *from == self.links[next].0 {
+                    if let Some(target) = self.index_of(to) {
+                        in_degree[target] -= 1;
+                    }
+                }
+            }
+        }
+        order
     }
 }
 
@@ -124,16 +371,62 @@ pub trait Link: Send + Sync {
     async fn call(&self, ctx: Context) -> Result<Context>;
 }
 
-/// Legacy link trait for backward compatibility
-#[async_trait]
-pub trait Link: Send + Sync {
-    /// Process the context and return a result
-    async fn call(&self, ctx: Context) -> Result<Context>;
+/// The remainder of a link invocation: inner middleware and the link itself
+pub struct Next<'a> {
+    name: &'a str,
+    link: &'a dyn Link,
+    middleware: &'a [Box<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    /// Name of the link being invoked
+    pub fn link_name(&self) -> &str {
+        self.name
+    }
+
+    /// Run the inner middleware and the link
+    ///
+    /// May be called more than once (to retry) or not at all (to short-circuit).
+    pub async fn run(&self, ctx: Context) -> Result<Context> {
+        match self.middleware.split_first() {
+            Some((outer, inner)) => {
+                let next = Next {
+                    name: self.name,
+                    link: self.link,
+                    middleware: inner,
+                };
+                outer.around(self.name, ctx, next).await
+            }
+            None => self.link.call(ctx).await,
+        }
+    }
 }
 
 /// Middleware trait for cross-cutting concerns
+///
+/// Middleware either observes a link call through `before`/`after`/`on_error`,
+/// or takes control of it by overriding `around`.
 #[async_trait]
 pub trait Middleware: Send + Sync {
+    /// Wrap the link call
+    ///
+    /// The default runs `before`, then the rest of the stack, then `after` or
+    /// `on_error`. Overrides decide whether, how often and with which context
+    /// `next` runs, and may return a context of their own instead.
+    async fn around(&self, name: &str, ctx: Context, next: Next<'_>) -> Result<Context> {
+        self.before(name, &ctx).await?;
+        match next.run(ctx.clone()).await {
+            Ok(output) => {
+                self.after(name, &output).await?;
+                Ok(output)
+            }
+            Err(err) => {
+                self.on_error(name, &ctx, &err).await?;
+                Err(err)
+            }
+        }
+    }
+
     /// Called before link execution
     async fn before(&self, name: &str, ctx: &Context) -> Result<()> {
         Ok(())
@@ -150,6 +443,27 @@ pub trait Middleware: Send + Sync {
     }
 }
 
+/// Shared middleware, so callers can keep a handle (e.g. to read metrics) after
+/// handing a clone to a chain
+#[async_trait]
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    async fn around(&self, name: &str, ctx: Context, next: Next<'_>) -> Result<Context> {
+        (**self).around(name, ctx, next).await
+    }
+
+    async fn before(&self, name: &str, ctx: &Context) -> Result<()> {
+        (**self).before(name, ctx).await
+    }
+
+    async fn after(&self, name: &str, ctx: &Context) -> Result<()> {
+        (**self).after(name, ctx).await
+    }
+
+    async fn on_error(&self, name: &str, ctx: &Context, err: &Box<dyn std::error::Error + Send + Sync>) -> Result<()> {
+        (**self).on_error(name, ctx, err).await
+    }
+}
+
 /// Simple logging middleware
 pub struct LoggingMiddleware;
 
@@ -212,4 +526,265 @@ impl Middleware for PerformanceMiddleware {
         // For simplicity, we'll skip detailed timing in this stub
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Appends its name to the `trace` array so tests can observe execution order
+    struct TraceLink {
+        name: &'static str,
+        fail: bool,
+    }
+
+    impl TraceLink {
+        fn new(name: &'static str) -> Box<dyn Link> {
+            Box::new(Self { name, fail: false })
+        }
+
+        fn failing(name: &'static str) -> Box<dyn Link> {
+            Box::new(Self { name, fail: true })
+        }
+    }
+
+    #[async_trait]
+    impl Link for TraceLink {
+        async fn call(&self, ctx: Context) -> Result<Context> {
+            if self.fail {
+                return Err(format!("{} failed", self.name).into());
+            }
+            let mut trace = ctx.get("trace").and_then(|v| v.as_array().cloned()).unwrap_or_default();
+            trace.push(json!(self.name));
+            Ok(ctx.insert("trace".to_string(), Value::Array(trace)))
+        }
+    }
+
+    fn trace_of(ctx: &Context) -> Vec<String> {
+        ctx.get("trace")
+            .and_then(|v| v.as_array())
+            .map(|items| items.iter().filter_map(|v| v.as_str().map(String::from)).collect())
+            .unwrap_or_default()
+    }
+
+    struct ErrorCounter {
+        errors: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for ErrorCounter {
+        async fn on_error(&self, name: &str, _ctx: &Context, _err: &Box<dyn std::error::Error + Send + Sync>) -> Result<()> {
+            self.errors.lock().unwrap().push(name.to_string());
+            Ok(())
+        }
+    }
+
+    /// Records `before`/`after` hooks in a shared log
+    struct Observer {
+        label: &'static str,
+        log: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    #[async_trait]
+    impl Middleware for Observer {
+        async fn before(&self, name: &str, _ctx: &Context) -> Result<()> {
+            self.log.lock().unwrap().push(format!("{}:before:{}", self.label, name));
+            Ok(())
+        }
+
+        async fn after(&self, name: &str, _ctx: &Context) -> Result<()> {
+            self.log.lock().unwrap().push(format!("{}:after:{}", self.label, name));
+            Ok(())
+        }
+    }
+
+    /// Retries once, then replaces a persistent failure with a marker context
+    struct RetryThenFallback;
+
+    #[async_trait]
+    impl Middleware for RetryThenFallback {
+        async fn around(&self, _name: &str, ctx: Context, next: Next<'_>) -> Result<Context> {
+            for _ in 0..2 {
+                if let Ok(output) = next.run(ctx.clone()).await {
+                    return Ok(output);
+                }
+            }
+            Ok(ctx.insert("fallback".to_string(), json!(next.link_name())))
+        }
+    }
+
+    /// Fails on its first call only
+    struct FlakyLink {
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Link for FlakyLink {
+        async fn call(&self, ctx: Context) -> Result<Context> {
+            let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if call == 0 {
+                return Err("first call fails".into());
+            }
+            Ok(ctx.insert("calls".to_string(), json!(call + 1)))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unconnected_links_run_in_insertion_order() {
+        let mut chain = Chain::new();
+        for name in ["e", "d", "c", "b", "a"] {
+            chain.add_link(name.to_string(), TraceLink::new(name));
+        }
+
+        let ctx = chain.run(Context::empty()).await.unwrap();
+        assert_eq!(trace_of(&ctx), vec!["e", "d", "c", "b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_predicates_route_between_branches() {
+        let mut chain = Chain::new();
+        chain.add_link("start".to_string(), TraceLink::new("start"));
+        chain.add_link("left".to_string(), TraceLink::new("left"));
+        chain.add_link("right".to_string(), TraceLink::new("right"));
+        chain.connect("start".to_string(), "left".to_string(), |ctx| {
+            ctx.get("route").and_then(|v| v.as_str()) == Some("left")
+        }).unwrap();
+        chain.connect("start".to_string(), "right".to_string(), |ctx| {
+            ctx.get("route").and_then(|v| v.as_str()) == Some("right")
+        }).unwrap();
+
+        let ctx = Context::empty().insert("route".to_string(), json!("right"));
+        let ctx = chain.run(ctx).await.unwrap();
+        assert_eq!(trace_of(&ctx), vec!["start", "right"]);
+    }
+
+    #[tokio::test]
+    async fn test_join_runs_after_all_branches() {
+        let mut chain = Chain::new();
+        chain.add_link("join".to_string(), TraceLink::new("join"));
+        chain.add_link("source".to_string(), TraceLink::new("source"));
+        chain.add_link("a".to_string(), TraceLink::new("a"));
+        chain.add_link("b".to_string(), TraceLink::new("b"));
+        chain.connect("source".to_string(), "a".to_string(), |_| true).unwrap();
+        chain.connect("source".to_string(), "b".to_string(), |_| true).unwrap();
+        chain.connect("a".to_string(), "join".to_string(), |_| true).unwrap();
+        chain.connect("b".to_string(), "join".to_string(), |_| false).unwrap();
+
+        assert_eq!(chain.execution_order(), vec!["source", "a", "b", "join"]);
+        let ctx = chain.run(Context::empty()).await.unwrap();
+        assert_eq!(trace_of(&ctx), vec!["source", "a", "b", "join"]);
+    }
+
+    #[tokio::test]
+    async fn test_skipped_link_does_not_activate_successors() {
+        let mut chain = Chain::new();
+        chain.add_link("a".to_string(), TraceLink::new("a"));
+        chain.add_link("b".to_string(), TraceLink::new("b"));
+        chain.add_link("c".to_string(), TraceLink::new("c"));
+        chain.connect("a".to_string(), "b".to_string(), |_| false).unwrap();
+        chain.connect("b".to_string(), "c".to_string(), |_| true).unwrap();
+
+        let ctx = chain.run(Context::empty()).await.unwrap();
+        assert_eq!(trace_of(&ctx), vec!["a"]);
+    }
+
+    #[test]
+    fn test_connect_rejects_cycles_and_unknown_links() {
+        let mut chain = Chain::new();
+        chain.add_link("a".to_string(), TraceLink::new("a"));
+        chain.add_link("b".to_string(), TraceLink::new("b"));
+        chain.add_link("c".to_string(), TraceLink::new("c"));
+        chain.connect("a".to_string(), "b".to_string(), |_| true).unwrap();
+        chain.connect("b".to_string(), "c".to_string(), |_| true).unwrap();
+
+        assert!(matches!(
+            chain.connect("c".to_string(), "a".to_string(), |_| true),
+            Err(ConnectError::Cycle { .. })
+        ));
+        assert!(chain.connect("a".to_string(), "a".to_string(), |_| true).is_err());
+        assert_eq!(
+            chain.connect("a".to_string(), "missing".to_string(), |_| true),
+            Err(ConnectError::UnknownLink("missing".to_string()))
+        );
+    }
+
+    crate::context_key!(FrameCount: u64 = "frame_count");
+    crate::context_key!(Labels: Vec<String> = "labels");
+    crate::context_key!(ShadowCount: String = "frame_count");
+
+    #[test]
+    fn test_typed_slots_round_trip_without_json() {
+        let ctx = Context::empty()
+            .insert_typed::<FrameCount>(42)
+            .insert_typed::<Labels>(vec!["a".to_string()]);
+
+        assert_eq!(ctx.get_typed::<FrameCount>(), Some(&42));
+        assert_eq!(ctx.get_typed::<Labels>().map(Vec::len), Some(1));
+        assert!(ctx.get("frame_count").is_none());
+
+        // Keys are distinct types, so a clashing name cannot alias another slot
+        assert!(ctx.get_typed::<ShadowCount>().is_none());
+    }
+
+    #[test]
+    fn test_untyped_updates_keep_typed_slots() {
+        let ctx = Context::empty()
+            .insert_typed::<FrameCount>(7)
+            .insert("peer_id".to_string(), json!("abc"))
+            .remove("peer_id");
+
+        assert_eq!(ctx.get_typed::<FrameCount>(), Some(&7));
+        assert!(ctx.get("peer_id").is_none());
+    }
+
+    #[test]
+    fn test_to_json_renders_typed_slots() {
+        let ctx = Context::empty()
+            .insert("peer_id".to_string(), json!("abc"))
+            .insert_typed::<Labels>(vec!["x".to_string(), "y".to_string()]);
+
+        assert_eq!(ctx.to_json(), json!({"peer_id": "abc", "labels": ["x", "y"]}));
+    }
+
+    #[tokio::test]
+    async fn test_link_failure_notifies_middleware() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut chain = Chain::new();
+        chain.add_link("ok".to_string(), TraceLink::new("ok"));
+        chain.add_link("broken".to_string(), TraceLink::failing("broken"));
+        chain.add_link("never".to_string(), TraceLink::new("never"));
+        chain.use_middleware(Box::new(ErrorCounter { errors: errors.clone() }));
+
+        let result = chain.run(Context::empty()).await;
+        assert!(result.is_err());
+        assert_eq!(*errors.lock().unwrap(), vec!["broken".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_middleware_nests_around_links() {
+        let log = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut chain = Chain::new();
+        chain.add_link("only".to_string(), TraceLink::new("only"));
+        chain.use_middleware(Box::new(Observer { label: "outer", log: log.clone() }));
+        chain.use_middleware(Box::new(Observer { label: "inner", log: log.clone() }));
+
+        chain.run(Context::empty()).await.unwrap();
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec!["outer:before:only", "inner:before:only", "inner:after:only", "outer:after:only"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_around_can_retry_and_substitute() {
+        let mut chain = Chain::new();
+        chain.add_link("flaky".to_string(), Box::new(FlakyLink { calls: Default::default() }));
+        chain.add_link("broken".to_string(), TraceLink::failing("broken"));
+        chain.use_middleware(Box::new(RetryThenFallback));
+
+        let ctx = chain.run(Context::empty()).await.unwrap();
+        assert_eq!(ctx.get("calls"), Some(&json!(2)));
+        assert_eq!(ctx.get("fallback"), Some(&json!("broken")));
+    }
+}
@@ -24,9 +24,9 @@ impl ApplicationChainFactory {
 
         // Connect links in sequence with simple predicates that always pass
         // (CodeUChain may require connections even for sequential execution)
-        chain.connect("argument_processing".to_string(), "configuration".to_string(), |_| true);
-        chain.connect("configuration".to_string(), "service_initialization".to_string(), |_| true);
-        chain.connect("service_initialization".to_string(), "lifecycle_management".to_string(), |_| true);
+        chain.connect("argument_processing".to_string(), "configuration".to_string(), |_| true).expect("static chain topology");
+        chain.connect("configuration".to_string(), "service_initialization".to_string(), |_| true).expect("static chain topology");
+        chain.connect("service_initialization".to_string(), "lifecycle_management".to_string(), |_| true).expect("static chain topology");
 
         chain
     }
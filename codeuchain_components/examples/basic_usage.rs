@@ -22,7 +22,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     middleware_stack.add_security();
 
     // Create remote desktop chain
-    let mut remote_desktop_chain = RemoteDesktopChain::new();
+    let mut remote_desktop_chain = RemoteDesktopChain::new("1.4.2");
 
     // Add middleware to the chain
     for middleware in middleware_stack.get_middlewares() {
@@ -165,7 +165,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_direct_ip_connection() {
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new("1.4.2");
         let context = create_connection_context("192.168.1.100:21116");
 
         let result = chain.process(context).await.unwrap();
@@ -188,7 +188,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_domain_connection() {
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new("1.4.2");
         let context = create_connection_context("example.com:21116");
 
         let result = chain.process(context).await.unwrap();
@@ -211,7 +211,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_peer_id_connection() {
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new("1.4.2");
         let context = create_connection_context("123456789");
 
         let result = chain.process(context).await.unwrap();
@@ -234,7 +234,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_streaming_session() {
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new("1.4.2");
         let context = create_streaming_context();
 
         let result = chain.process(context).await.unwrap();
@@ -261,7 +261,7 @@ mod tests {
         middleware_stack.add_logging();
         middleware_stack.add_performance_monitoring();
 
-        let mut chain = RemoteDesktopChain::new();
+        let mut chain = RemoteDesktopChain::new("1.4.2");
         for middleware in middleware_stack.get_middlewares() {
             chain.add_middleware(middleware.as_ref());
         }
@@ -287,7 +287,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_multiple_frame_processing() {
-        let chain = RemoteDesktopChain::new();
+        let chain = RemoteDesktopChain::new("1.4.2");
         let mut context = create_streaming_context();
 
         // Process multiple frames
@@ -24,7 +24,7 @@ pub fn create_codeuchain_client() -> Result<(), Box<dyn std::error::Error>> {
     println!("3. Start connection with modular processing");
 
     // Create a client chain with comprehensive middleware
-    let mut chain = ClientChain::new();
+    let mut chain = ClientChain::new("1.4.2");
 
     // Add middleware stack for observability and reliability
     chain = chain
@@ -19,7 +19,7 @@ impl IPCChainFactory {
         chain.connect("config_validator".to_string(), "config_processor".to_string(), |ctx| {
             // Always proceed to processor after validation
             true
-        });
+        }).expect("static chain topology");
 
         // Add middleware
         chain.use_middleware(Box::new(LoggingMiddleware::new()));
@@ -41,7 +41,7 @@ impl IPCChainFactory {
         chain.connect("message_validator".to_string(), "message_processor".to_string(), |ctx| {
             // Always proceed to processor after validation
             true
-        });
+        }).expect("static chain topology");
 
         // Add middleware
         chain.use_middleware(Box::new(LoggingMiddleware::new()));
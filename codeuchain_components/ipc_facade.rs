@@ -1,5 +1,7 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use lazy_static::lazy_static;
 use crate::core::{Context, Chain, Link};
 use crate::types::*;
@@ -7,10 +9,22 @@ use crate::contexts::*;
 use crate::middleware::*;
 use crate::ipc_links::*;
 use crate::ipc_chains::*;
+use crate::migration::{MIGRATION_STATUS_QUERY, MIGRATION_TRACKER};
+
+#[cfg(feature = "full")]
+use hbb_common::{
+    bytes_codec::BytesCodec,
+    config::Config,
+    futures::StreamExt as _,
+    futures_util::sink::SinkExt,
+    tokio_util::codec::Framed,
+};
+#[cfg(feature = "full")]
+use parity_tokio_ipc::{ConnectionClient, Endpoint, Incoming};
 
 /// Type aliases for compatibility
-pub type ResultType<T> = crate::types::Result<T>;
-pub type LinkResult<T> = crate::types::Result<T>;
+pub type ResultType<T> = crate::core::Result<T>;
+pub type LinkResult<T> = crate::core::Result<T>;
 
 /// IPC Facade for CodeUChain migration
 /// Maintains API compatibility while using CodeUChain internally
@@ -27,13 +41,26 @@ pub trait IPCFacade {
 }
 
 /// IPC Connection abstraction
+///
+/// With the `full` feature this is a client socket to an IPC server (ours or the
+/// legacy `ipc::start`); without it, messages are handled in process and the
+/// replies queued for `receive_message`.
 pub struct IPCConnection {
     pub context: RustDeskChainContext,
-    pub chain: Chain,
+    transport: IPCTransport,
+}
+
+enum IPCTransport {
+    #[cfg(feature = "full")]
+    Socket(Framed<ConnectionClient, BytesCodec>),
+    InProcess(std::collections::VecDeque<IPCData>),
 }
 
 /// IPC Data types (simplified for migration)
-#[derive(Debug, Clone, Serialize, Deserialize)]
+///
+/// Encoded on the wire exactly like the matching `ipc::Data` variants, so both
+/// sides interoperate with the legacy IPC; see [`IPCData::to_wire`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum IPCData {
     Config { name: String, value: Option<String> },
     Options(Option<HashMap<String, String>>),
@@ -44,11 +71,149 @@ pub enum IPCData {
     Test,
 }
 
+/// Wire mirror of the `ipc::Data` variants the facade understands
+///
+/// `ipc::Data` frames are JSON, adjacently tagged with `t`/`c`. Frames for any
+/// other variant fail to decode and are skipped, as the legacy reader does.
+mod wire {
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    #[serde(tag = "t", content = "c")]
+    pub enum Data {
+        Login {
+            id: i32,
+            is_file_transfer: bool,
+            #[serde(default)]
+            is_view_camera: bool,
+            #[serde(default)]
+            is_terminal: bool,
+            peer_id: String,
+            #[serde(default)]
+            name: String,
+            #[serde(default)]
+            authorized: bool,
+            #[serde(default)]
+            port_forward: String,
+            #[serde(default)]
+            keyboard: bool,
+            #[serde(default)]
+            clipboard: bool,
+            #[serde(default)]
+            audio: bool,
+            #[serde(default)]
+            file: bool,
+            #[serde(default)]
+            file_transfer_enabled: bool,
+            #[serde(default)]
+            restart: bool,
+            #[serde(default)]
+            recording: bool,
+            #[serde(default)]
+            block_input: bool,
+            #[serde(default)]
+            from_switch: bool,
+        },
+        ChatMessage {
+            text: String,
+        },
+        SystemInfo(Option<String>),
+        Close,
+        Config((String, Option<String>)),
+        Options(Option<HashMap<String, String>>),
+        Test,
+    }
+}
+
+impl IPCData {
+    /// Encode as an `ipc::Data` JSON frame
+    pub fn to_wire(&self) -> Vec<u8> {
+        let data = match self.clone() {
+            IPCData::Config { name, value } => wire::Data::Config((name, value)),
+            IPCData::Options(options) => wire::Data::Options(options),
+            IPCData::SystemInfo(info) => wire::Data::SystemInfo(info),
+            IPCData::Login { id, is_file_transfer, peer_id } => wire::Data::Login {
+                id,
+                is_file_transfer,
+                is_view_camera: false,
+                is_terminal: false,
+                peer_id,
+                name: String::new(),
+                authorized: false,
+                port_forward: String::new(),
+                keyboard: false,
+                clipboard: false,
+                audio: false,
+                file: false,
+                file_transfer_enabled: false,
+                restart: false,
+                recording: false,
+                block_input: false,
+                from_switch: false,
+            },
+            IPCData::ChatMessage { text } => wire::Data::ChatMessage { text },
+            IPCData::Close => wire::Data::Close,
+            IPCData::Test => wire::Data::Test,
+        };
+        serde_json::to_vec(&data).unwrap_or_default()
+    }
+
+    /// Decode an `ipc::Data` JSON frame; `None` for variants the facade does not handle
+    pub fn from_wire(bytes: &[u8]) -> Option<Self> {
+        let data = serde_json::from_slice::<wire::Data>(bytes).ok()?;
+        Some(match data {
+            wire::Data::Config((name, value)) => IPCData::Config { name, value },
+            wire::Data::Options(options) => IPCData::Options(options),
+            wire::Data::SystemInfo(info) => IPCData::SystemInfo(info),
+            wire::Data::Login { id, is_file_transfer, peer_id, .. } => IPCData::Login { id, is_file_transfer, peer_id },
+            wire::Data::ChatMessage { text } => IPCData::ChatMessage { text },
+            wire::Data::Close => IPCData::Close,
+            wire::Data::Test => IPCData::Test,
+        })
+    }
+
+    /// Context consumed by the IPC links, with the request under `ipc_data`
+    fn to_context(&self) -> Context {
+        let ipc_data = match self {
+            IPCData::Config { name, value } => match value {
+                Some(value) => json!({"ipc_action": "config", "config_name": name, "config_value": value}),
+                None => json!({"ipc_action": "config", "config_name": name}),
+            },
+            IPCData::Options(options) => match options {
+                Some(options) => json!({
+                    "ipc_action": "options",
+                    "options": serde_json::to_string(options).unwrap_or_default(),
+                }),
+                None => json!({"ipc_action": "options"}),
+            },
+            IPCData::SystemInfo(_) => json!({"ipc_action": "system_info"}),
+            IPCData::Login { id, is_file_transfer, peer_id } => json!({
+                "ipc_action": "login",
+                "login_id": id.to_string(),
+                "is_file_transfer": is_file_transfer.to_string(),
+                "peer_id": peer_id,
+            }),
+            IPCData::ChatMessage { text } => json!({"ipc_action": "chat", "chat_text": text}),
+            IPCData::Close => json!({"ipc_action": "close"}),
+            IPCData::Test => json!({"ipc_action": "test"}),
+        };
+        Context::empty().insert("ipc_data".to_string(), ipc_data)
+    }
+}
+
+/// Side effects of an options update, see [`CodeUChainIPC::with_options_hook`]
+///
+/// Called before the options are written; the returned guard is dropped after.
+pub type OptionsHook = Arc<dyn Fn(&HashMap<String, String>) -> Box<dyn Send> + Send + Sync>;
+
 /// CodeUChain-based IPC implementation
+#[derive(Clone)]
 pub struct CodeUChainIPC {
-    config_chain: Chain,
-    message_chain: Chain,
-    system_chain: Chain,
+    config_chain: Arc<Chain>,
+    message_chain: Arc<Chain>,
+    system_chain: Arc<Chain>,
+    options_hook: Option<OptionsHook>,
 }
 
 impl CodeUChainIPC {
@@ -67,120 +232,187 @@ impl CodeUChainIPC {
         // Add middleware to all chains
         let logging_mw = LoggingMiddleware::new();
         let security_mw = SecurityMiddleware::new();
-        let rate_limit_mw = RateLimitMiddleware::new(100); // 100 requests per window
 
         config_chain.use_middleware(Box::new(logging_mw.clone()));
         config_chain.use_middleware(Box::new(security_mw.clone()));
-        config_chain.use_middleware(Box::new(rate_limit_mw.clone()));
 
         message_chain.use_middleware(Box::new(logging_mw.clone()));
         message_chain.use_middleware(Box::new(security_mw.clone()));
-        message_chain.use_middleware(Box::new(rate_limit_mw.clone()));
 
         system_chain.use_middleware(Box::new(logging_mw));
         system_chain.use_middleware(Box::new(security_mw));
-        system_chain.use_middleware(Box::new(rate_limit_mw));
 
         Self {
-            config_chain,
-            message_chain,
-            system_chain,
+            config_chain: Arc::new(config_chain),
+            message_chain: Arc::new(message_chain),
+            system_chain: Arc::new(system_chain),
+            options_hook: None,
         }
     }
+
+    /// Run `hook` around every options update, for the restarts and privacy
+    /// mode switch `ipc::handle` does then
+    pub fn with_options_hook(mut self, hook: OptionsHook) -> Self {
+        self.options_hook = Some(hook);
+        self
+    }
+
+    /// Route one request through its chain and build the reply the legacy server would send
+    pub async fn handle(&self, data: IPCData) -> std::result::Result<Option<IPCData>, Box<dyn std::error::Error + Send + Sync>> {
+        if let IPCData::Config { name, value: None } = &data {
+            if name == MIGRATION_STATUS_QUERY {
+                return Ok(Some(IPCData::Config {
+                    name: name.clone(),
+                    value: Some(MIGRATION_TRACKER.report_json()),
+                }));
+            }
+        }
+
+        let chain = match &data {
+            IPCData::Config { .. } | IPCData::Options(_) => &self.config_chain,
+            IPCData::SystemInfo(_) => &self.system_chain,
+            IPCData::Login { .. } | IPCData::ChatMessage { .. } | IPCData::Close | IPCData::Test => &self.message_chain,
+        };
+        let _guard = match (&data, &self.options_hook) {
+            (IPCData::Options(Some(options)), Some(hook)) => Some(hook(options)),
+            _ => None,
+        };
+        let result = chain.run(data.to_context()).await?;
+        let response = |key: &str| result.get(key).and_then(Value::as_str).map(str::to_string);
+
+        Ok(match data {
+            IPCData::Config { name, value: None } => Some(IPCData::Config {
+                value: response("config_response"),
+                name,
+            }),
+            IPCData::Options(None) => Some(IPCData::Options(
+                response("options_response").and_then(|options| serde_json::from_str(&options).ok()),
+            )),
+            // Acknowledged like the legacy server, `ipc::set_options` waits for it
+            IPCData::Options(Some(_)) => Some(IPCData::Options(None)),
+            IPCData::SystemInfo(_) => Some(IPCData::SystemInfo(response("system_info_response"))),
+            _ => None,
+        })
+    }
+
+    /// Accept connections on `incoming` until the task is dropped
+    ///
+    /// `incoming` should come from `ipc::new_listener`, which leaves a running
+    /// server alone, removes a stale socket and writes the pid file.
+    #[cfg(feature = "full")]
+    pub fn serve(&self, mut incoming: Incoming, postfix: &str) -> tokio::task::JoinHandle<()> {
+        let facade = self.clone();
+        let postfix = postfix.to_owned();
+        tokio::spawn(async move {
+            while let Some(result) = incoming.next().await {
+                let stream = match result {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        hbb_common::log::error!("Couldn't get client: {:?}", err);
+                        continue;
+                    }
+                };
+                let facade = facade.clone();
+                let postfix = postfix.clone();
+                tokio::spawn(async move {
+                    let mut framed = Framed::new(stream, BytesCodec::new());
+                    while let Some(frame) = framed.next().await {
+                        let bytes = match frame {
+                            Ok(bytes) => bytes,
+                            Err(err) => {
+                                hbb_common::log::trace!("ipc '{}' connection closed: {}", postfix, err);
+                                break;
+                            }
+                        };
+                        let Some(data) = IPCData::from_wire(&bytes) else {
+                            continue;
+                        };
+                        match facade.handle(data).await {
+                            Ok(Some(reply)) => {
+                                if let Err(err) = framed.send(bytes::Bytes::from(reply.to_wire())).await {
+                                    hbb_common::log::trace!("ipc '{}' reply failed: {}", postfix, err);
+                                    break;
+                                }
+                            }
+                            Ok(None) => {}
+                            Err(err) => hbb_common::log::error!("ipc '{}' chain failed: {}", postfix, err),
+                        }
+                    }
+                });
+            }
+        })
+    }
 }
 
 #[async_trait::async_trait]
 impl IPCFacade for CodeUChainIPC {
+    /// Not supported, the listener setup lives in `ipc::new_listener`; pass its
+    /// listener to [`CodeUChainIPC::serve`] instead
     async fn start_server(&self, postfix: &str) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // For now, return success - actual server implementation would go here
-        println!("CodeUChain IPC server started for postfix: {}", postfix);
-        Ok(())
+        Err(format!("ipc{} server needs a listener from `ipc::new_listener`, see `CodeUChainIPC::serve`", postfix).into())
     }
 
     async fn connect_client(&self, ms_timeout: u64, postfix: &str) -> std::result::Result<IPCConnection, Box<dyn std::error::Error + Send + Sync>> {
-        // Create appropriate chain based on postfix
-        let chain = match postfix {
-            "" => IPCChainFactory::create_config_chain(),
-            "_msg" => IPCChainFactory::create_message_chain(),
-            "_sys" => IPCChainFactory::create_system_chain(),
-            _ => IPCChainFactory::create_config_chain(),
-        };
-
         let context = RustDeskChainContext::new(
             format!("ipc_client_{}", postfix),
             ConnType::DEFAULT_CONN,
             None
         );
 
-        Ok(IPCConnection { context, chain })
+        #[cfg(feature = "full")]
+        let transport = {
+            let path = Config::ipc_path(postfix);
+            let client = tokio::time::timeout(
+                std::time::Duration::from_millis(ms_timeout),
+                Endpoint::connect(&path),
+            ).await??;
+            IPCTransport::Socket(Framed::new(client, BytesCodec::new()))
+        };
+        #[cfg(not(feature = "full"))]
+        let transport = {
+            let _ = ms_timeout;
+            IPCTransport::InProcess(Default::default())
+        };
+
+        Ok(IPCConnection { context, transport })
     }
 
     async fn send_message(&self, connection: &mut IPCConnection, data: IPCData) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        // Convert IPCData to context data
-        let mut context_data = HashMap::new();
-        match &data {
-            IPCData::Config { name, value } => {
-                context_data.insert("ipc_action".to_string(), "config".to_string());
-                context_data.insert("config_name".to_string(), name.clone());
-                if let Some(val) = value {
-                    context_data.insert("config_value".to_string(), val.clone());
-                }
+        match &mut connection.transport {
+            #[cfg(feature = "full")]
+            IPCTransport::Socket(framed) => {
+                framed.send(bytes::Bytes::from(data.to_wire())).await?;
             }
-            IPCData::Options(opts) => {
-                context_data.insert("ipc_action".to_string(), "options".to_string());
-                if let Some(opts) = opts {
-                    context_data.insert("options".to_string(), serde_json::to_string(opts)?);
+            IPCTransport::InProcess(replies) => {
+                if let Some(reply) = self.handle(data).await? {
+                    replies.push_back(reply);
                 }
             }
-            IPCData::SystemInfo(_) => {
-                context_data.insert("ipc_action".to_string(), "system_info".to_string());
-            }
-            IPCData::Login { id, is_file_transfer, peer_id } => {
-                context_data.insert("ipc_action".to_string(), "login".to_string());
-                context_data.insert("login_id".to_string(), id.to_string());
-                context_data.insert("is_file_transfer".to_string(), is_file_transfer.to_string());
-                context_data.insert("peer_id".to_string(), peer_id.clone());
-            }
-            IPCData::ChatMessage { text } => {
-                context_data.insert("ipc_action".to_string(), "chat".to_string());
-                context_data.insert("chat_text".to_string(), text.clone());
-            }
-            IPCData::Close => {
-                context_data.insert("ipc_action".to_string(), "close".to_string());
-            }
-            IPCData::Test => {
-                context_data.insert("ipc_action".to_string(), "test".to_string());
-            }
         }
-
-        // Update context and run chain
-        // For IPC, we store the data in the underlying context
-        let mut new_data = HashMap::new();
-        for (k, v) in &context_data {
-            new_data.insert(k.clone(), serde_json::Value::String(v.clone()));
-        }
-        let updated_context = Context::new(new_data);
-        
-        connection.chain.run(updated_context).await?;
-
         Ok(())
     }
 
-    async fn receive_message(&self, _connection: &mut IPCConnection) -> std::result::Result<Option<IPCData>, Box<dyn std::error::Error + Send + Sync>> {
-        // For now, return None - actual implementation would check for responses
-        // In a real implementation, this would check the context for response data
-        Ok(None)
+    async fn receive_message(&self, connection: &mut IPCConnection) -> std::result::Result<Option<IPCData>, Box<dyn std::error::Error + Send + Sync>> {
+        match &mut connection.transport {
+            #[cfg(feature = "full")]
+            IPCTransport::Socket(framed) => match framed.next().await {
+                Some(frame) => Ok(IPCData::from_wire(&frame?)),
+                None => Err("reset by the peer".into()),
+            },
+            IPCTransport::InProcess(replies) => Ok(replies.pop_front()),
+        }
     }
 
     async fn get_config(&self, name: &str) -> std::result::Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
         let mut connection = self.connect_client(1000, "").await?;
         self.send_message(&mut connection, IPCData::Config { name: name.to_string(), value: None }).await?;
 
-        // In real implementation, would extract result from context
-        // For now, return mock data
-        match name {
-            "id" => Ok(Some("test_id".to_string())),
-            "permanent-password" => Ok(Some("test_password".to_string())),
+        let reply = tokio::time::timeout(
+            std::time::Duration::from_millis(1000),
+            self.receive_message(&mut connection),
+        ).await??;
+        match reply {
+            Some(IPCData::Config { name: name2, value }) if name2 == name => Ok(value),
             _ => Ok(None),
         }
     }
@@ -195,15 +427,24 @@ impl IPCFacade for CodeUChainIPC {
         let mut connection = self.connect_client(1000, "").await?;
         self.send_message(&mut connection, IPCData::Options(None)).await?;
 
-        // Mock options return
-        let mut options = HashMap::new();
-        options.insert("test_option".to_string(), "test_value".to_string());
-        Ok(options)
+        let reply = tokio::time::timeout(
+            std::time::Duration::from_millis(1000),
+            self.receive_message(&mut connection),
+        ).await??;
+        match reply {
+            Some(IPCData::Options(Some(options))) => Ok(options),
+            _ => Ok(HashMap::new()),
+        }
     }
 
     async fn set_options(&self, options: HashMap<String, String>) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
         let mut connection = self.connect_client(1000, "").await?;
         self.send_message(&mut connection, IPCData::Options(Some(options))).await?;
+        // Wait for the server to have written them, like `ipc::set_options`
+        tokio::time::timeout(
+            std::time::Duration::from_millis(1000),
+            self.receive_message(&mut connection),
+        ).await.ok();
         Ok(())
     }
 }
@@ -239,4 +480,124 @@ pub async fn get_options() -> HashMap<String, String> {
 pub async fn set_options(value: HashMap<String, String>) -> ResultType<()> {
     IPC_FACADE.set_options(value).await?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wire_format_matches_legacy_data() {
+        // Frames as produced by serde_json::to_vec(&ipc::Data::...)
+        let cases = vec![
+            (IPCData::Config { name: "id".to_string(), value: None }, r#"{"t":"Config","c":["id",null]}"#),
+            (IPCData::Config { name: "id".to_string(), value: Some("123".to_string()) }, r#"{"t":"Config","c":["id","123"]}"#),
+            (IPCData::SystemInfo(None), r#"{"t":"SystemInfo","c":null}"#),
+            (IPCData::ChatMessage { text: "hi".to_string() }, r#"{"t":"ChatMessage","c":{"text":"hi"}}"#),
+            (IPCData::Close, r#"{"t":"Close"}"#),
+            (IPCData::Test, r#"{"t":"Test"}"#),
+        ];
+        for (data, expected) in cases {
+            assert_eq!(String::from_utf8(data.to_wire()).unwrap(), expected);
+            assert_eq!(IPCData::from_wire(expected.as_bytes()), Some(data));
+        }
+    }
+
+    #[test]
+    fn test_decodes_full_legacy_login_and_skips_unknown_variants() {
+        let login = r#"{"t":"Login","c":{"id":7,"is_file_transfer":true,"is_view_camera":false,"is_terminal":false,
+            "peer_id":"123456789","name":"alice","authorized":true,"port_forward":"","keyboard":true,"clipboard":true,
+            "audio":true,"file":true,"file_transfer_enabled":true,"restart":true,"recording":false,"block_input":true,
+            "from_switch":false}}"#;
+        assert_eq!(
+            IPCData::from_wire(login.as_bytes()),
+            Some(IPCData::Login { id: 7, is_file_transfer: true, peer_id: "123456789".to_string() })
+        );
+
+        assert_eq!(IPCData::from_wire(br#"{"t":"ClickTime","c":0}"#), None);
+        assert_eq!(IPCData::from_wire(b"not json"), None);
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_routed_through_chains() {
+        let ipc = CodeUChainIPC::new();
+
+        let set = IPCData::Config { name: "salt".to_string(), value: Some("routed-salt".to_string()) };
+        assert_eq!(ipc.handle(set).await.unwrap(), None);
+        let reply = ipc.handle(IPCData::Config { name: "salt".to_string(), value: None }).await.unwrap();
+        assert_eq!(reply, Some(IPCData::Config { name: "salt".to_string(), value: Some("routed-salt".to_string()) }));
+
+        let reply = ipc.handle(IPCData::SystemInfo(None)).await.unwrap();
+        assert!(matches!(reply, Some(IPCData::SystemInfo(Some(info))) if info.contains("username")));
+
+        let options = HashMap::from([("enable-hwcodec".to_string(), "N".to_string())]);
+        assert_eq!(ipc.handle(IPCData::Options(Some(options.clone()))).await.unwrap(), Some(IPCData::Options(None)));
+        let reply = ipc.handle(IPCData::Options(None)).await.unwrap();
+        assert!(matches!(reply, Some(IPCData::Options(Some(read))) if read.get("enable-hwcodec") == options.get("enable-hwcodec")));
+
+        let reply = ipc.handle(IPCData::Config { name: MIGRATION_STATUS_QUERY.to_string(), value: None }).await.unwrap();
+        assert!(matches!(reply, Some(IPCData::Config { value: Some(report), .. }) if report.contains("\"subsystem\":\"ipc\"")));
+
+        assert_eq!(ipc.handle(IPCData::ChatMessage { text: "hello".to_string() }).await.unwrap(), None);
+        assert!(ipc.handle(IPCData::Login { id: 1, is_file_transfer: false, peer_id: " ".to_string() }).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_options_hook_guards_the_update() {
+        struct Guard(Arc<std::sync::Mutex<Vec<String>>>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                let written = config_store::get_options().get("codeuchain-hook").cloned().unwrap_or_default();
+                self.0.lock().unwrap().push(format!("dropped after {}", written));
+            }
+        }
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let hook_events = events.clone();
+        let ipc = CodeUChainIPC::new().with_options_hook(Arc::new(move |options: &HashMap<String, String>| {
+            hook_events.lock().unwrap().push(format!("before {}", options["codeuchain-hook"]));
+            Box::new(Guard(hook_events.clone())) as Box<dyn Send>
+        }));
+
+        let options = HashMap::from([("codeuchain-hook".to_string(), "1".to_string())]);
+        assert_eq!(ipc.handle(IPCData::Options(Some(options))).await.unwrap(), Some(IPCData::Options(None)));
+        ipc.handle(IPCData::Options(None)).await.unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["before 1".to_string(), "dropped after 1".to_string()]);
+    }
+
+    #[cfg(not(feature = "full"))]
+    #[tokio::test]
+    async fn test_in_process_get_config() {
+        let ipc = CodeUChainIPC::new();
+        ipc.set_config("unlock-pin", "1234".to_string()).await.unwrap();
+        assert_eq!(ipc.get_config("unlock-pin").await.unwrap(), Some("1234".to_string()));
+        ipc.set_config("unknown", "x".to_string()).await.unwrap();
+        assert_eq!(ipc.get_config("unknown").await.unwrap(), None);
+        assert!(ipc.start_server("").await.is_err());
+    }
+
+    #[cfg(feature = "full")]
+    #[tokio::test]
+    async fn test_serves_legacy_clients_over_ipc_socket() {
+        let postfix = format!("_codeuchain_test_{}", std::process::id());
+        let ipc = CodeUChainIPC::new();
+        let incoming = Endpoint::new(Config::ipc_path(&postfix)).incoming().unwrap();
+        let server = ipc.serve(incoming, &postfix);
+
+        // Speak the legacy framing directly, as `ipc::connect` would
+        let client = Endpoint::connect(&Config::ipc_path(&postfix)).await.unwrap();
+        let mut framed = Framed::new(client, BytesCodec::new());
+        framed.send(bytes::Bytes::from_static(br#"{"t":"ClickTime","c":0}"#)).await.unwrap();
+        framed.send(bytes::Bytes::from_static(br#"{"t":"Config","c":["id",null]}"#)).await.unwrap();
+        let reply = framed.next().await.unwrap().unwrap();
+        let expected = IPCData::Config { name: "id".to_string(), value: Some(Config::get_id()) };
+        assert_eq!(IPCData::from_wire(&reply), Some(expected));
+
+        let mut connection = ipc.connect_client(1000, &postfix).await.unwrap();
+        ipc.send_message(&mut connection, IPCData::SystemInfo(None)).await.unwrap();
+        assert!(matches!(ipc.receive_message(&mut connection).await.unwrap(), Some(IPCData::SystemInfo(Some(_)))));
+
+        server.abort();
+        std::fs::remove_file(Config::ipc_path(&postfix)).ok();
+    }
+}
@@ -3,11 +3,12 @@ use crate::contexts::*;
 use crate::core::{Context, Link};
 use async_trait::async_trait;
 use std::result::Result as StdResult;
+use std::collections::HashMap;
 use std::sync::Arc;
 use serde_json;
 
 /// Type aliases for compatibility
-pub type ResultType<T> = crate::types::Result<T>;
+pub type ResultType<T> = crate::core::Result<T>;
 pub type LinkResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 /// IPC Config Validator Link - Validates configuration requests
@@ -45,10 +46,10 @@ impl Link for ConfigValidatorLink {
                 if config_name.trim().is_empty() {
                     return Err(Box::new(CodeUChainError::ValidationError("Empty config name".to_string())));
                 }
-                println!("✅ Config validation passed for: {}", config_name);
+                log::debug!("Config validation passed for: {}", config_name);
             }
             "options" => {
-                println!("✅ Options validation passed");
+                log::debug!("Options validation passed");
             }
             _ => {
                 return Err(Box::new(CodeUChainError::ValidationError(format!("Unknown IPC action: {}", action))));
@@ -59,7 +60,7 @@ impl Link for ConfigValidatorLink {
     }
 }
 
-/// IPC Config Processor Link - Processes configuration requests
+/// IPC Config Processor Link - Serves config and option requests from the config store
 pub struct ConfigProcessorLink;
 
 impl ConfigProcessorLink {
@@ -89,52 +90,21 @@ impl Link for ConfigProcessorLink {
                     .and_then(|v| v.as_str())
                     .unwrap_or("");
 
-                // Process config get/set
                 if let Some(config_value) = ipc_data.get("config_value") {
-                    // Set operation
-                    let value_str = config_value.as_str().unwrap_or("");
-                    println!("📝 Setting config {} = {}", config_name, value_str);
-
-                    // In real implementation, would update actual config storage
-                    // For now, just log the operation
-                } else {
-                    // Get operation
-                    println!("📖 Getting config: {}", config_name);
-
-                    // In real implementation, would retrieve from config storage
-                    // For now, return mock response
-                    let mock_response = match config_name {
-                        "id" => Some("rustdesk_test_id".to_string()),
-                        "permanent-password" => Some("mock_password".to_string()),
-                        _ => None,
-                    };
-
-                    if let Some(response) = mock_response {
-                        let mut new_data = data.clone();
-                        new_data.insert("config_response".to_string(), serde_json::Value::String(response));
-                        return Ok(Context::new(new_data));
-                    }
+                    let value = config_value.as_str().unwrap_or("");
+                    config_store::set(config_name, value);
+                } else if let Some(value) = config_store::get(config_name) {
+                    return Ok(ctx.insert("config_response".to_string(), serde_json::Value::String(value)));
                 }
             }
             "options" => {
                 if let Some(options_str) = ipc_data.get("options").and_then(|v| v.as_str()) {
-                    // Set options
-                    if let Ok(options) = serde_json::from_str::<std::collections::HashMap<String, String>>(options_str) {
-                        println!("📝 Setting options: {:?}", options);
-                        // In real implementation, would update options storage
-                    }
+                    let options = serde_json::from_str::<HashMap<String, String>>(options_str)
+                        .map_err(|_| CodeUChainError::ValidationError("Invalid options format".to_string()))?;
+                    config_store::set_options(options);
                 } else {
-                    // Get options
-                    println!("📖 Getting options");
-                    let mock_options = std::collections::HashMap::from([
-                        ("test_option".to_string(), "test_value".to_string()),
-                        ("enable_hwcodec".to_string(), "true".to_string()),
-                    ]);
-
-                    let mut new_data = data.clone();
-                    new_data.insert("options_response".to_string(),
-                        serde_json::Value::String(serde_json::to_string(&mock_options).unwrap_or_default()));
-                    return Ok(Context::new(new_data));
+                    let options = serde_json::to_string(&config_store::get_options()).unwrap_or_default();
+                    return Ok(ctx.insert("options_response".to_string(), serde_json::Value::String(options)));
                 }
             }
             _ => {}
@@ -144,6 +114,106 @@ impl Link for ConfigProcessorLink {
     }
 }
 
+/// The config served over IPC, as the legacy `ipc::handle` serves it
+///
+/// Names the legacy server computes outside `Config`, like `temporary-password`,
+/// are not served here and read as unset.
+#[cfg(feature = "full")]
+pub mod config_store {
+    use hbb_common::config::Config;
+    use std::collections::HashMap;
+
+    pub fn get(name: &str) -> Option<String> {
+        Some(match name {
+            "id" => Config::get_id(),
+            "permanent-password" => Config::get_permanent_password(),
+            "salt" => Config::get_salt(),
+            "rendezvous_server" => format!(
+                "{},{}",
+                Config::get_rendezvous_server(),
+                Config::get_rendezvous_servers().join(",")
+            ),
+            "rendezvous_servers" => Config::get_rendezvous_servers().join(","),
+            "unlock-pin" => Config::get_unlock_pin(),
+            "trusted-devices" => Config::get_trusted_devices_json(),
+            _ => return None,
+        })
+    }
+
+    /// Whether `name` is served here, for reading or, with `set`, writing
+    pub fn serves(name: &str, set: bool) -> bool {
+        const SET: [&str; 4] = ["id", "permanent-password", "salt", "unlock-pin"];
+        if set {
+            SET.contains(&name)
+        } else {
+            SET.contains(&name) || ["rendezvous_server", "rendezvous_servers", "trusted-devices"].contains(&name)
+        }
+    }
+
+    /// Returns false for names that can't be set
+    pub fn set(name: &str, value: &str) -> bool {
+        match name {
+            "id" => {
+                Config::set_key_confirmed(false);
+                Config::set_id(value);
+            }
+            "permanent-password" => Config::set_permanent_password(value),
+            "salt" => Config::set_salt(value),
+            "unlock-pin" => Config::set_unlock_pin(value),
+            _ => return false,
+        }
+        hbb_common::log::info!("{} updated", name);
+        true
+    }
+
+    pub fn get_options() -> HashMap<String, String> {
+        Config::get_options()
+    }
+
+    pub fn set_options(options: HashMap<String, String>) {
+        Config::set_options(options);
+    }
+}
+
+/// In-memory stand-in for `Config` when built without `hbb_common`
+#[cfg(not(feature = "full"))]
+pub mod config_store {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const NAMES: [&str; 4] = ["id", "permanent-password", "salt", "unlock-pin"];
+
+    lazy_static::lazy_static! {
+        static ref CONFIG: Mutex<HashMap<String, String>> = Default::default();
+        static ref OPTIONS: Mutex<HashMap<String, String>> = Default::default();
+    }
+
+    pub fn get(name: &str) -> Option<String> {
+        if !NAMES.contains(&name) {
+            return None;
+        }
+        Some(CONFIG.lock().unwrap().get(name).cloned().unwrap_or_default())
+    }
+
+    /// Returns false for names that can't be set
+    pub fn set(name: &str, value: &str) -> bool {
+        if !NAMES.contains(&name) {
+            return false;
+        }
+        CONFIG.lock().unwrap().insert(name.to_string(), value.to_string());
+        true
+    }
+
+    pub fn get_options() -> HashMap<String, String> {
+        OPTIONS.lock().unwrap().clone()
+    }
+
+    /// Replaces all the options, like `Config::set_options`
+    pub fn set_options(options: HashMap<String, String>) {
+        *OPTIONS.lock().unwrap() = options;
+    }
+}
+
 /// IPC Message Validator Link - Validates message requests
 pub struct MessageValidatorLink;
 
@@ -179,7 +249,7 @@ impl Link for MessageValidatorLink {
                     return Err(Box::new(CodeUChainError::ValidationError("Empty peer_id".to_string())));
                 }
 
-                println!("✅ Login validation passed for peer: {}", peer_id);
+                log::debug!("Login validation passed for peer: {}", peer_id);
             }
             "chat" => {
                 // Validate chat message
@@ -191,13 +261,13 @@ impl Link for MessageValidatorLink {
                     return Err(Box::new(CodeUChainError::ValidationError("Chat message too long".to_string())));
                 }
 
-                println!("✅ Chat message validation passed");
+                log::debug!("Chat message validation passed");
             }
             "close" => {
-                println!("✅ Close message validation passed");
+                log::debug!("Close message validation passed");
             }
             "test" => {
-                println!("✅ Test message validation passed");
+                log::debug!("Test message validation passed");
             }
             _ => {
                 return Err(Box::new(CodeUChainError::ValidationError(format!("Unknown message action: {}", action))));
@@ -245,7 +315,7 @@ impl Link for MessageProcessorLink {
                     .map(|s| s == "true")
                     .unwrap_or(false);
 
-                println!("🔐 Processing login - ID: {}, Peer: {}, FileTransfer: {}",
+                log::debug!("Processing login - ID: {}, Peer: {}, FileTransfer: {}",
                     login_id, peer_id, is_file_transfer);
 
                 // In real implementation, would handle authentication logic
@@ -255,17 +325,17 @@ impl Link for MessageProcessorLink {
                     .and_then(|v| v.as_str())
                     .unwrap_or("");
 
-                println!("💬 Processing chat message: {}", chat_text);
+                log::debug!("Processing chat message: {}", chat_text);
 
                 // In real implementation, would handle chat message routing
             }
             "close" => {
-                println!("🔌 Processing close message");
+                log::debug!("Processing close message");
 
                 // In real implementation, would handle connection cleanup
             }
             "test" => {
-                println!("🧪 Processing test message");
+                log::debug!("Processing test message");
 
                 // Test message - could trigger diagnostics
             }
@@ -277,6 +347,8 @@ impl Link for MessageProcessorLink {
 }
 
 /// IPC System Info Link - Provides system information
+///
+/// The values are placeholders, rustdesk serves `Data::SystemInfo` itself.
 pub struct SystemInfoLink;
 
 impl SystemInfoLink {
@@ -307,7 +379,7 @@ impl Link for SystemInfoLink {
                 whoami::username()
             );
 
-            println!("ℹ️  System info: {}", info);
+            log::debug!("System info: {}", info);
 
             let mut new_data = data.clone();
             new_data.insert("system_info_response".to_string(), serde_json::Value::String(info));
@@ -3,14 +3,25 @@
 use crate::types::*;
 use crate::contexts::*;
 use crate::core::{Context, Link};
+use crate::streaming::{CancelSignal, ContextReceiver, ContextSender, StreamLink};
 use async_trait::async_trait;
+use serde::{Serialize, Serializer, ser::SerializeStruct};
 use std::result::Result as StdResult;
 use std::net::SocketAddr;
 use std::sync::Arc;
+#[cfg(not(feature = "full"))]
 use tokio::net::TcpStream;
+#[cfg(not(feature = "full"))]
 use tokio::time::{timeout, Duration};
+#[cfg(not(feature = "full"))]
 use tokio::io::AsyncWriteExt;
-// Mock implementations for hbb_common types since the actual crate is not available
+
+/// Real rendezvous / relay connection flow, available with the `full` feature
+#[cfg(feature = "full")]
+pub mod rendezvous;
+
+// Mock implementations for hbb_common types when the crate is not linked in
+#[cfg(not(feature = "full"))]
 mod mock_hbb_common {
     pub fn is_ip_str(_s: &str) -> bool {
         // Mock: assume valid IP
@@ -59,24 +70,74 @@ mod mock_hbb_common {
     }
 }
 
+#[cfg(not(feature = "full"))]
 use mock_hbb_common::*;
+#[cfg(feature = "full")]
+use hbb_common::{is_domain_port_str, is_ip_str};
 
-// Mock constants
+#[cfg(not(feature = "full"))]
 const CONNECT_TIMEOUT: u64 = 30000; // 30 seconds
+#[cfg(feature = "full")]
+const CONNECT_TIMEOUT: u64 = hbb_common::config::CONNECT_TIMEOUT;
 const READ_TIMEOUT: u64 = 30000; // 30 seconds
 
 /// Result type for link operations
 pub type LinkResult<T> = StdResult<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// Transport to the peer: the framed hbb_common stream when linked, plain TCP otherwise
+#[cfg(feature = "full")]
+pub type PeerStream = hbb_common::Stream;
+#[cfg(not(feature = "full"))]
+pub type PeerStream = TcpStream;
+
 /// Connection establishment result
 pub struct ConnectionResult {
-    pub stream: TcpStream,
-    pub peer_addr: SocketAddr,
+    pub stream: PeerStream,
+    pub peer_addr: Option<SocketAddr>,
+    pub is_direct: bool,
+    pub connection_type: String,
+    pub signed_id_pk: Option<Vec<u8>>,
+}
+
+/// Live connection of an established session, stored in the context by `ConnectionLink`
+///
+/// Only the connection metadata is serialized; the stream itself stays in memory.
+#[derive(Clone)]
+pub struct PeerConnection {
+    pub stream: Arc<tokio::sync::Mutex<PeerStream>>,
+    pub peer_addr: Option<SocketAddr>,
     pub is_direct: bool,
     pub connection_type: String,
     pub signed_id_pk: Option<Vec<u8>>,
 }
 
+impl From<ConnectionResult> for PeerConnection {
+    fn from(result: ConnectionResult) -> Self {
+        Self {
+            stream: Arc::new(tokio::sync::Mutex::new(result.stream)),
+            peer_addr: result.peer_addr,
+            is_direct: result.is_direct,
+            connection_type: result.connection_type,
+            signed_id_pk: result.signed_id_pk,
+        }
+    }
+}
+
+impl Serialize for PeerConnection {
+    fn serialize<S: Serializer>(&self, serializer: S) -> StdResult<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("PeerConnection", 3)?;
+        state.serialize_field("peer_addr", &self.peer_addr.map(|addr| addr.to_string()))?;
+        state.serialize_field("is_direct", &self.is_direct)?;
+        state.serialize_field("connection_type", &self.connection_type)?;
+        state.end()
+    }
+}
+
+crate::context_key!(
+    /// Typed slot holding the live peer connection
+    pub PeerConnectionKey: PeerConnection = "peer_connection"
+);
+
 /// Connection Link - Handles establishing connections
 pub struct ConnectionLink {
     pub timeout_ms: u64,
@@ -84,16 +145,27 @@ pub struct ConnectionLink {
     pub enable_udp_punch: bool,
     pub enable_ipv6: bool,
     pub force_relay: bool,
+    pub rendezvous_servers: Vec<String>,
+    pub licence_key: String,
+    pub token: String,
+    /// RustDesk version announced to the rendezvous server, not this crate's
+    pub version: String,
 }
 
 impl ConnectionLink {
-    pub fn new() -> Self {
+    /// `version` is the RustDesk version of the embedding application, e.g.
+    /// `crate::VERSION` of rustdesk
+    pub fn new(version: &str) -> Self {
         Self {
             timeout_ms: CONNECT_TIMEOUT,
             max_retries: 3,
             enable_udp_punch: true,
             enable_ipv6: true,
             force_relay: false,
+            rendezvous_servers: default_rendezvous_servers(),
+            licence_key: String::new(),
+            token: String::new(),
+            version: version.to_string(),
         }
     }
 
@@ -116,18 +188,45 @@ impl ConnectionLink {
         self.force_relay = force;
         self
     }
+
+    pub fn with_rendezvous_servers(mut self, servers: Vec<String>) -> Self {
+        self.rendezvous_servers = servers;
+        self
+    }
+
+    pub fn with_licence_key(mut self, key: &str) -> Self {
+        self.licence_key = key.to_string();
+        self
+    }
+
+    pub fn with_token(mut self, token: &str) -> Self {
+        self.token = token.to_string();
+        self
+    }
+}
+
+#[cfg(feature = "full")]
+fn default_rendezvous_servers() -> Vec<String> {
+    rendezvous::default_rendezvous_servers()
+}
+
+#[cfg(not(feature = "full"))]
+fn default_rendezvous_servers() -> Vec<String> {
+    vec![
+        "rs-ny.rustdesk.com:21116".to_string(),
+        "rs-sg.rustdesk.com:21116".to_string(),
+    ]
 }
 
 #[async_trait]
 impl Link for ConnectionLink {
     async fn call(&self, ctx: Context) -> LinkResult<Context> {
-        let data = ctx.data().clone();
-        let rustdesk_ctx_json = data.get("rustdesk_context")
-            .ok_or("Missing rustdesk_context")?;
-        let rustdesk_ctx: RustDeskContext = serde_json::from_value(rustdesk_ctx_json.clone())?;
+        let rustdesk_ctx = ctx.rustdesk_state()
+            .ok_or("Missing rustdesk_context")?
+            .into_owned();
 
         let result_ctx = match rustdesk_ctx {
-            RustDeskContext::Initial(connection_info) => {
+            RustDeskContext::Initial(mut connection_info) => {
                 match self.establish_connection(&connection_info).await {
                     Ok(result) => {
                         // Create session context
@@ -139,6 +238,10 @@ impl Link for ConnectionLink {
                             supported_encodings: vec!["vp8".to_string(), "vp9".to_string()],
                         };
 
+                        connection_info.peer_addr = result.peer_addr.map(|addr| addr.to_string());
+                        if let Ok(local_addr) = local_addr_of(&result.stream) {
+                            connection_info.local_addr = Some(local_addr.to_string());
+                        }
                         let session = SessionContext {
                             connection_info,
                             session_id: rand::random::<u64>(),
@@ -147,18 +250,15 @@ impl Link for ConnectionLink {
                         };
 
                         let new_data = RustDeskContext::Connected(session);
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(new_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(new_data)
+                            .insert_typed::<PeerConnectionKey>(result.into())
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: None,
                             error: format!("Connection failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -169,9 +269,70 @@ impl Link for ConnectionLink {
     }
 }
 
+#[cfg(feature = "full")]
+fn local_addr_of(stream: &PeerStream) -> std::io::Result<SocketAddr> {
+    Ok(stream.local_addr())
+}
+
+#[cfg(not(feature = "full"))]
+fn local_addr_of(stream: &PeerStream) -> std::io::Result<SocketAddr> {
+    stream.local_addr()
+}
+
+#[cfg(feature = "full")]
 impl ConnectionLink {
     async fn establish_connection(&self, info: &ConnectionInfo) -> LinkResult<ConnectionResult> {
-        // For testing purposes, if the peer_id looks like a test peer, return a mock connection
+        let established = if is_ip_str(&info.peer_id) {
+            rendezvous::connect_direct_ip(&info.peer_id, self.timeout_ms).await?
+        } else if is_domain_port_str(&info.peer_id) {
+            rendezvous::connect_direct(&info.peer_id, self.timeout_ms).await?
+        } else {
+            self.connect_via_rendezvous(info).await?
+        };
+
+        Ok(ConnectionResult {
+            stream: established.stream,
+            peer_addr: established.peer_addr,
+            is_direct: established.is_direct,
+            connection_type: established.connection_type.to_string(),
+            signed_id_pk: established.signed_id_pk,
+        })
+    }
+
+    async fn connect_via_rendezvous(&self, info: &ConnectionInfo) -> LinkResult<rendezvous::Established> {
+        let request = rendezvous::ConnectRequest {
+            peer_id: &info.peer_id,
+            conn_type: &info.conn_type,
+            licence_key: &self.licence_key,
+            token: &self.token,
+            version: &self.version,
+            force_relay: self.force_relay,
+            timeout_ms: self.timeout_ms,
+        };
+
+        let mut last_error = None;
+        for server in &self.rendezvous_servers {
+            match rendezvous::connect_via_rendezvous(server, &request).await {
+                Ok(established) => return Ok(established),
+                Err(e) => {
+                    hbb_common::log::info!("Failed to connect via {}: {}", server, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(match last_error {
+            Some(e) => e.to_string().into(),
+            None => "No rendezvous server configured".into(),
+        })
+    }
+}
+
+#[cfg(not(feature = "full"))]
+impl ConnectionLink {
+    async fn establish_connection(&self, info: &ConnectionInfo) -> LinkResult<ConnectionResult> {
+        // For testing purposes, if the peer_id looks like a test peer, return a mock connection.
+        // Not with `full`, tests connecting test peers are built without it
         if info.peer_id.starts_with("test-peer-") || info.peer_id == "127.0.0.1" {
             return self.mock_connection(info).await;
         }
@@ -209,7 +370,7 @@ impl ConnectionLink {
 
         Ok(ConnectionResult {
             stream,
-            peer_addr: local_addr,
+            peer_addr: Some(local_addr),
             is_direct: true,
             connection_type: "Mock Direct".to_string(),
             signed_id_pk: Some(vec![1, 2, 3, 4]),
@@ -230,7 +391,7 @@ impl ConnectionLink {
 
         Ok(ConnectionResult {
             stream,
-            peer_addr,
+            peer_addr: Some(peer_addr),
             is_direct: true,
             connection_type: "Direct IP".to_string(),
             signed_id_pk: None,
@@ -248,7 +409,7 @@ impl ConnectionLink {
 
         Ok(ConnectionResult {
             stream,
-            peer_addr,
+            peer_addr: Some(peer_addr),
             is_direct: true,
             connection_type: "Direct Domain".to_string(),
             signed_id_pk: None,
@@ -256,15 +417,9 @@ impl ConnectionLink {
     }
 
     async fn connect_via_rendezvous(&self, info: &ConnectionInfo) -> LinkResult<ConnectionResult> {
-        // Get rendezvous servers
-        let rendezvous_servers = vec![
-            "rs-ny.rustdesk.com:21116".to_string(),
-            "rs-sg.rustdesk.com:21116".to_string(),
-        ];
-
         // Try to connect via rendezvous server for NAT traversal
-        for server in rendezvous_servers {
-            match self.attempt_rendezvous_connection(&server, info).await {
+        for server in &self.rendezvous_servers {
+            match self.attempt_rendezvous_connection(server, info).await {
                 Ok(result) => return Ok(result),
                 Err(e) => {
                     println!("Failed to connect via {}: {}", server, e);
@@ -317,7 +472,7 @@ impl ConnectionLink {
 
         Ok(ConnectionResult {
             stream,
-            peer_addr,
+            peer_addr: Some(peer_addr),
             is_direct: false,
             connection_type: "Rendezvous".to_string(),
             signed_id_pk: Some(vec![1, 2, 3, 4]), // Mock
@@ -363,10 +518,9 @@ impl VideoLink {
 #[async_trait]
 impl Link for VideoLink {
     async fn call(&self, ctx: Context) -> LinkResult<Context> {
-        let data = ctx.data().clone();
-        let rustdesk_ctx_json = data.get("rustdesk_context")
-            .ok_or("Missing rustdesk_context")?;
-        let rustdesk_ctx: RustDeskContext = serde_json::from_value(rustdesk_ctx_json.clone())?;
+        let rustdesk_ctx = ctx.rustdesk_state()
+            .ok_or("Missing rustdesk_context")?
+            .into_owned();
 
         let result_ctx = match rustdesk_ctx {
             RustDeskContext::Connected(session) => {
@@ -388,18 +542,14 @@ impl Link for VideoLink {
                             pending_input: vec![],
                         };
 
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Video capture failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -414,18 +564,14 @@ impl Link for VideoLink {
                             clipboard,
                             pending_input,
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Video capture failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -504,10 +650,9 @@ impl AudioLink {
 #[async_trait]
 impl Link for AudioLink {
     async fn call(&self, ctx: Context) -> LinkResult<Context> {
-        let data = ctx.data().clone();
-        let rustdesk_ctx_json = data.get("rustdesk_context")
-            .ok_or("Missing rustdesk_context")?;
-        let rustdesk_ctx: RustDeskContext = serde_json::from_value(rustdesk_ctx_json.clone())?;
+        let rustdesk_ctx = ctx.rustdesk_state()
+            .ok_or("Missing rustdesk_context")?
+            .into_owned();
 
         let result_ctx = match rustdesk_ctx {
             RustDeskContext::Connected(session) => {
@@ -529,18 +674,14 @@ impl Link for AudioLink {
                             pending_input: vec![],
                         };
 
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Audio processing failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -555,18 +696,14 @@ impl Link for AudioLink {
                             clipboard,
                             pending_input,
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Audio processing failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -599,6 +736,61 @@ impl AudioLink {
     }
 }
 
+#[async_trait]
+impl StreamLink for VideoLink {
+    /// Capture a frame every `capture_interval_ms` for the latest session context
+    async fn run(&self, input: ContextReceiver, output: ContextSender, cancel: CancelSignal) -> LinkResult<()> {
+        run_capture_loop(self, self.capture_interval_ms, input, output, cancel).await
+    }
+}
+
+#[async_trait]
+impl StreamLink for AudioLink {
+    /// Produce one buffer of samples every `buffer_size / sample_rate` seconds
+    async fn run(&self, input: ContextReceiver, output: ContextSender, cancel: CancelSignal) -> LinkResult<()> {
+        let interval_ms = (self.buffer_size as u64 * 1000) / self.sample_rate.max(1) as u64;
+        run_capture_loop(self, interval_ms, input, output, cancel).await
+    }
+}
+
+/// Drive a capture link on a fixed interval for the lifetime of a session
+///
+/// Each context received on `input` replaces the session state the next capture
+/// starts from; captured contexts carry over to the following tick so other
+/// streams in the same `Streaming` state are preserved. Ticks missed while the
+/// output is full are skipped rather than queued, so a slow consumer lowers the
+/// capture rate instead of growing latency. The loop ends once the session
+/// reaches the `Error` state.
+async fn run_capture_loop<L: Link>(
+    link: &L,
+    interval_ms: u64,
+    mut input: ContextReceiver,
+    output: ContextSender,
+    mut cancel: CancelSignal,
+) -> LinkResult<()> {
+    let mut ticker = tokio::time::interval(tokio::time::Duration::from_millis(interval_ms.max(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let mut current: Option<Context> = None;
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Ok(()),
+            ctx = input.recv() => match ctx {
+                Some(ctx) => current = Some(ctx),
+                None => return Ok(()),
+            },
+            _ = ticker.tick(), if current.is_some() => {
+                let captured = link.call(current.take().expect("checked by guard")).await?;
+                let failed = matches!(captured.rustdesk_state().as_deref(), Some(RustDeskContext::Error { .. }));
+                current = Some(captured.clone());
+                if output.send(captured).await.is_err() || failed {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
 /// Clipboard Link - Handles clipboard synchronization
 pub struct ClipboardLink {
     pub max_clipboard_size: usize,
@@ -638,10 +830,9 @@ impl ClipboardLink {
 #[async_trait]
 impl Link for ClipboardLink {
     async fn call(&self, ctx: Context) -> LinkResult<Context> {
-        let data = ctx.data().clone();
-        let rustdesk_ctx_json = data.get("rustdesk_context")
-            .ok_or("Missing rustdesk_context")?;
-        let rustdesk_ctx: RustDeskContext = serde_json::from_value(rustdesk_ctx_json.clone())?;
+        let rustdesk_ctx = ctx.rustdesk_state()
+            .ok_or("Missing rustdesk_context")?
+            .into_owned();
 
         let result_ctx = match rustdesk_ctx {
             RustDeskContext::Connected(session) => {
@@ -664,9 +855,7 @@ impl Link for ClipboardLink {
                                 pending_input: vec![],
                             };
 
-                            let mut new_data_map = data.clone();
-                            new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                            Context::new(new_data_map)
+                            ctx.with_rustdesk_context(streaming_data)
                         } else {
                             // No clipboard update, stay in connected state
                             ctx
@@ -677,9 +866,7 @@ impl Link for ClipboardLink {
                             session: Some(session),
                             error: format!("Clipboard check failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -694,18 +881,14 @@ impl Link for ClipboardLink {
                             clipboard: new_clipboard_data.or(clipboard),
                             pending_input,
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Clipboard check failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -780,10 +963,9 @@ impl InputLink {
 #[async_trait]
 impl Link for InputLink {
     async fn call(&self, ctx: Context) -> LinkResult<Context> {
-        let data = ctx.data().clone();
-        let rustdesk_ctx_json = data.get("rustdesk_context")
-            .ok_or("Missing rustdesk_context")?;
-        let rustdesk_ctx: RustDeskContext = serde_json::from_value(rustdesk_ctx_json.clone())?;
+        let rustdesk_ctx = ctx.rustdesk_state()
+            .ok_or("Missing rustdesk_context")?
+            .into_owned();
 
         let result_ctx = match rustdesk_ctx {
             RustDeskContext::Streaming { session, video_frame, audio_frame, clipboard, mut pending_input } => {
@@ -799,18 +981,14 @@ impl Link for InputLink {
                             clipboard,
                             pending_input,
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(streaming_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(streaming_data)
                     }
                     Err(e) => {
                         let error_data = RustDeskContext::Error {
                             session: Some(session),
                             error: format!("Input check failed: {}", e),
                         };
-                        let mut new_data_map = data.clone();
-                        new_data_map.insert("rustdesk_context".to_string(), serde_json::to_value(error_data)?);
-                        Context::new(new_data_map)
+                        ctx.with_rustdesk_context(error_data)
                     }
                 }
             }
@@ -879,7 +1057,7 @@ mod tests {
         let ctx = Context::new(initial_data.clone());
         println!("Initial context data: {:?}", ctx.data());
 
-        let link = ConnectionLink::new();
+        let link = ConnectionLink::new("1.4.2");
         let result = link.call(ctx).await;
 
         match result {
@@ -0,0 +1,407 @@
+// Real connection establishment for ConnectionLink, built on hbb_common
+//
+// Mirrors the connect flow of `client::Client` (direct IP, domain:port,
+// rendezvous punch hole, relay fallback and forced relay) without depending on
+// the session `Interface`. The secure handshake is left to the session layer.
+
+use crate::types::ConnType;
+use hbb_common::{
+    bail,
+    config::{RELAY_PORT, RENDEZVOUS_PORT, RENDEZVOUS_SERVERS},
+    log,
+    protobuf::Message as _,
+    rendezvous_proto::{self, *},
+    socket_client::{check_port, connect_tcp, connect_tcp_local, ipv4_to_ipv6},
+    AddrMangle, ResultType, Stream,
+};
+use std::net::SocketAddr;
+
+/// Parameters for one connection attempt
+pub struct ConnectRequest<'a> {
+    pub peer_id: &'a str,
+    pub conn_type: &'a ConnType,
+    pub licence_key: &'a str,
+    pub token: &'a str,
+    /// RustDesk version of the caller
+    pub version: &'a str,
+    pub force_relay: bool,
+    pub timeout_ms: u64,
+}
+
+/// Transport produced by a successful attempt
+pub struct Established {
+    pub stream: Stream,
+    /// Address of the peer, or of the relay server; `None` if it could not be resolved
+    pub peer_addr: Option<SocketAddr>,
+    pub is_direct: bool,
+    pub connection_type: &'static str,
+    pub signed_id_pk: Option<Vec<u8>>,
+}
+
+/// Default rendezvous servers, with the rendezvous port applied
+pub fn default_rendezvous_servers() -> Vec<String> {
+    RENDEZVOUS_SERVERS
+        .iter()
+        .map(|server| check_port(server, RENDEZVOUS_PORT))
+        .collect()
+}
+
+/// Connect straight to `ip` on the default direct-access port
+pub async fn connect_direct_ip(ip: &str, timeout_ms: u64) -> ResultType<Established> {
+    connect_direct(&check_port(ip, RELAY_PORT + 1), timeout_ms).await
+}
+
+/// Connect straight to an explicit `host:port`
+pub async fn connect_direct(addr: &str, timeout_ms: u64) -> ResultType<Established> {
+    let stream = connect_tcp_local(addr, None, timeout_ms).await?;
+    let peer_addr = resolve(addr).await;
+    Ok(Established {
+        stream,
+        peer_addr,
+        is_direct: true,
+        connection_type: "TCP",
+        signed_id_pk: None,
+    })
+}
+
+/// Punch a hole to `req.peer_id` through `rendezvous_server`
+///
+/// Falls back to the relay server handed out by the rendezvous server when the
+/// direct connection fails or `force_relay` is set.
+pub async fn connect_via_rendezvous(
+    rendezvous_server: &str,
+    req: &ConnectRequest<'_>,
+) -> ResultType<Established> {
+    let mut socket = connect_tcp(rendezvous_server, req.timeout_ms).await?;
+    let my_addr = socket.local_addr();
+    let nat_type = if req.force_relay {
+        NatType::SYMMETRIC
+    } else {
+        NatType::UNKNOWN_NAT
+    };
+
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_punch_hole_request(PunchHoleRequest {
+        id: req.peer_id.to_owned(),
+        token: req.token.to_owned(),
+        nat_type: nat_type.into(),
+        licence_key: req.licence_key.to_owned(),
+        conn_type: proto_conn_type(req.conn_type).into(),
+        version: req.version.to_owned(),
+        force_relay: req.force_relay,
+        ..Default::default()
+    });
+
+    let mut punched = None;
+    for i in 1..=3u64 {
+        log::info!("#{} punch attempt with {}, id: {}", i, my_addr, req.peer_id);
+        socket.send(&msg_out).await?;
+        let Some(msg_in) = next_rendezvous_msg(&mut socket, i * 3000).await else {
+            continue;
+        };
+        match msg_in.union {
+            Some(rendezvous_message::Union::PunchHoleResponse(ph)) => {
+                if ph.socket_addr.is_empty() {
+                    bail!(punch_hole_failure(&ph));
+                }
+                punched = Some((
+                    AddrMangle::decode(&ph.socket_addr),
+                    ph.relay_server.clone(),
+                    Vec::<u8>::from(ph.pk),
+                ));
+                break;
+            }
+            Some(rendezvous_message::Union::RelayResponse(rr)) => {
+                log::info!("relay requested from peer, relay_server: {}", rr.relay_server);
+                let signed_id_pk = Vec::<u8>::from(rr.pk());
+                let relay_server = rr.relay_server.clone();
+                let stream =
+                    create_relay(req, rr.uuid, &relay_server, my_addr.is_ipv4()).await?;
+                return Ok(relay_established(stream, &relay_server, signed_id_pk).await);
+            }
+            other => log::error!("Unexpected rendezvous message: {:?}", other),
+        }
+    }
+    drop(socket);
+
+    let Some((peer_addr, relay_server, signed_id_pk)) = punched else {
+        bail!("Failed to connect via rendezvous server");
+    };
+    log::info!("Hole punched {} = {}, relay_server: {}", req.peer_id, peer_addr, relay_server);
+
+    if !req.force_relay {
+        match connect_tcp_local(peer_addr, Some(my_addr), req.timeout_ms).await {
+            Ok(stream) => {
+                return Ok(Established {
+                    stream,
+                    peer_addr: Some(peer_addr),
+                    is_direct: true,
+                    connection_type: "TCP",
+                    signed_id_pk: Some(signed_id_pk),
+                })
+            }
+            Err(e) => log::info!("Direct connection to {} failed: {}", peer_addr, e),
+        }
+    }
+    if relay_server.is_empty() {
+        bail!("Failed to make direct connection to remote desktop");
+    }
+    let stream = request_relay(rendezvous_server, &relay_server, !signed_id_pk.is_empty(), req)
+        .await
+        .map_err(|e| hbb_common::anyhow::anyhow!("Failed to connect via relay server: {}", e))?;
+    Ok(relay_established(stream, &relay_server, signed_id_pk).await)
+}
+
+/// Ask the rendezvous server to pair us with the peer on `relay_server`
+async fn request_relay(
+    rendezvous_server: &str,
+    relay_server: &str,
+    secure: bool,
+    req: &ConnectRequest<'_>,
+) -> ResultType<Stream> {
+    for i in 1..=3 {
+        // hbbs wants a fresh NAT address per attempt, hence a new socket each time
+        let mut socket = connect_tcp(rendezvous_server, req.timeout_ms).await?;
+        let ipv4 = socket.local_addr().is_ipv4();
+        let uuid = relay_uuid();
+        log::info!("#{} request relay attempt, id: {}, uuid: {}", i, req.peer_id, uuid);
+        let mut msg_out = RendezvousMessage::new();
+        msg_out.set_request_relay(RequestRelay {
+            id: req.peer_id.to_owned(),
+            token: req.token.to_owned(),
+            uuid: uuid.clone(),
+            relay_server: relay_server.to_owned(),
+            secure,
+            ..Default::default()
+        });
+        socket.send(&msg_out).await?;
+
+        if let Some(msg_in) = next_rendezvous_msg(&mut socket, req.timeout_ms).await {
+            if let Some(rendezvous_message::Union::RelayResponse(rs)) = msg_in.union {
+                if !rs.refuse_reason.is_empty() {
+                    bail!(rs.refuse_reason);
+                }
+                return create_relay(req, uuid, relay_server, ipv4).await;
+            }
+        }
+    }
+    bail!("Timeout")
+}
+
+/// Open the relay leg and announce ourselves with the pairing uuid
+async fn create_relay(
+    req: &ConnectRequest<'_>,
+    uuid: String,
+    relay_server: &str,
+    ipv4: bool,
+) -> ResultType<Stream> {
+    let mut conn = connect_tcp(
+        ipv4_to_ipv6(check_port(relay_server, RELAY_PORT), ipv4),
+        req.timeout_ms,
+    )
+    .await?;
+    let mut msg_out = RendezvousMessage::new();
+    msg_out.set_request_relay(RequestRelay {
+        licence_key: req.licence_key.to_owned(),
+        id: req.peer_id.to_owned(),
+        uuid,
+        conn_type: proto_conn_type(req.conn_type).into(),
+        ..Default::default()
+    });
+    conn.send(&msg_out).await?;
+    Ok(conn)
+}
+
+async fn relay_established(stream: Stream, relay_server: &str, signed_id_pk: Vec<u8>) -> Established {
+    let peer_addr = resolve(&check_port(relay_server, RELAY_PORT)).await;
+    Established {
+        stream,
+        peer_addr,
+        is_direct: false,
+        connection_type: "Relay",
+        signed_id_pk: (!signed_id_pk.is_empty()).then_some(signed_id_pk),
+    }
+}
+
+async fn resolve(addr: &str) -> Option<SocketAddr> {
+    tokio::net::lookup_host(addr).await.ok()?.next()
+}
+
+/// Next rendezvous message, skipping key exchange frames
+async fn next_rendezvous_msg(conn: &mut Stream, timeout_ms: u64) -> Option<RendezvousMessage> {
+    for _ in 0..2 {
+        let Some(Ok(bytes)) = conn.next_timeout(timeout_ms).await else {
+            break;
+        };
+        let Ok(msg_in) = RendezvousMessage::parse_from_bytes(&bytes) else {
+            break;
+        };
+        if !matches!(msg_in.union, Some(rendezvous_message::Union::KeyExchange(_))) {
+            return Some(msg_in);
+        }
+    }
+    None
+}
+
+fn punch_hole_failure(ph: &PunchHoleResponse) -> String {
+    if !ph.other_failure.is_empty() {
+        return ph.other_failure.clone();
+    }
+    match ph.failure.enum_value() {
+        Ok(punch_hole_response::Failure::ID_NOT_EXIST) => "ID does not exist",
+        Ok(punch_hole_response::Failure::OFFLINE) => "Remote desktop is offline",
+        Ok(punch_hole_response::Failure::LICENSE_MISMATCH) => "Key mismatch",
+        Ok(punch_hole_response::Failure::LICENSE_OVERUSE) => "Key overuse",
+        _ => "other punch hole failure",
+    }
+    .to_owned()
+}
+
+fn proto_conn_type(conn_type: &ConnType) -> rendezvous_proto::ConnType {
+    match conn_type {
+        ConnType::DEFAULT_CONN => rendezvous_proto::ConnType::DEFAULT_CONN,
+        ConnType::FILE_TRANSFER => rendezvous_proto::ConnType::FILE_TRANSFER,
+        ConnType::PORT_FORWARD => rendezvous_proto::ConnType::PORT_FORWARD,
+        ConnType::RDP => rendezvous_proto::ConnType::RDP,
+    }
+}
+
+/// Pairing id shared with the relay server; only uniqueness matters
+fn relay_uuid() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Accept one framed connection on `listener`
+    async fn accept(listener: &TcpListener) -> Stream {
+        let (tcp, addr) = listener.accept().await.unwrap();
+        Stream::from(tcp, addr)
+    }
+
+    async fn read_msg(stream: &mut Stream) -> RendezvousMessage {
+        let bytes = stream.next_timeout(3000).await.unwrap().unwrap();
+        RendezvousMessage::parse_from_bytes(&bytes).unwrap()
+    }
+
+    fn request<'a>(conn_type: &'a ConnType, force_relay: bool) -> ConnectRequest<'a> {
+        ConnectRequest {
+            peer_id: "123456789",
+            conn_type,
+            licence_key: "",
+            token: "",
+            version: "1.4.2",
+            force_relay,
+            timeout_ms: 3000,
+        }
+    }
+
+    /// Stand-in hbbs answering every punch hole request with `response`
+    async fn spawn_rendezvous(response: PunchHoleResponse) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut conn = accept(&listener).await;
+            let msg = read_msg(&mut conn).await;
+            assert!(msg.has_punch_hole_request());
+            assert_eq!(msg.punch_hole_request().version, "1.4.2");
+            let mut reply = RendezvousMessage::new();
+            reply.set_punch_hole_response(response);
+            conn.send(&reply).await.unwrap();
+
+            // Relay negotiation uses a fresh connection
+            if let Ok((tcp, addr)) = listener.accept().await {
+                let mut conn = Stream::from(tcp, addr);
+                let msg = read_msg(&mut conn).await;
+                let mut reply = RendezvousMessage::new();
+                reply.set_relay_response(RelayResponse {
+                    uuid: msg.request_relay().uuid.clone(),
+                    ..Default::default()
+                });
+                conn.send(&reply).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_punch_hole_connects_directly() {
+        let peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_addr = peer.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = peer.accept().await;
+        });
+        let server = spawn_rendezvous(PunchHoleResponse {
+            socket_addr: AddrMangle::encode(peer_addr).into(),
+            ..Default::default()
+        })
+        .await;
+
+        let conn_type = ConnType::DEFAULT_CONN;
+        let established = connect_via_rendezvous(&server, &request(&conn_type, false))
+            .await
+            .unwrap();
+        assert!(established.is_direct);
+        assert_eq!(established.peer_addr, Some(peer_addr));
+    }
+
+    #[tokio::test]
+    async fn test_force_relay_goes_through_relay_server() {
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let relay_task = tokio::spawn(async move {
+            let mut conn = accept(&relay).await;
+            read_msg(&mut conn).await
+        });
+        let server = spawn_rendezvous(PunchHoleResponse {
+            socket_addr: AddrMangle::encode("127.0.0.1:9".parse().unwrap()).into(),
+            relay_server: relay_addr.to_string(),
+            ..Default::default()
+        })
+        .await;
+
+        let conn_type = ConnType::FILE_TRANSFER;
+        let established = connect_via_rendezvous(&server, &request(&conn_type, true))
+            .await
+            .unwrap();
+        assert!(!established.is_direct);
+        assert_eq!(established.connection_type, "Relay");
+        assert_eq!(established.peer_addr, Some(relay_addr));
+
+        let announced = relay_task.await.unwrap();
+        assert_eq!(announced.request_relay().id, "123456789");
+        assert!(!announced.request_relay().uuid.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_punch_hole_failure_is_reported() {
+        let server = spawn_rendezvous(PunchHoleResponse {
+            failure: punch_hole_response::Failure::ID_NOT_EXIST.into(),
+            ..Default::default()
+        })
+        .await;
+
+        let conn_type = ConnType::DEFAULT_CONN;
+        let err = connect_via_rendezvous(&server, &request(&conn_type, false))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "ID does not exist");
+    }
+
+    #[tokio::test]
+    async fn test_direct_domain_port() {
+        let peer = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = peer.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = peer.accept().await;
+        });
+
+        let established = connect_direct(&addr.to_string(), 3000).await.unwrap();
+        assert!(established.is_direct);
+        assert_eq!(established.peer_addr, Some(addr));
+    }
+}
@@ -192,7 +192,7 @@ impl ConnectionManagementChain {
         let mut chain = Chain::new();
 
         // Add connection-related links
-        chain.add_link("connection_establishment".to_string(), Box::new(crate::links::ConnectionLink::new()));
+        chain.add_link("connection_establishment".to_string(), Box::new(crate::links::ConnectionLink::new("1.4.2")));
         chain.add_link("connection_monitoring".to_string(), Box::new(ConnectionMonitoringLink::new()));
         chain.add_link("connection_cleanup".to_string(), Box::new(ConnectionCleanupLink::new()));
 
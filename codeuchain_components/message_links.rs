@@ -1,18 +1,14 @@
 // Message Processing Links for CodeUChain-based RustDesk
 // These Links handle specific protobuf message types and replace the direct message processing in io_loop.rs
 
+use crate::core::{Context, Link};
+use crate::streaming::{CancelSignal, ContextReceiver, ContextSender, StreamLink};
 use crate::types::*;
-use crate::contexts::*;
-use codeuchain::{Context, Link};
 use async_trait::async_trait;
 use std::result::Result as StdResult;
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
-// Import RustDesk protobuf types
-use hbb_common::message_proto::*;
-use hbb_common::protobuf::Message as ProtoMessage;
-
 /// Result type for message processing links
 pub type MessageResult<T> = StdResult<T, Box<dyn std::error::Error + Send + Sync>>;
 
@@ -54,22 +50,22 @@ impl Link for VideoMessageLink {
         let data = ctx.data().clone();
 
         // Extract video frame from context data
-        if let Some(video_data) = data.get("video_frame") {
-            if let Ok(video_frame) = serde_json::from_value::<VideoFrame>(video_data.clone()) {
-                // Process video frame (decode, render, etc.)
-                self.process_video_frame(&video_frame).await?;
+        if let Some(video_frame) = video_frame_of(&ctx) {
+            // Process video frame (decode, render, etc.)
+            self.process_video_frame(&video_frame).await?;
 
-                // Send to UI if sender available
-                if let Some(sender) = &self.ui_sender {
-                    let ui_update = UiUpdate::VideoFrame(video_frame);
-                    let _ = sender.send(ui_update);
-                }
+            let timestamp = video_frame.timestamp;
 
-                // Update context with processed video data
-                let mut new_data = data.clone();
-                new_data.insert("last_video_timestamp".to_string(), serde_json::to_value(video_frame.timestamp)?);
-                return Ok(Context::new(new_data));
+            // Send to UI if sender available
+            if let Some(sender) = &self.ui_sender {
+                let ui_update = UiUpdate::VideoFrame(video_frame);
+                let _ = sender.send(ui_update);
             }
+
+            // Update context with processed video data
+            let mut new_data = data.clone();
+            new_data.insert("last_video_timestamp".to_string(), serde_json::to_value(timestamp)?);
+            return Ok(Context::new(new_data));
         }
 
         // If no video frame in context, pass through unchanged
@@ -77,6 +73,39 @@ impl Link for VideoMessageLink {
     }
 }
 
+/// Render frames for the lifetime of the session. When rendering falls behind,
+/// queued frames are still decoded, inter-coded frames depend on the ones before
+/// them, but only the newest one is rendered, so latency stays bounded instead
+/// of growing with the backlog.
+#[async_trait]
+impl StreamLink for VideoMessageLink {
+    async fn run(&self, mut input: ContextReceiver, output: ContextSender, mut cancel: CancelSignal) -> MessageResult<()> {
+        loop {
+            let mut ctx = tokio::select! {
+                _ = cancel.cancelled() => return Ok(()),
+                ctx = input.recv() => match ctx {
+                    Some(ctx) => ctx,
+                    None => return Ok(()),
+                },
+            };
+            while let Ok(newer) = input.try_recv() {
+                if let Some(frame) = video_frame_of(&ctx) {
+                    self.process_video_frame(&frame).await?;
+                }
+                ctx = newer;
+            }
+            let ctx = self.call(ctx).await?;
+            if output.send(ctx).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn video_frame_of(ctx: &Context) -> Option<VideoFrame> {
+    serde_json::from_value(ctx.get("video_frame")?.clone()).ok()
+}
+
 impl VideoMessageLink {
     async fn process_video_frame(&self, frame: &VideoFrame) -> MessageResult<()> {
         // Here we would integrate with scrap for video decoding
@@ -146,6 +175,8 @@ impl Link for AudioMessageLink {
                 // Process audio frame (decode, play, etc.)
                 self.process_audio_frame(&audio_frame).await?;
 
+                let timestamp = audio_frame.timestamp;
+
                 // Send to UI if sender available
                 if let Some(sender) = &self.ui_sender {
                     let ui_update = UiUpdate::AudioFrame(audio_frame);
@@ -154,7 +185,7 @@ impl Link for AudioMessageLink {
 
                 // Update context with processed audio data
                 let mut new_data = data.clone();
-                new_data.insert("last_audio_timestamp".to_string(), serde_json::to_value(audio_frame.timestamp)?);
+                new_data.insert("last_audio_timestamp".to_string(), serde_json::to_value(timestamp)?);
                 return Ok(Context::new(new_data));
             }
         }
@@ -228,6 +259,8 @@ impl Link for ClipboardMessageLink {
                 // Process clipboard data
                 self.process_clipboard_data(&clipboard).await?;
 
+                let timestamp = clipboard.timestamp;
+
                 // Send to UI if sender available
                 if let Some(sender) = &self.ui_sender {
                     let ui_update = UiUpdate::ClipboardUpdate(clipboard);
@@ -236,7 +269,7 @@ impl Link for ClipboardMessageLink {
 
                 // Update context with processed clipboard data
                 let mut new_data = data.clone();
-                new_data.insert("last_clipboard_timestamp".to_string(), serde_json::to_value(clipboard.timestamp)?);
+                new_data.insert("last_clipboard_timestamp".to_string(), serde_json::to_value(timestamp)?);
                 return Ok(Context::new(new_data));
             }
         }
@@ -319,13 +352,7 @@ impl Link for InputMessageLink {
                 // Process input events
                 self.process_input_events(&events).await?;
 
-                // Send to UI if sender available (for local echo or feedback)
-                if let Some(sender) = &self.ui_sender {
-                    for event in &events {
-                        // Input events are typically sent to the remote peer, not UI
-                        // But we might want to send feedback for some events
-                    }
-                }
+                // Input events go to the remote peer, not the UI
 
                 // Update context with processed input events
                 let mut new_data = data.clone();
@@ -527,4 +554,60 @@ impl Link for MessageRouterLink {
         // If no specific link matches, pass through unchanged
         Ok(ctx)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    fn frame_ctx(timestamp: u64) -> Context {
+        let frame = VideoFrame {
+            timestamp,
+            width: 1920,
+            height: 1080,
+            data: vec![0; 16],
+            format: "i420".to_string(),
+            codec: "vp9".to_string(),
+        };
+        let mut data = HashMap::new();
+        data.insert("video_frame".to_string(), serde_json::to_value(frame).unwrap());
+        Context::new(data)
+    }
+
+    #[tokio::test]
+    async fn test_video_stream_skips_to_newest_frame() {
+        let (in_tx, in_rx) = mpsc::channel(8);
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        for timestamp in 1..=3 {
+            in_tx.send(frame_ctx(timestamp)).await.unwrap();
+        }
+        drop(in_tx);
+
+        let (_cancel_tx, cancel) = CancelSignal::new();
+        VideoMessageLink::new().run(in_rx, out_tx, cancel).await.unwrap();
+
+        let ctx = out_rx.recv().await.unwrap();
+        assert_eq!(ctx.get("last_video_timestamp").and_then(Value::as_u64), Some(3));
+        assert!(out_rx.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_video_stream_decodes_skipped_frames() {
+        let (in_tx, in_rx) = mpsc::channel(8);
+        let (out_tx, mut out_rx) = mpsc::channel(8);
+        let broken = frame_ctx(1).insert("video_frame".to_string(), serde_json::json!({
+            "timestamp": 1, "width": 0, "height": 0, "data": [],
+            "format": "i420", "codec": "vp9"
+        }));
+        in_tx.send(broken).await.unwrap();
+        in_tx.send(frame_ctx(2)).await.unwrap();
+        drop(in_tx);
+
+        // Skipped, the broken frame is still decoded and fails the stream
+        let (_cancel_tx, cancel) = CancelSignal::new();
+        assert!(VideoMessageLink::new().run(in_rx, out_tx, cancel).await.is_err());
+        assert!(out_rx.recv().await.is_none());
+    }
+}
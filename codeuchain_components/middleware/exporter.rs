@@ -0,0 +1,308 @@
+// OpenMetrics exporter for chain middleware metrics
+//
+// Renders per-link call counters, error counters and latency histograms in the
+// OpenMetrics text format, either served over a local HTTP endpoint or written
+// to a file for node exporters' textfile collectors.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// Upper bounds of the latency histogram buckets, in milliseconds
+pub const LATENCY_BUCKETS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+/// Wait after a failed accept before listening again
+const ACCEPT_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Content type of the OpenMetrics text exposition format
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Latency histogram over [`LATENCY_BUCKETS_MS`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyHistogram {
+    /// Observations per bucket, the last entry counting those above every bound
+    pub bucket_counts: Vec<u64>,
+    pub sum_seconds: f64,
+    pub count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            bucket_counts: vec![0; LATENCY_BUCKETS_MS.len() + 1],
+            sum_seconds: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let ms = duration.as_secs_f64() * 1000.0;
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| ms <= bound as f64)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.bucket_counts[bucket] += 1;
+        self.sum_seconds += duration.as_secs_f64();
+        self.count += 1;
+    }
+
+    pub fn from_durations<'a>(durations: impl IntoIterator<Item = &'a Duration>) -> Self {
+        let mut histogram = Self::default();
+        for duration in durations {
+            histogram.observe(*duration);
+        }
+        histogram
+    }
+}
+
+/// Metrics of a single link
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkMetrics {
+    pub link: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub latency: LatencyHistogram,
+}
+
+/// Anything that can report per-link metrics to an exporter
+pub trait MetricsSource: Send + Sync {
+    fn collect(&self) -> Vec<LinkMetrics>;
+}
+
+/// Renders registered metrics sources in OpenMetrics text format
+pub struct OpenMetricsExporter {
+    prefix: String,
+    sources: Vec<(String, Arc<dyn MetricsSource>)>,
+}
+
+impl OpenMetricsExporter {
+    pub fn new() -> Self {
+        Self {
+            prefix: "codeuchain".to_string(),
+            sources: Vec::new(),
+        }
+    }
+
+    /// Prefix of every metric family name
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Register a source; its samples are labelled with `chain`
+    pub fn with_source(mut self, chain: &str, source: Arc<dyn MetricsSource>) -> Self {
+        self.sources.push((chain.to_string(), source));
+        self
+    }
+
+    /// Render all sources as one OpenMetrics exposition
+    pub fn render(&self) -> String {
+        let samples: Vec<(&str, LinkMetrics)> = self
+            .sources
+            .iter()
+            .flat_map(|(chain, source)| {
+                let mut metrics = source.collect();
+                metrics.sort_by(|a, b| a.link.cmp(&b.link));
+                metrics.into_iter().map(move |m| (chain.as_str(), m))
+            })
+            .collect();
+
+        let mut out = String::new();
+        let calls = format!("{}_link_calls", self.prefix);
+        let _ = writeln!(out, "# TYPE {} counter", calls);
+        let _ = writeln!(out, "# HELP {} Link invocations.", calls);
+        for (chain, m) in &samples {
+            let _ = writeln!(out, "{}_total{{{}}} {}", calls, labels(chain, &m.link), m.calls);
+        }
+
+        let errors = format!("{}_link_errors", self.prefix);
+        let _ = writeln!(out, "# TYPE {} counter", errors);
+        let _ = writeln!(out, "# HELP {} Link invocations that returned an error.", errors);
+        for (chain, m) in &samples {
+            let _ = writeln!(out, "{}_total{{{}}} {}", errors, labels(chain, &m.link), m.errors);
+        }
+
+        let latency = format!("{}_link_latency_seconds", self.prefix);
+        let _ = writeln!(out, "# TYPE {} histogram", latency);
+        let _ = writeln!(out, "# UNIT {} seconds", latency);
+        let _ = writeln!(out, "# HELP {} Link call latency.", latency);
+        for (chain, m) in &samples {
+            let labels = labels(chain, &m.link);
+            let mut cumulative = 0;
+            for (i, count) in m.latency.bucket_counts.iter().enumerate() {
+                cumulative += count;
+                let le = match LATENCY_BUCKETS_MS.get(i) {
+                    Some(bound) => format_bound(*bound),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", latency, labels, le, cumulative);
+            }
+            let _ = writeln!(out, "{}_count{{{}}} {}", latency, labels, m.latency.count);
+            let _ = writeln!(out, "{}_sum{{{}}} {}", latency, labels, m.latency.sum_seconds);
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Write the current exposition to `path`, replacing it atomically
+    pub fn write_to_file(&self, path: &Path) -> std::io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.render())?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Rewrite `path` every `interval` until the task is aborted
+    pub fn spawn_file_writer(self: Arc<Self>, path: PathBuf, interval: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(e) = self.write_to_file(&path) {
+                    println!("[METRICS] Failed to write {}: {}", path.display(), e);
+                }
+            }
+        })
+    }
+
+    /// Serve `GET /metrics` on `addr` until the task is aborted
+    ///
+    /// Returns the bound address, which differs from `addr` when binding port 0.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> std::io::Result<(SocketAddr, JoinHandle<()>)> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        println!("[METRICS] Serving OpenMetrics on http://{}/metrics", local_addr);
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // e.g. out of file descriptors, which won't clear up at once
+                        println!("[METRICS] Accept failed: {}", e);
+                        tokio::time::sleep(ACCEPT_RETRY_DELAY).await;
+                        continue;
+                    }
+                };
+                let exporter = self.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let n = match stream.read(&mut buf).await {
+                        Ok(n) => n,
+                        Err(_) => return,
+                    };
+                    let request = String::from_utf8_lossy(&buf[..n]);
+                    let response = match request.lines().next() {
+                        Some(line) if line.starts_with("GET /metrics ") || line == "GET /metrics" => {
+                            let body = exporter.render();
+                            format!(
+                                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                OPENMETRICS_CONTENT_TYPE,
+                                body.len(),
+                                body
+                            )
+                        }
+                        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+                    };
+                    let _ = stream.write_all(response.as_bytes()).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        Ok((local_addr, handle))
+    }
+}
+
+/// Bucket bound in seconds, always with a fractional part as OpenMetrics expects
+fn format_bound(bound_ms: u64) -> String {
+    let seconds = bound_ms as f64 / 1000.0;
+    if seconds.fract() == 0.0 {
+        format!("{:.1}", seconds)
+    } else {
+        format!("{}", seconds)
+    }
+}
+
+fn labels(chain: &str, link: &str) -> String {
+    format!("chain=\"{}\",link=\"{}\"", escape_label(chain), escape_label(link))
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixedSource(Vec<LinkMetrics>);
+
+    impl MetricsSource for FixedSource {
+        fn collect(&self) -> Vec<LinkMetrics> {
+            self.0.clone()
+        }
+    }
+
+    fn exporter() -> OpenMetricsExporter {
+        let mut latency = LatencyHistogram::default();
+        latency.observe(Duration::from_millis(3));
+        latency.observe(Duration::from_millis(40));
+        latency.observe(Duration::from_secs(20));
+
+        OpenMetricsExporter::new().with_source(
+            "client",
+            Arc::new(FixedSource(vec![LinkMetrics {
+                link: "vid\"eo".to_string(),
+                calls: 3,
+                errors: 1,
+                latency,
+            }])),
+        )
+    }
+
+    #[test]
+    fn test_render_counters_and_cumulative_buckets() {
+        let text = exporter().render();
+        assert!(text.contains("codeuchain_link_calls_total{chain=\"client\",link=\"vid\\\"eo\"} 3"));
+        assert!(text.contains("codeuchain_link_errors_total{chain=\"client\",link=\"vid\\\"eo\"} 1"));
+        assert!(text.contains("_bucket{chain=\"client\",link=\"vid\\\"eo\",le=\"0.001\"} 0"));
+        assert!(text.contains("_bucket{chain=\"client\",link=\"vid\\\"eo\",le=\"0.005\"} 1"));
+        assert!(text.contains("_bucket{chain=\"client\",link=\"vid\\\"eo\",le=\"10.0\"} 2"));
+        assert!(text.contains("_bucket{chain=\"client\",link=\"vid\\\"eo\",le=\"+Inf\"} 3"));
+        assert!(text.contains("codeuchain_link_latency_seconds_count{chain=\"client\",link=\"vid\\\"eo\"} 3"));
+        assert!(text.ends_with("# EOF\n"));
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics_endpoint() {
+        let exporter = Arc::new(exporter());
+        let (addr, handle) = exporter.serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains(OPENMETRICS_CONTENT_TYPE));
+        assert!(response.contains("codeuchain_link_calls_total"));
+        handle.abort();
+    }
+
+    #[test]
+    fn test_write_to_file() {
+        let path = std::env::temp_dir().join(format!("codeuchain-metrics-{}.prom", std::process::id()));
+        exporter().write_to_file(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# EOF"));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
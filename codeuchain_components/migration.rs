@@ -1,12 +1,52 @@
 use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "full")]
+use hbb_common::config::Config;
+
+/// Prefix of the `Config` options switching a subsystem, e.g. `codeuchain-ipc=Y`
+pub const MIGRATION_OPTION_PREFIX: &str = "codeuchain-";
+
+/// IPC config name answered with [`MigrationTracker::report_json`]
+pub const MIGRATION_STATUS_QUERY: &str = "codeuchain-migration";
+
+/// File the global tracker persists phase statuses to
+pub const MIGRATION_STATE_FILE: &str = "codeuchain_migration.json";
+
+/// Subsystems that can be switched, and the phase that migrates each of them
+pub const SUBSYSTEMS: [(&str, &str); 7] = [
+    ("ipc", "phase2_ipc"),
+    ("common", "phase2_common"),
+    ("platform", "phase2_platform"),
+    ("client", "phase3_client"),
+    ("server", "phase3_server"),
+    ("ui", "phase4_ui"),
+    ("core_main", "phase4_core_main"),
+];
+
+/// Subsystems whose chain implementation the application actually runs; the
+/// others stay legacy whatever their phase or switch says
+pub const WIRED_SUBSYSTEMS: [&str; 1] = ["ipc"];
 
 /// Global migration tracker instance
+///
+/// With the `full` feature, statuses persist next to the RustDesk config and
+/// subsystem switches follow the live `Config` options.
 lazy_static::lazy_static! {
-    pub static ref MIGRATION_TRACKER: MigrationTracker = MigrationTracker::new();
+    pub static ref MIGRATION_TRACKER: MigrationTracker = {
+        let tracker = MigrationTracker::new();
+        #[cfg(feature = "full")]
+        let tracker = tracker
+            .with_state_file(Config::path(MIGRATION_STATE_FILE))
+            .with_config_options();
+        tracker
+    };
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum MigrationStatus {
     NotStarted,
     InProgress,
@@ -14,17 +54,56 @@ pub enum MigrationStatus {
     Failed,
 }
 
-pub struct MigrationTracker {
+/// Which implementation a subsystem runs on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationMode {
+    Legacy,
+    Chain,
+}
+
+impl MigrationMode {
+    /// Parse a `Config` option value; empty or unknown values leave the default
+    pub fn from_option(value: &str) -> Option<Self> {
+        match value {
+            "Y" | "chain" => Some(Self::Chain),
+            "N" | "legacy" => Some(Self::Legacy),
+            _ => None,
+        }
+    }
+}
+
+/// State of one subsystem, as reported by the status query
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SubsystemReport {
+    pub subsystem: String,
+    pub phase: String,
+    pub status: MigrationStatus,
+    pub mode: MigrationMode,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
     phases: HashMap<String, MigrationStatus>,
 }
 
+pub struct MigrationTracker {
+    phases: RwLock<HashMap<String, MigrationStatus>>,
+    /// Switches applied through [`MigrationTracker::apply_options`] or `set_mode`
+    overrides: RwLock<HashMap<String, MigrationMode>>,
+    state_file: Option<PathBuf>,
+    #[cfg(feature = "full")]
+    follow_config: bool,
+}
+
 impl MigrationTracker {
     pub fn new() -> Self {
         let mut phases = HashMap::new();
 
         // Initialize all phases
         phases.insert("phase1_foundation".to_string(), MigrationStatus::Completed);
-        phases.insert("phase2_ipc".to_string(), MigrationStatus::Completed);
+        // Legacy until switched on with `codeuchain-ipc=Y`
+        phases.insert("phase2_ipc".to_string(), MigrationStatus::InProgress);
         phases.insert("phase2_common".to_string(), MigrationStatus::NotStarted);
         phases.insert("phase2_platform".to_string(), MigrationStatus::NotStarted);
         phases.insert("phase3_client".to_string(), MigrationStatus::Completed);
@@ -33,22 +112,218 @@ impl MigrationTracker {
         phases.insert("phase4_core_main".to_string(), MigrationStatus::NotStarted);
         phases.insert("phase5_integration".to_string(), MigrationStatus::NotStarted);
 
-        Self { phases }
+        Self {
+            phases: RwLock::new(phases),
+            overrides: RwLock::new(HashMap::new()),
+            state_file: None,
+            #[cfg(feature = "full")]
+            follow_config: false,
+        }
+    }
+
+    /// Persist statuses to `path`, restoring any saved there before
+    pub fn with_state_file(mut self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        match Self::load(&path) {
+            Ok(Some(saved)) => self.phases.get_mut().extend(saved.phases),
+            Ok(None) => {}
+            Err(e) => log::info!("Ignoring unreadable migration state {}: {}", path.display(), e),
+        }
+        self.state_file = Some(path);
+        self
+    }
+
+    /// Read subsystem switches from the live `Config` options on every lookup
+    #[cfg(feature = "full")]
+    pub fn with_config_options(mut self) -> Self {
+        self.follow_config = true;
+        self
+    }
+
+    fn load(path: &Path) -> std::io::Result<Option<PersistedState>> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Write statuses to the state file, if one is configured
+    pub fn save(&self) -> std::io::Result<()> {
+        let Some(path) = &self.state_file else {
+            return Ok(());
+        };
+        let state = PersistedState {
+            phases: self.phases.read().clone(),
+        };
+        let text = serde_json::to_string_pretty(&state)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, text)?;
+        std::fs::rename(&tmp, path)
     }
 
     pub fn get_status(&self, phase: &str) -> MigrationStatus {
-        self.phases.get(phase).cloned().unwrap_or(MigrationStatus::NotStarted)
+        self.phases.read().get(phase).cloned().unwrap_or(MigrationStatus::NotStarted)
     }
 
-    pub fn set_status(&mut self, phase: &str, status: MigrationStatus) {
-        self.phases.insert(phase.to_string(), status);
+    pub fn set_status(&self, phase: &str, status: MigrationStatus) {
+        self.phases.write().insert(phase.to_string(), status);
+        if let Err(e) = self.save() {
+            log::info!("Failed to persist migration status of {}: {}", phase, e);
+        }
     }
 
     pub fn is_completed(&self, phase: &str) -> bool {
         matches!(self.get_status(phase), MigrationStatus::Completed)
     }
 
-    pub fn get_all_statuses(&self) -> &HashMap<String, MigrationStatus> {
-        &self.phases
+    pub fn get_all_statuses(&self) -> HashMap<String, MigrationStatus> {
+        self.phases.read().clone()
+    }
+
+    /// Option key switching `subsystem`
+    pub fn option_key(subsystem: &str) -> String {
+        format!("{}{}", MIGRATION_OPTION_PREFIX, subsystem.replace('_', "-"))
+    }
+
+    /// Implementation `subsystem` should run on right now
+    ///
+    /// Always legacy for subsystems not in [`WIRED_SUBSYSTEMS`]. Otherwise an
+    /// explicit switch wins, else a subsystem runs on chains once its phase is
+    /// completed.
+    pub fn mode(&self, subsystem: &str) -> MigrationMode {
+        if !WIRED_SUBSYSTEMS.contains(&subsystem) {
+            return MigrationMode::Legacy;
+        }
+        if let Some(mode) = self.overrides.read().get(subsystem) {
+            return *mode;
+        }
+        #[cfg(feature = "full")]
+        if self.follow_config {
+            if let Some(mode) = MigrationMode::from_option(&Config::get_option(&Self::option_key(subsystem))) {
+                return mode;
+            }
+        }
+        let phase = SUBSYSTEMS
+            .iter()
+            .find(|(name, _)| *name == subsystem)
+            .map(|(_, phase)| *phase);
+        match phase {
+            Some(phase) if self.is_completed(phase) => MigrationMode::Chain,
+            _ => MigrationMode::Legacy,
+        }
+    }
+
+    pub fn uses_chain(&self, subsystem: &str) -> bool {
+        self.mode(subsystem) == MigrationMode::Chain
+    }
+
+    /// Force `subsystem` onto an implementation, or back to its default with `None`
+    pub fn set_mode(&self, subsystem: &str, mode: Option<MigrationMode>) {
+        let mut overrides = self.overrides.write();
+        match mode {
+            Some(mode) => overrides.insert(subsystem.to_string(), mode),
+            None => overrides.remove(subsystem),
+        };
+    }
+
+    /// Apply `codeuchain-*` switches from an options map; other keys are ignored
+    pub fn apply_options(&self, options: &HashMap<String, String>) {
+        for (subsystem, _) in SUBSYSTEMS {
+            if let Some(value) = options.get(&Self::option_key(subsystem)) {
+                self.set_mode(subsystem, MigrationMode::from_option(value));
+            }
+        }
+    }
+
+    /// Status and effective mode of every subsystem
+    pub fn report(&self) -> Vec<SubsystemReport> {
+        SUBSYSTEMS
+            .iter()
+            .map(|(subsystem, phase)| SubsystemReport {
+                subsystem: subsystem.to_string(),
+                phase: phase.to_string(),
+                status: self.get_status(phase),
+                mode: self.mode(subsystem),
+            })
+            .collect()
+    }
+
+    pub fn report_json(&self) -> String {
+        serde_json::to_string(&self.report()).unwrap_or_default()
+    }
+
+    /// Render a [`report_json`](Self::report_json) answer as a table for the CLI
+    pub fn format_report(json: &str) -> Option<String> {
+        let report: Vec<SubsystemReport> = serde_json::from_str(json).ok()?;
+        let mut out = format!("{:<12} {:<8} {:<18} {}\n", "SUBSYSTEM", "MODE", "PHASE", "STATUS");
+        for entry in report {
+            let mode = match entry.mode {
+                MigrationMode::Legacy => "legacy",
+                MigrationMode::Chain => "chain",
+            };
+            out.push_str(&format!("{:<12} {:<8} {:<18} {:?}\n", entry.subsystem, mode, entry.phase, entry.status));
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_defaults_to_phase_and_follows_options() {
+        let tracker = MigrationTracker::new();
+        assert_eq!(tracker.mode("ipc"), MigrationMode::Legacy);
+        assert_eq!(tracker.mode("ui"), MigrationMode::Legacy);
+        assert_eq!(tracker.mode("unknown"), MigrationMode::Legacy);
+
+        let mut options = HashMap::new();
+        options.insert("codeuchain-ipc".to_string(), "Y".to_string());
+        options.insert("codeuchain-core-main".to_string(), "Y".to_string());
+        options.insert("enable-abr".to_string(), "N".to_string());
+        tracker.apply_options(&options);
+        assert!(tracker.uses_chain("ipc"));
+        // Nothing runs core_main on chains, switching it changes nothing
+        assert!(!tracker.uses_chain("core_main"));
+
+        // Clearing the option restores the phase default
+        options.insert("codeuchain-ipc".to_string(), "".to_string());
+        tracker.apply_options(&options);
+        assert!(!tracker.uses_chain("ipc"));
+    }
+
+    #[test]
+    fn test_status_persists_across_trackers() {
+        let path = std::env::temp_dir().join(format!("codeuchain-migration-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let tracker = MigrationTracker::new().with_state_file(&path);
+        tracker.set_status("phase4_ui", MigrationStatus::Completed);
+        tracker.set_status("phase2_ipc", MigrationStatus::Completed);
+        assert!(tracker.uses_chain("ipc"));
+
+        let restored = MigrationTracker::new().with_state_file(&path);
+        assert!(restored.is_completed("phase4_ui"));
+        assert!(restored.uses_chain("ipc"));
+        assert_eq!(restored.get_status("phase2_common"), MigrationStatus::NotStarted);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_report_round_trips_for_cli() {
+        let tracker = MigrationTracker::new();
+        tracker.set_mode("ipc", Some(MigrationMode::Legacy));
+        tracker.set_mode("ui", Some(MigrationMode::Chain));
+        let json = tracker.report_json();
+        let table = MigrationTracker::format_report(&json).unwrap();
+        assert!(table.lines().any(|line| line.starts_with("ipc") && line.contains("legacy") && line.contains("InProgress")));
+        assert!(table.lines().any(|line| line.starts_with("ui") && line.contains("legacy") && line.contains("NotStarted")));
+        assert_eq!(table.lines().count(), SUBSYSTEMS.len() + 1);
+        assert!(MigrationTracker::format_report("garbage").is_none());
     }
 }
@@ -1,11 +1,15 @@
 // CodeUChain-based modular components for RustDesk
 
 pub mod core; // Core CodeUChain abstractions
+pub mod streaming; // Long-lived streaming chains for media
 pub mod types;
 pub mod contexts;
 pub mod links;
+pub mod message_links; // Protocol message processing links
 pub mod chains;
 pub mod middleware;
+pub mod chain_loader; // Declarative chain definitions (TOML/JSON)
+pub mod replay; // Record/replay harness for chains
 pub mod migration; // Migration infrastructure
 pub mod ipc_facade; // IPC facade for API compatibility
 pub mod ipc_links; // IPC processing links
@@ -27,8 +31,11 @@ pub mod performance_tests; // Performance regression tests
 pub use types::*;
 pub use contexts::*;
 pub use links::*;
+pub use message_links::*;
 pub use chains::*;
 pub use middleware::*;
+pub use chain_loader::*;
+pub use replay::*;
 pub use migration::*;
 pub use ipc_facade::*;
 pub use ipc_links::*;
@@ -48,6 +55,7 @@ pub use e2e_integration_tests::*;
 pub use performance_tests::*;
 
 pub use core::*;
+pub use streaming::*;
 
 #[cfg(test)]
 mod tests {
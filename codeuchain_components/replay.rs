@@ -0,0 +1,547 @@
+//! Record/replay harness for chains
+//!
+//! [`RecordingMiddleware`] captures every context entering and leaving each link,
+//! with timestamps, optionally streaming the events to a JSON-lines file. A
+//! [`Recording`] loaded back from that file can be replayed through a chain with
+//! [`Replayer`], which feeds each link its recorded input and diffs the output it
+//! produces now against the recorded one.
+//!
+//! Typed context slots are recorded as their JSON rendering and replayed as
+//! untyped values, so links that only read typed slots cannot be replayed yet.
+
+use crate::core::{Chain, Context, Middleware, Next, Result};
+use crate::context_key;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{BufRead, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+context_key!(
+    /// Run a context belongs to while it is being recorded
+    RecordRunKey: u64 = "record_run"
+);
+
+/// Point of a link call an event was captured at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordPhase {
+    Enter,
+    Exit,
+    Error,
+}
+
+/// One captured context
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Chain run the call belongs to; concurrent runs get distinct ids
+    pub run: u64,
+    pub link: String,
+    pub phase: RecordPhase,
+    /// Wall clock time of the capture, in milliseconds since the Unix epoch
+    pub at_ms: u64,
+    /// Time spent in the link, for `Exit` and `Error` events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub elapsed_us: Option<u64>,
+    /// Context entering (`Enter`, `Error`) or leaving (`Exit`) the link
+    pub context: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A recorded link call: its input and what it produced
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    pub run: u64,
+    pub link: String,
+    pub input: Value,
+    pub outcome: std::result::Result<Value, String>,
+}
+
+/// Sequence of recorded events
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub events: Vec<RecordedEvent>,
+}
+
+impl Recording {
+    /// Load a JSON-lines recording, skipping blank lines
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let mut events = Vec::new();
+        for (number, line) in std::io::BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line)
+                .map_err(|e| format!("{}:{}: {}", path.display(), number + 1, e))?;
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+
+    /// Write the recording as JSON lines
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        for event in &self.events {
+            serde_json::to_writer(&mut writer, event)?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Ids of the recorded runs, in order of first appearance
+    pub fn runs(&self) -> Vec<u64> {
+        let mut seen = BTreeSet::new();
+        self.events.iter().map(|e| e.run).filter(|run| seen.insert(*run)).collect()
+    }
+
+    /// Pair every `Enter` event with the `Exit` or `Error` closing it
+    ///
+    /// Calls that never completed (e.g. the session was killed) are dropped.
+    pub fn calls(&self) -> Vec<RecordedCall> {
+        let mut open: HashMap<(u64, &str), Vec<&RecordedEvent>> = HashMap::new();
+        let mut calls = Vec::new();
+        for event in &self.events {
+            let key = (event.run, event.link.as_str());
+            match event.phase {
+                RecordPhase::Enter => open.entry(key).or_default().push(event),
+                RecordPhase::Exit | RecordPhase::Error => {
+                    let Some(enter) = open.get_mut(&key).and_then(Vec::pop) else {
+                        continue;
+                    };
+                    let outcome = match event.phase {
+                        RecordPhase::Exit => Ok(event.context.clone()),
+                        _ => Err(event.error.clone().unwrap_or_default()),
+                    };
+                    calls.push(RecordedCall {
+                        run: event.run,
+                        link: event.link.clone(),
+                        input: enter.context.clone(),
+                        outcome,
+                    });
+                }
+            }
+        }
+        calls
+    }
+}
+
+struct RecorderState {
+    events: Vec<RecordedEvent>,
+    sink: Option<BufWriter<std::fs::File>>,
+}
+
+/// Middleware capturing every context entering and leaving each link
+///
+/// Add it first so it is the outermost layer: it then records what the chain
+/// handed to the link and what the chain got back, after retries and fallbacks.
+/// Share it through an `Arc` to read the recording while the chain is alive.
+pub struct RecordingMiddleware {
+    state: Mutex<RecorderState>,
+    next_run: AtomicU64,
+    keep_in_memory: bool,
+}
+
+impl RecordingMiddleware {
+    /// Record into memory
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(RecorderState {
+                events: Vec::new(),
+                sink: None,
+            }),
+            next_run: AtomicU64::new(1),
+            keep_in_memory: true,
+        }
+    }
+
+    /// Record into a JSON-lines file, flushing after every event
+    ///
+    /// Events are not kept in memory, so long sessions can be recorded.
+    pub fn to_file(path: &Path) -> Result<Self> {
+        let sink = BufWriter::new(std::fs::File::create(path)?);
+        Ok(Self {
+            state: Mutex::new(RecorderState {
+                events: Vec::new(),
+                sink: Some(sink),
+            }),
+            next_run: AtomicU64::new(1),
+            keep_in_memory: false,
+        })
+    }
+
+    /// Events captured in memory so far
+    pub fn recording(&self) -> Recording {
+        Recording {
+            events: self.state.lock().events.clone(),
+        }
+    }
+
+    fn record(&self, event: RecordedEvent) {
+        let mut state = self.state.lock();
+        if let Some(sink) = state.sink.as_mut() {
+            let written = serde_json::to_writer(&mut *sink, &event)
+                .map_err(std::io::Error::from)
+                .and_then(|_| sink.write_all(b"\n"))
+                .and_then(|_| sink.flush());
+            if let Err(e) = written {
+                println!("[RECORD] Failed to write event for '{}': {}", event.link, e);
+            }
+        }
+        if self.keep_in_memory {
+            state.events.push(event);
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RecordingMiddleware {
+    async fn around(&self, name: &str, ctx: Context, next: Next<'_>) -> Result<Context> {
+        // The first link of a run tags the context so later links join the same run
+        let (run, ctx) = match ctx.get_typed::<RecordRunKey>() {
+            Some(run) => (*run, ctx),
+            None => {
+                let run = self.next_run.fetch_add(1, Ordering::Relaxed);
+                (run, ctx.insert_typed::<RecordRunKey>(run))
+            }
+        };
+
+        let input = recorded_json(&ctx);
+        self.record(RecordedEvent {
+            run,
+            link: name.to_string(),
+            phase: RecordPhase::Enter,
+            at_ms: now_ms(),
+            elapsed_us: None,
+            context: input.clone(),
+            error: None,
+        });
+
+        let start = Instant::now();
+        let result = next.run(ctx).await;
+        let elapsed_us = Some(start.elapsed().as_micros() as u64);
+        match &result {
+            Ok(output) => self.record(RecordedEvent {
+                run,
+                link: name.to_string(),
+                phase: RecordPhase::Exit,
+                at_ms: now_ms(),
+                elapsed_us,
+                context: recorded_json(output),
+                error: None,
+            }),
+            Err(err) => self.record(RecordedEvent {
+                run,
+                link: name.to_string(),
+                phase: RecordPhase::Error,
+                at_ms: now_ms(),
+                elapsed_us,
+                context: input,
+                error: Some(err.to_string()),
+            }),
+        }
+        result
+    }
+}
+
+fn recorded_json(ctx: &Context) -> Value {
+    let mut map = ctx.to_map();
+    map.remove(<RecordRunKey as crate::core::ContextKey>::NAME);
+    Value::Object(map)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A value that differs between the recording and the replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueDiff {
+    /// JSON pointer into the link output, e.g. `/frame/width`
+    pub path: String,
+    pub recorded: Option<Value>,
+    pub replayed: Option<Value>,
+}
+
+/// A link call whose replay diverged from the recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkMismatch {
+    pub run: u64,
+    pub link: String,
+    pub diffs: Vec<ValueDiff>,
+}
+
+impl std::fmt::Display for LinkMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "run {} link '{}':", self.run, self.link)?;
+        for diff in &self.diffs {
+            let show = |value: &Option<Value>| match value {
+                Some(value) => value.to_string(),
+                None => "<missing>".to_string(),
+            };
+            write!(f, "\n  {}: recorded {}, replayed {}", diff.path, show(&diff.recorded), show(&diff.replayed))?;
+        }
+        Ok(())
+    }
+}
+
+/// Outcome of replaying a recording
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    pub calls: usize,
+    /// Recorded calls to links the chain no longer has
+    pub missing_links: Vec<String>,
+    pub mismatches: Vec<LinkMismatch>,
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_links.is_empty() && self.mismatches.is_empty()
+    }
+}
+
+impl std::fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "replayed {} calls, {} mismatched", self.calls, self.mismatches.len())?;
+        for link in &self.missing_links {
+            write!(f, "\nmissing link '{}'", link)?;
+        }
+        for mismatch in &self.mismatches {
+            write!(f, "\n{}", mismatch)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replays recordings through the links of a chain
+///
+/// Each recorded call is replayed on its own, with the recorded input, so a
+/// divergence is reported at the link that introduced it instead of at every
+/// link downstream. Middleware is bypassed.
+pub struct Replayer<'a> {
+    chain: &'a Chain,
+    ignored_keys: Vec<String>,
+}
+
+impl<'a> Replayer<'a> {
+    pub fn new(chain: &'a Chain) -> Self {
+        Self {
+            chain,
+            ignored_keys: Vec::new(),
+        }
+    }
+
+    /// Skip keys that legitimately differ between runs, such as timestamps
+    ///
+    /// Matches the key at any depth of the output.
+    pub fn with_ignored_keys(mut self, keys: &[&str]) -> Self {
+        self.ignored_keys.extend(keys.iter().map(|key| key.to_string()));
+        self
+    }
+
+    pub async fn replay(&self, recording: &Recording) -> ReplayReport {
+        let mut report = ReplayReport::default();
+        for call in recording.calls() {
+            let Some(link) = self.chain.link(&call.link) else {
+                if !report.missing_links.contains(&call.link) {
+                    report.missing_links.push(call.link.clone());
+                }
+                continue;
+            };
+            report.calls += 1;
+
+            let input = match &call.input {
+                Value::Object(map) => Context::new(map.clone().into_iter().collect()),
+                _ => Context::empty(),
+            };
+            let replayed = link.call(input).await.map(|ctx| recorded_json(&ctx)).map_err(|e| e.to_string());
+
+            let mut diffs = Vec::new();
+            match (&call.outcome, &replayed) {
+                (Ok(recorded), Ok(replayed)) => self.diff("", recorded, replayed, &mut diffs),
+                (Err(recorded), Err(replayed)) if recorded == replayed => {}
+                (recorded, replayed) => diffs.push(ValueDiff {
+                    path: String::new(),
+                    recorded: Some(outcome_json(recorded)),
+                    replayed: Some(outcome_json(replayed)),
+                }),
+            }
+            if !diffs.is_empty() {
+                report.mismatches.push(LinkMismatch {
+                    run: call.run,
+                    link: call.link,
+                    diffs,
+                });
+            }
+        }
+        report
+    }
+
+    fn diff(&self, path: &str, recorded: &Value, replayed: &Value, diffs: &mut Vec<ValueDiff>) {
+        match (recorded, replayed) {
+            (Value::Object(a), Value::Object(b)) => {
+                let keys: BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+                for key in keys {
+                    if self.ignored_keys.contains(key) {
+                        continue;
+                    }
+                    let path = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+                    match (a.get(key), b.get(key)) {
+                        (Some(a), Some(b)) => self.diff(&path, a, b, diffs),
+                        (a, b) => diffs.push(ValueDiff {
+                            path,
+                            recorded: a.cloned(),
+                            replayed: b.cloned(),
+                        }),
+                    }
+                }
+            }
+            (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+                for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                    self.diff(&format!("{}/{}", path, index), a, b, diffs);
+                }
+            }
+            (a, b) if a != b => diffs.push(ValueDiff {
+                path: path.to_string(),
+                recorded: Some(a.clone()),
+                replayed: Some(b.clone()),
+            }),
+            _ => {}
+        }
+    }
+}
+
+fn outcome_json(outcome: &std::result::Result<Value, String>) -> Value {
+    match outcome {
+        Ok(value) => serde_json::json!({ "ok": value }),
+        Err(error) => serde_json::json!({ "error": error }),
+    }
+}
+
+/// Replay the recording at `path` through `chain`, panicking with the report on divergence
+///
+/// Meant for golden tests built from recorded sessions.
+pub async fn assert_replay_matches(chain: &Chain, path: &Path, ignored_keys: &[&str]) {
+    let recording = Recording::load(path).unwrap_or_else(|e| panic!("Cannot load {}: {}", path.display(), e));
+    let report = Replayer::new(chain).with_ignored_keys(ignored_keys).replay(&recording).await;
+    assert!(report.calls > 0, "{} holds no completed calls", path.display());
+    assert!(report.is_clean(), "{}", report);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::Link;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    struct Scale(u64);
+
+    #[async_trait]
+    impl Link for Scale {
+        async fn call(&self, ctx: Context) -> Result<Context> {
+            let n = ctx.get("n").and_then(Value::as_u64).ok_or("missing n")?;
+            Ok(ctx
+                .insert("n".to_string(), json!(n * self.0))
+                .insert("at".to_string(), json!(now_ms())))
+        }
+    }
+
+    fn chain(factor: u64, recorder: Option<Arc<RecordingMiddleware>>) -> Chain {
+        let mut chain = Chain::new();
+        if let Some(recorder) = recorder {
+            chain.use_middleware(Box::new(recorder));
+        }
+        chain.add_link("double".to_string(), Box::new(Scale(2)));
+        chain.add_link("scale".to_string(), Box::new(Scale(factor)));
+        chain
+    }
+
+    fn ctx_with(n: u64) -> Context {
+        Context::empty().insert("n".to_string(), json!(n))
+    }
+
+    #[tokio::test]
+    async fn test_records_every_link_per_run() {
+        let recorder = Arc::new(RecordingMiddleware::new());
+        let chain = chain(3, Some(recorder.clone()));
+        let (a, b) = tokio::join!(chain.run(ctx_with(1)), chain.run(ctx_with(5)));
+        assert_eq!(a.unwrap().get("n"), Some(&json!(6)));
+        assert_eq!(b.unwrap().get("n"), Some(&json!(30)));
+        assert!(chain.run(Context::empty()).await.is_err());
+
+        let recording = recorder.recording();
+        assert_eq!(recording.runs().len(), 3);
+        let calls = recording.calls();
+        assert_eq!(calls.len(), 5);
+        for run in recording.runs() {
+            let links: Vec<&str> = calls.iter().filter(|c| c.run == run).map(|c| c.link.as_str()).collect();
+            assert!(links == ["double", "scale"] || links == ["double"], "{:?}", links);
+        }
+        let failed = calls.iter().find(|c| c.outcome.is_err()).unwrap();
+        assert_eq!(failed.outcome, Err("missing n".to_string()));
+        assert!(recording.events.iter().all(|e| e.context.get("record_run").is_none()));
+    }
+
+    #[tokio::test]
+    async fn test_file_recording_replays_clean_and_reports_divergence() {
+        let path = std::env::temp_dir().join(format!("codeuchain-replay-{}.jsonl", std::process::id()));
+        let recorder = Arc::new(RecordingMiddleware::to_file(&path).unwrap());
+        let recorded = chain(3, Some(recorder.clone()));
+        recorded.run(ctx_with(1)).await.unwrap();
+        recorded.run(Context::empty()).await.unwrap_err();
+        assert!(recorder.recording().events.is_empty());
+
+        let recording = Recording::load(&path).unwrap();
+        assert_eq!(recording.calls().len(), 3);
+
+        // Same links: only the timestamps may differ
+        assert_replay_matches(&chain(3, None), &path, &["at"]).await;
+
+        // A changed link is reported where it diverged, not downstream
+        let report = Replayer::new(&chain(4, None)).with_ignored_keys(&["at"]).replay(&recording).await;
+        assert_eq!(report.calls, 3);
+        assert_eq!(report.mismatches.len(), 1);
+        let mismatch = &report.mismatches[0];
+        assert_eq!(mismatch.link, "scale");
+        assert_eq!(mismatch.diffs, vec![ValueDiff {
+            path: "/n".to_string(),
+            recorded: Some(json!(6)),
+            replayed: Some(json!(8)),
+        }]);
+
+        let mut renamed = Chain::new();
+        renamed.add_link("double".to_string(), Box::new(Scale(2)));
+        let report = Replayer::new(&renamed).with_ignored_keys(&["at"]).replay(&recording).await;
+        assert_eq!(report.missing_links, vec!["scale".to_string()]);
+        assert!(!report.is_clean());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Recorded from the audio, error handling and recovery flows of `e2e_tests.rs`
+    #[tokio::test]
+    async fn test_golden_media_session() {
+        use crate::links::{AudioLink, ConnectionLink, VideoLink};
+
+        let mut chain = Chain::new();
+        chain.add_link("connection".to_string(), Box::new(ConnectionLink::new("1.4.2")));
+        chain.add_link("video".to_string(), Box::new(VideoLink::new()));
+        chain.add_link(
+            "audio".to_string(),
+            Box::new(AudioLink::new().with_sample_rate(48000).with_channels(2).playback_only()),
+        );
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/replay/media_session.jsonl");
+        assert_replay_matches(&chain, &path, &["timestamp"]).await;
+    }
+}
@@ -101,25 +101,25 @@ impl ServerChainFactory {
             "service_orchestration".to_string(),
             "connection_lifecycle".to_string(),
             Box::new(|ctx: &Context| ctx.get("services_initialized").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false))
-        );
+        ).expect("static chain topology");
 
         chain.connect(
             "connection_lifecycle".to_string(),
             "media_capture".to_string(),
             Box::new(|ctx: &Context| ctx.get("connection_status").unwrap_or(&serde_json::Value::String("".to_string())).as_str().unwrap_or("") == "active")
-        );
+        ).expect("static chain topology");
 
         chain.connect(
             "media_capture".to_string(),
             "security_enforcement".to_string(),
             Box::new(|ctx: &Context| ctx.get("media_capture_active").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false))
-        );
+        ).expect("static chain topology");
 
         chain.connect(
             "security_enforcement".to_string(),
             "resource_management".to_string(),
             Box::new(|ctx: &Context| ctx.get("security_validated").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false))
-        );
+        ).expect("static chain topology");
 
         // Add middleware
         chain.use_middleware(Box::new(LoggingMiddleware::new()));
@@ -0,0 +1,356 @@
+//! Streaming CodeUChain abstractions
+//!
+//! A [`Chain`](crate::core::Chain) runs once per context. Continuous media does not
+//! fit that shape, so this module provides long-lived pipelines instead:
+//! - StreamLink: a stage consuming and producing a stream of contexts
+//! - StreamingChain: an ordered set of stages joined by bounded queues
+//! - StreamHandle: the running pipeline, with its input, output and cancellation
+//!
+//! Queues are bounded, so a slow stage applies backpressure to everything upstream
+//! of it. Cancelling the handle stops every stage; an error in any stage cancels
+//! the rest and is returned from [`StreamHandle::join`].
+
+use crate::core::{Context, Link, Result};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinHandle;
+
+/// Default capacity of the queue between two stages
+pub const DEFAULT_STREAM_CAPACITY: usize = 8;
+
+/// Sending half of the queue between two stages
+pub type ContextSender = mpsc::Sender<Context>;
+
+/// Receiving half of the queue between two stages
+pub type ContextReceiver = mpsc::Receiver<Context>;
+
+/// Cancellation signal shared by all stages of a streaming chain
+#[derive(Clone)]
+pub struct CancelSignal {
+    rx: watch::Receiver<bool>,
+}
+
+impl CancelSignal {
+    pub(crate) fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, Self { rx })
+    }
+
+    /// Whether the chain has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.borrow()
+    }
+
+    /// Wait until the chain is cancelled
+    pub async fn cancelled(&mut self) {
+        while !*self.rx.borrow() {
+            if self.rx.changed().await.is_err() {
+                // The handle is gone and nobody can cancel any more
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+/// A long-lived stage of a streaming chain
+///
+/// A stage runs until its input closes, its output is dropped by the next stage,
+/// or the chain is cancelled. Returning an error cancels the whole chain.
+#[async_trait]
+pub trait StreamLink: Send + Sync {
+    async fn run(&self, input: ContextReceiver, output: ContextSender, cancel: CancelSignal) -> Result<()>;
+}
+
+/// Adapter running a one-shot [`Link`] on every context of the stream
+pub struct EachLink<L> {
+    link: L,
+}
+
+impl<L: Link> EachLink<L> {
+    pub fn new(link: L) -> Self {
+        Self { link }
+    }
+}
+
+#[async_trait]
+impl<L: Link> StreamLink for EachLink<L> {
+    async fn run(&self, mut input: ContextReceiver, output: ContextSender, mut cancel: CancelSignal) -> Result<()> {
+        loop {
+            let ctx = tokio::select! {
+                _ = cancel.cancelled() => return Ok(()),
+                ctx = input.recv() => match ctx {
+                    Some(ctx) => ctx,
+                    None => return Ok(()),
+                },
+            };
+
+            let ctx = self.link.call(ctx).await?;
+            if output.send(ctx).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Ordered pipeline of streaming stages
+pub struct StreamingChain {
+    stages: Vec<(String, Arc<dyn StreamLink>)>,
+    capacity: usize,
+}
+
+impl StreamingChain {
+    /// Create a new empty streaming chain
+    pub fn new() -> Self {
+        Self {
+            stages: Vec::new(),
+            capacity: DEFAULT_STREAM_CAPACITY,
+        }
+    }
+
+    /// Set the capacity of each queue between stages
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Append a streaming stage
+    pub fn add_stream_link(&mut self, name: String, link: Arc<dyn StreamLink>) {
+        self.stages.push((name, link));
+    }
+
+    /// Append a one-shot link, invoked once per context
+    pub fn add_link<L: Link + 'static>(&mut self, name: String, link: L) {
+        self.add_stream_link(name, Arc::new(EachLink::new(link)));
+    }
+
+    /// Names of the stages, in pipeline order
+    pub fn stage_names(&self) -> Vec<&str> {
+        self.stages.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Start every stage on the tokio runtime
+    pub fn spawn(&self) -> StreamHandle {
+        let (cancel_tx, cancel) = CancelSignal::new();
+        let cancel_tx = Arc::new(cancel_tx);
+        let (input, mut rx) = mpsc::channel(self.capacity);
+        let mut tasks = Vec::with_capacity(self.stages.len());
+
+        for (name, link) in &self.stages {
+            let (tx, next_rx) = mpsc::channel(self.capacity);
+            let link = link.clone();
+            let name = name.clone();
+            let cancel = cancel.clone();
+            let cancel_tx = cancel_tx.clone();
+            let stage_input = std::mem::replace(&mut rx, next_rx);
+
+            tasks.push(tokio::spawn(async move {
+                let result = link.run(stage_input, tx, cancel).await;
+                if let Err(e) = &result {
+                    log::error!("Stream stage '{}' failed: {}", name, e);
+                    let _ = cancel_tx.send(true);
+                }
+                result.map_err(|e| format!("Stream stage '{}' failed: {}", name, e).into())
+            }));
+        }
+
+        StreamHandle {
+            input: Some(input),
+            output: rx,
+            cancel_tx,
+            tasks,
+        }
+    }
+}
+
+/// A running streaming chain
+pub struct StreamHandle {
+    input: Option<ContextSender>,
+    output: ContextReceiver,
+    cancel_tx: Arc<watch::Sender<bool>>,
+    tasks: Vec<JoinHandle<Result<()>>>,
+}
+
+impl StreamHandle {
+    /// Sender feeding the first stage; `None` once the input was closed
+    pub fn sender(&self) -> Option<ContextSender> {
+        self.input.clone()
+    }
+
+    /// Push a context into the chain, waiting while the first queue is full
+    pub async fn send(&self, ctx: Context) -> Result<()> {
+        let input = self.input.as_ref().ok_or("Stream input closed")?;
+        input.send(ctx).await.map_err(|_| "Streaming chain stopped".into())
+    }
+
+    /// Receive the next context leaving the last stage
+    pub async fn recv(&mut self) -> Option<Context> {
+        self.output.recv().await
+    }
+
+    /// Close the input so the stages drain and finish
+    pub fn close(&mut self) {
+        self.input = None;
+    }
+
+    /// Stop every stage without draining
+    pub fn cancel(&self) {
+        let _ = self.cancel_tx.send(true);
+    }
+
+    /// Whether the chain has been cancelled, explicitly or by a failing stage
+    pub fn is_cancelled(&self) -> bool {
+        *self.cancel_tx.borrow()
+    }
+
+    /// Wait for all stages to finish, returning the first stage error
+    pub async fn join(mut self) -> Result<()> {
+        self.input = None;
+        // Stop consuming the output so the last stage is not blocked on it
+        self.output.close();
+        let mut first_error = None;
+        for task in self.tasks.drain(..) {
+            let result = match task.await {
+                Ok(result) => result,
+                Err(e) => Err(format!("Stream stage panicked: {}", e).into()),
+            };
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        // Stages must not outlive the session that owns the handle
+        let _ = self.cancel_tx.send(true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::time::{timeout, Duration};
+
+    struct Increment;
+
+    #[async_trait]
+    impl Link for Increment {
+        async fn call(&self, ctx: Context) -> Result<Context> {
+            let n = ctx.get("n").and_then(Value::as_u64).unwrap_or(0);
+            if n == 99 {
+                return Err("bad frame".into());
+            }
+            Ok(ctx.insert("n".to_string(), Value::from(n + 1)))
+        }
+    }
+
+    /// Stage that counts contexts without ever forwarding them
+    struct Sink {
+        seen: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl StreamLink for Sink {
+        async fn run(&self, mut input: ContextReceiver, _output: ContextSender, mut cancel: CancelSignal) -> Result<()> {
+            loop {
+                tokio::select! {
+                    biased;
+                    ctx = input.recv() => match ctx {
+                        Some(_) => { self.seen.fetch_add(1, Ordering::SeqCst); }
+                        None => return Ok(()),
+                    },
+                    _ = cancel.cancelled() => return Ok(()),
+                }
+            }
+        }
+    }
+
+    fn ctx_with(n: u64) -> Context {
+        Context::empty().insert("n".to_string(), Value::from(n))
+    }
+
+    #[tokio::test]
+    async fn test_stages_run_in_order() {
+        let mut chain = StreamingChain::new();
+        chain.add_link("first".to_string(), Increment);
+        chain.add_link("second".to_string(), Increment);
+        assert_eq!(chain.stage_names(), vec!["first", "second"]);
+
+        let mut handle = chain.spawn();
+        for n in 0..3 {
+            handle.send(ctx_with(n * 10)).await.unwrap();
+        }
+        handle.close();
+
+        let mut out = Vec::new();
+        while let Some(ctx) = handle.recv().await {
+            out.push(ctx.get("n").and_then(Value::as_u64).unwrap());
+        }
+        assert_eq!(out, vec![2, 12, 22]);
+        handle.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_bounded_queues_apply_backpressure() {
+        let mut chain = StreamingChain::new().with_capacity(1);
+        chain.add_link("inc".to_string(), Increment);
+        let handle = chain.spawn();
+
+        // Nobody reads the output: one context sits in each queue and one in the stage
+        let mut accepted = 0;
+        for n in 0..10 {
+            match timeout(Duration::from_millis(50), handle.send(ctx_with(n))).await {
+                Ok(result) => {
+                    result.unwrap();
+                    accepted += 1;
+                }
+                Err(_) => break,
+            }
+        }
+        assert!(accepted < 10, "input should block once queues are full");
+
+        handle.cancel();
+        handle.join().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_stops_stages() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let mut chain = StreamingChain::new();
+        chain.add_stream_link("sink".to_string(), Arc::new(Sink { seen: seen.clone() }));
+        let handle = chain.spawn();
+
+        handle.send(ctx_with(1)).await.unwrap();
+        let sender = handle.sender().unwrap();
+        handle.cancel();
+        assert!(handle.is_cancelled());
+
+        // Stages exit even though an input sender is still alive
+        timeout(Duration::from_secs(1), handle.join()).await.unwrap().unwrap();
+        drop(sender);
+    }
+
+    #[tokio::test]
+    async fn test_stage_error_cancels_chain() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let mut chain = StreamingChain::new();
+        chain.add_link("inc".to_string(), Increment);
+        chain.add_stream_link("sink".to_string(), Arc::new(Sink { seen: seen.clone() }));
+        let handle = chain.spawn();
+
+        handle.send(ctx_with(1)).await.unwrap();
+        handle.send(ctx_with(99)).await.unwrap();
+
+        let err = timeout(Duration::from_secs(1), handle.join()).await.unwrap().unwrap_err();
+        assert!(err.to_string().contains("inc"));
+        assert_eq!(seen.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,8 +1,9 @@
 // CodeUChain-based RustDesk - Core Types and Contexts
 
-use crate::core::{Context, Link};
+use crate::core::{Context, ContextKey, Link};
 use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashMap;
 
 /// Connection types for remote desktop sessions
@@ -231,22 +232,43 @@ impl RustDeskContext {
     }
 }
 
+crate::context_key!(
+    /// Typed slot holding the session state machine
+    pub RustDeskState: RustDeskContext = "rustdesk_context"
+);
+
 /// Trait for context conversion helpers
+///
+/// The session state lives in the typed [`RustDeskState`] slot. Contexts built from
+/// plain JSON (older callers and fixtures) may still carry it under the untyped
+/// `rustdesk_context` key, which `rustdesk_state` decodes as a fallback.
 pub trait ContextHelpers {
     fn as_rustdesk_context(&self) -> Option<&RustDeskContext>;
+    fn rustdesk_state(&self) -> Option<Cow<'_, RustDeskContext>>;
     fn into_rustdesk_context(self) -> Option<RustDeskContext>;
+    fn with_rustdesk_context(&self, state: RustDeskContext) -> Context;
 }
 
 impl ContextHelpers for Context {
     fn as_rustdesk_context(&self) -> Option<&RustDeskContext> {
-        // This would need to be implemented based on how we store the context
-        // For now, returning None as placeholder
-        None
+        self.get_typed::<RustDeskState>()
+    }
+
+    fn rustdesk_state(&self) -> Option<Cow<'_, RustDeskContext>> {
+        if let Some(state) = self.get_typed::<RustDeskState>() {
+            return Some(Cow::Borrowed(state));
+        }
+        self.get(RustDeskState::NAME)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+            .map(Cow::Owned)
     }
 
     fn into_rustdesk_context(self) -> Option<RustDeskContext> {
-        // This would need to be implemented based on how we store the context
-        // For now, returning None as placeholder
-        None
+        self.rustdesk_state().map(Cow::into_owned)
     }
-}
\ No newline at end of file
+
+    fn with_rustdesk_context(&self, state: RustDeskContext) -> Context {
+        // Drop any legacy JSON copy so readers never see a stale state
+        self.remove(RustDeskState::NAME).insert_typed::<RustDeskState>(state)
+    }
+}
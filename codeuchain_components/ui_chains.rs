@@ -111,19 +111,19 @@ impl UIChainFactory {
             "event_routing".to_string(),
             "session_management".to_string(),
             Box::new(|ctx: &Context| ctx.get("event_routing_complete").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false)),
-        );
+        ).expect("static chain topology");
 
         chain.connect(
             "session_management".to_string(),
             "state_sync".to_string(),
             Box::new(|ctx: &Context| ctx.get("session_active").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false)),
-        );
+        ).expect("static chain topology");
 
         chain.connect(
             "state_sync".to_string(),
             "user_interaction".to_string(),
             Box::new(|ctx: &Context| ctx.get("ui_ready").unwrap_or(&serde_json::Value::Bool(false)).as_bool().unwrap_or(false)),
-        );
+        ).expect("static chain topology");
 
         chain
     }
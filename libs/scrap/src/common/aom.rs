@@ -7,6 +7,8 @@
 include!(concat!(env!("OUT_DIR"), "/aom_ffi.rs"));
 
 use crate::codec::{base_bitrate, codec_thread_num};
+use crate::damage::{self, Damage};
+use crate::layers::{References, TemporalLayers};
 use crate::{codec::EncoderApi, EncodeFrame, STRIDE_ALIGN};
 use crate::{common::GoogleImage, generate_call_macro, generate_call_ptr_macro, Error, Result};
 use crate::{EncodeInput, EncodeYuvFormat, Pixfmt};
@@ -55,6 +57,8 @@ pub struct AomEncoder {
     height: usize,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    layers: TemporalLayers,
+    layer: usize,
 }
 
 // https://webrtc.googlesource.com/src/+/refs/heads/main/modules/video_coding/codecs/av1/libaom_av1_encoder.cc
@@ -226,6 +230,8 @@ impl EncoderApi for AomEncoder {
                     height: config.height as _,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    layers: Default::default(),
+                    layer: 0,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -240,6 +246,10 @@ impl EncoderApi for AomEncoder {
         {
             frames.push(Self::create_frame(frame));
         }
+        // Key frames refresh all the buffers, every viewer needs them
+        if frames.iter().any(|f| f.key) {
+            self.layer = 0;
+        }
         if frames.len() > 0 {
             Ok(Self::create_video_frame(frames))
         } else {
@@ -284,6 +294,57 @@ impl EncoderApi for AomEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_damage(&mut self, damage: &Damage) -> ResultType<()> {
+        // One entry per 16x16 macroblock, a null map marks all of them active
+        let mut map = Vec::new();
+        let mut active_map = aom_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: damage::tiles(self.height) as _,
+            cols: damage::tiles(self.width) as _,
+        };
+        if !damage.is_full()
+            && (damage.rows, damage.cols) == (active_map.rows as _, active_map.cols as _)
+        {
+            map.extend(damage.tiles().iter().map(|dirty| *dirty as u8));
+            active_map.active_map = map.as_mut_ptr();
+        }
+        call_aom!(aom_codec_control(
+            &mut self.ctx,
+            aome_enc_control_id::AOME_SET_ACTIVEMAP as i32,
+            &mut active_map as *mut aom_active_map_t
+        ));
+        Ok(())
+    }
+
+    fn set_lossless(&mut self, lossless: bool) -> ResultType<bool> {
+        // Cyclic refresh would quantize some blocks again, see `set_controls`
+        let aq_mode: i32 = if lossless { 0 } else { 3 };
+        call_aom!(aom_codec_control(
+            &mut self.ctx,
+            aome_enc_control_id::AV1E_SET_AQ_MODE as i32,
+            aq_mode
+        ));
+        call_aom!(aom_codec_control(
+            &mut self.ctx,
+            aome_enc_control_id::AV1E_SET_LOSSLESS as i32,
+            lossless as i32
+        ));
+        if lossless {
+            // In the base layer, for every viewer
+            self.set_temporal_layers(self.layers.layers());
+        }
+        Ok(true)
+    }
+
+    fn set_temporal_layers(&mut self, layers: usize) -> usize {
+        self.layers = TemporalLayers::new(layers);
+        self.layers.layers()
+    }
+
+    fn temporal_layer(&self) -> usize {
+        self.layer
+    }
 }
 
 impl AomEncoder {
@@ -309,12 +370,19 @@ impl AomEncoder {
         ));
         let pts = webrtc::kTimeBaseDen / 1000 * ms;
         let duration = webrtc::kTimeBaseDen / 1000;
+        let (layer, references) = self.layers.next();
+        self.layer = layer;
+        let flags = if self.layers.layers() > 1 {
+            Self::layer_flags(references)
+        } else {
+            0
+        };
         call_aom!(aom_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             duration as _, // Duration
-            0,             // Flags
+            flags as _,
         ));
 
         Ok(EncodeFrames {
@@ -323,6 +391,29 @@ impl AomEncoder {
         })
     }
 
+    // Only the last and golden buffers, the others hold the key frame or older frames
+    fn layer_flags(references: References) -> u32 {
+        let mut flags = AOM_EFLAG_NO_REF_LAST2
+            | AOM_EFLAG_NO_REF_LAST3
+            | AOM_EFLAG_NO_REF_ARF
+            | AOM_EFLAG_NO_REF_BWD
+            | AOM_EFLAG_NO_REF_ARF2
+            | AOM_EFLAG_NO_UPD_ARF;
+        if !references.ref_golden {
+            flags |= AOM_EFLAG_NO_REF_GF;
+        }
+        if !references.update_last {
+            flags |= AOM_EFLAG_NO_UPD_LAST;
+        }
+        if !references.update_golden {
+            flags |= AOM_EFLAG_NO_UPD_GF;
+        }
+        if !references.update_entropy {
+            flags |= AOM_EFLAG_NO_UPD_ENTROPY;
+        }
+        flags
+    }
+
     #[inline]
     pub fn create_video_frame(frames: Vec<EncodedVideoFrame>) -> VideoFrame {
         let mut vf = VideoFrame::new();
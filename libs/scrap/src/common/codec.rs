@@ -1,7 +1,10 @@
 use std::{
     collections::HashMap,
     ops::{Deref, DerefMut},
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Arc, Mutex,
+    },
     time::Instant,
 };
 
@@ -14,6 +17,7 @@ use crate::vram::*;
 use crate::{
     aom::{self, AomDecoder, AomEncoder, AomEncoderConfig},
     common::GoogleImage,
+    damage::Damage,
     vpxcodec::{self, VpxDecoder, VpxDecoderConfig, VpxEncoder, VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, EncodeInput, EncodeYuvFormat, ImageRgb, ImageTexture,
 };
@@ -32,8 +36,9 @@ use hbb_common::{
     lazy_static, log,
     message_proto::message::{
         supported_decoding::PreferCodec, video_frame, Chroma, CodecAbility, EncodedVideoFrames,
-        SupportedDecoding, SupportedEncoding, VideoFrame,
+        OptionMessage, SupportedDecoding, SupportedEncoding, VideoFrame,
     },
+    protobuf::{Message as _, UnknownValueRef},
     sysinfo::{LoadAverageExt, System},
     PeerConfig,
     ResultType,
@@ -41,6 +46,7 @@ use hbb_common::{
 
 lazy_static::lazy_static! {
     static ref PEER_DECODINGS: Arc<Mutex<HashMap<i32, SupportedDecoding>>> = Default::default();
+    static ref PEER_LOSSLESS: Arc<Mutex<HashMap<i32, LosslessMode>>> = Default::default();
     static ref ENCODE_CODEC_FORMAT: Arc<Mutex<CodecFormat>> = Arc::new(Mutex::new(CodecFormat::VP9));
     static ref THREAD_LOG_TIME: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
     static ref USABLE_ENCODING: Arc<Mutex<Option<SupportedEncoding>>> = Arc::new(Mutex::new(None));
@@ -81,6 +87,22 @@ pub trait EncoderApi {
     fn is_hardware(&self) -> bool;
 
     fn disable(&self);
+
+    /// Hint which tiles changed since the previous frame, the others may be
+    /// skipped. Ignored by the encoders without active maps.
+    fn set_damage(&mut self, damage: &Damage) -> ResultType<()>;
+
+    /// Encode the next frames losslessly or not. Returns `false` if the
+    /// encoder has no lossless mode.
+    fn set_lossless(&mut self, lossless: bool) -> ResultType<bool>;
+
+    /// Encode the next frames in temporal layers, see [`crate::layers`],
+    /// starting a new period. Returns the number of layers used, 1 if the
+    /// encoder can't.
+    fn set_temporal_layers(&mut self, layers: usize) -> usize;
+
+    /// The temporal layer of the last frame encoded.
+    fn temporal_layer(&self) -> usize;
 }
 
 pub struct Encoder {
@@ -128,6 +150,8 @@ pub enum EncodingUpdate {
     Update(i32, SupportedDecoding),
     Remove(i32),
     NewOnlyVP9(i32),
+    /// The lossless mode a peer asked for, see [`LosslessMode::from_request`]
+    Lossless(i32, LosslessMode),
     Check,
 }
 
@@ -178,6 +202,7 @@ impl Encoder {
             }
             EncodingUpdate::Remove(id) => {
                 decodings.remove(&id);
+                PEER_LOSSLESS.lock().unwrap().remove(&id);
             }
             EncodingUpdate::NewOnlyVP9(id) => {
                 decodings.insert(
@@ -188,8 +213,12 @@ impl Encoder {
                     },
                 );
             }
+            EncodingUpdate::Lossless(id, mode) => {
+                PEER_LOSSLESS.lock().unwrap().insert(id, mode);
+            }
             EncodingUpdate::Check => {}
         }
+        LosslessMode::resolve(&decodings);
 
         let vp8_useable = decodings.len() > 0 && decodings.iter().all(|(_, s)| s.ability_vp8 > 0);
         let av1_useable = decodings.len() > 0
@@ -403,9 +432,11 @@ impl Encoder {
 
     pub fn use_i444(config: &EncoderCfg) -> bool {
         let decodings = PEER_DECODINGS.lock().unwrap().clone();
-        let prefer_i444 = decodings
-            .iter()
-            .all(|d| d.1.prefer_chroma == Chroma::I444.into());
+        // Lossless frames are only pixel exact without chroma subsampling
+        let prefer_i444 = LosslessMode::get() != LosslessMode::Off
+            || decodings
+                .iter()
+                .all(|d| d.1.prefer_chroma == Chroma::I444.into());
         let i444_useable = match config {
             EncoderCfg::VPX(vpx) => match vpx.codec {
                 VpxVideoCodecId::VP8 => false,
@@ -927,6 +958,105 @@ impl Quality {
     }
 }
 
+pub const OPTION_LOSSLESS_ENCODING: &str = "lossless-encoding";
+
+/// The `OptionMessage` field of the lossless mode a peer asks for, a varint.
+///
+/// Not declared in message.proto yet, so it's far above the fields there.
+pub const LOSSLESS_OPTION_FIELD: u32 = 1000;
+
+/// When the frames are encoded losslessly.
+///
+/// The host's `lossless-encoding` option decides if set, else the mode every
+/// peer asked for. It's worked out as either changes, see
+/// [`LosslessMode::refresh`]. Only VP9 and AV1 have a lossless mode, which any of their
+/// decoders can decode, so nothing more is negotiated with the peers than the
+/// codec and 4:4:4, preferred in this mode if all of them support it.
+///
+/// The peers ask for it with field [`LOSSLESS_OPTION_FIELD`] of their
+/// `OptionMessage`, which hosts without lossless encoding skip as unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LosslessMode {
+    Off,
+    /// Once the screen stopped changing for a while, the last frame is encoded
+    /// again losslessly. Moving content is still encoded with `Quality`.
+    Static,
+    /// Every frame, with a much higher bitrate
+    Always,
+}
+
+// The mode in effect, see `LosslessMode::get`
+static LOSSLESS_MODE: AtomicU8 = AtomicU8::new(0);
+
+impl LosslessMode {
+    /// The mode in effect, cheap enough to check every frame
+    pub fn get() -> Self {
+        Self::from_value(LOSSLESS_MODE.load(Ordering::Relaxed) as _).unwrap_or(Self::Off)
+    }
+
+    /// Work out the mode in effect again, after the option changed. The
+    /// peers changing do it through [`Encoder::update`].
+    pub fn refresh() -> Self {
+        let decodings = PEER_DECODINGS.lock().unwrap();
+        Self::resolve(&decodings)
+    }
+
+    // Takes `PEER_LOSSLESS` under `PEER_DECODINGS`, in the order of `Encoder::update`
+    fn resolve(decodings: &HashMap<i32, SupportedDecoding>) -> Self {
+        let mode = Self::from_option(&Config::get_option(OPTION_LOSSLESS_ENCODING))
+            .unwrap_or_else(|| {
+                let requests = PEER_LOSSLESS.lock().unwrap();
+                decodings
+                    .keys()
+                    .map(|id| requests.get(id).copied().unwrap_or(Self::Off))
+                    .min()
+                    .unwrap_or(Self::Off)
+            });
+        LOSSLESS_MODE.store(mode.value(), Ordering::Relaxed);
+        mode
+    }
+
+    fn value(self) -> u8 {
+        match self {
+            Self::Off => 0,
+            Self::Static => 1,
+            Self::Always => 2,
+        }
+    }
+
+    fn from_value(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::Off),
+            1 => Some(Self::Static),
+            2 => Some(Self::Always),
+            _ => None,
+        }
+    }
+
+    /// `None` if unset, leaving it to the peers
+    pub fn from_option(value: &str) -> Option<Self> {
+        match value {
+            "off" => Some(Self::Off),
+            "static" => Some(Self::Static),
+            "always" => Some(Self::Always),
+            _ => None,
+        }
+    }
+
+    /// Ask the peer for this mode in `option`
+    pub fn request(self, option: &mut OptionMessage) {
+        option.mut_unknown_fields().add_varint(LOSSLESS_OPTION_FIELD, self.value() as _);
+    }
+
+    /// The mode asked for in `option`, `None` if it asks for none
+    pub fn from_request(option: &OptionMessage) -> Option<Self> {
+        match option.unknown_fields().get(LOSSLESS_OPTION_FIELD)? {
+            UnknownValueRef::Varint(value) => Self::from_value(value),
+            _ => None,
+        }
+    }
+}
+
 pub fn base_bitrate(width: u32, height: u32) -> u32 {
     const RESOLUTION_PRESETS: &[(u32, u32, u32)] = &[
         (640, 480, 400),     // VGA, 307k pixels
@@ -0,0 +1,164 @@
+//! Damage tracking, which tiles of a frame changed since the previous one.
+//!
+//! The damage is found by diffing the captured pixels tile by tile, whatever
+//! the capturer. It lets the video service skip static frames and tell the
+//! encoder which blocks it may skip, see [`crate::codec::EncoderApi::set_damage`].
+
+#[cfg(not(target_os = "ios"))]
+use crate::{PixelBuffer, Pixfmt, TraitPixelBuffer};
+
+/// Side of a tile in pixels, the macroblock size of the vpx and aom active maps.
+pub const TILE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Damage {
+    pub cols: usize,
+    pub rows: usize,
+    /// Row major, `true` if the tile changed
+    tiles: Vec<bool>,
+}
+
+impl Damage {
+    pub fn full(width: usize, height: usize) -> Self {
+        let (cols, rows) = (tiles(width), tiles(height));
+        Self {
+            cols,
+            rows,
+            tiles: vec![true; cols * rows],
+        }
+    }
+
+    pub fn tiles(&self) -> &[bool] {
+        &self.tiles
+    }
+
+    pub fn is_dirty(&self, col: usize, row: usize) -> bool {
+        self.tiles[row * self.cols + col]
+    }
+
+    pub fn count(&self) -> usize {
+        self.tiles.iter().filter(|dirty| **dirty).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.tiles.contains(&true)
+    }
+
+    pub fn is_full(&self) -> bool {
+        !self.tiles.contains(&false)
+    }
+}
+
+/// The number of tiles covering `pixels`.
+#[inline]
+pub fn tiles(pixels: usize) -> usize {
+    (pixels + TILE_SIZE - 1) / TILE_SIZE
+}
+
+/// Keeps the previous frame to diff the next one with.
+#[cfg(not(target_os = "ios"))]
+#[derive(Default)]
+pub struct DamageTracker {
+    last: Vec<u8>,
+    width: usize,
+    height: usize,
+    stride: usize,
+}
+
+#[cfg(not(target_os = "ios"))]
+impl DamageTracker {
+    /// The damage of `frame`, full if there is no comparable previous frame.
+    pub fn update(&mut self, frame: &PixelBuffer) -> Damage {
+        let (width, height) = (frame.width(), frame.height());
+        let bpp = match frame.pixfmt() {
+            Pixfmt::BGRA | Pixfmt::RGBA | Pixfmt::RGB565LE => frame.pixfmt().bytes_per_pixel(),
+            // Planar, not worth diffing
+            _ => {
+                self.reset();
+                return Damage::full(width, height);
+            }
+        };
+        let stride = frame.stride().first().copied().unwrap_or_default();
+        let data = frame.data();
+        if stride < width * bpp || data.len() < stride * height {
+            self.reset();
+            return Damage::full(width, height);
+        }
+        let data = &data[..stride * height];
+        if self.last.len() != data.len()
+            || (self.width, self.height, self.stride) != (width, height, stride)
+        {
+            self.last = data.to_vec();
+            self.width = width;
+            self.height = height;
+            self.stride = stride;
+            return Damage::full(width, height);
+        }
+        let mut damage = Damage {
+            cols: tiles(width),
+            rows: tiles(height),
+            tiles: vec![false; tiles(width) * tiles(height)],
+        };
+        let tile_bytes = TILE_SIZE * bpp;
+        for y in 0..height {
+            let row = y * stride..y * stride + width * bpp;
+            let (new, old) = (&data[row.clone()], &self.last[row]);
+            if new == old {
+                continue;
+            }
+            let dirty = &mut damage.tiles[(y / TILE_SIZE) * damage.cols..][..damage.cols];
+            for (col, (new, old)) in new
+                .chunks(tile_bytes)
+                .zip(old.chunks(tile_bytes))
+                .enumerate()
+            {
+                if !dirty[col] && new != old {
+                    dirty[col] = true;
+                }
+            }
+        }
+        if !damage.is_empty() {
+            self.last.copy_from_slice(data);
+        }
+        damage
+    }
+
+    /// Forget the previous frame, the next one is fully damaged.
+    pub fn reset(&mut self) {
+        self.last = Vec::new();
+    }
+}
+
+#[cfg(all(test, any(target_os = "windows", target_os = "linux")))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_damage_tiles() {
+        let (width, height) = (40, 20);
+        let mut data = vec![0u8; width * height * 4];
+        let mut tracker = DamageTracker::default();
+        let damage = tracker.update(&PixelBuffer::new(&data, Pixfmt::BGRA, width, height));
+        assert!(damage.is_full());
+        assert_eq!((damage.cols, damage.rows), (3, 2));
+
+        let damage = tracker.update(&PixelBuffer::new(&data, Pixfmt::BGRA, width, height));
+        assert!(damage.is_empty());
+
+        // One pixel in the last, partial, tile
+        data[(17 * width + 39) * 4] = 1;
+        let damage = tracker.update(&PixelBuffer::new(&data, Pixfmt::BGRA, width, height));
+        assert_eq!(damage.count(), 1);
+        assert!(damage.is_dirty(2, 1));
+
+        // Two pixels on a tile border
+        data[(3 * width + 15) * 4 + 2] = 1;
+        data[(3 * width + 16) * 4] = 1;
+        let damage = tracker.update(&PixelBuffer::new(&data, Pixfmt::BGRA, width, height));
+        assert_eq!(damage.tiles(), &[true, true, false, false, false, false]);
+
+        let half = &data[..width * 4 * 10];
+        let damage = tracker.update(&PixelBuffer::new(half, Pixfmt::BGRA, width, 10));
+        assert!(damage.is_full());
+    }
+}
@@ -0,0 +1,113 @@
+//! Temporal layers, so the viewers of one stream can take different frame rates.
+//!
+//! With `n` layers, a period of `2^(n - 1)` frames starts with a frame of the
+//! base layer 0, the other frames being in higher layers, e.g. `0 2 1 2` for 3
+//! layers. A frame only references frames of its layer or lower ones:
+//!
+//! - layer 0 references and updates the last frame buffer,
+//! - layer 1 of 3 references the last frame buffer and updates the golden one,
+//! - the top layer references both and updates nothing.
+//!
+//! Dropping the frames above a layer leaves a stream at `1 / 2^k` of the frame
+//! rate that any decoder can decode, nothing has to be negotiated.
+
+/// Enough for a frame rate ratio of 4 between the viewers.
+pub const MAX_LAYERS: usize = 3;
+
+/// The frame buffers a frame may reference and update.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct References {
+    pub ref_golden: bool,
+    pub update_last: bool,
+    pub update_golden: bool,
+    pub update_entropy: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TemporalLayers {
+    layers: usize,
+    index: usize,
+}
+
+impl Default for TemporalLayers {
+    fn default() -> Self {
+        Self::new(1)
+    }
+}
+
+impl TemporalLayers {
+    pub fn new(layers: usize) -> Self {
+        Self {
+            layers: layers.clamp(1, MAX_LAYERS),
+            index: 0,
+        }
+    }
+
+    pub fn layers(&self) -> usize {
+        self.layers
+    }
+
+    /// The layer and the references of the next frame.
+    pub fn next(&mut self) -> (usize, References) {
+        let layer = layer(self.index, self.layers);
+        self.index = (self.index + 1) % (1 << (self.layers - 1));
+        let top = layer == self.layers - 1 && layer > 0;
+        let references = References {
+            ref_golden: top && self.layers > 2,
+            update_last: layer == 0,
+            update_golden: !top && layer > 0,
+            update_entropy: layer == 0,
+        };
+        (layer, references)
+    }
+}
+
+/// The layer of the frame `index` of a stream.
+pub fn layer(index: usize, layers: usize) -> usize {
+    let layers = layers.clamp(1, MAX_LAYERS);
+    let i = index % (1 << (layers - 1));
+    if i == 0 {
+        0
+    } else {
+        layers - 1 - i.trailing_zeros() as usize
+    }
+}
+
+/// The highest layer to send to a viewer taking `fps` of a stream encoded at
+/// `stream_fps`, the base layer at least.
+pub fn max_layer(fps: u32, stream_fps: u32, layers: usize) -> usize {
+    let layers = layers.clamp(1, MAX_LAYERS);
+    // Layer `l` and the lower ones make `stream_fps / 2^(layers - 1 - l)`
+    (0..layers)
+        .rev()
+        .find(|l| stream_fps >> (layers - 1 - l) <= fps)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_temporal_layers() {
+        let mut layers = TemporalLayers::new(3);
+        let pattern: Vec<_> = (0..8).map(|_| layers.next().0).collect();
+        assert_eq!(pattern, [0, 2, 1, 2, 0, 2, 1, 2]);
+        assert!(layers.next().1.update_last);
+        let (_, top) = layers.next();
+        assert!(top.ref_golden && !top.update_last && !top.update_golden);
+        let (_, middle) = layers.next();
+        assert!(!middle.ref_golden && middle.update_golden && !middle.update_entropy);
+
+        let mut single = TemporalLayers::default();
+        assert_eq!(single.next(), single.next());
+        assert_eq!(single.next().0, 0);
+        assert_eq!(layer(3, 2), 1);
+
+        assert_eq!(max_layer(30, 30, 3), 2);
+        assert_eq!(max_layer(20, 30, 3), 1);
+        assert_eq!(max_layer(8, 30, 3), 0);
+        assert_eq!(max_layer(1, 30, 3), 0);
+        assert_eq!(max_layer(1, 30, 1), 0);
+    }
+}
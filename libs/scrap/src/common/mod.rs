@@ -37,8 +37,10 @@ cfg_if! {
 
 pub mod codec;
 pub mod convert;
+pub mod damage;
 #[cfg(feature = "hwcodec")]
 pub mod hwcodec;
+pub mod layers;
 #[cfg(feature = "mediacodec")]
 pub mod mediacodec;
 pub mod vpxcodec;
@@ -51,7 +53,11 @@ pub const HW_STRIDE_ALIGN: usize = 0; // recommended by av_frame_get_buffer
 pub mod aom;
 #[cfg(not(any(target_os = "ios")))]
 pub mod camera;
+pub mod playback;
 pub mod record;
+pub mod seal;
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub mod synthetic;
 mod vpx;
 
 #[repr(usize)]
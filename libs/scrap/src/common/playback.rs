@@ -0,0 +1,628 @@
+//! Reading back the files written by [`crate::record::Recorder`].
+//!
+//! WebM files (VP8/VP9/AV1) are fully demuxed so they can be cut and decoded;
+//! MP4 files (H264/H265, written by the hwcodec muxer) only expose metadata.
+
+use crate::CodecFormat;
+use hbb_common::{bail, ResultType};
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+};
+use webm::mux::{self, Segment, Track};
+
+/// Metadata encoded in a recording's file name by `RecorderContext2::set_filename`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingFile {
+    pub path: PathBuf,
+    /// `incoming` recordings are made by the controlled side
+    pub server: bool,
+    pub id: String,
+    /// Local time the file was started, `%Y%m%d%H%M%S%3f`
+    pub started: String,
+    pub camera: bool,
+    pub display_idx: usize,
+    pub format: CodecFormat,
+}
+
+impl RecordingFile {
+    /// Parse `{incoming|outgoing}_{id}_{time}_{display|camera}{idx}_{codec}.{webm|mp4}`.
+    pub fn parse(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_str()?;
+        let ext = path.extension()?.to_str()?;
+        if ext != "webm" && ext != "mp4" {
+            return None;
+        }
+        let (direction, rest) = stem.split_once('_')?;
+        let server = match direction {
+            "incoming" => true,
+            "outgoing" => false,
+            _ => return None,
+        };
+        // The id may contain '_', so take the fixed fields from the right
+        let mut parts = rest.rsplitn(4, '_');
+        let codec = parts.next()?;
+        let source = parts.next()?;
+        let started = parts.next()?;
+        let id = parts.next()?;
+        let (camera, display_idx) = if let Some(idx) = source.strip_prefix("camera") {
+            (true, idx.parse().ok()?)
+        } else {
+            (false, source.strip_prefix("display")?.parse().ok()?)
+        };
+        let format = match codec {
+            "vp8" => CodecFormat::VP8,
+            "vp9" => CodecFormat::VP9,
+            "av1" => CodecFormat::AV1,
+            "h264" => CodecFormat::H264,
+            "h265" => CodecFormat::H265,
+            _ => return None,
+        };
+        if id.is_empty() || started.len() != 17 || !started.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        Some(Self {
+            path: path.to_path_buf(),
+            server,
+            id: id.to_owned(),
+            started: started.to_owned(),
+            camera,
+            display_idx,
+            format,
+        })
+    }
+
+    /// Key grouping the files of one recorded stream
+    pub fn stream_key(&self) -> (bool, &str, bool, usize) {
+        (self.server, &self.id, self.camera, self.display_idx)
+    }
+}
+
+/// Recordings in `dir`, oldest first. Other files are ignored.
+pub fn list(dir: &Path) -> ResultType<Vec<RecordingFile>> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| RecordingFile::parse(&entry.path()))
+        .collect();
+    files.sort_by(|a, b| a.started.cmp(&b.started).then(a.path.cmp(&b.path)));
+    Ok(files)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingInfo {
+    pub format: CodecFormat,
+    pub width: usize,
+    pub height: usize,
+    pub duration_ms: u64,
+    pub frames: usize,
+    pub key_frames: usize,
+}
+
+/// One encoded frame, with its pts in milliseconds as written by the recorder.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoPacket {
+    pub pts: i64,
+    pub key: bool,
+    pub data: Vec<u8>,
+}
+
+/// Read the metadata of a WebM or MP4 recording.
+pub fn probe(path: &Path) -> ResultType<RecordingInfo> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("webm") => Ok(WebmReader::open(path)?.info),
+        Some("mp4") => probe_mp4(&std::fs::read(path)?),
+        _ => bail!("not a recording: {}", path.display()),
+    }
+}
+
+/// Demuxed video track of a WebM recording.
+pub struct WebmReader {
+    pub info: RecordingInfo,
+    pub codec_private: Option<Vec<u8>>,
+    pub packets: Vec<VideoPacket>,
+}
+
+// EBML element ids used by the recorder's muxer
+const EBML_HEADER: u64 = 0x1A45DFA3;
+const SEGMENT: u64 = 0x18538067;
+const INFO: u64 = 0x1549A966;
+const TIMECODE_SCALE: u64 = 0x2AD7B1;
+const DURATION: u64 = 0x4489;
+const TRACKS: u64 = 0x1654AE6B;
+const TRACK_ENTRY: u64 = 0xAE;
+const TRACK_NUMBER: u64 = 0xD7;
+const CODEC_ID: u64 = 0x86;
+const CODEC_PRIVATE: u64 = 0x63A2;
+const VIDEO: u64 = 0xE0;
+const PIXEL_WIDTH: u64 = 0xB0;
+const PIXEL_HEIGHT: u64 = 0xBA;
+const CLUSTER: u64 = 0x1F43B675;
+const CLUSTER_TIMECODE: u64 = 0xE7;
+const SIMPLE_BLOCK: u64 = 0xA3;
+const BLOCK_GROUP: u64 = 0xA0;
+const BLOCK: u64 = 0xA1;
+const REFERENCE_BLOCK: u64 = 0xFB;
+
+struct Element<'a> {
+    id: u64,
+    data: &'a [u8],
+}
+
+/// Iterates the elements of an EBML master element's payload.
+///
+/// An element of unknown size (left by a recorder that never wrote its tail)
+/// extends to the end of its parent.
+struct Elements<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Element<'a>> {
+        let (id, id_len) = read_vint(self.buf, false)?;
+        let (size, size_len) = read_vint(&self.buf[id_len..], true)?;
+        let start = id_len + size_len;
+        let end = match size {
+            Some(size) => start.checked_add(size as usize)?.min(self.buf.len()),
+            None => self.buf.len(),
+        };
+        if start > end {
+            return None;
+        }
+        let data = &self.buf[start..end];
+        self.buf = &self.buf[end..];
+        Some(Element {
+            id: id.unwrap_or_default(),
+            data,
+        })
+    }
+}
+
+fn elements(buf: &[u8]) -> Elements<'_> {
+    Elements { buf }
+}
+
+/// Read an EBML variable length integer, returning its value and length.
+///
+/// Ids keep their marker bit; sizes drop it and map all-ones to `None` (unknown).
+fn read_vint(buf: &[u8], is_size: bool) -> Option<(Option<u64>, usize)> {
+    let first = *buf.first()?;
+    if first == 0 {
+        return None;
+    }
+    let len = first.leading_zeros() as usize + 1;
+    if buf.len() < len {
+        return None;
+    }
+    let mut value = if is_size {
+        first as u64 & (0xFFu64 >> len)
+    } else {
+        first as u64
+    };
+    for b in &buf[1..len] {
+        value = (value << 8) | *b as u64;
+    }
+    if is_size && value == (1u64 << (7 * len)) - 1 {
+        return Some((None, len));
+    }
+    Some((Some(value), len))
+}
+
+fn read_uint(data: &[u8]) -> u64 {
+    data.iter().take(8).fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+fn read_float(data: &[u8]) -> f64 {
+    match data.len() {
+        4 => f32::from_be_bytes([data[0], data[1], data[2], data[3]]) as f64,
+        8 => f64::from_be_bytes(data.try_into().unwrap_or_default()),
+        _ => 0.0,
+    }
+}
+
+impl WebmReader {
+    pub fn open(path: &Path) -> ResultType<Self> {
+        let mut buf = Vec::new();
+        File::open(path)?.read_to_end(&mut buf)?;
+        Self::parse(&buf)
+    }
+
+    pub fn parse(buf: &[u8]) -> ResultType<Self> {
+        let mut top = elements(buf);
+        match top.next() {
+            Some(e) if e.id == EBML_HEADER => {}
+            _ => bail!("missing EBML header"),
+        }
+        let Some(segment) = top.find(|e| e.id == SEGMENT) else {
+            bail!("missing segment");
+        };
+
+        let mut timecode_scale = 1_000_000u64;
+        let mut duration = None;
+        let mut track = None;
+        let mut packets = Vec::new();
+        for element in elements(segment.data) {
+            match element.id {
+                INFO => {
+                    for e in elements(element.data) {
+                        match e.id {
+                            TIMECODE_SCALE => timecode_scale = read_uint(e.data).max(1),
+                            DURATION => duration = Some(read_float(e.data)),
+                            _ => {}
+                        }
+                    }
+                }
+                TRACKS => {
+                    track = elements(element.data)
+                        .filter(|e| e.id == TRACK_ENTRY)
+                        .map(|e| parse_track(e.data))
+                        .find(|t| t.video);
+                }
+                CLUSTER => parse_cluster(element.data, &mut packets),
+                _ => {}
+            }
+        }
+
+        let Some(track) = track else {
+            bail!("no video track");
+        };
+        packets.retain(|p: &(u64, VideoPacket)| p.0 == track.number);
+        let mut packets: Vec<VideoPacket> = packets.into_iter().map(|(_, p)| p).collect();
+        // Timecodes are in units of timecode_scale ns; the recorder writes ms
+        for p in packets.iter_mut() {
+            p.pts = (p.pts as i128 * timecode_scale as i128 / 1_000_000) as i64;
+        }
+        let duration_ms = match duration {
+            Some(d) => (d * timecode_scale as f64 / 1_000_000.0) as u64,
+            None => packets
+                .iter()
+                .map(|p| p.pts)
+                .max()
+                .zip(packets.iter().map(|p| p.pts).min())
+                .map(|(max, min)| (max - min) as u64)
+                .unwrap_or_default(),
+        };
+        Ok(Self {
+            info: RecordingInfo {
+                format: track.format,
+                width: track.width,
+                height: track.height,
+                duration_ms,
+                frames: packets.len(),
+                key_frames: packets.iter().filter(|p| p.key).count(),
+            },
+            codec_private: track.codec_private,
+            packets,
+        })
+    }
+
+    /// Packets needed to play `[start_ms, end_ms)`, starting at the key frame at or before `start_ms`.
+    pub fn range(&self, start_ms: i64, end_ms: i64) -> &[VideoPacket] {
+        let first = self.packets.first().map(|p| p.pts).unwrap_or_default();
+        let (start, end) = (first + start_ms, first + end_ms);
+        let from = self
+            .packets
+            .iter()
+            .rposition(|p| p.key && p.pts <= start)
+            .or_else(|| self.packets.iter().position(|p| p.key))
+            .unwrap_or(self.packets.len());
+        let to = self.packets[from..]
+            .iter()
+            .position(|p| p.pts >= end)
+            .map_or(self.packets.len(), |n| from + n);
+        &self.packets[from..to]
+    }
+
+    /// Write `[start_ms, end_ms)` (relative to the first frame) to a new WebM file.
+    ///
+    /// The cut starts at the preceding key frame so it plays from its first frame.
+    pub fn cut(&self, output: &Path, start_ms: i64, end_ms: i64) -> ResultType<usize> {
+        let packets = self.range(start_ms, end_ms);
+        let Some(base) = packets.first().map(|p| p.pts) else {
+            bail!("no frames in range");
+        };
+        let codec = match self.info.format {
+            CodecFormat::VP8 => mux::VideoCodecId::VP8,
+            CodecFormat::VP9 => mux::VideoCodecId::VP9,
+            CodecFormat::AV1 => mux::VideoCodecId::AV1,
+            _ => bail!("unsupported codec {:?}", self.info.format),
+        };
+        let Some(mut webm) = Segment::new(mux::Writer::new(File::create(output)?)) else {
+            bail!("Failed to create webm mux");
+        };
+        let mut vt = webm.add_video_track(self.info.width as _, self.info.height as _, None, codec);
+        if self.info.format == CodecFormat::AV1 {
+            let codec_private = self.codec_private.clone().unwrap_or(vec![0, 0, 0, 0]);
+            if !webm.set_codec_private(vt.track_number(), &codec_private) {
+                bail!("Failed to set codec private");
+            }
+        }
+        for p in packets {
+            if !vt.add_frame(&p.data, (p.pts - base) as u64 * 1_000_000, p.key) {
+                bail!("Failed to write frame at {} ms", p.pts);
+            }
+        }
+        if !webm.finalize(None) {
+            bail!("Failed to finalize {}", output.display());
+        }
+        Ok(packets.len())
+    }
+}
+
+struct TrackEntry {
+    number: u64,
+    video: bool,
+    format: CodecFormat,
+    width: usize,
+    height: usize,
+    codec_private: Option<Vec<u8>>,
+}
+
+fn parse_track(data: &[u8]) -> TrackEntry {
+    let mut track = TrackEntry {
+        number: 0,
+        video: false,
+        format: CodecFormat::Unknown,
+        width: 0,
+        height: 0,
+        codec_private: None,
+    };
+    for e in elements(data) {
+        match e.id {
+            TRACK_NUMBER => track.number = read_uint(e.data),
+            CODEC_ID => {
+                track.format = match e.data {
+                    b"V_VP8" => CodecFormat::VP8,
+                    b"V_VP9" => CodecFormat::VP9,
+                    b"V_AV1" => CodecFormat::AV1,
+                    _ => CodecFormat::Unknown,
+                }
+            }
+            CODEC_PRIVATE => track.codec_private = Some(e.data.to_vec()),
+            VIDEO => {
+                track.video = true;
+                for v in elements(e.data) {
+                    match v.id {
+                        PIXEL_WIDTH => track.width = read_uint(v.data) as usize,
+                        PIXEL_HEIGHT => track.height = read_uint(v.data) as usize,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    track
+}
+
+fn parse_cluster(data: &[u8], packets: &mut Vec<(u64, VideoPacket)>) {
+    let mut timecode = 0i64;
+    for e in elements(data) {
+        match e.id {
+            CLUSTER_TIMECODE => timecode = read_uint(e.data) as i64,
+            SIMPLE_BLOCK => {
+                if let Some((track, relative, flags, frame)) = parse_block(e.data) {
+                    packets.push((
+                        track,
+                        VideoPacket {
+                            pts: timecode + relative as i64,
+                            key: flags & 0x80 != 0,
+                            data: frame.to_vec(),
+                        },
+                    ));
+                }
+            }
+            BLOCK_GROUP => {
+                let mut block = None;
+                let mut referenced = false;
+                for g in elements(e.data) {
+                    match g.id {
+                        BLOCK => block = parse_block(g.data),
+                        REFERENCE_BLOCK => referenced = true,
+                        _ => {}
+                    }
+                }
+                if let Some((track, relative, _, frame)) = block {
+                    packets.push((
+                        track,
+                        VideoPacket {
+                            pts: timecode + relative as i64,
+                            key: !referenced,
+                            data: frame.to_vec(),
+                        },
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Split a (Simple)Block into track number, relative timecode, flags and payload.
+///
+/// Lacing is never used by the recorder, so laced blocks are skipped.
+fn parse_block(data: &[u8]) -> Option<(u64, i16, u8, &[u8])> {
+    let (track, len) = read_vint(data, true)?;
+    let rest = data.get(len..)?;
+    if rest.len() < 3 {
+        return None;
+    }
+    let relative = i16::from_be_bytes([rest[0], rest[1]]);
+    let flags = rest[2];
+    if flags & 0x06 != 0 {
+        return None;
+    }
+    Some((track?, relative, flags, &rest[3..]))
+}
+
+/// Metadata from an MP4's `moov` box: duration, first video track and sample counts.
+fn probe_mp4(buf: &[u8]) -> ResultType<RecordingInfo> {
+    let Some(moov) = mp4_boxes(buf).find(|(t, _)| t == b"moov").map(|(_, d)| d) else {
+        bail!("missing moov box, the recording was not finalized");
+    };
+    let mut info = RecordingInfo {
+        format: CodecFormat::Unknown,
+        width: 0,
+        height: 0,
+        duration_ms: 0,
+        frames: 0,
+        key_frames: 0,
+    };
+    for (kind, data) in mp4_boxes(moov) {
+        match &kind {
+            b"mvhd" => info.duration_ms = mvhd_duration_ms(data).unwrap_or_default(),
+            b"trak" if info.format == CodecFormat::Unknown => {
+                let Some(stbl) = mp4_path(data, &[b"mdia", b"minf", b"stbl"]) else {
+                    continue;
+                };
+                for (kind, data) in mp4_boxes(stbl) {
+                    match &kind {
+                        // full box header (4) + entry count (4), then sample entries
+                        b"stsd" => {
+                            if let Some((entry, sample)) = data.get(8..).and_then(|d| mp4_boxes(d).next()) {
+                                info.format = match &entry {
+                                    b"avc1" | b"avc3" => CodecFormat::H264,
+                                    b"hvc1" | b"hev1" => CodecFormat::H265,
+                                    _ => CodecFormat::Unknown,
+                                };
+                                // reserved (6) + data ref (2) + predefined/reserved (16), then width, height
+                                if sample.len() >= 28 {
+                                    info.width = u16::from_be_bytes([sample[24], sample[25]]) as usize;
+                                    info.height = u16::from_be_bytes([sample[26], sample[27]]) as usize;
+                                }
+                            }
+                        }
+                        // full box header (4) + sample size (4) + sample count (4)
+                        b"stsz" if data.len() >= 12 => info.frames = read_uint(&data[8..12]) as usize,
+                        b"stss" if data.len() >= 8 => info.key_frames = read_uint(&data[4..8]) as usize,
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    if info.format == CodecFormat::Unknown {
+        bail!("no H264/H265 video track");
+    }
+    Ok(info)
+}
+
+fn mvhd_duration_ms(data: &[u8]) -> Option<u64> {
+    let (timescale, duration) = match *data.first()? {
+        1 => (read_uint(data.get(20..24)?), read_uint(data.get(24..32)?)),
+        _ => (read_uint(data.get(12..16)?), read_uint(data.get(16..20)?)),
+    };
+    (timescale > 0).then(|| duration * 1000 / timescale)
+}
+
+fn mp4_path<'a>(mut data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    for kind in path {
+        data = mp4_boxes(data).find(|(t, _)| t == *kind)?.1;
+    }
+    Some(data)
+}
+
+fn mp4_boxes(mut buf: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        if buf.len() < 8 {
+            return None;
+        }
+        let mut size = read_uint(&buf[0..4]) as usize;
+        let kind = [buf[4], buf[5], buf[6], buf[7]];
+        let mut header = 8;
+        if size == 1 {
+            size = read_uint(buf.get(8..16)?) as usize;
+            header = 16;
+        } else if size == 0 {
+            size = buf.len();
+        }
+        if size < header || size > buf.len() {
+            return None;
+        }
+        let data = &buf[header..size];
+        buf = &buf[size..];
+        Some((kind, data))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_recording_filename() {
+        let file = RecordingFile::parse(Path::new(
+            "/tmp/incoming_my_id_20240102030405678_display1_vp9.webm",
+        ))
+        .unwrap();
+        assert!(file.server);
+        assert_eq!(file.id, "my_id");
+        assert_eq!(file.started, "20240102030405678");
+        assert!(!file.camera);
+        assert_eq!(file.display_idx, 1);
+        assert_eq!(file.format, CodecFormat::VP9);
+
+        let file =
+            RecordingFile::parse(Path::new("outgoing_123456789_20240102030405678_camera0_h265.mp4"))
+                .unwrap();
+        assert!(!file.server && file.camera);
+        assert_eq!(file.format, CodecFormat::H265);
+
+        assert!(RecordingFile::parse(Path::new("incoming_1_2024_display0_vp9.webm")).is_none());
+        assert!(RecordingFile::parse(Path::new("notes_1_20240102030405678_display0_vp9.webm")).is_none());
+    }
+
+    fn element(id: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut out = id.to_vec();
+        out.push(0x80 | payload.len() as u8);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    fn simple_block(relative: i16, key: bool, data: &[u8]) -> Vec<u8> {
+        let mut payload = vec![0x81];
+        payload.extend_from_slice(&relative.to_be_bytes());
+        payload.push(if key { 0x80 } else { 0 });
+        payload.extend_from_slice(data);
+        element(&[0xA3], &payload)
+    }
+
+    #[test]
+    fn test_demux_webm_and_select_range() {
+        let video = [element(&[0xB0], &[0x07, 0x80]), element(&[0xBA], &[0x04, 0x38])].concat();
+        let track = [
+            element(&[0xD7], &[1]),
+            element(&[0x86], b"V_VP9"),
+            element(&[0xE0], &video),
+        ]
+        .concat();
+        let tracks = element(&[0x16, 0x54, 0xAE, 0x6B], &element(&[0xAE], &track));
+        let cluster = [
+            element(&[0xE7], &[0x03, 0xE8]),
+            simple_block(0, true, b"k0"),
+            simple_block(40, false, b"d1"),
+            simple_block(80, true, b"k2"),
+            simple_block(120, false, b"d3"),
+        ]
+        .concat();
+        // Unknown-size segment, as left by a recorder that was killed
+        let mut segment = vec![0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+        segment.extend(tracks);
+        segment.extend(element(&[0x1F, 0x43, 0xB6, 0x75], &cluster));
+        let file = [element(&[0x1A, 0x45, 0xDF, 0xA3], &[]), segment].concat();
+
+        let reader = WebmReader::parse(&file).unwrap();
+        assert_eq!(reader.info.format, CodecFormat::VP9);
+        assert_eq!((reader.info.width, reader.info.height), (1920, 1080));
+        assert_eq!((reader.info.frames, reader.info.key_frames), (4, 2));
+        assert_eq!(reader.info.duration_ms, 120);
+        assert_eq!(reader.packets[1].pts, 1040);
+
+        let range: Vec<_> = reader.range(100, 130).iter().map(|p| p.data.clone()).collect();
+        assert_eq!(range, vec![b"k2".to_vec(), b"d3".to_vec()]);
+        assert_eq!(reader.range(0, 40).len(), 1);
+    }
+}
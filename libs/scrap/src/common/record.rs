@@ -1,16 +1,17 @@
-use crate::CodecFormat;
+use crate::{seal::SealedWriter, CodecFormat};
 #[cfg(feature = "hwcodec")]
 use hbb_common::anyhow::anyhow;
 use hbb_common::{
     bail, chrono, log,
     message_proto::message::{video_frame, message::Union, EncodedVideoFrame, Message},
+    sodiumoxide::crypto::secretbox::Key,
     ResultType,
 };
 #[cfg(feature = "hwcodec")]
 use hwcodec::mux::{MuxContext, Muxer};
 use std::{
     fs::{File, OpenOptions},
-    io,
+    io::{self, prelude::*, SeekFrom},
     ops::{Deref, DerefMut},
     path::PathBuf,
     sync::mpsc::Sender,
@@ -28,6 +29,8 @@ pub struct RecorderContext {
     pub display_idx: usize,
     pub camera: bool,
     pub tx: Option<Sender<RecordState>>,
+    /// Encrypt the files with this key, see [`crate::seal`]
+    pub key: Option<Key>,
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +64,7 @@ impl RecorderContext2 {
             } else {
                 ".mp4"
             };
+        let file = if ctx.key.is_some() { file + ".enc" } else { file };
         self.filename = PathBuf::from(&ctx.dir)
             .join(file)
             .to_string_lossy()
@@ -156,6 +160,10 @@ impl Recorder {
                     WebmRecorder::new(self.ctx.clone(), (*ctx2).clone())?,
                 )),
                 #[cfg(feature = "hwcodec")]
+                _ if self.ctx.key.is_some() => {
+                    bail!("only VP8, VP9 and AV1 recordings can be encrypted")
+                }
+                #[cfg(feature = "hwcodec")]
                 _ => Some(Box::new(HwRecorder::new(
                     self.ctx.clone(),
                     (*ctx2).clone(),
@@ -270,9 +278,62 @@ impl Recorder {
     }
 }
 
+/// A recording file, sealed as it is written if a key is given.
+pub enum RecordFile {
+    Plain(File),
+    Sealed(SealedWriter<File>),
+}
+
+impl RecordFile {
+    pub fn create(path: &str, key: Option<Key>) -> io::Result<Self> {
+        let file = match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(path)?,
+            Err(e) => return Err(e),
+        };
+        Ok(match key {
+            Some(key) => Self::Sealed(SealedWriter::new(file, key)?),
+            None => Self::Plain(file),
+        })
+    }
+
+    /// Write everything out, marking a sealed file complete.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Sealed(writer) => writer.finish(),
+        }
+    }
+}
+
+impl Write for RecordFile {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(file) => file.write(data),
+            Self::Sealed(writer) => writer.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(file) => file.flush(),
+            Self::Sealed(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Seek for RecordFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::Plain(file) => file.seek(pos),
+            Self::Sealed(writer) => writer.seek(pos),
+        }
+    }
+}
+
 struct WebmRecorder {
     vt: VideoTrack,
-    webm: Option<Segment<Writer<File>>>,
+    webm: Option<Segment<Writer<RecordFile>>>,
     ctx: RecorderContext,
     ctx2: RecorderContext2,
     key: bool,
@@ -282,16 +343,7 @@ struct WebmRecorder {
 
 impl RecorderApi for WebmRecorder {
     fn new(ctx: RecorderContext, ctx2: RecorderContext2) -> ResultType<Self> {
-        let out = match {
-            OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(&ctx2.filename)
-        } {
-            Ok(file) => file,
-            Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => File::create(&ctx2.filename)?,
-            Err(e) => return Err(e.into()),
-        };
+        let out = RecordFile::create(&ctx2.filename, ctx.key.clone())?;
         let mut webm = match mux::Segment::new(mux::Writer::new(out)) {
             Some(v) => v,
             None => bail!("Failed to create webm mux"),
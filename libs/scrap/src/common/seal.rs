@@ -0,0 +1,246 @@
+//! Recordings encrypted at rest.
+//!
+//! A sealed file is [`MAGIC`], a random salt and chunks of
+//! `len: u32 LE, secretbox(offset: u64 LE, data)`, each holding the plain
+//! bytes at `offset`. Later chunks overwrite earlier ones, which keeps the
+//! seeks of the muxer back to the header. The nonce of chunk `n` is the salt
+//! followed by `n` as u64 LE, so chunks can't be moved, reordered or dropped
+//! without failing authentication. A complete file ends with an empty chunk at
+//! offset `u64::MAX`.
+//!
+//! The file only grows by whole sealed chunks, nothing plain is ever written.
+
+use hbb_common::{
+    bail,
+    sodiumoxide::{
+        crypto::secretbox::{self, Key, Nonce, NONCEBYTES},
+        randombytes,
+    },
+    ResultType,
+};
+use std::io::{self, prelude::*, SeekFrom};
+
+pub const MAGIC: &[u8; 8] = b"RDREC\x00\x00\x02";
+const SALT_LEN: usize = NONCEBYTES - 8;
+const END_OFFSET: u64 = u64::MAX;
+// Writes are gathered into chunks of up to this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Seals everything written to `inner`, see the module docs.
+///
+/// Seeks only move the position of the next chunk, `inner` is only appended
+/// to. Dropping it seals what is left and marks the file complete.
+pub struct SealedWriter<W: Write> {
+    inner: W,
+    key: Key,
+    salt: [u8; SALT_LEN],
+    seq: u64,
+    /// Position of the next write
+    pos: u64,
+    /// Length of the plain file
+    end: u64,
+    /// Data at `buf_start` not sealed yet
+    buf_start: u64,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> SealedWriter<W> {
+    pub fn new(mut inner: W, key: Key) -> io::Result<Self> {
+        let mut salt = [0u8; SALT_LEN];
+        randombytes::randombytes_into(&mut salt);
+        inner.write_all(MAGIC)?;
+        inner.write_all(&salt)?;
+        Ok(Self {
+            inner,
+            key,
+            salt,
+            seq: 0,
+            pos: 0,
+            end: 0,
+            buf_start: 0,
+            buf: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Seal what is left and mark the file complete.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.seal_buffered()?;
+        self.seal(END_OFFSET, &[])?;
+        self.finished = true;
+        self.inner.flush()
+    }
+
+    fn seal_buffered(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::take(&mut self.buf);
+        self.seal(self.buf_start, &buf)
+    }
+
+    fn seal(&mut self, offset: u64, data: &[u8]) -> io::Result<()> {
+        let mut plain = Vec::with_capacity(8 + data.len());
+        plain.extend_from_slice(&offset.to_le_bytes());
+        plain.extend_from_slice(data);
+        let sealed = secretbox::seal(&plain, &nonce(&self.salt, self.seq), &self.key);
+        self.seq += 1;
+        let mut chunk = Vec::with_capacity(4 + sealed.len());
+        chunk.extend_from_slice(&(sealed.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(&sealed);
+        self.inner.write_all(&chunk)
+    }
+}
+
+impl<W: Write> Write for SealedWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::new(io::ErrorKind::Other, "sealed file finished"));
+        }
+        if self.buf.is_empty() {
+            self.buf_start = self.pos;
+        }
+        self.buf.extend_from_slice(data);
+        self.pos += data.len() as u64;
+        self.end = self.end.max(self.pos);
+        if self.buf.len() >= CHUNK_SIZE {
+            self.seal_buffered()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal_buffered()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Seek for SealedWriter<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.end.checked_add_signed(delta),
+        };
+        let Some(pos) = pos else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid seek"));
+        };
+        if pos != self.pos {
+            self.seal_buffered()?;
+            self.pos = pos;
+        }
+        Ok(pos)
+    }
+}
+
+impl<W: Write> Drop for SealedWriter<W> {
+    fn drop(&mut self) {
+        self.finish().ok();
+    }
+}
+
+fn nonce(salt: &[u8; SALT_LEN], seq: u64) -> Nonce {
+    let mut nonce = [0u8; NONCEBYTES];
+    nonce[..SALT_LEN].copy_from_slice(salt);
+    nonce[SALT_LEN..].copy_from_slice(&seq.to_le_bytes());
+    Nonce(nonce)
+}
+
+/// The plain file of a sealed one, and whether it is complete.
+pub fn unseal(data: &[u8], key: &Key) -> ResultType<(Vec<u8>, bool)> {
+    if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + SALT_LEN {
+        bail!("not an encrypted recording");
+    }
+    let salt: [u8; SALT_LEN] = data[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into()?;
+    let mut rest = &data[MAGIC.len() + SALT_LEN..];
+    let mut plain = Vec::new();
+    let mut seq = 0u64;
+    let mut complete = false;
+    while !rest.is_empty() {
+        if complete {
+            bail!("data after the end of the recording");
+        }
+        if rest.len() < 4 {
+            bail!("truncated chunk {}", seq);
+        }
+        let len = u32::from_le_bytes(rest[..4].try_into()?) as usize;
+        rest = &rest[4..];
+        if rest.len() < len {
+            bail!("truncated chunk {}", seq);
+        }
+        let Ok(chunk) = secretbox::open(&rest[..len], &nonce(&salt, seq), key) else {
+            bail!("chunk {} failed authentication", seq);
+        };
+        if chunk.len() < 8 {
+            bail!("chunk {} too short", seq);
+        }
+        rest = &rest[len..];
+        seq += 1;
+        let offset = u64::from_le_bytes(chunk[..8].try_into()?);
+        let data = &chunk[8..];
+        if offset == END_OFFSET {
+            complete = true;
+            continue;
+        }
+        let start = usize::try_from(offset)?;
+        let end = start + data.len();
+        if plain.len() < end {
+            plain.resize(end, 0);
+        }
+        plain[start..end].copy_from_slice(data);
+    }
+    Ok((plain, complete))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seal_with_rewritten_header() {
+        let key = secretbox::gen_key();
+        let mut sealed = Vec::new();
+        {
+            let mut writer = SealedWriter::new(&mut sealed, key.clone()).unwrap();
+            writer.write_all(b"HEADER--frame1").unwrap();
+            writer.flush().unwrap();
+            writer.write_all(b"frame2").unwrap();
+            // finalize rewrites the head of the file
+            assert_eq!(writer.seek(SeekFrom::Current(0)).unwrap(), 20);
+            writer.seek(SeekFrom::Start(0)).unwrap();
+            writer.write_all(b"header").unwrap();
+            assert_eq!(writer.seek(SeekFrom::End(0)).unwrap(), 20);
+        }
+        assert!(!sealed.windows(6).any(|w| w == b"frame1"));
+        assert_eq!(
+            unseal(&sealed, &key).unwrap(),
+            (b"header--frame1frame2".to_vec(), true)
+        );
+        assert!(unseal(&sealed, &secretbox::gen_key()).is_err());
+
+        // The chunks of the first flush, the second write and the rewrite
+        let chunks = {
+            let mut chunks = Vec::new();
+            let mut rest = &sealed[MAGIC.len() + SALT_LEN..];
+            while !rest.is_empty() {
+                let len = 4 + u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+                chunks.push(&rest[..len]);
+                rest = &rest[len..];
+            }
+            chunks
+        };
+        assert_eq!(chunks.len(), 4);
+        let head = &sealed[..MAGIC.len() + SALT_LEN];
+        // Replaying the stale header after the rewrite
+        let reordered = [head, chunks[0], chunks[1], chunks[2], chunks[0]].concat();
+        assert!(unseal(&reordered, &key).is_err());
+        let swapped = [head, chunks[0], chunks[2], chunks[1], chunks[3]].concat();
+        assert!(unseal(&swapped, &key).is_err());
+        let cut = [head, chunks[0], chunks[1]].concat();
+        assert_eq!(unseal(&cut, &key).unwrap(), (b"HEADER--frame1frame2".to_vec(), false));
+    }
+}
@@ -0,0 +1,394 @@
+//! Synthetic capturer, deterministic frames without a display or a GPU.
+//!
+//! Meant for headless CI and load testing. The source is described by a spec:
+//!
+//! - `pattern[:WxH]`: color bars with a box moving one step per frame.
+//! - `scenes[:WxH]:<scene>*<frames>,...`: scenes shown for a number of frames
+//!   each, in a loop. A scene is `pattern` or a `RRGGBB` color; a color held
+//!   for several frames is a static screen.
+//! - `replay:<path>`: the frames of a `.y4m` file (4:2:0 only) or of the
+//!   `.png` files of a directory sorted by name, in a loop. All frames must
+//!   have the size of the first one.
+//!
+//! The size defaults to 1280x720. Frame `n` only depends on the spec and `n`,
+//! `n` counting the calls to [`TraitCapturer::frame`].
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "vram")]
+use crate::AdapterDevice;
+use crate::{
+    common::{bail, ResultType},
+    Frame, PixelBuffer, Pixfmt, TraitCapturer,
+};
+
+const DEFAULT_SIZE: (usize, usize) = (1280, 720);
+const BOX_STEP: usize = 8;
+// White, yellow, cyan, green, magenta, red, blue, black
+const BARS: [u32; 8] = [
+    0xffffff, 0xffff00, 0x00ffff, 0x00ff00, 0xff00ff, 0xff0000, 0x0000ff, 0x000000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scene {
+    Pattern,
+    /// `0xRRGGBB`
+    Color(u32),
+}
+
+enum Source {
+    /// Scenes and the number of frames they last
+    Scenes(Vec<(Scene, usize)>),
+    /// The file content and the offsets of the I420 frames
+    Y4m(Vec<u8>, Vec<usize>),
+    Png(Vec<PathBuf>),
+}
+
+impl Source {
+    fn len(&self) -> usize {
+        match self {
+            Source::Scenes(scenes) => scenes.iter().map(|(_, frames)| frames).sum(),
+            Source::Y4m(_, frames) => frames.len(),
+            Source::Png(files) => files.len(),
+        }
+    }
+}
+
+pub struct SyntheticCapturer {
+    width: usize,
+    height: usize,
+    source: Source,
+    index: usize,
+    pixfmt: Pixfmt,
+    data: Vec<u8>,
+    last_data: Vec<u8>, // for faster compare and copy
+}
+
+impl SyntheticCapturer {
+    pub fn new(spec: &str) -> ResultType<Self> {
+        let (width, height, source) = match spec.split_once(':') {
+            Some(("replay", path)) => replay(Path::new(path))?,
+            _ => {
+                let (width, height, scenes) = parse_scenes(spec)?;
+                (width, height, Source::Scenes(scenes))
+            }
+        };
+        if width == 0 || height == 0 || source.len() == 0 {
+            bail!("Empty synthetic capture source: {}", spec);
+        }
+        let pixfmt = match source {
+            Source::Png(_) => Pixfmt::RGBA,
+            _ => Pixfmt::BGRA,
+        };
+        Ok(Self {
+            width,
+            height,
+            source,
+            index: 0,
+            pixfmt,
+            data: Vec::new(),
+            last_data: Vec::new(),
+        })
+    }
+
+    /// The frame size of `spec`, without loading a replay.
+    pub fn size(spec: &str) -> ResultType<(usize, usize)> {
+        match spec.split_once(':') {
+            Some(("replay", path)) => {
+                let path = Path::new(path);
+                if path.is_dir() {
+                    let files = png_files(path)?;
+                    let (w, h) = image::image_dimensions(&files[0])?;
+                    Ok((w as _, h as _))
+                } else {
+                    let mut header = Vec::new();
+                    BufReader::new(File::open(path)?).read_until(b'\n', &mut header)?;
+                    let (w, h, _) = y4m_header(&header)?;
+                    Ok((w, h))
+                }
+            }
+            _ => parse_scenes(spec).map(|(w, h, _)| (w, h)),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn render(&mut self, index: usize) -> ResultType<()> {
+        let (width, height) = (self.width, self.height);
+        match &self.source {
+            Source::Scenes(scenes) => {
+                let mut n = index;
+                for (scene, frames) in scenes {
+                    if n < *frames {
+                        render_scene(&mut self.data, width, height, *scene, index);
+                        break;
+                    }
+                    n -= frames;
+                }
+            }
+            Source::Y4m(data, frames) => {
+                let (y, uv) = (width * height, ((width + 1) / 2) * ((height + 1) / 2));
+                let frame = &data[frames[index]..frames[index] + y + 2 * uv];
+                self.data.resize(width * height * 4, 0);
+                let half = ((width + 1) / 2) as _;
+                unsafe {
+                    crate::I420ToARGB(
+                        frame.as_ptr(),
+                        width as _,
+                        frame[y..].as_ptr(),
+                        half,
+                        frame[y + uv..].as_ptr(),
+                        half,
+                        self.data.as_mut_ptr(),
+                        (width * 4) as _,
+                        width as _,
+                        height as _,
+                    );
+                }
+            }
+            Source::Png(files) => {
+                let image = image::open(&files[index])?.to_rgba8();
+                if image.width() as usize != width || image.height() as usize != height {
+                    bail!(
+                        "{} is {}x{}, expected {}x{}",
+                        files[index].display(),
+                        image.width(),
+                        image.height(),
+                        width,
+                        height
+                    );
+                }
+                self.data = image.into_raw();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TraitCapturer for SyntheticCapturer {
+    fn frame<'a>(&'a mut self, _timeout: std::time::Duration) -> io::Result<Frame<'a>> {
+        let index = self.index % self.source.len();
+        self.index += 1;
+        self.render(index)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        crate::would_block_if_equal(&mut self.last_data, &self.data)?;
+        Ok(Frame::PixelBuffer(PixelBuffer::new(
+            &self.data,
+            self.pixfmt,
+            self.width,
+            self.height,
+        )))
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        false
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "vram")]
+    fn device(&self) -> AdapterDevice {
+        AdapterDevice::default()
+    }
+
+    #[cfg(feature = "vram")]
+    fn set_output_texture(&mut self, _texture: bool) {}
+}
+
+fn parse_size(s: &str) -> Option<(usize, usize)> {
+    let (w, h) = s.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+/// `pattern[:WxH]` or `scenes[:WxH]:<scene>*<frames>,...`
+fn parse_scenes(spec: &str) -> ResultType<(usize, usize, Vec<(Scene, usize)>)> {
+    let mut parts = spec.split(':');
+    let kind = parts.next().unwrap_or_default();
+    let mut rest: Vec<&str> = parts.collect();
+    let (width, height) = match rest.first().and_then(|s| parse_size(s)) {
+        Some(size) => {
+            rest.remove(0);
+            size
+        }
+        None => DEFAULT_SIZE,
+    };
+    let scenes = match (kind, &rest[..]) {
+        ("pattern", []) => vec![(Scene::Pattern, 1)],
+        ("scenes", [scenes]) => {
+            let mut v = Vec::new();
+            for s in scenes.split(',') {
+                let Some((scene, frames)) = s.trim().split_once('*') else {
+                    bail!("Missing frames of synthetic scene {}", s);
+                };
+                let scene = if scene == "pattern" {
+                    Scene::Pattern
+                } else if scene.len() == 6 {
+                    Scene::Color(u32::from_str_radix(scene, 16)?)
+                } else {
+                    bail!("Invalid synthetic scene {}", scene);
+                };
+                v.push((scene, frames.parse()?));
+            }
+            v
+        }
+        _ => bail!("Invalid synthetic capture spec: {}", spec),
+    };
+    Ok((width, height, scenes))
+}
+
+fn render_scene(data: &mut Vec<u8>, width: usize, height: usize, scene: Scene, index: usize) {
+    data.resize(width * height * 4, 0);
+    let bgra = |rgb: u32| [rgb as u8, (rgb >> 8) as u8, (rgb >> 16) as u8, 0xff];
+    match scene {
+        Scene::Color(rgb) => {
+            let pixel = bgra(rgb);
+            for p in data.chunks_exact_mut(4) {
+                p.copy_from_slice(&pixel);
+            }
+        }
+        Scene::Pattern => {
+            let side = (height / 8).max(1).min(width);
+            let x0 = (index * BOX_STEP) % (width - side + 1);
+            let y0 = (height - side) / 2;
+            for (y, row) in data.chunks_exact_mut(width * 4).enumerate() {
+                for (x, p) in row.chunks_exact_mut(4).enumerate() {
+                    let in_box = (x0..x0 + side).contains(&x) && (y0..y0 + side).contains(&y);
+                    let rgb = if in_box {
+                        0x808080
+                    } else {
+                        BARS[x * BARS.len() / width]
+                    };
+                    p.copy_from_slice(&bgra(rgb));
+                }
+            }
+        }
+    }
+}
+
+fn replay(path: &Path) -> ResultType<(usize, usize, Source)> {
+    if path.is_dir() {
+        let files = png_files(path)?;
+        let (w, h) = image::image_dimensions(&files[0])?;
+        return Ok((w as _, h as _, Source::Png(files)));
+    }
+    let data = std::fs::read(path)?;
+    let (width, height, mut offset) = y4m_header(&data)?;
+    let frame_len = width * height + 2 * ((width + 1) / 2) * ((height + 1) / 2);
+    let mut frames = Vec::new();
+    while offset < data.len() {
+        if !data[offset..].starts_with(b"FRAME") {
+            bail!("Invalid y4m frame at {}", offset);
+        }
+        let Some(end) = data[offset..].iter().position(|b| *b == b'\n') else {
+            bail!("Truncated y4m frame at {}", offset);
+        };
+        offset += end + 1;
+        if offset + frame_len > data.len() {
+            bail!("Truncated y4m frame at {}", offset);
+        }
+        frames.push(offset);
+        offset += frame_len;
+    }
+    Ok((width, height, Source::Y4m(data, frames)))
+}
+
+fn png_files(dir: &Path) -> ResultType<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| {
+            p.extension()
+                .map_or(false, |ext| ext.eq_ignore_ascii_case("png"))
+        })
+        .collect();
+    if files.is_empty() {
+        bail!("No png files in {}", dir.display());
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Width, height and length of the stream header.
+fn y4m_header(data: &[u8]) -> ResultType<(usize, usize, usize)> {
+    let Some(end) = data.iter().position(|b| *b == b'\n') else {
+        bail!("Missing y4m header");
+    };
+    let header = std::str::from_utf8(&data[..end])?;
+    let mut params = header.split(' ');
+    if params.next() != Some("YUV4MPEG2") {
+        bail!("Not a y4m file");
+    }
+    let (mut width, mut height) = (0, 0);
+    for param in params {
+        match param.split_at(param.len().min(1)) {
+            ("W", w) => width = w.parse()?,
+            ("H", h) => height = h.parse()?,
+            ("C", c) if !c.starts_with("420") => bail!("Unsupported y4m colorspace {}", c),
+            _ => {}
+        }
+    }
+    Ok((width, height, end + 1))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn next(c: &mut SyntheticCapturer) -> Option<Vec<u8>> {
+        match c.frame(Duration::ZERO) {
+            Ok(Frame::PixelBuffer(p)) => Some(crate::TraitPixelBuffer::data(&p).to_vec()),
+            Ok(_) => panic!("not a pixel buffer"),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn test_synthetic_frames() {
+        let mut c = SyntheticCapturer::new("scenes:64x32:ff0000*2,pattern*2").unwrap();
+        assert_eq!((c.width(), c.height()), (64, 32));
+        assert_eq!(&next(&mut c).unwrap()[..4], &[0, 0, 0xff, 0xff]);
+        // A static scene doesn't change
+        assert!(next(&mut c).is_none());
+        let a = next(&mut c).unwrap();
+        let b = next(&mut c).unwrap();
+        assert_ne!(a, b);
+        // Looping, the frames only depend on their index
+        assert!(next(&mut c).is_some());
+        assert!(next(&mut c).is_none());
+        assert_eq!(next(&mut c).unwrap(), a);
+
+        assert_eq!(SyntheticCapturer::size("pattern").unwrap(), DEFAULT_SIZE);
+        assert!(SyntheticCapturer::new("scenes:ff0000").is_err());
+        assert!(SyntheticCapturer::new("scenes:red*1").is_err());
+
+        let y4m = std::env::temp_dir().join("synthetic_capture_test.y4m");
+        let mut data = b"YUV4MPEG2 W4 H2 F30:1 C420jpeg\n".to_vec();
+        for v in [16u8, 235] {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(&[v; 8]);
+            data.extend_from_slice(&[128; 4]);
+        }
+        std::fs::write(&y4m, &data).unwrap();
+        let spec = format!("replay:{}", y4m.display());
+        assert_eq!(SyntheticCapturer::size(&spec).unwrap(), (4, 2));
+        let mut c = SyntheticCapturer::new(&spec).unwrap();
+        assert_eq!(next(&mut c).unwrap()[..3], [0, 0, 0]);
+        assert_eq!(next(&mut c).unwrap()[..3], [255, 255, 255]);
+        std::fs::remove_file(&y4m).ok();
+    }
+}
@@ -9,6 +9,8 @@ use hbb_common::message_proto::message::{Chroma, EncodedVideoFrame, EncodedVideo
 use hbb_common::ResultType;
 
 use crate::codec::{base_bitrate, codec_thread_num, EncoderApi};
+use crate::damage::{self, Damage};
+use crate::layers::{References, TemporalLayers};
 use crate::{EncodeInput, EncodeYuvFormat, GoogleImage, Pixfmt, STRIDE_ALIGN};
 
 use super::vpx::{vp8e_enc_control_id::*, vpx_codec_err_t::*, *};
@@ -39,6 +41,8 @@ pub struct VpxEncoder {
     id: VpxVideoCodecId,
     i444: bool,
     yuvfmt: EncodeYuvFormat,
+    layers: TemporalLayers,
+    layer: usize,
 }
 
 pub struct VpxDecoder {
@@ -165,6 +169,8 @@ impl EncoderApi for VpxEncoder {
                     id: config.codec,
                     i444,
                     yuvfmt: Self::get_yuvfmt(config.width, config.height, i444),
+                    layers: Default::default(),
+                    layer: 0,
                 })
             }
             _ => Err(anyhow!("encoder type mismatch")),
@@ -182,6 +188,10 @@ impl EncoderApi for VpxEncoder {
         for ref frame in self.flush().with_context(|| "Failed to flush")? {
             frames.push(VpxEncoder::create_frame(frame));
         }
+        // Key frames refresh all the buffers, every viewer needs them
+        if frames.iter().any(|f| f.key) {
+            self.layer = 0;
+        }
 
         // to-do: flush periodically, e.g. 1 second
         if frames.len() > 0 {
@@ -228,6 +238,55 @@ impl EncoderApi for VpxEncoder {
     }
 
     fn disable(&self) {}
+
+    fn set_damage(&mut self, damage: &Damage) -> ResultType<()> {
+        // One entry per 16x16 macroblock, a null map marks all of them active
+        let mut map = Vec::new();
+        let mut active_map = vpx_active_map_t {
+            active_map: ptr::null_mut(),
+            rows: damage::tiles(self.height) as _,
+            cols: damage::tiles(self.width) as _,
+        };
+        if !damage.is_full()
+            && (damage.rows, damage.cols) == (active_map.rows as _, active_map.cols as _)
+        {
+            map.extend(damage.tiles().iter().map(|dirty| *dirty as u8));
+            active_map.active_map = map.as_mut_ptr();
+        }
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP8E_SET_ACTIVEMAP as _,
+            &mut active_map as *mut vpx_active_map_t
+        ));
+        Ok(())
+    }
+
+    fn set_lossless(&mut self, lossless: bool) -> ResultType<bool> {
+        // VP8 has no lossless mode
+        if self.id != VpxVideoCodecId::VP9 {
+            return Ok(false);
+        }
+        // The quantizers are ignored, all set to 0
+        call_vpx!(vpx_codec_control_(
+            &mut self.ctx,
+            VP9E_SET_LOSSLESS as _,
+            lossless as c_uint
+        ));
+        if lossless {
+            // In the base layer, for every viewer
+            self.set_temporal_layers(self.layers.layers());
+        }
+        Ok(true)
+    }
+
+    fn set_temporal_layers(&mut self, layers: usize) -> usize {
+        self.layers = TemporalLayers::new(layers);
+        self.layers.layers()
+    }
+
+    fn temporal_layer(&self) -> usize {
+        self.layer
+    }
 }
 
 impl VpxEncoder {
@@ -252,12 +311,19 @@ impl VpxEncoder {
             data.as_ptr() as _,
         ));
 
+        let (layer, references) = self.layers.next();
+        self.layer = layer;
+        let flags = if self.layers.layers() > 1 {
+            Self::layer_flags(references)
+        } else {
+            0
+        };
         call_vpx!(vpx_codec_encode(
             &mut self.ctx,
             &image,
             pts as _,
             1, // Duration
-            0, // Flags
+            flags as _,
             VPX_DL_REALTIME as _,
         ));
 
@@ -267,6 +333,24 @@ impl VpxEncoder {
         })
     }
 
+    // The alternate reference buffer is left out
+    fn layer_flags(references: References) -> u32 {
+        let mut flags = VP8_EFLAG_NO_REF_ARF | VP8_EFLAG_NO_UPD_ARF;
+        if !references.ref_golden {
+            flags |= VP8_EFLAG_NO_REF_GF;
+        }
+        if !references.update_last {
+            flags |= VP8_EFLAG_NO_UPD_LAST;
+        }
+        if !references.update_golden {
+            flags |= VP8_EFLAG_NO_UPD_GF;
+        }
+        if !references.update_entropy {
+            flags |= VP8_EFLAG_NO_UPD_ENTROPY;
+        }
+        flags
+    }
+
     /// Notify the encoder to return any pending packets
     pub fn flush(&mut self) -> Result<EncodeFrames> {
         call_vpx!(vpx_codec_encode(
@@ -204,6 +204,21 @@ impl EncoderApi for VRamEncoder {
     fn disable(&self) {
         HwCodecConfig::clear(true, true);
     }
+    fn set_damage(&mut self, _damage: &crate::damage::Damage) -> ResultType<()> {
+        Ok(())
+    }
+
+    fn set_lossless(&mut self, _lossless: bool) -> ResultType<bool> {
+        Ok(false)
+    }
+
+    fn set_temporal_layers(&mut self, _layers: usize) -> usize {
+        1
+    }
+
+    fn temporal_layer(&self) -> usize {
+        0
+    }
 }
 
 impl VRamEncoder {
@@ -13,6 +13,9 @@ use hbb_common::{
 };
 use std::sync::{Arc, RwLock};
 
+mod terminal;
+pub use terminal::start_terminal;
+
 #[derive(Clone)]
 pub struct Session {
     id: String,
@@ -22,7 +25,7 @@ pub struct Session {
 }
 
 impl Session {
-    pub fn new(id: &str, sender: mpsc::UnboundedSender<Data>) -> Self {
+    pub fn new(id: &str, conn_type: ConnType, sender: mpsc::UnboundedSender<Data>) -> Self {
         let mut password = "".to_owned();
         if PeerConfig::load(id).password.is_empty() {
             password = rpassword::prompt_password("Enter password: ").unwrap();
@@ -35,11 +38,12 @@ impl Session {
         };
         session.lc.write().unwrap().initialize(
             id.to_owned(),
-            ConnType::PORT_FORWARD,
+            conn_type,
             None,
             false,
             None,
             None,
+            None,
         );
         session
     }
@@ -47,7 +51,7 @@ impl Session {
 
 #[async_trait]
 impl Interface for Session {
-    fn get_login_config_handler(&self) -> Arc<RwLock<LoginConfigHandler>> {
+    fn get_lch(&self) -> Arc<RwLock<LoginConfigHandler>> {
         return self.lc.clone();
     }
 
@@ -55,14 +59,20 @@ impl Interface for Session {
         match msgtype {
             "input-password" => {
                 self.sender
-                    .send(Data::Login((self.password.clone(), true)))
+                    .send(Data::Login((
+                        String::new(),
+                        String::new(),
+                        self.password.clone(),
+                        true,
+                    )))
                     .ok();
             }
             "re-input-password" => {
                 log::error!("{}: {}", title, text);
                 match rpassword::prompt_password("Enter password: ") {
                     Ok(password) => {
-                        let login_data = Data::Login((password, true));
+                        let login_data =
+                            Data::Login((String::new(), String::new(), password, true));
                         self.sender.send(login_data).ok();
                     }
                     Err(e) => {
@@ -87,6 +97,8 @@ impl Interface for Session {
         self.lc.write().unwrap().handle_peer_info(&pi);
     }
 
+    fn set_multiple_windows_session(&self, _sessions: Vec<WindowsSession>) {}
+
     async fn handle_hash(&self, pass: &str, hash: Hash, peer: &mut Stream) {
         log::info!(
             "password={}",
@@ -126,12 +138,12 @@ impl Interface for Session {
 #[tokio::main(flavor = "current_thread")]
 pub async fn connect_test(id: &str, key: String, token: String) {
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, sender);
     match crate::client::Client::start(id, &key, &token, ConnType::PORT_FORWARD, handler).await {
         Err(err) => {
             log::error!("Failed to connect {}: {}", &id, err);
         }
-        Ok((mut stream, direct)) => {
+        Ok(((mut stream, direct, ..), _)) => {
             log::info!("direct: {}", direct);
             // rpassword::prompt_password("Input anything to exit").ok();
             loop {
@@ -160,34 +172,231 @@ pub async fn connect_test(id: &str, key: String, token: String) {
     }
 }
 
+/// A forward of [`start_port_forwards`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Forward {
+    Tcp {
+        port: i32,
+        remote_host: String,
+        remote_port: i32,
+    },
+    Socks5 {
+        port: i32,
+        /// Loopback unless given, the proxy has no authentication
+        bind: String,
+    },
+    /// The remote side listening on `remote_port`
+    Reverse {
+        remote_port: i32,
+        local_host: String,
+        local_port: i32,
+    },
+}
+
+impl Forward {
+    /// Parse the value of the `option` command line option:
+    /// `remote-id:local-port:remote-port[:remote-host]` for `port-forward`,
+    /// `remote-id:local-port[:bind-address]` for `socks5` and
+    /// `remote-id:remote-port:local-port[:local-host]` for `reverse-forward`.
+    pub fn parse(option: &str, s: &str) -> Result<(String, Self), &'static str> {
+        if option == "reverse-forward" {
+            return match Self::parse("port-forward", s) {
+                Ok((
+                    id,
+                    Self::Tcp {
+                        port,
+                        remote_host,
+                        remote_port,
+                    },
+                )) => Ok((
+                    id,
+                    Self::Reverse {
+                        remote_port: port,
+                        local_host: remote_host,
+                        local_port: remote_port,
+                    },
+                )),
+                _ => Err("Wrong reverse-forward options"),
+            };
+        }
+        let options: Vec<&str> = s.split(':').collect();
+        let port = options
+            .get(1)
+            .and_then(|p| p.parse::<i32>().ok())
+            .ok_or("Wrong local-port")?;
+        if option == "socks5" {
+            // The bind address may be IPv6
+            let bind = match s.splitn(3, ':').nth(2) {
+                Some("") => return Err("Wrong socks5 options"),
+                Some(bind) => bind.to_owned(),
+                None => "127.0.0.1".to_owned(),
+            };
+            return Ok((options[0].to_owned(), Self::Socks5 { port, bind }));
+        }
+        if options.len() < 3 {
+            return Err("Wrong port-forward options");
+        }
+        let remote_port = options[2].parse::<i32>().map_err(|_| "Wrong remote-port")?;
+        let remote_host = options.get(3).unwrap_or(&"localhost").to_string();
+        Ok((
+            options[0].to_owned(),
+            Self::Tcp {
+                port,
+                remote_host,
+                remote_port,
+            },
+        ))
+    }
+
+}
+
+/// Run all `forwards` to `id`, the local ones in one session, logging in
+/// once for all.
 #[tokio::main(flavor = "current_thread")]
-pub async fn start_one_port_forward(
-    id: String,
-    port: i32,
-    remote_host: String,
-    remote_port: i32,
-    key: String,
-    token: String,
-) {
+pub async fn start_port_forwards(id: String, forwards: Vec<Forward>, key: String, token: String) {
     crate::common::test_rendezvous_server();
     crate::common::test_nat_type();
     let (sender, mut receiver) = mpsc::unbounded_channel::<Data>();
-    let handler = Session::new(&id, sender);
-    if let Err(err) = crate::port_forward::listen(
-        handler.id.clone(),
-        handler.password.clone(),
-        port,
-        handler.clone(),
-        receiver,
-        &key,
-        &token,
-        handler.lc.clone(),
-        remote_host,
-        remote_port,
-    )
-    .await
-    {
-        log::error!("Failed to listen on {}: {}", port, err);
-    }
-    log::info!("port forward (:{}) exit", port);
+    let handler = Session::new(&id, ConnType::PORT_FORWARD, sender);
+    let mut tunnels = Vec::new();
+    let mut reverse = Vec::new();
+    for forward in forwards {
+        match forward {
+            Forward::Tcp {
+                port,
+                remote_host,
+                remote_port,
+            } => tunnels.push(crate::port_forward::TunnelListener {
+                addr: format!("0.0.0.0:{}", port),
+                target: Some((remote_host, remote_port)),
+            }),
+            Forward::Socks5 { port, bind } => tunnels.push(crate::port_forward::TunnelListener {
+                addr: if bind.contains(':') {
+                    format!("[{}]:{}", bind, port)
+                } else {
+                    format!("{}:{}", bind, port)
+                },
+                target: None,
+            }),
+            Forward::Reverse {
+                remote_port,
+                local_host,
+                local_port,
+            } => reverse.push((remote_port, local_host, local_port)),
+        }
+    }
+    let mut queues = Vec::new();
+    if !tunnels.is_empty() {
+        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+        queues.push(sender);
+        let handler = handler.clone();
+        let key = key.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let res = crate::port_forward::listen_tunnels(
+                handler.id.clone(),
+                handler.password.clone(),
+                tunnels,
+                handler.clone(),
+                receiver,
+                &key,
+                &token,
+                handler.lc.clone(),
+            )
+            .await;
+            if let Err(err) = res {
+                log::error!("Failed to run port forwards: {}", err);
+            }
+            log::info!("port forwards exit");
+        });
+    }
+    for (remote_port, local_host, local_port) in reverse {
+        let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+        queues.push(sender);
+        let handler = handler.clone();
+        let key = key.clone();
+        let token = token.clone();
+        tokio::spawn(async move {
+            let res = crate::port_forward::listen_reverse(
+                handler.id.clone(),
+                handler.password.clone(),
+                remote_port,
+                handler.clone(),
+                receiver,
+                &key,
+                &token,
+                handler.lc.clone(),
+                local_host,
+                local_port,
+            )
+            .await;
+            if let Err(err) = res {
+                log::error!("Failed to listen on remote port {}: {}", remote_port, err);
+            }
+            log::info!("reverse port forward (:{}) exit", remote_port);
+        });
+    }
+    // Passwords entered for one session are for all of them
+    while let Some(data) = receiver.recv().await {
+        queues.retain(|q| q.send(data.clone()).is_ok());
+        if queues.is_empty() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_forward() {
+        assert_eq!(
+            Forward::parse("port-forward", "123:8080:80"),
+            Ok((
+                "123".to_owned(),
+                Forward::Tcp {
+                    port: 8080,
+                    remote_host: "localhost".to_owned(),
+                    remote_port: 80
+                }
+            ))
+        );
+        assert_eq!(
+            Forward::parse("port-forward", "123:2222:22:10.0.0.5").unwrap().1,
+            Forward::Tcp {
+                port: 2222,
+                remote_host: "10.0.0.5".to_owned(),
+                remote_port: 22
+            }
+        );
+        assert_eq!(
+            Forward::parse("socks5", "123:1080"),
+            Ok((
+                "123".to_owned(),
+                Forward::Socks5 {
+                    port: 1080,
+                    bind: "127.0.0.1".to_owned()
+                }
+            ))
+        );
+        assert_eq!(
+            Forward::parse("socks5", "123:1080:::").unwrap().1,
+            Forward::Socks5 {
+                port: 1080,
+                bind: "::".to_owned()
+            }
+        );
+        assert!(Forward::parse("port-forward", "123:1080").is_err());
+        assert!(Forward::parse("port-forward", "123:x:80").is_err());
+        assert!(Forward::parse("socks5", "123:1080:").is_err());
+        assert_eq!(
+            Forward::parse("reverse-forward", "123:27000:27001").unwrap().1,
+            Forward::Reverse {
+                remote_port: 27000,
+                local_host: "localhost".to_owned(),
+                local_port: 27001
+            }
+        );
+    }
 }
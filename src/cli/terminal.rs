@@ -0,0 +1,458 @@
+//! `rustdesk --terminal <peer-id>[:<terminal-id>]`
+//!
+//! Attaches the local tty to a persistent terminal on the peer. Terminals
+//! outlive the connection, so detaching and attaching again later resumes the
+//! same shell, much like tmux. The peer keeps the service id in the peer
+//! config, as the Flutter terminal does.
+//!
+//! `--list-terminals` prints the ids of the open terminals. The peer only
+//! reports them in reply to an open request, so listing opens the requested
+//! terminal if it does not exist yet.
+//!
+//! While attached, `Ctrl-]` is the escape key:
+//! `Ctrl-] d` detaches, `Ctrl-] k` closes the terminal, `Ctrl-] Ctrl-]` sends
+//! a literal `Ctrl-]`.
+
+use super::Session;
+use crate::client::*;
+use hbb_common::{
+    allow_err, bail,
+    config::{keys, READ_TIMEOUT},
+    futures::StreamExt,
+    log,
+    message_proto::*,
+    protobuf::Message as _,
+    rendezvous_proto::ConnType,
+    timeout,
+    tokio::{self, sync::mpsc},
+    ResultType, Stream,
+};
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+const ESCAPE: u8 = 0x1d; // Ctrl-]
+const RESIZE_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+#[tokio::main(flavor = "current_thread")]
+pub async fn start_terminal(
+    id: String,
+    terminal_id: i32,
+    list_only: bool,
+    key: String,
+    token: String,
+) {
+    let (sender, receiver) = mpsc::unbounded_channel::<Data>();
+    let handler = Session::new(&id, ConnType::TERMINAL, sender);
+    {
+        let mut lc = handler.lc.write().unwrap();
+        if !lc.get_toggle_option(keys::OPTION_TERMINAL_PERSISTENT) {
+            lc.toggle_option(keys::OPTION_TERMINAL_PERSISTENT.to_owned());
+        }
+    }
+    if let Err(err) = run(handler, receiver, terminal_id, list_only, &key, &token).await {
+        eprintln!("{}", err);
+    }
+}
+
+async fn run(
+    handler: Session,
+    mut receiver: mpsc::UnboundedReceiver<Data>,
+    terminal_id: i32,
+    list_only: bool,
+    key: &str,
+    token: &str,
+) -> ResultType<()> {
+    let ((mut stream, direct, ..), (feedback, rendezvous_server)) =
+        Client::start(&handler.id, key, token, ConnType::TERMINAL, handler.clone()).await?;
+    handler.update_direct(Some(direct));
+    let _keep_it = hc_connection(feedback, rendezvous_server, token).await;
+    if !login(&handler, &mut receiver, &mut stream).await? {
+        return Ok(());
+    }
+
+    let mut size = tty_size();
+    send_action(&mut stream, |action| {
+        action.set_open(OpenTerminal {
+            terminal_id,
+            rows: size.0 as _,
+            cols: size.1 as _,
+            ..Default::default()
+        })
+    })
+    .await;
+
+    let mut raw_mode = None;
+    let (input_tx, mut input_rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    let mut escape = Escape::default();
+    let mut resize_timer = tokio::time::interval(RESIZE_CHECK_INTERVAL);
+    let mut stdout = std::io::stdout();
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => {
+                let bytes = match res {
+                    Err(_) => bail!("Timeout"),
+                    Ok(Some(Ok(bytes))) => bytes,
+                    Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+                    Ok(None) => bail!("Reset by the peer"),
+                };
+                let msg_in = Message::parse_from_bytes(&bytes)?;
+                let response = match msg_in.union {
+                    Some(message::Union::TerminalResponse(response)) => response,
+                    Some(message::Union::TestDelay(t)) => {
+                        handler.handle_test_delay(t, &mut stream).await;
+                        continue;
+                    }
+                    _ => continue,
+                };
+                use terminal_response::Union;
+                match response.union {
+                    Some(Union::Opened(opened)) => {
+                        if !opened.success {
+                            bail!("Failed to open terminal: {}", opened.message);
+                        }
+                        if !opened.service_id.is_empty() {
+                            let mut lc = handler.lc.write().unwrap();
+                            let key = lc.get_key_terminal_service_id().to_owned();
+                            lc.set_option(key, opened.service_id.clone());
+                        }
+                        if list_only {
+                            let mut ids = opened.persistent_sessions.clone();
+                            ids.push(opened.terminal_id);
+                            ids.sort();
+                            ids.dedup();
+                            for id in ids {
+                                println!("{}", id);
+                            }
+                            return Ok(());
+                        }
+                        eprintln!(
+                            "{} (terminal {}, pid {}), Ctrl-] d to detach\r",
+                            opened.message, opened.terminal_id, opened.pid
+                        );
+                        raw_mode = Some(RawMode::enable()?);
+                        spawn_stdin_reader(input_tx.clone());
+                    }
+                    Some(Union::Data(data)) if data.terminal_id == terminal_id => {
+                        let output = if data.compressed {
+                            hbb_common::compress::decompress(&data.data)
+                        } else {
+                            data.data.to_vec()
+                        };
+                        stdout.write_all(&output)?;
+                        stdout.flush()?;
+                    }
+                    Some(Union::Closed(closed)) if closed.terminal_id == terminal_id => {
+                        drop(raw_mode.take());
+                        eprintln!("\nTerminal {} exited ({})", terminal_id, closed.exit_code);
+                        return Ok(());
+                    }
+                    Some(Union::Error(err)) => {
+                        drop(raw_mode.take());
+                        bail!("{}", err.message);
+                    }
+                    _ => {}
+                }
+            }
+            Some(input) = input_rx.recv() => {
+                let (data, command) = escape.feed(&input);
+                if !data.is_empty() {
+                    send_action(&mut stream, |action| {
+                        action.set_data(TerminalData {
+                            terminal_id,
+                            data: data.into(),
+                            ..Default::default()
+                        })
+                    })
+                    .await;
+                }
+                match command {
+                    Some(Command::Detach) => {
+                        drop(raw_mode.take());
+                        eprintln!("\nDetached from terminal {}", terminal_id);
+                        return Ok(());
+                    }
+                    Some(Command::Close) => {
+                        send_action(&mut stream, |action| {
+                            action.set_close(CloseTerminal {
+                                terminal_id,
+                                ..Default::default()
+                            })
+                        })
+                        .await;
+                    }
+                    None => {}
+                }
+            }
+            Some(data) = receiver.recv() => {
+                if let Data::Message(msg) = data {
+                    allow_err!(stream.send(&msg).await);
+                }
+            }
+            _ = resize_timer.tick(), if raw_mode.is_some() => {
+                let new_size = tty_size();
+                if new_size != size {
+                    size = new_size;
+                    send_action(&mut stream, |action| {
+                        action.set_resize(ResizeTerminal {
+                            terminal_id,
+                            rows: size.0 as _,
+                            cols: size.1 as _,
+                            ..Default::default()
+                        })
+                    })
+                    .await;
+                }
+            }
+        }
+    }
+}
+
+/// Run the handshake until the peer info arrives, `false` if login was given up.
+async fn login(
+    handler: &Session,
+    receiver: &mut mpsc::UnboundedReceiver<Data>,
+    stream: &mut Stream,
+) -> ResultType<bool> {
+    loop {
+        tokio::select! {
+            res = timeout(READ_TIMEOUT, stream.next()) => match res {
+                Err(_) => bail!("Timeout"),
+                Ok(Some(Ok(bytes))) => {
+                    let msg_in = Message::parse_from_bytes(&bytes)?;
+                    match msg_in.union {
+                        Some(message::Union::Hash(hash)) => {
+                            handler.handle_hash(&handler.password, hash, stream).await;
+                        }
+                        Some(message::Union::LoginResponse(lr)) => match lr.union {
+                            Some(login_response::Union::Error(err)) => {
+                                if !handler.handle_login_error(&err) {
+                                    return Ok(false);
+                                }
+                            }
+                            Some(login_response::Union::PeerInfo(pi)) => {
+                                handler.handle_peer_info(pi);
+                                return Ok(true);
+                            }
+                            _ => {}
+                        },
+                        Some(message::Union::TestDelay(t)) => {
+                            handler.handle_test_delay(t, stream).await;
+                        }
+                        _ => {}
+                    }
+                }
+                Ok(Some(Err(err))) => bail!("Connection closed: {}", err),
+                Ok(None) => bail!("Reset by the peer"),
+            },
+            Some(data) = receiver.recv() => match data {
+                Data::Login((os_username, os_password, password, remember)) => {
+                    handler
+                        .handle_login_from_ui(os_username, os_password, password, remember, stream)
+                        .await;
+                }
+                Data::Message(msg) => {
+                    allow_err!(stream.send(&msg).await);
+                }
+                _ => {}
+            },
+        }
+    }
+}
+
+async fn send_action(stream: &mut Stream, f: impl FnOnce(&mut TerminalAction)) {
+    let mut action = TerminalAction::new();
+    f(&mut action);
+    let mut msg_out = Message::new();
+    msg_out.set_terminal_action(action);
+    allow_err!(stream.send(&msg_out).await);
+}
+
+fn spawn_stdin_reader(tx: mpsc::UnboundedSender<Vec<u8>>) {
+    std::thread::spawn(move || {
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 4096];
+        loop {
+            match stdin.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    if tx.send(buf[..n].to_vec()).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Failed to read stdin: {}", e);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+#[derive(Debug, PartialEq)]
+enum Command {
+    Detach,
+    Close,
+}
+
+/// Splits the escape sequences out of the local input.
+#[derive(Default)]
+struct Escape {
+    pending: bool,
+}
+
+impl Escape {
+    /// Returns the bytes to send and the command, if any. Input after a
+    /// command is dropped.
+    fn feed(&mut self, input: &[u8]) -> (Vec<u8>, Option<Command>) {
+        let mut data = Vec::with_capacity(input.len());
+        for &b in input {
+            if !self.pending {
+                if b == ESCAPE {
+                    self.pending = true;
+                } else {
+                    data.push(b);
+                }
+                continue;
+            }
+            self.pending = false;
+            match b {
+                b'd' | b'D' => return (data, Some(Command::Detach)),
+                b'k' | b'K' => return (data, Some(Command::Close)),
+                ESCAPE => data.push(ESCAPE),
+                _ => data.extend_from_slice(&[ESCAPE, b]),
+            }
+        }
+        (data, None)
+    }
+}
+
+/// Puts the local tty into raw mode until dropped.
+struct RawMode {
+    #[cfg(not(windows))]
+    saved: hbb_common::libc::termios,
+    #[cfg(windows)]
+    saved: (u32, u32),
+}
+
+#[cfg(not(windows))]
+impl RawMode {
+    fn enable() -> ResultType<Self> {
+        use hbb_common::libc;
+        unsafe {
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                bail!("stdin is not a terminal");
+            }
+            let mut raw = saved;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                bail!("Failed to set raw mode: {}", std::io::Error::last_os_error());
+            }
+            Ok(Self { saved })
+        }
+    }
+}
+
+#[cfg(not(windows))]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        use hbb_common::libc;
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+#[cfg(windows)]
+impl RawMode {
+    fn enable() -> ResultType<Self> {
+        use winapi::um::{
+            consoleapi::{GetConsoleMode, SetConsoleMode},
+            processenv::GetStdHandle,
+            winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
+            wincon::{
+                ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+                ENABLE_VIRTUAL_TERMINAL_INPUT, ENABLE_VIRTUAL_TERMINAL_PROCESSING,
+            },
+        };
+        unsafe {
+            let input = GetStdHandle(STD_INPUT_HANDLE);
+            let output = GetStdHandle(STD_OUTPUT_HANDLE);
+            let (mut in_mode, mut out_mode) = (0, 0);
+            if GetConsoleMode(input, &mut in_mode) == 0
+                || GetConsoleMode(output, &mut out_mode) == 0
+            {
+                bail!("stdin is not a console");
+            }
+            let cooked = ENABLE_ECHO_INPUT | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT;
+            let raw_in = (in_mode & !cooked) | ENABLE_VIRTUAL_TERMINAL_INPUT;
+            SetConsoleMode(input, raw_in);
+            SetConsoleMode(output, out_mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING);
+            Ok(Self {
+                saved: (in_mode, out_mode),
+            })
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        use winapi::um::{
+            consoleapi::SetConsoleMode,
+            processenv::GetStdHandle,
+            winbase::{STD_INPUT_HANDLE, STD_OUTPUT_HANDLE},
+        };
+        unsafe {
+            SetConsoleMode(GetStdHandle(STD_INPUT_HANDLE), self.saved.0);
+            SetConsoleMode(GetStdHandle(STD_OUTPUT_HANDLE), self.saved.1);
+        }
+    }
+}
+
+/// (rows, cols) of the local tty, 24x80 if unknown.
+fn tty_size() -> (u16, u16) {
+    #[cfg(not(windows))]
+    unsafe {
+        use hbb_common::libc;
+        let mut ws: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) == 0 && ws.ws_col > 0 {
+            return (ws.ws_row, ws.ws_col);
+        }
+    }
+    #[cfg(windows)]
+    unsafe {
+        use winapi::um::{
+            processenv::GetStdHandle, winbase::STD_OUTPUT_HANDLE,
+            wincon::GetConsoleScreenBufferInfo,
+        };
+        let mut info = std::mem::zeroed();
+        if GetConsoleScreenBufferInfo(GetStdHandle(STD_OUTPUT_HANDLE), &mut info) != 0 {
+            let w = info.srWindow;
+            return ((w.Bottom - w.Top + 1) as u16, (w.Right - w.Left + 1) as u16);
+        }
+    }
+    (24, 80)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escape_sequences() {
+        let mut escape = Escape::default();
+        assert_eq!(escape.feed(b"ls\r"), (b"ls\r".to_vec(), None));
+        assert_eq!(escape.feed(&[ESCAPE, ESCAPE, b'x']), (vec![ESCAPE, b'x'], None));
+        assert_eq!(escape.feed(&[b'a', ESCAPE]), (vec![b'a'], None));
+        // The escape key split across reads
+        assert_eq!(escape.feed(b"d"), (vec![], Some(Command::Detach)));
+        assert_eq!(escape.feed(&[ESCAPE, b'q']), (vec![ESCAPE, b'q'], None));
+        assert_eq!(
+            escape.feed(&[b'z', ESCAPE, b'k', b'y']),
+            (vec![b'z'], Some(Command::Close))
+        );
+    }
+}
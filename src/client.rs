@@ -74,7 +74,7 @@ use hbb_common::{
 };
 pub use helper::*;
 use scrap::{
-    codec::Decoder,
+    codec::{Decoder, LosslessMode, OPTION_LOSSLESS_ENCODING},
     record::{Recorder, RecorderContext},
     CodecFormat, ImageFormat, ImageRgb, ImageTexture,
 };
@@ -1658,6 +1658,7 @@ impl VideoHandler {
                 display_idx,
                 camera,
                 tx: None,
+                key: None,
             })
             .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))));
         } else {
@@ -2258,10 +2259,37 @@ impl LoginConfigHandler {
         if view_only || self.get_toggle_option("disable-clipboard") {
             msg.disable_clipboard = BoolOption::Yes.into();
         }
+        let lossless = self.lossless_request();
+        if lossless != LosslessMode::Off {
+            lossless.request(&mut msg);
+        }
         msg.supported_decoding = MessageField::some(self.get_supported_decoding());
         Some(msg)
     }
 
+    // The lossless mode to ask the peer for
+    fn lossless_request(&self) -> LosslessMode {
+        LosslessMode::from_option(&self.get_option(OPTION_LOSSLESS_ENCODING))
+            .unwrap_or(LosslessMode::Off)
+    }
+
+    /// Save the lossless mode to ask the peer for, `off`, `static` or `always`.
+    /// Return a [`Message`] that contains the lossless mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The lossless mode.
+    pub fn save_lossless_encoding(&mut self, value: String) -> Message {
+        self.set_option(OPTION_LOSSLESS_ENCODING.to_owned(), value);
+        let mut option = OptionMessage::new();
+        self.lossless_request().request(&mut option);
+        let mut misc = Misc::new();
+        misc.set_option(option);
+        let mut msg_out = Message::new();
+        msg_out.set_misc(misc);
+        msg_out
+    }
+
     pub fn get_supported_decoding(&self) -> SupportedDecoding {
         Decoder::supported_decodings(
             Some(&self.id),
@@ -445,6 +445,24 @@ fn core_main_legacy() -> Option<Vec<String>> {
         } else if args[0] == "--get-id" {
             println!("{}", crate::ipc::get_id());
             return None;
+        } else if args[0] == "--codeuchain-status" {
+            match crate::ipc::get_config(crate::ipc::CODEUCHAIN_MIGRATION_QUERY) {
+                Ok(Some(report)) => {
+                    #[cfg(feature = "codeuchain_integration")]
+                    if args.len() < 2 || args[1] != "--json" {
+                        if let Some(table) =
+                            codeuchain_rustdesk::MigrationTracker::format_report(&report)
+                        {
+                            print!("{}", table);
+                            return None;
+                        }
+                    }
+                    println!("{}", report);
+                }
+                Ok(None) => println!("CodeUChain integration is not enabled in the running service"),
+                Err(err) => println!("Failed to query the running service: {}", err),
+            }
+            return None;
         } else if args[0] == "--set-id" {
             if args.len() == 2 {
                 if crate::platform::is_installed() && is_root() {
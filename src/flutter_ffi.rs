@@ -426,6 +426,12 @@ pub fn session_set_image_quality(session_id: SessionID, value: String) {
     }
 }
 
+pub fn session_set_lossless_encoding(session_id: SessionID, value: String) {
+    if let Some(session) = sessions::get_session_by_session_id(&session_id) {
+        session.save_lossless_encoding(value);
+    }
+}
+
 pub fn session_get_keyboard_mode(session_id: SessionID) -> Option<String> {
     if let Some(session) = sessions::get_session_by_session_id(&session_id) {
         Some(session.get_keyboard_mode())
@@ -1,14 +1,13 @@
-use crate::hbbs_http::create_http_client;
+use crate::{hbbs_http::create_http_client, record_sink::RecordSink};
 use bytes::Bytes;
 use hbb_common::{bail, config::Config, lazy_static, log, ResultType};
 use reqwest::blocking::{Body, Client};
-use scrap::record::RecordState;
 use serde::Serialize;
 use serde_json::Map;
 use std::{
     fs::File,
     io::{prelude::*, SeekFrom},
-    sync::{mpsc::Receiver, Arc, Mutex},
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 
@@ -24,66 +23,30 @@ pub fn is_enable() -> bool {
     ENABLE.lock().unwrap().clone()
 }
 
-pub fn run(rx: Receiver<RecordState>) {
-    let mut uploader = RecordUploader {
-        client: create_http_client(),
-        api_server: crate::get_api_server(
-            Config::get_option("api-server").unwrap_or_default(),
-            Config::get_option("custom-rendezvous-server").unwrap_or_default(),
-        ),
-        filepath: Default::default(),
-        filename: Default::default(),
-        upload_size: Default::default(),
-        running: Default::default(),
-        last_send: Instant::now(),
-    };
-    std::thread::spawn(move || loop {
-        if let Err(e) = match rx.recv() {
-            Ok(state) => match state {
-                RecordState::NewFile(filepath) => uploader.handle_new_file(filepath),
-                RecordState::NewFrame => {
-                    if uploader.running {
-                        uploader.handle_frame(false)
-                    } else {
-                        Ok(())
-                    }
-                }
-                RecordState::WriteTail => {
-                    if uploader.running {
-                        uploader.handle_tail()
-                    } else {
-                        Ok(())
-                    }
-                }
-                RecordState::RemoveFile => {
-                    if uploader.running {
-                        uploader.handle_remove()
-                    } else {
-                        Ok(())
-                    }
-                }
-            },
-            Err(e) => {
-                log::trace!("upload thread stop: {}", e);
-                break;
-            }
-        } {
-            uploader.running = false;
-            log::error!("upload stop: {}", e);
-        }
-    });
-}
-
-struct RecordUploader {
+pub struct RecordUploader {
     client: Client,
     api_server: String,
     filepath: String,
     filename: String,
     upload_size: u64,
-    running: bool,
     last_send: Instant,
 }
+
 impl RecordUploader {
+    pub fn new() -> Self {
+        Self {
+            client: create_http_client(),
+            api_server: crate::get_api_server(
+                Config::get_option("api-server").unwrap_or_default(),
+                Config::get_option("custom-rendezvous-server").unwrap_or_default(),
+            ),
+            filepath: Default::default(),
+            filename: Default::default(),
+            upload_size: Default::default(),
+            last_send: Instant::now(),
+        }
+    }
+
     fn send<Q, B>(&self, query: &Q, body: B) -> ResultType<()>
     where
         Q: Serialize + ?Sized,
@@ -115,7 +78,6 @@ impl RecordUploader {
                     self.filename = filename.clone();
                     self.filepath = filepath.clone();
                     self.upload_size = 0;
-                    self.running = true;
                     self.last_send = Instant::now();
                     self.send(&[("type", "new"), ("file", &filename)], Bytes::new())?;
                     Ok(())
@@ -203,3 +165,25 @@ impl RecordUploader {
         Ok(())
     }
 }
+
+impl RecordSink for RecordUploader {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+
+    fn new_file(&mut self, filepath: &str) -> ResultType<()> {
+        self.handle_new_file(filepath.to_owned())
+    }
+
+    fn frame(&mut self) -> ResultType<()> {
+        self.handle_frame(false)
+    }
+
+    fn tail(&mut self) -> ResultType<()> {
+        self.handle_tail()
+    }
+
+    fn remove(&mut self) -> ResultType<()> {
+        self.handle_remove()
+    }
+}
@@ -310,6 +310,12 @@ pub async fn start(postfix: &str) -> ResultType<()> {
                                     break;
                                 }
                                 Ok(Some(data)) => {
+                                    #[cfg(feature = "codeuchain_integration")]
+                                    if codeuchain_rustdesk::MIGRATION_TRACKER.uses_chain("ipc")
+                                        && handle_chain(&data, &mut stream).await
+                                    {
+                                        continue;
+                                    }
                                     handle(data, &mut stream).await;
                                 }
                                 _ => {}
@@ -546,6 +552,8 @@ async fn handle(data: Data, stream: &mut Connection) {
                     value = Some(Config::get_unlock_pin());
                 } else if name == "trusted-devices" {
                     value = Some(Config::get_trusted_devices_json());
+                } else if name == CODEUCHAIN_MIGRATION_QUERY {
+                    value = codeuchain_migration_report();
                 } else {
                     value = None;
                 }
@@ -583,6 +591,7 @@ async fn handle(data: Data, stream: &mut Connection) {
                     crate::privacy_mode::switch(v);
                 }
                 Config::set_options(value);
+                scrap::codec::LosslessMode::refresh();
                 allow_err!(stream.send(&Data::Options(None)).await);
             }
         },
@@ -959,6 +968,61 @@ where
     }
 }
 
+/// Config name reporting which subsystems run on CodeUChain chains,
+/// the same as `codeuchain_rustdesk::MIGRATION_STATUS_QUERY`
+pub const CODEUCHAIN_MIGRATION_QUERY: &str = "codeuchain-migration";
+
+fn codeuchain_migration_report() -> Option<String> {
+    #[cfg(feature = "codeuchain_integration")]
+    return Some(codeuchain_rustdesk::MIGRATION_TRACKER.report_json());
+    #[cfg(not(feature = "codeuchain_integration"))]
+    None
+}
+
+#[cfg(feature = "codeuchain_integration")]
+lazy_static::lazy_static! {
+    static ref CHAIN_IPC: codeuchain_rustdesk::CodeUChainIPC =
+        codeuchain_rustdesk::CodeUChainIPC::new().with_options_hook(std::sync::Arc::new(
+            |options: &HashMap<String, String>| {
+                let guards = (CheckIfRestart::new(), CheckTestNatType::new());
+                if let Some(v) = options.get("privacy-mode-impl-key") {
+                    crate::privacy_mode::switch(v);
+                }
+                Box::new(guards) as Box<dyn Send>
+            }
+        ));
+}
+
+/// Handle `data` through the CodeUChain IPC chains when `codeuchain-ipc` is on.
+///
+/// Only the config names and options the chains serve are taken, returns false
+/// for anything else so `handle` serves it.
+#[cfg(feature = "codeuchain_integration")]
+async fn handle_chain(data: &Data, stream: &mut Connection) -> bool {
+    use codeuchain_rustdesk::{config_store, IPCData};
+    let request = match data {
+        Data::Config((name, value)) if config_store::serves(name, value.is_some()) => {
+            IPCData::Config {
+                name: name.clone(),
+                value: value.clone(),
+            }
+        }
+        Data::Options(options) => IPCData::Options(options.clone()),
+        _ => return false,
+    };
+    match CHAIN_IPC.handle(request).await {
+        Ok(Some(reply)) => {
+            allow_err!(stream.send_raw(reply.to_wire().into()).await);
+        }
+        Ok(None) => {}
+        Err(err) => {
+            log::error!("ipc chain failed, falling back to legacy: {}", err);
+            return false;
+        }
+    }
+    true
+}
+
 #[tokio::main(flavor = "current_thread")]
 pub async fn get_config(name: &str) -> ResultType<Option<String>> {
     get_config_async(name, 1_000).await
@@ -47,6 +47,8 @@ mod custom_server;
 mod lang;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 mod port_forward;
+#[cfg(not(any(target_os = "ios")))]
+mod reverse_tunnel;
 
 /// CodeUChain-based modular components
 #[path = "../codeuchain_components/mod.rs"]
@@ -74,6 +76,9 @@ mod ui_session_interface;
 
 mod hbbs_http;
 
+#[cfg(not(any(target_os = "ios")))]
+pub mod record_sink;
+
 #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
 pub mod clipboard_file;
 
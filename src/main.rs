@@ -44,8 +44,12 @@ fn main() {
     use clap::App;
     use hbb_common::log;
     let args = format!(
-        "-p, --port-forward=[PORT-FORWARD-OPTIONS] 'Format: remote-id:local-port:remote-port[:remote-host]'
+        "-p, --port-forward=[PORT-FORWARD-OPTIONS]... 'Format: remote-id:local-port:remote-port[:remote-host], repeatable'
+        -D, --socks5=[SOCKS5-OPTIONS]... 'Local SOCKS5 proxy tunneling through the remote side, format: remote-id:local-port[:bind-address], loopback by default, repeatable'
+        -R, --reverse-forward=[REVERSE-FORWARD-OPTIONS]... 'Remote port tunneled back to this side, format: remote-id:remote-port:local-port[:local-host], repeatable'
         -c, --connect=[REMOTE_ID] 'test only'
+        -t, --terminal=[TERMINAL-OPTIONS] 'Attach to a persistent terminal, format: remote-id[:terminal-id]'
+        --list-terminals 'List the persistent terminals of --terminal and exit'
         -k, --key=[KEY] ''
        -s, --server=[] 'Start server'",
     );
@@ -57,48 +61,61 @@ fn main() {
         .get_matches();
     use hbb_common::{config::LocalConfig, env_logger::*};
     init_from_env(Env::default().filter_or(DEFAULT_FILTER_ENV, "info"));
-    if let Some(p) = matches.value_of("port-forward") {
-        let options: Vec<String> = p.split(":").map(|x| x.to_owned()).collect();
-        if options.len() < 3 {
-            log::error!("Wrong port-forward options");
-            return;
-        }
-        let mut port = 0;
-        if let Ok(v) = options[1].parse::<i32>() {
-            port = v;
-        } else {
-            log::error!("Wrong local-port");
-            return;
-        }
-        let mut remote_port = 0;
-        if let Ok(v) = options[2].parse::<i32>() {
-            remote_port = v;
-        } else {
-            log::error!("Wrong remote-port");
-            return;
-        }
-        let mut remote_host = "localhost".to_owned();
-        if options.len() > 3 {
-            remote_host = options[3].clone();
+    let forward_options = ["port-forward", "socks5", "reverse-forward"];
+    if forward_options.iter().any(|o| matches.is_present(o)) {
+        let mut id = None;
+        let mut forwards = Vec::new();
+        for (option, p) in forward_options
+            .iter()
+            .flat_map(|o| matches.values_of(o).into_iter().flatten().map(move |p| (*o, p)))
+        {
+            match cli::Forward::parse(option, p) {
+                Ok((forward_id, forward)) => {
+                    if id.get_or_insert(forward_id.clone()) != &forward_id {
+                        log::error!("All forwards must be to the same remote-id");
+                        return;
+                    }
+                    forwards.push(forward);
+                }
+                Err(err) => {
+                    log::error!("{}", err);
+                    return;
+                }
+            }
         }
         common::test_rendezvous_server();
         common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
-        cli::start_one_port_forward(
-            options[0].clone(),
-            port,
-            remote_host,
-            remote_port,
-            key,
-            token,
-        );
+        cli::start_port_forwards(id.unwrap_or_default(), forwards, key, token);
     } else if let Some(p) = matches.value_of("connect") {
         common::test_rendezvous_server();
         common::test_nat_type();
         let key = matches.value_of("key").unwrap_or("").to_owned();
         let token = LocalConfig::get_option("access_token");
         cli::connect_test(p, key, token);
+    } else if let Some(p) = matches.value_of("terminal") {
+        let (id, terminal_id) = match p.split_once(':') {
+            Some((id, terminal_id)) => match terminal_id.parse::<i32>() {
+                Ok(terminal_id) => (id, terminal_id),
+                Err(_) => {
+                    log::error!("Wrong terminal-id");
+                    return;
+                }
+            },
+            None => (p, 0),
+        };
+        common::test_rendezvous_server();
+        common::test_nat_type();
+        let key = matches.value_of("key").unwrap_or("").to_owned();
+        let token = LocalConfig::get_option("access_token");
+        cli::start_terminal(
+            id.to_owned(),
+            terminal_id,
+            matches.is_present("list-terminals"),
+            key,
+            token,
+        );
     } else if let Some(p) = matches.value_of("server") {
         log::info!("id={}", hbb_common::config::Config::get_id());
         crate::start_server(true, false);
@@ -7,7 +7,7 @@ use hbb_common::{
     anyhow::anyhow,
     bail,
     config::Config,
-    libc::{c_char, c_int, c_long, c_void},
+    libc::{c_char, c_int, c_long, c_uchar, c_uint, c_ulong, c_void},
     log,
     message_proto::{DisplayInfo, Resolution},
     regex::{Captures, Regex},
@@ -82,6 +82,43 @@ extern "C" {
 extern "C" {
     fn XOpenDisplay(display_name: *const c_char) -> *mut c_void;
     // fn XCloseDisplay(d: *mut c_void) -> c_int;
+    fn XDefaultRootWindow(display: *mut c_void) -> c_ulong;
+    fn XInternAtom(display: *mut c_void, name: *const c_char, only_if_exists: c_int) -> c_ulong;
+    fn XGetWindowProperty(
+        display: *mut c_void,
+        window: c_ulong,
+        property: c_ulong,
+        long_offset: c_long,
+        long_length: c_long,
+        delete: c_int,
+        req_type: c_ulong,
+        actual_type_return: *mut c_ulong,
+        actual_format_return: *mut c_int,
+        nitems_return: *mut c_ulong,
+        bytes_after_return: *mut c_ulong,
+        prop_return: *mut *mut c_uchar,
+    ) -> c_int;
+    fn XGetGeometry(
+        display: *mut c_void,
+        drawable: c_ulong,
+        root_return: *mut c_ulong,
+        x_return: *mut c_int,
+        y_return: *mut c_int,
+        width_return: *mut c_uint,
+        height_return: *mut c_uint,
+        border_width_return: *mut c_uint,
+        depth_return: *mut c_uint,
+    ) -> c_int;
+    fn XTranslateCoordinates(
+        display: *mut c_void,
+        src_w: c_ulong,
+        dest_w: c_ulong,
+        src_x: c_int,
+        src_y: c_int,
+        dest_x_return: *mut c_int,
+        dest_y_return: *mut c_int,
+        child_return: *mut c_ulong,
+    ) -> c_int;
 }
 
 #[link(name = "Xfixes")]
@@ -184,6 +221,114 @@ pub fn get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
     res
 }
 
+// The items of a window property, `format` bits each, `None` if it's missing.
+unsafe fn get_window_property(
+    d: *mut c_void,
+    window: c_ulong,
+    name: &str,
+) -> Option<(c_int, Vec<c_ulong>, Vec<u8>)> {
+    let name = std::ffi::CString::new(name).ok()?;
+    let atom = XInternAtom(d, name.as_ptr(), 1);
+    if atom == 0 {
+        return None;
+    }
+    let mut actual_type = 0;
+    let mut format = 0;
+    let mut nitems = 0;
+    let mut bytes_after = 0;
+    let mut prop: *mut c_uchar = std::ptr::null_mut();
+    // AnyPropertyType
+    if XGetWindowProperty(
+        d,
+        window,
+        atom,
+        0,
+        c_long::MAX / 4,
+        0,
+        0,
+        &mut actual_type,
+        &mut format,
+        &mut nitems,
+        &mut bytes_after,
+        &mut prop,
+    ) != 0
+        || prop.is_null()
+    {
+        return None;
+    }
+    // Format 32 items are longs, whatever their size
+    let res = match format {
+        32 => (
+            format,
+            std::slice::from_raw_parts(prop as *const c_ulong, nitems as _).to_vec(),
+            vec![],
+        ),
+        8 => (
+            format,
+            vec![],
+            std::slice::from_raw_parts(prop, nitems as _).to_vec(),
+        ),
+        _ => (format, vec![], vec![]),
+    };
+    XFree(prop as _);
+    Some(res)
+}
+
+pub fn get_windows() -> Vec<super::WindowInfo> {
+    let mut windows = Vec::new();
+    DISPLAY.with(|conn| {
+        let Ok(d) = conn.try_borrow_mut() else {
+            return;
+        };
+        if d.is_null() {
+            return;
+        }
+        unsafe {
+            let root = XDefaultRootWindow(*d);
+            let Some((32, clients, _)) = get_window_property(*d, root, "_NET_CLIENT_LIST") else {
+                return;
+            };
+            for window in clients {
+                let title = match get_window_property(*d, window, "_NET_WM_NAME")
+                    .or_else(|| get_window_property(*d, window, "WM_NAME"))
+                {
+                    Some((8, _, title)) => String::from_utf8_lossy(&title).into_owned(),
+                    _ => continue,
+                };
+                let (mut root_return, mut x, mut y) = (0, 0, 0);
+                let (mut width, mut height, mut border, mut depth) = (0, 0, 0, 0);
+                if XGetGeometry(
+                    *d,
+                    window,
+                    &mut root_return,
+                    &mut x,
+                    &mut y,
+                    &mut width,
+                    &mut height,
+                    &mut border,
+                    &mut depth,
+                ) == 0
+                {
+                    continue;
+                }
+                let mut child = 0;
+                if XTranslateCoordinates(*d, window, root, 0, 0, &mut x, &mut y, &mut child) == 0 {
+                    continue;
+                }
+                windows.push(super::WindowInfo {
+                    id: window as _,
+                    title,
+                    x,
+                    y,
+                    width: width as _,
+                    height: height as _,
+                });
+            }
+        }
+    });
+    windows
+}
+
 pub fn get_cursor() -> ResultType<Option<u64>> {
     let mut res = None;
     DISPLAY.with(|conn| {
@@ -40,6 +40,18 @@ lazy_static::lazy_static! {
     static ref INSTALLING_SERVICE: Arc<Mutex<bool>>= Default::default();
 }
 
+/// A visible top level window, in desktop coordinates.
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub title: String,
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
 pub fn installing_service() -> bool {
     INSTALLING_SERVICE.lock().unwrap().clone()
 }
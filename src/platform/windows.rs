@@ -114,6 +114,40 @@ pub fn get_focused_display(displays: Vec<DisplayInfo>) -> Option<usize> {
     }
 }
 
+pub fn get_windows() -> Vec<super::WindowInfo> {
+    unsafe extern "system" fn enum_window(hwnd: HWND, lparam: LPARAM) -> BOOL {
+        let windows = &mut *(lparam as *mut Vec<super::WindowInfo>);
+        if IsWindowVisible(hwnd) == FALSE || IsIconic(hwnd) != FALSE {
+            return TRUE;
+        }
+        let len = GetWindowTextLengthW(hwnd);
+        if len <= 0 {
+            return TRUE;
+        }
+        let mut title = vec![0u16; len as usize + 1];
+        let len = GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as _);
+        let mut rect: RECT = mem::zeroed();
+        if GetWindowRect(hwnd, &mut rect as *mut RECT) == 0 {
+            return TRUE;
+        }
+        windows.push(super::WindowInfo {
+            id: hwnd as u64,
+            title: String::from_utf16_lossy(&title[..len.max(0) as usize]),
+            x: rect.left,
+            y: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        });
+        TRUE
+    }
+
+    let mut windows = Vec::new();
+    unsafe {
+        EnumWindows(Some(enum_window), &mut windows as *mut _ as LPARAM);
+    }
+    windows
+}
+
 pub fn get_cursor_pos() -> Option<(i32, i32)> {
     unsafe {
         #[allow(invalid_value)]
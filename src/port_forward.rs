@@ -1,6 +1,13 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{Arc, RwLock},
+};
 
-use crate::client::*;
+use crate::{
+    client::*,
+    reverse_tunnel::{self, Channels, Frame},
+};
 use hbb_common::{
     allow_err, bail,
     config::READ_TIMEOUT,
@@ -9,12 +16,23 @@ use hbb_common::{
     message_proto::*,
     protobuf::Message as _,
     rendezvous_proto::ConnType,
-    tcp, timeout,
-    tokio::{self, net::TcpStream, sync::mpsc},
+    sleep, tcp, timeout,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::{mpsc, Mutex},
+    },
     tokio_util::codec::{BytesCodec, Framed},
     ResultType, Stream,
 };
 
+lazy_static::lazy_static! {
+    // The tunnels of a session share its LoginConfigHandler, whose port_forward
+    // is the address sent at login, so only one of them logs in at a time.
+    static ref LOGIN_LOCK: Mutex<()> = Default::default();
+}
+
 fn run_rdp(port: u16) {
     std::process::Command::new("cmdkey")
         .arg("/delete:localhost")
@@ -70,7 +88,7 @@ pub async fn listen(
                 let id = id.clone();
                 let password = password.clone();
                 let mut forward = Framed::new(forward, BytesCodec::new());
-                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), &mut forward, key, token, is_rdp).await {
+                match connect_and_login(&id, &password, &mut ui_receiver, interface.clone(), Some(&mut forward), key, token, is_rdp).await {
                     Ok(Some(stream)) => {
                         let interface = interface.clone();
                         tokio::spawn(async move {
@@ -103,12 +121,285 @@ pub async fn listen(
     Ok(())
 }
 
+/// A local listener of [`listen_tunnels`].
+#[derive(Debug, Clone)]
+pub struct TunnelListener {
+    /// The local address to listen on
+    pub addr: String,
+    /// `None` for a SOCKS5 proxy tunneling each CONNECT request to the
+    /// requested address, like `ssh -D`
+    pub target: Option<(String, i32)>,
+}
+
+/// A local connection waiting for the remote side to connect its channel.
+struct Pending {
+    socket: TcpStream,
+    from: SocketAddr,
+    target: String,
+    socks5: bool,
+}
+
+/// Like [`listen`] for several `listeners`, logging in once: each accepted
+/// connection is a channel of the session, see [`reverse_tunnel`]. The remote
+/// side checks every channel as any port forward.
+pub async fn listen_tunnels(
+    id: String,
+    password: String,
+    listeners: Vec<TunnelListener>,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let mut bound = Vec::new();
+    for TunnelListener { addr, target } in listeners {
+        let listener = tcp::new_listener(addr, true).await?;
+        log::info!("listening on {:?}", listener.local_addr()?);
+        bound.push((listener, target));
+    }
+    let (tx_pending, rx_pending) = mpsc::unbounded_channel();
+    let accepts: Vec<_> = bound
+        .into_iter()
+        .map(|(listener, target)| tokio::spawn(accept_tunnels(listener, target, tx_pending.clone())))
+        .collect();
+    let res = run_tunnels(
+        id,
+        password,
+        interface,
+        ui_receiver,
+        rx_pending,
+        key,
+        token,
+        lc,
+    )
+    .await;
+    accepts.iter().for_each(|a| a.abort());
+    res
+}
+
+/// Accept the connections of `listener`, reading the CONNECT request of
+/// SOCKS5 clients in a task of their own so a slow client holds up no other.
+async fn accept_tunnels(
+    listener: TcpListener,
+    target: Option<(String, i32)>,
+    tx_pending: mpsc::UnboundedSender<Pending>,
+) {
+    loop {
+        let (mut socket, from) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                log::error!("Failed to accept: {}", err);
+                sleep(0.1).await;
+                continue;
+            }
+        };
+        log::info!("new connection from {:?}", from);
+        let target = target.clone();
+        let tx_pending = tx_pending.clone();
+        tokio::spawn(async move {
+            let (target, socks5) = match target {
+                Some((host, port)) => (format!("{}:{}", host, port), false),
+                None => match timeout(READ_TIMEOUT, socks5_handshake(&mut socket)).await {
+                    Ok(Ok((host, port))) => (format!("{}:{}", host, port), true),
+                    Ok(Err(err)) => {
+                        log::error!("SOCKS5 handshake from {:?} failed: {}", from, err);
+                        return;
+                    }
+                    Err(_) => {
+                        log::error!("SOCKS5 handshake from {:?} timed out", from);
+                        return;
+                    }
+                },
+            };
+            tx_pending
+                .send(Pending {
+                    socket,
+                    from,
+                    target,
+                    socks5,
+                })
+                .ok();
+        });
+    }
+}
+
+async fn run_tunnels(
+    id: String,
+    password: String,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    rx_pending: mpsc::UnboundedReceiver<Pending>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+) -> ResultType<()> {
+    let mut ui_receiver = ui_receiver;
+    let login = LOGIN_LOCK.lock().await;
+    lc.write().unwrap().port_forward = (reverse_tunnel::TUNNEL_HOST.to_owned(), 0);
+    let res = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await;
+    drop(login);
+    let Some(mut stream) = res? else {
+        return Ok(());
+    };
+    forward_tunnels(&mut stream, rx_pending, &mut ui_receiver).await
+}
+
+/// Tunnel the `pending` local connections over the logged in `stream`.
+async fn forward_tunnels(
+    stream: &mut Stream,
+    mut rx_pending: mpsc::UnboundedReceiver<Pending>,
+    ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
+) -> ResultType<()> {
+    let mut channels = Channels::new();
+    let mut pending = HashMap::new();
+    let mut next_channel = 0u32;
+    loop {
+        tokio::select! {
+            Some(p) = rx_pending.recv() => {
+                log::info!("tunnel channel {} from {:?} to {}", next_channel, p.from, p.target);
+                allow_err!(stream.send_bytes(Frame::Connect(next_channel, p.target.clone()).encode()).await);
+                pending.insert(next_channel, p);
+                next_channel = next_channel.wrapping_add(1);
+            }
+            res = stream.next() => {
+                if let Some(Ok(bytes)) = res {
+                    match Frame::decode(&bytes)? {
+                        Frame::Open(channel) if pending.contains_key(&channel) => {
+                            let Some(Pending { mut socket, socks5, .. }) = pending.remove(&channel) else {
+                                continue;
+                            };
+                            channels.spawn(channel, async move {
+                                if socks5 {
+                                    socks5_reply(&mut socket, SOCKS5_SUCCEEDED).await?;
+                                }
+                                Ok(socket)
+                            }, false)?;
+                        }
+                        Frame::Close(channel) if pending.contains_key(&channel) => {
+                            let Some(Pending { mut socket, target, socks5, .. }) = pending.remove(&channel) else {
+                                continue;
+                            };
+                            log::error!("tunnel to {} refused by the remote side", target);
+                            if socks5 {
+                                tokio::spawn(async move {
+                                    socks5_reply(&mut socket, SOCKS5_GENERAL_FAILURE).await.ok();
+                                });
+                            }
+                        }
+                        frame => channels.handle(frame),
+                    }
+                } else {
+                    break;
+                }
+            },
+            frame = channels.next() => {
+                allow_err!(stream.send_bytes(frame.encode()).await);
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Make the remote side listen on `remote_port` and tunnel the connections it
+/// accepts to `local_host:local_port`, like `ssh -R`.
+pub async fn listen_reverse(
+    id: String,
+    password: String,
+    remote_port: i32,
+    interface: impl Interface,
+    ui_receiver: mpsc::UnboundedReceiver<Data>,
+    key: &str,
+    token: &str,
+    lc: Arc<RwLock<LoginConfigHandler>>,
+    local_host: String,
+    local_port: i32,
+) -> ResultType<()> {
+    let mut ui_receiver = ui_receiver;
+    let login = LOGIN_LOCK.lock().await;
+    lc.write().unwrap().port_forward = (reverse_tunnel::TUNNEL_HOST.to_owned(), 0);
+    let res = connect_and_login(
+        &id,
+        &password,
+        &mut ui_receiver,
+        interface.clone(),
+        None,
+        key,
+        token,
+        false,
+    )
+    .await;
+    drop(login);
+    let Some(mut stream) = res? else {
+        return Ok(());
+    };
+    let Ok(port) = u16::try_from(remote_port) else {
+        bail!("Invalid port {}", remote_port);
+    };
+    // Asked for once logged in, the remote side binds nothing for an unauthorized peer
+    stream.send_bytes(Frame::Listen(port).encode()).await?;
+    match timeout(READ_TIMEOUT, stream.next()).await {
+        Ok(Some(Ok(bytes))) if Frame::decode(&bytes)? == Frame::Listen(port) => {}
+        _ => bail!("Failed to listen on remote port {}", remote_port),
+    }
+    let target = format!("{}:{}", local_host, local_port);
+    log::info!("remote port {} tunneled to {}", remote_port, target);
+    let mut channels = Channels::new();
+    loop {
+        tokio::select! {
+            res = stream.next() => {
+                if let Some(Ok(bytes)) = res {
+                    match Frame::decode(&bytes)? {
+                        Frame::Open(channel) => {
+                            log::info!("new reverse tunnel connection {}", channel);
+                            channels.connect(channel, target.clone())?;
+                        }
+                        frame => channels.handle(frame),
+                    }
+                } else {
+                    break;
+                }
+            },
+            frame = channels.next() => {
+                allow_err!(stream.send_bytes(frame.encode()).await);
+            }
+            d = ui_receiver.recv() => {
+                match d {
+                    Some(Data::Close) | None => {
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
 async fn connect_and_login(
     id: &str,
     password: &str,
     ui_receiver: &mut mpsc::UnboundedReceiver<Data>,
     interface: impl Interface,
-    forward: &mut Framed<TcpStream, BytesCodec>,
+    mut forward: Option<&mut Framed<TcpStream, BytesCodec>>,
     key: &str,
     token: &str,
     is_rdp: bool,
@@ -178,7 +469,7 @@ async fn connect_and_login(
                     _ => {}
                 }
             },
-            res = forward.next() => {
+            res = async { forward.as_mut().unwrap().next().await }, if forward.is_some() => {
                 if let Some(Ok(bytes)) = res {
                     buffer.extend(bytes);
                 } else {
@@ -194,6 +485,75 @@ async fn connect_and_login(
     Ok(Some(stream))
 }
 
+const SOCKS5_VERSION: u8 = 5;
+const SOCKS5_NO_AUTH: u8 = 0;
+const SOCKS5_NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const SOCKS5_CMD_CONNECT: u8 = 1;
+const SOCKS5_ATYP_IPV4: u8 = 1;
+const SOCKS5_ATYP_DOMAIN: u8 = 3;
+const SOCKS5_ATYP_IPV6: u8 = 4;
+const SOCKS5_SUCCEEDED: u8 = 0;
+const SOCKS5_GENERAL_FAILURE: u8 = 1;
+const SOCKS5_COMMAND_NOT_SUPPORTED: u8 = 7;
+const SOCKS5_ADDRESS_NOT_SUPPORTED: u8 = 8;
+
+/// Negotiate no authentication and read the CONNECT request of a SOCKS5
+/// client, see RFC 1928. Returns the requested host and port.
+async fn socks5_handshake(socket: &mut TcpStream) -> ResultType<(String, i32)> {
+    let mut head = [0u8; 2];
+    socket.read_exact(&mut head).await?;
+    if head[0] != SOCKS5_VERSION {
+        bail!("unsupported SOCKS version {}", head[0]);
+    }
+    let mut methods = vec![0u8; head[1] as usize];
+    socket.read_exact(&mut methods).await?;
+    if !methods.contains(&SOCKS5_NO_AUTH) {
+        socket
+            .write_all(&[SOCKS5_VERSION, SOCKS5_NO_ACCEPTABLE_METHOD])
+            .await?;
+        bail!("no supported authentication method");
+    }
+    socket.write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH]).await?;
+    let mut request = [0u8; 4];
+    socket.read_exact(&mut request).await?;
+    if request[1] != SOCKS5_CMD_CONNECT {
+        socks5_reply(socket, SOCKS5_COMMAND_NOT_SUPPORTED).await?;
+        bail!("unsupported command {}", request[1]);
+    }
+    let host = match request[3] {
+        SOCKS5_ATYP_IPV4 => {
+            let mut ip = [0u8; 4];
+            socket.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        SOCKS5_ATYP_DOMAIN => {
+            let mut name = vec![0u8; socket.read_u8().await? as usize];
+            socket.read_exact(&mut name).await?;
+            String::from_utf8(name)?
+        }
+        SOCKS5_ATYP_IPV6 => {
+            let mut ip = [0u8; 16];
+            socket.read_exact(&mut ip).await?;
+            // The remote side joins host and port with a colon
+            format!("[{}]", Ipv6Addr::from(ip))
+        }
+        atyp => {
+            socks5_reply(socket, SOCKS5_ADDRESS_NOT_SUPPORTED).await?;
+            bail!("unsupported address type {}", atyp);
+        }
+    };
+    let port = socket.read_u16().await?;
+    Ok((host, port as _))
+}
+
+async fn socks5_reply(socket: &mut TcpStream, reply: u8) -> ResultType<()> {
+    // The bound address means nothing for a tunnel, clients ignore it
+    socket
+        .write_all(&[SOCKS5_VERSION, reply, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
 async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) -> ResultType<()> {
     log::info!("new port forwarding connection started");
     let mut forward = forward;
@@ -218,3 +578,137 @@ async fn run_forward(forward: Framed<TcpStream, BytesCodec>, stream: Stream) ->
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_socks5_handshake() {
+        run_socks5_handshake();
+    }
+
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_socks5_handshake() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests: Vec<(Vec<u8>, Option<(&str, i32)>)> = vec![
+            (
+                [&[5, 2, 2, 0, 5, 1, 0, 3, 11][..], &b"example.com"[..], &[1, 187][..]].concat(),
+                Some(("example.com", 443)),
+            ),
+            (
+                [&[5, 1, 0, 5, 1, 0, 4][..], &[0; 15][..], &[1, 0, 22][..]].concat(),
+                Some(("[::1]", 22)),
+            ),
+            // BIND
+            (vec![5, 1, 0, 5, 2, 0, 1], None),
+        ];
+        for (request, expected) in requests {
+            let client = tokio::spawn(async move {
+                let mut client = TcpStream::connect(addr).await.unwrap();
+                client.write_all(&request).await.unwrap();
+                let mut response = vec![];
+                client.read_to_end(&mut response).await.ok();
+                response
+            });
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let res = socks5_handshake(&mut socket).await;
+            match expected {
+                Some((host, port)) => {
+                    assert_eq!(res.unwrap(), (host.to_owned(), port));
+                    socks5_reply(&mut socket, SOCKS5_SUCCEEDED).await.unwrap();
+                }
+                None => assert!(res.is_err()),
+            }
+            drop(socket);
+            let response = client.await.unwrap();
+            assert_eq!(&response[..2], &[SOCKS5_VERSION, SOCKS5_NO_AUTH]);
+            let reply = if expected.is_some() {
+                SOCKS5_SUCCEEDED
+            } else {
+                SOCKS5_COMMAND_NOT_SUPPORTED
+            };
+            assert_eq!(&response[2..4], &[SOCKS5_VERSION, reply]);
+        }
+    }
+
+    #[test]
+    fn test_accept_tunnels() {
+        run_accept_tunnels();
+    }
+
+    // A client stalling its handshake holds up no other
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_accept_tunnels() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx_pending, mut rx_pending) = mpsc::unbounded_channel();
+        tokio::spawn(accept_tunnels(listener, None, tx_pending));
+        let _stalled = TcpStream::connect(addr).await.unwrap();
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        let request = [&[5, 1, 0, 5, 1, 0, 3, 9][..], &b"localhost"[..], &[0, 80][..]].concat();
+        client.write_all(&request).await.unwrap();
+        let pending = timeout(1000, rx_pending.recv()).await.unwrap().unwrap();
+        assert_eq!(pending.target, "localhost:80");
+        assert!(pending.socks5);
+    }
+
+    #[test]
+    fn test_forward_tunnels() {
+        run_forward_tunnels();
+    }
+
+    // A local connection tunneled to an echo server through a session on loopback
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_forward_tunnels() {
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = echo.accept().await.unwrap();
+            let mut buf = [0u8; 4];
+            socket.read_exact(&mut buf).await.unwrap();
+            socket.write_all(&buf).await.unwrap();
+        });
+
+        let session = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let session_addr = session.local_addr().unwrap();
+        let (local, remote) = tokio::join!(TcpStream::connect(session_addr), session.accept());
+        let mut local = Stream::from(local.unwrap(), session_addr);
+        let (remote, remote_addr) = remote.unwrap();
+        let mut remote = Stream::from(remote, remote_addr);
+        // The controlled side, as the port forward loop of a connection
+        tokio::spawn(async move {
+            let mut channels = Channels::new();
+            loop {
+                tokio::select! {
+                    Some(Ok(bytes)) = remote.next() => match Frame::decode(&bytes).unwrap() {
+                        Frame::Connect(channel, addr) => {
+                            let socket = async move { Ok(TcpStream::connect(addr).await?) };
+                            channels.spawn(channel, socket, true).unwrap();
+                        }
+                        frame => channels.handle(frame),
+                    },
+                    frame = channels.next() => remote.send_bytes(frame.encode()).await.unwrap(),
+                }
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx_pending, rx_pending) = mpsc::unbounded_channel();
+        let target = Some(("127.0.0.1".to_owned(), echo_port as i32));
+        tokio::spawn(accept_tunnels(listener, target, tx_pending));
+        let (tx_ui, mut rx_ui) = mpsc::unbounded_channel();
+        let tunnels =
+            tokio::spawn(async move { forward_tunnels(&mut local, rx_pending, &mut rx_ui).await });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        timeout(3000, client.read_exact(&mut buf)).await.unwrap().unwrap();
+        assert_eq!(&buf, b"ping");
+        tx_ui.send(Data::Close).unwrap();
+        assert!(tunnels.await.unwrap().is_ok());
+    }
+}
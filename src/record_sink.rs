@@ -0,0 +1,118 @@
+//! Destinations for session recordings.
+//!
+//! The recorder writes a WebM/MP4 file and reports its progress as
+//! [`RecordState`] events. Every configured [`RecordSink`] receives those
+//! events on a background thread and moves the data where it has to go.
+//!
+//! Sinks are selected with the `record-sinks` option, a comma separated list
+//! of `http`, `local` and `s3`. `http` is also enabled whenever the api server
+//! asks for uploads. `encrypt` in the list makes the recorder write encrypted
+//! `.enc` files instead, see [`encryption_key`], which the sinks then move as
+//! any recording. Use `recording decrypt` to read one back.
+
+use hbb_common::{config::Config, log, ResultType};
+use scrap::record::RecordState;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+mod encrypt;
+mod local;
+mod s3;
+
+pub use encrypt::{decrypt_file, encryption_key, parse_key};
+pub use local::LocalRotationSink;
+pub use s3::{S3Config, S3Sink};
+
+pub const OPTION_RECORD_SINKS: &str = "record-sinks";
+
+/// Receives the progress of one recorder.
+///
+/// Methods follow the [`RecordState`] events. An error disables the sink until
+/// the recorder starts its next file.
+pub trait RecordSink: Send {
+    fn name(&self) -> &'static str;
+    /// A new file was created at `filepath`.
+    fn new_file(&mut self, filepath: &str) -> ResultType<()>;
+    /// Frames were appended to the current file.
+    fn frame(&mut self) -> ResultType<()>;
+    /// The file is complete; its header may have been rewritten.
+    fn tail(&mut self) -> ResultType<()>;
+    /// The recorder deleted the file because it was too short.
+    fn remove(&mut self) -> ResultType<()>;
+}
+
+/// Sinks selected by the current options.
+pub fn configured_sinks() -> Vec<Box<dyn RecordSink>> {
+    let option = Config::get_option(OPTION_RECORD_SINKS);
+    let mut names: Vec<&str> = option
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty() && *s != "encrypt")
+        .collect();
+    if crate::hbbs_http::record_upload::is_enable() && !names.contains(&"http") {
+        names.push("http");
+    }
+    names.sort();
+    names.dedup();
+
+    let mut sinks: Vec<Box<dyn RecordSink>> = Vec::new();
+    for name in names {
+        let sink: ResultType<Box<dyn RecordSink>> = match name {
+            "http" => Ok(Box::new(crate::hbbs_http::record_upload::RecordUploader::new())),
+            "local" => Ok(Box::new(LocalRotationSink::from_options())),
+            "s3" => S3Config::from_options().map(|c| Box::new(S3Sink::new(c)) as _),
+            _ => {
+                log::error!("unknown record sink: {}", name);
+                continue;
+            }
+        };
+        match sink {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => log::error!("record sink {} disabled: {}", name, e),
+        }
+    }
+    sinks
+}
+
+/// Start the configured sinks, returning the sender for the recorder.
+///
+/// `None` if no sink is configured.
+pub fn start() -> Option<Sender<RecordState>> {
+    let sinks = configured_sinks();
+    if sinks.is_empty() {
+        return None;
+    }
+    let (tx, rx) = channel();
+    run(rx, sinks);
+    Some(tx)
+}
+
+/// Feed every event from `rx` to `sinks` on a background thread.
+pub fn run(rx: Receiver<RecordState>, sinks: Vec<Box<dyn RecordSink>>) {
+    let mut sinks: Vec<(Box<dyn RecordSink>, bool)> =
+        sinks.into_iter().map(|sink| (sink, false)).collect();
+    std::thread::spawn(move || loop {
+        let state = match rx.recv() {
+            Ok(state) => state,
+            Err(e) => {
+                log::trace!("record sink thread stop: {}", e);
+                break;
+            }
+        };
+        for (sink, running) in sinks.iter_mut() {
+            let res = match &state {
+                RecordState::NewFile(filepath) => {
+                    *running = true;
+                    sink.new_file(filepath)
+                }
+                _ if !*running => continue,
+                RecordState::NewFrame => sink.frame(),
+                RecordState::WriteTail => sink.tail(),
+                RecordState::RemoveFile => sink.remove(),
+            };
+            if let Err(e) = res {
+                *running = false;
+                log::error!("record sink {} stop: {}", sink.name(), e);
+            }
+        }
+    });
+}
@@ -0,0 +1,84 @@
+use super::OPTION_RECORD_SINKS;
+use hbb_common::{
+    bail,
+    config::Config,
+    sodiumoxide::{
+        base64,
+        crypto::secretbox::{self, Key},
+    },
+    ResultType,
+};
+use scrap::seal::unseal;
+use std::path::Path;
+
+/// Base64 encoded 32-byte secretbox key
+pub const OPTION_ENCRYPTION_KEY: &str = "record-encryption-key";
+
+/// The key recordings are encrypted with at rest, `None` unless `encrypt` is
+/// one of the record sinks.
+///
+/// The recorder seals the file as it writes it, see [`scrap::seal`], so it is
+/// never unencrypted on disk and the other sinks only see the `.enc` file. An
+/// invalid key is an error, nothing must be recorded then.
+pub fn encryption_key() -> ResultType<Option<Key>> {
+    let enabled = Config::get_option(OPTION_RECORD_SINKS)
+        .split(',')
+        .any(|s| s.trim() == "encrypt");
+    if !enabled {
+        return Ok(None);
+    }
+    parse_key(&Config::get_option(OPTION_ENCRYPTION_KEY)).map(Some)
+}
+
+pub fn parse_key(encoded: &str) -> ResultType<Key> {
+    let Ok(raw) = base64::decode(encoded.trim(), base64::Variant::Original) else {
+        bail!("{} is not valid base64", OPTION_ENCRYPTION_KEY);
+    };
+    match Key::from_slice(&raw) {
+        Some(key) => Ok(key),
+        None => bail!("{} must be {} bytes", OPTION_ENCRYPTION_KEY, secretbox::KEYBYTES),
+    }
+}
+
+/// Decrypt an `.enc` recording to `output`.
+///
+/// Returns whether the recording is complete, it is not if the recorder
+/// stopped without finishing the file.
+pub fn decrypt_file(input: &Path, output: &Path, key: &Key) -> ResultType<bool> {
+    let (plain, complete) = match unseal(&std::fs::read(input)?, key) {
+        Ok(unsealed) => unsealed,
+        Err(e) => bail!("{}: {}", input.display(), e),
+    };
+    std::fs::write(output, plain)?;
+    Ok(complete)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use scrap::record::RecordFile;
+    use std::io::{prelude::*, SeekFrom};
+
+    #[test]
+    fn test_decrypt_recording_file() {
+        let dir = std::env::temp_dir().join(format!("record-encrypt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let encrypted = dir.join("incoming_1_20240101000000000_display0_vp9.webm.enc");
+        let key = secretbox::gen_key();
+
+        let mut file = RecordFile::create(encrypted.to_str().unwrap(), Some(key.clone())).unwrap();
+        file.write_all(b"HEADER--frame1").unwrap();
+        file.flush().unwrap();
+        assert!(!std::fs::read(&encrypted).unwrap().windows(6).any(|w| w == b"frame1"));
+        file.write_all(b"frame2").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"header").unwrap();
+        file.finish().unwrap();
+
+        let restored = dir.join("restored.webm");
+        assert!(decrypt_file(&encrypted, &restored, &key).unwrap());
+        assert_eq!(std::fs::read(&restored).unwrap(), b"header--frame1frame2");
+        assert!(decrypt_file(&encrypted, &restored, &secretbox::gen_key()).is_err());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,217 @@
+use super::RecordSink;
+use hbb_common::{config::Config, log, ResultType};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+pub const OPTION_MAX_SIZE_MB: &str = "record-local-max-size-mb";
+pub const OPTION_MAX_AGE_DAYS: &str = "record-local-max-age-days";
+// Modified this recently, a file may still be written by a recorder of another process
+const IN_PROGRESS_AGE: Duration = Duration::from_secs(10 * 60);
+
+lazy_static::lazy_static! {
+    // The files the recorders of this process are writing
+    static ref WRITING: Mutex<HashSet<PathBuf>> = Default::default();
+}
+
+/// Keeps the recording directory within a size and age budget.
+///
+/// Whenever a file is started or completed, the oldest recordings (plain or
+/// encrypted) are deleted until the directory fits. Files still being
+/// written, by any recorder of this process or modified in the last minutes,
+/// are never touched.
+pub struct LocalRotationSink {
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    current: Option<PathBuf>,
+}
+
+impl LocalRotationSink {
+    pub fn new(max_size: Option<u64>, max_age: Option<Duration>) -> Self {
+        Self {
+            max_size,
+            max_age,
+            current: None,
+        }
+    }
+
+    pub fn from_options() -> Self {
+        let max_size = Config::get_option(OPTION_MAX_SIZE_MB)
+            .parse::<u64>()
+            .ok()
+            .filter(|mb| *mb > 0)
+            .map(|mb| mb * 1024 * 1024);
+        let max_age = Config::get_option(OPTION_MAX_AGE_DAYS)
+            .parse::<u64>()
+            .ok()
+            .filter(|days| *days > 0)
+            .map(|days| Duration::from_secs(days * 24 * 3600));
+        Self::new(max_size, max_age)
+    }
+
+    // Done with the current file, other sinks may rotate it
+    fn release(&mut self) {
+        if let Some(path) = self.current.take() {
+            WRITING.lock().unwrap().remove(&path);
+        }
+    }
+
+    fn rotate(&self) -> ResultType<()> {
+        let Some(dir) = self.current.as_ref().and_then(|p| p.parent()) else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if !is_recording(&path)
+                || self.current.as_ref() == Some(&path)
+                || WRITING.lock().unwrap().contains(&path)
+            {
+                continue;
+            }
+            let meta = std::fs::metadata(&path)?;
+            let modified = meta.modified().unwrap_or(now);
+            if now.duration_since(modified).unwrap_or_default() < IN_PROGRESS_AGE {
+                continue;
+            }
+            files.push((modified, meta.len(), path));
+        }
+        files.sort();
+
+        let mut total: u64 = files.iter().map(|f| f.1).sum();
+        for (modified, len, path) in files {
+            let expired = self
+                .max_age
+                .map_or(false, |age| now.duration_since(modified).unwrap_or_default() > age);
+            let oversize = self.max_size.map_or(false, |max| total > max);
+            if !expired && !oversize {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(_) => {
+                    total -= len;
+                    log::info!("rotated recording {}", path.display());
+                }
+                Err(e) => log::error!("failed to rotate {}: {}", path.display(), e),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_recording(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    (name.starts_with("incoming_") || name.starts_with("outgoing_"))
+        && [".webm", ".mp4", ".cast", ".webm.enc", ".mp4.enc", ".cast.enc"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+impl RecordSink for LocalRotationSink {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+
+    fn new_file(&mut self, filepath: &str) -> ResultType<()> {
+        self.release();
+        let path = PathBuf::from(filepath);
+        WRITING.lock().unwrap().insert(path.clone());
+        self.current = Some(path);
+        self.rotate()
+    }
+
+    fn frame(&mut self) -> ResultType<()> {
+        Ok(())
+    }
+
+    fn tail(&mut self) -> ResultType<()> {
+        let res = self.rotate();
+        self.release();
+        res
+    }
+
+    fn remove(&mut self) -> ResultType<()> {
+        self.release();
+        Ok(())
+    }
+}
+
+impl Drop for LocalRotationSink {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A finished recording, modified `ago`
+    fn write_recording(path: &Path, ago: Duration) {
+        std::fs::write(path, vec![0u8; 1024 * 1024]).unwrap();
+        let file = std::fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - ago).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_keeps_newest_within_budget() {
+        let dir = std::env::temp_dir().join(format!("record-rotation-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = [
+            "incoming_1_20240101000000000_display0_vp9.webm",
+            "incoming_1_20240101000001000_display0_vp9.webm.enc",
+            "incoming_1_20240101000002000_display0_vp9.webm",
+            "notes.txt",
+        ];
+        for (i, name) in names.iter().enumerate() {
+            write_recording(&dir.join(name), Duration::from_secs(3600 - i as u64 * 60));
+        }
+        let current = dir.join("incoming_1_20240101000003000_display0_vp9.webm");
+        std::fs::write(&current, vec![0u8; 1024 * 1024]).unwrap();
+
+        let mut sink = LocalRotationSink::new(Some(2 * 1024 * 1024), None);
+        sink.new_file(current.to_str().unwrap()).unwrap();
+
+        assert!(!dir.join(names[0]).exists());
+        assert!(dir.join(names[1]).exists());
+        assert!(dir.join(names[2]).exists());
+        assert!(dir.join(names[3]).exists());
+        assert!(current.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_rotation_skips_files_in_progress() {
+        let dir = std::env::temp_dir().join(format!("record-in-progress-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let old = dir.join("incoming_1_20240101000000000_display0_vp9.webm");
+        write_recording(&old, Duration::from_secs(3600));
+        // Another process writing
+        let recent = dir.join("outgoing_2_20240101000001000_display0_vp9.webm");
+        write_recording(&recent, Duration::from_secs(60));
+        // Another recorder of this process, idle for long
+        let display1 = dir.join("incoming_1_20240101000002000_display1_vp9.webm");
+        write_recording(&display1, Duration::from_secs(1800));
+        let mut writing = LocalRotationSink::new(None, None);
+        writing.new_file(display1.to_str().unwrap()).unwrap();
+
+        let mut sink = LocalRotationSink::new(Some(0), None);
+        let current = dir.join("incoming_1_20240101000003000_display0_vp9.webm");
+        sink.new_file(current.to_str().unwrap()).unwrap();
+        assert!(!old.exists());
+        assert!(recent.exists());
+        assert!(display1.exists());
+
+        // Complete, it can go
+        writing.tail().unwrap();
+        sink.tail().unwrap();
+        assert!(!display1.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,535 @@
+use super::RecordSink;
+use crate::hbbs_http::create_http_client;
+use hbb_common::{bail, config::Config, log, ResultType};
+use reqwest::{
+    blocking::{Client, Response},
+    Method,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{prelude::*, SeekFrom},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+pub const OPTION_S3_ENDPOINT: &str = "record-s3-endpoint";
+pub const OPTION_S3_BUCKET: &str = "record-s3-bucket";
+pub const OPTION_S3_REGION: &str = "record-s3-region";
+pub const OPTION_S3_ACCESS_KEY: &str = "record-s3-access-key";
+pub const OPTION_S3_SECRET_KEY: &str = "record-s3-secret-key";
+pub const OPTION_S3_PREFIX: &str = "record-s3-prefix";
+
+// S3 rejects non-final multipart parts smaller than 5 MiB
+const PART_SIZE: u64 = 5 * 1024 * 1024;
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// `http(s)://host[:port]`, buckets are addressed path-style
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prepended to the file name to build the object key
+    pub prefix: String,
+}
+
+impl S3Config {
+    pub fn from_options() -> ResultType<Self> {
+        let get = |key: &str| Config::get_option(key).trim().to_owned();
+        let config = Self {
+            endpoint: get(OPTION_S3_ENDPOINT).trim_end_matches('/').to_owned(),
+            bucket: get(OPTION_S3_BUCKET),
+            region: get(OPTION_S3_REGION),
+            access_key: get(OPTION_S3_ACCESS_KEY),
+            secret_key: get(OPTION_S3_SECRET_KEY),
+            prefix: get(OPTION_S3_PREFIX),
+        };
+        for (key, value) in [
+            (OPTION_S3_ENDPOINT, &config.endpoint),
+            (OPTION_S3_BUCKET, &config.bucket),
+            (OPTION_S3_ACCESS_KEY, &config.access_key),
+            (OPTION_S3_SECRET_KEY, &config.secret_key),
+        ] {
+            if value.is_empty() {
+                bail!("{} is not set", key);
+            }
+        }
+        let region = if config.region.is_empty() {
+            "us-east-1".to_owned()
+        } else {
+            config.region
+        };
+        Ok(Self { region, ..config })
+    }
+}
+
+struct Part {
+    number: u32,
+    etag: String,
+    start: u64,
+    end: u64,
+}
+
+/// Streams recordings to an S3-compatible object store.
+///
+/// Once 5 MiB have been written a multipart upload is started and every full
+/// part is sent as the recording grows. On completion the rest is sent, the
+/// first part is uploaded again to pick up the header written by the muxer on
+/// finalize, and the upload is completed. Shorter recordings are sent with a
+/// single put.
+pub struct S3Sink {
+    client: Client,
+    config: S3Config,
+    filepath: String,
+    key: String,
+    upload_id: Option<String>,
+    parts: Vec<Part>,
+    last_check: Instant,
+}
+
+impl S3Sink {
+    pub fn new(config: S3Config) -> Self {
+        Self::with_client(config, create_http_client())
+    }
+
+    fn with_client(config: S3Config, client: Client) -> Self {
+        Self {
+            client,
+            config,
+            filepath: Default::default(),
+            key: Default::default(),
+            upload_id: None,
+            parts: Vec::new(),
+            last_check: Instant::now(),
+        }
+    }
+
+    fn uploaded(&self) -> u64 {
+        self.parts.last().map_or(0, |p| p.end)
+    }
+
+    fn send(&self, method: Method, query: &[(&str, &str)], body: Vec<u8>) -> ResultType<Response> {
+        let now = chrono::Utc::now();
+        let (url, headers) = sign(&self.config, &method, &self.key, query, &body, now)?;
+        let mut req = self.client.request(method, url);
+        for (name, value) in headers {
+            req = req.header(name, value);
+        }
+        let resp = req.body(body).send()?;
+        if !resp.status().is_success() {
+            bail!("{}: {}", resp.status(), resp.text().unwrap_or_default());
+        }
+        Ok(resp)
+    }
+
+    fn read_range(&self, start: u64, end: u64) -> ResultType<Vec<u8>> {
+        let mut file = File::open(&self.filepath)?;
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![0u8; (end - start) as usize];
+        file.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn upload_part(&mut self, number: u32, start: u64, end: u64) -> ResultType<String> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            bail!("no multipart upload in progress");
+        };
+        let body = self.read_range(start, end)?;
+        let number_str = number.to_string();
+        let resp = self.send(
+            Method::PUT,
+            &[("partNumber", &number_str), ("uploadId", &upload_id)],
+            body,
+        )?;
+        match resp.headers().get("etag").and_then(|v| v.to_str().ok()) {
+            Some(etag) => Ok(etag.to_owned()),
+            None => bail!("part {} has no ETag", number),
+        }
+    }
+
+    fn push_part(&mut self, end: u64) -> ResultType<()> {
+        if self.upload_id.is_none() {
+            let resp = self.send(Method::POST, &[("uploads", "")], Vec::new())?;
+            let Some(upload_id) = xml_value(&resp.text()?, "UploadId") else {
+                bail!("CreateMultipartUpload returned no UploadId");
+            };
+            self.upload_id = Some(upload_id);
+        }
+        let number = self.parts.len() as u32 + 1;
+        let start = self.uploaded();
+        let etag = self.upload_part(number, start, end)?;
+        self.parts.push(Part {
+            number,
+            etag,
+            start,
+            end,
+        });
+        Ok(())
+    }
+
+    fn complete(&mut self) -> ResultType<()> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            bail!("no multipart upload in progress");
+        };
+        let mut body = String::from("<CompleteMultipartUpload>");
+        for part in &self.parts {
+            body += &format!(
+                "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                part.number, part.etag
+            );
+        }
+        body += "</CompleteMultipartUpload>";
+        let resp = self.send(Method::POST, &[("uploadId", &upload_id)], body.into_bytes())?;
+        // Errors after the headers were sent come back as 200 with an Error body
+        let text = resp.text()?;
+        if text.contains("<Error>") {
+            bail!("CompleteMultipartUpload failed: {}", text);
+        }
+        self.upload_id = None;
+        Ok(())
+    }
+
+    fn abort(&mut self) -> ResultType<()> {
+        if let Some(upload_id) = self.upload_id.take() {
+            self.send(Method::DELETE, &[("uploadId", &upload_id)], Vec::new())?;
+        }
+        self.parts.clear();
+        Ok(())
+    }
+}
+
+impl RecordSink for S3Sink {
+    fn name(&self) -> &'static str {
+        "s3"
+    }
+
+    fn new_file(&mut self, filepath: &str) -> ResultType<()> {
+        if let Err(e) = self.abort() {
+            log::error!("failed to abort unfinished upload of {}: {}", self.key, e);
+        }
+        let Some(filename) = Path::new(filepath).file_name().and_then(|n| n.to_str()) else {
+            bail!("invalid recording path {}", filepath);
+        };
+        self.key = format!("{}{}", self.config.prefix, filename);
+        self.filepath = filepath.to_owned();
+        self.last_check = Instant::now();
+        Ok(())
+    }
+
+    fn frame(&mut self) -> ResultType<()> {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return Ok(());
+        }
+        self.last_check = Instant::now();
+        let len = std::fs::metadata(&self.filepath)?.len();
+        if len.saturating_sub(self.uploaded()) >= PART_SIZE {
+            self.push_part(len)?;
+        }
+        Ok(())
+    }
+
+    fn tail(&mut self) -> ResultType<()> {
+        let len = std::fs::metadata(&self.filepath)?.len();
+        if self.parts.is_empty() {
+            let body = self.read_range(0, len)?;
+            self.send(Method::PUT, &[], body)?;
+        } else {
+            if len > self.uploaded() {
+                self.push_part(len)?;
+            }
+            let (start, end) = (self.parts[0].start, self.parts[0].end);
+            self.parts[0].etag = self.upload_part(1, start, end)?;
+            self.complete()?;
+            self.parts.clear();
+        }
+        log::info!("uploaded recording to {}/{}", self.config.bucket, self.key);
+        Ok(())
+    }
+
+    fn remove(&mut self) -> ResultType<()> {
+        self.abort()
+    }
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(xml[start..end].to_owned())
+}
+
+fn uri_encode(s: &str, keep_slash: bool) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if keep_slash => out.push('/'),
+            _ => out += &format!("%{:02X}", b),
+        }
+    }
+    out
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 64;
+    let mut block = if key.len() > BLOCK {
+        Sha256::digest(key).to_vec()
+    } else {
+        key.to_vec()
+    };
+    block.resize(BLOCK, 0);
+    let ipad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new().chain_update(ipad).chain_update(data).finalize();
+    Sha256::new()
+        .chain_update(opad)
+        .chain_update(inner)
+        .finalize()
+        .to_vec()
+}
+
+/// Build the url and the AWS Signature Version 4 headers for a request.
+fn sign(
+    config: &S3Config,
+    method: &Method,
+    key: &str,
+    query: &[(&str, &str)],
+    body: &[u8],
+    now: chrono::DateTime<chrono::Utc>,
+) -> ResultType<(String, Vec<(&'static str, String)>)> {
+    let endpoint = url::Url::parse(&config.endpoint)?;
+    let Some(host) = endpoint.host_str() else {
+        bail!("{} has no host", config.endpoint);
+    };
+    let host = match endpoint.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    };
+    let path = format!(
+        "{}/{}/{}",
+        endpoint.path().trim_end_matches('/'),
+        uri_encode(&config.bucket, false),
+        uri_encode(key, true)
+    );
+    let mut query: Vec<_> = query
+        .iter()
+        .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+        .collect();
+    query.sort();
+    let query = query
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<_>>()
+        .join("&");
+
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex::encode(Sha256::digest(body));
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        method, path, query, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut signing_key = format!("AWS4{}", config.secret_key).into_bytes();
+    for part in [date.as_str(), config.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        config.access_key, scope, signed_headers, signature
+    );
+
+    let mut url = format!("{}://{}{}", endpoint.scheme(), host, path);
+    if !query.is_empty() {
+        url = format!("{}?{}", url, query);
+    }
+    Ok((
+        url,
+        vec![
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", amz_date),
+            ("authorization", authorization),
+        ],
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        collections::HashMap,
+        io::BufReader,
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+    };
+
+    fn test_config(endpoint: String) -> S3Config {
+        S3Config {
+            endpoint,
+            bucket: "recordings".to_owned(),
+            region: "us-east-1".to_owned(),
+            access_key: "AKIDEXAMPLE".to_owned(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_owned(),
+            prefix: "host1/".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_sign_v4() {
+        let config = test_config("http://127.0.0.1:9000".to_owned());
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let (url, headers) = sign(&config, &Method::PUT, "host1/a b.webm", &[], b"", now).unwrap();
+        assert_eq!(url, "http://127.0.0.1:9000/recordings/host1/a%20b.webm");
+        let auth = &headers.iter().find(|h| h.0 == "authorization").unwrap().1;
+        assert_eq!(
+            auth,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240101/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+             Signature=736a7a40bd823c455a1693271ec4377292162ccde9e8e29eef58c9d5e3a06fe0"
+        );
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[derive(Default)]
+    struct Store {
+        parts: HashMap<u32, Vec<u8>>,
+        objects: HashMap<String, Vec<u8>>,
+    }
+
+    /// Minimal stand-in for an S3-compatible server such as MinIO.
+    fn serve(listener: TcpListener, store: Arc<Mutex<Store>>) {
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let store = store.clone();
+                std::thread::spawn(move || handle_connection(stream.unwrap(), store));
+            }
+        });
+    }
+
+    fn handle_connection(stream: TcpStream, store: Arc<Mutex<Store>>) {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut stream = stream;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut parts = line.split_whitespace();
+            let method = parts.next().unwrap_or_default().to_owned();
+            let target = parts.next().unwrap_or_default().to_owned();
+            let mut len = 0;
+            let mut authorized = false;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                let lower = header.to_lowercase();
+                if let Some(v) = lower.strip_prefix("content-length:") {
+                    len = v.trim().parse().unwrap();
+                }
+                authorized |= lower.starts_with("authorization: aws4-hmac-sha256");
+            }
+            let mut body = vec![0u8; len];
+            reader.read_exact(&mut body).unwrap();
+
+            let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+            let mut store = store.lock().unwrap();
+            let (status, etag, reply) = if !authorized {
+                (403, String::new(), "<Error>AccessDenied</Error>".to_owned())
+            } else if method == "POST" && query == "uploads=" {
+                store.parts.clear();
+                (200, String::new(), "<Result><UploadId>u1</UploadId></Result>".to_owned())
+            } else if method == "PUT" && query.starts_with("partNumber=") {
+                let number: u32 = query[11..query.find('&').unwrap()].parse().unwrap();
+                store.parts.insert(number, body);
+                (200, format!("\"etag{}\"", number), String::new())
+            } else if method == "POST" && query == "uploadId=u1" {
+                let mut object = Vec::new();
+                let mut numbers: Vec<_> = store.parts.keys().cloned().collect();
+                numbers.sort();
+                for n in numbers {
+                    assert!(String::from_utf8_lossy(&body).contains(&format!("etag{}", n)));
+                    object.extend_from_slice(&store.parts[&n]);
+                }
+                store.objects.insert(path.to_owned(), object);
+                (200, String::new(), "<Result/>".to_owned())
+            } else if method == "PUT" && query.is_empty() {
+                store.objects.insert(path.to_owned(), body);
+                (200, "\"single\"".to_owned(), String::new())
+            } else if method == "DELETE" {
+                store.parts.clear();
+                (204, String::new(), String::new())
+            } else {
+                (400, String::new(), "<Error>Unsupported</Error>".to_owned())
+            };
+            let mut resp = format!("HTTP/1.1 {} X\r\nContent-Length: {}\r\n", status, reply.len());
+            if !etag.is_empty() {
+                resp += &format!("ETag: {}\r\n", etag);
+            }
+            resp += "\r\n";
+            resp += &reply;
+            stream.write_all(resp.as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_multipart_upload_with_rewritten_header() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let store: Arc<Mutex<Store>> = Default::default();
+        serve(listener, store.clone());
+
+        let dir = std::env::temp_dir().join(format!("record-s3-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("incoming_1_20240101000000000_display0_vp9.webm");
+        let mut data: Vec<u8> = (0..PART_SIZE + 1000).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let mut sink = S3Sink::with_client(test_config(endpoint), Client::new());
+        sink.new_file(path.to_str().unwrap()).unwrap();
+        sink.last_check -= CHECK_INTERVAL;
+        sink.frame().unwrap();
+        assert_eq!(sink.parts.len(), 1);
+
+        data.extend_from_slice(b"tail");
+        data[..6].copy_from_slice(b"header");
+        std::fs::write(&path, &data).unwrap();
+        sink.tail().unwrap();
+        let object = store.lock().unwrap().objects
+            ["/recordings/host1/incoming_1_20240101000000000_display0_vp9.webm"]
+            .clone();
+        assert_eq!(object, data);
+
+        // Short recordings go up in a single put
+        let short = dir.join("incoming_1_20240101000001000_display0_vp9.webm");
+        std::fs::write(&short, b"short").unwrap();
+        sink.new_file(short.to_str().unwrap()).unwrap();
+        sink.tail().unwrap();
+        let object = store.lock().unwrap().objects
+            ["/recordings/host1/incoming_1_20240101000001000_display0_vp9.webm"]
+            .clone();
+        assert_eq!(object, b"short");
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
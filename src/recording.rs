@@ -0,0 +1,299 @@
+//! Headless companion for session recordings written by `scrap::record`.
+//!
+//! Lists recordings, prints their metadata, cuts time ranges out of them and
+//! decodes them to PNG sequences, so sessions can be reviewed without a GUI.
+
+use hbb_common::{bail, ResultType};
+use scrap::{
+    codec::Decoder,
+    playback::{self, RecordingFile, RecordingInfo, WebmReader},
+    CodecFormat, ImageFormat, ImageRgb, ImageTexture,
+};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+const USAGE: &str = "Usage:
+    recording list [DIR] [--json]
+    recording info FILE [--json]
+    recording cut FILE START END OUTPUT
+    recording frames FILE OUTPUT_DIR [--start S] [--end S] [--every N]
+    recording decrypt FILE.enc KEY OUTPUT
+
+Times are in seconds from the first frame, e.g. 12.5. Cuts start at the
+preceding key frame. Only WebM (VP8/VP9/AV1) recordings can be cut or decoded.
+KEY is the base64 value of the record-encryption-key option.";
+
+fn main() {
+    let args: Vec<_> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let args: Vec<_> = args.into_iter().filter(|a| a != "--json").collect();
+    let res = match args.first().map(|s| s.as_str()) {
+        Some("list") => list(Path::new(args.get(1).map_or(".", |s| s.as_str())), json),
+        Some("info") if args.len() == 2 => info(Path::new(&args[1]), json),
+        Some("cut") if args.len() == 5 => cut(&args[1..]),
+        Some("frames") if args.len() >= 3 => frames(&args[1..]),
+        Some("decrypt") if args.len() == 4 => decrypt(&args[1..]),
+        _ => {
+            println!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn list(dir: &Path, json: bool) -> ResultType<()> {
+    let files = playback::list(dir)?;
+    // The recorder starts a new file whenever the resolution changes
+    let mut last_resolution: HashMap<(bool, String, bool, usize), (usize, usize)> = HashMap::new();
+    let mut rows = Vec::new();
+    for file in &files {
+        let info = playback::probe(&file.path);
+        let (server, id, camera, display) = file.stream_key();
+        let key = (server, id.to_owned(), camera, display);
+        let resolution_changed = match &info {
+            Ok(info) => last_resolution
+                .insert(key, (info.width, info.height))
+                .map_or(false, |prev| prev != (info.width, info.height)),
+            Err(_) => false,
+        };
+        rows.push((file, info, resolution_changed));
+    }
+
+    if json {
+        let items: Vec<_> = rows
+            .iter()
+            .map(|(file, info, changed)| {
+                let mut item = file_json(file);
+                match info {
+                    Ok(info) => {
+                        item["info"] = info_json(info);
+                        item["resolution_changed"] = (*changed).into();
+                    }
+                    Err(e) => item["error"] = e.to_string().into(),
+                }
+                item
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&items)?);
+        return Ok(());
+    }
+    for (file, info, changed) in rows {
+        let source = format!(
+            "{}{}",
+            if file.camera { "camera" } else { "display" },
+            file.display_idx
+        );
+        let details = match info {
+            Ok(info) => format!(
+                "{}x{}{} {}",
+                info.width,
+                info.height,
+                if changed { " (resolution changed)" } else { "" },
+                format_duration(info.duration_ms)
+            ),
+            Err(e) => format!("unreadable: {}", e),
+        };
+        println!(
+            "{} {:<8} {:<12} {:<9} {:<5} {}  {}",
+            format_started(&file.started),
+            if file.server { "incoming" } else { "outgoing" },
+            file.id,
+            source,
+            file.format.to_string(),
+            details,
+            file.path.display()
+        );
+    }
+    Ok(())
+}
+
+fn info(path: &Path, json: bool) -> ResultType<()> {
+    let info = playback::probe(path)?;
+    let file = RecordingFile::parse(path);
+    if json {
+        let mut item = file.as_ref().map(file_json).unwrap_or_else(|| {
+            serde_json::json!({ "path": path.display().to_string() })
+        });
+        item["info"] = info_json(&info);
+        println!("{}", serde_json::to_string_pretty(&item)?);
+        return Ok(());
+    }
+    println!("File:       {}", path.display());
+    if let Some(file) = file {
+        println!("Direction:  {}", if file.server { "incoming" } else { "outgoing" });
+        println!("Peer id:    {}", file.id);
+        println!(
+            "Source:     {} {}",
+            if file.camera { "camera" } else { "display" },
+            file.display_idx
+        );
+        println!("Started:    {}", format_started(&file.started));
+    }
+    println!("Codec:      {}", info.format.to_string());
+    println!("Resolution: {}x{}", info.width, info.height);
+    println!("Duration:   {}", format_duration(info.duration_ms));
+    println!("Frames:     {} ({} key frames)", info.frames, info.key_frames);
+    Ok(())
+}
+
+fn cut(args: &[String]) -> ResultType<()> {
+    let reader = open_webm(Path::new(&args[0]))?;
+    let (start, end) = (parse_seconds(&args[1])?, parse_seconds(&args[2])?);
+    if end <= start {
+        bail!("END must be after START");
+    }
+    let n = reader.cut(Path::new(&args[3]), start, end)?;
+    println!("Wrote {} frames to {}", n, args[3]);
+    Ok(())
+}
+
+fn frames(args: &[String]) -> ResultType<()> {
+    let reader = open_webm(Path::new(&args[0]))?;
+    let out_dir = PathBuf::from(&args[1]);
+    let mut start = 0;
+    let mut end = i64::MAX;
+    let mut every = 1usize;
+    for option in args[2..].chunks(2) {
+        match option {
+            [name, value] if name == "--start" => start = parse_seconds(value)?,
+            [name, value] if name == "--end" => end = parse_seconds(value)?,
+            [name, value] if name == "--every" => every = value.parse::<usize>()?.max(1),
+            _ => bail!("unknown option {:?}\n{}", option, USAGE),
+        }
+    }
+    std::fs::create_dir_all(&out_dir)?;
+
+    let packets = reader.range(start, end);
+    let first_pts = reader.packets.first().map(|p| p.pts).unwrap_or_default();
+    let mut decoder = Decoder::new(reader.info.format, None);
+    if !decoder.valid() {
+        bail!("no {} decoder available", reader.info.format.to_string());
+    }
+    // ABGR is RGBA in memory, as PNG wants it
+    let mut rgb = ImageRgb::new(ImageFormat::ABGR, 1);
+    let mut texture = ImageTexture::default();
+    let mut pixelbuffer = true;
+    let mut chroma = None;
+    let mut in_range = 0;
+    let mut written = 0;
+    for packet in packets {
+        let frame = encoded_frame(reader.info.format, packet);
+        let decoded =
+            decoder.handle_video_frame(&frame, &mut rgb, &mut texture, &mut pixelbuffer, &mut chroma)?;
+        // Frames before START are only decoded to prime the decoder from the key frame
+        let offset = packet.pts - first_pts;
+        if !decoded || offset < start {
+            continue;
+        }
+        in_range += 1;
+        if (in_range - 1) % every != 0 {
+            continue;
+        }
+        let path = out_dir.join(format!("frame_{:08}ms.png", offset));
+        repng::encode(
+            std::fs::File::create(&path)?,
+            rgb.w as u32,
+            rgb.h as u32,
+            &rgb.raw,
+        )?;
+        written += 1;
+    }
+    println!("Wrote {} frames to {}", written, out_dir.display());
+    Ok(())
+}
+
+fn decrypt(args: &[String]) -> ResultType<()> {
+    let key = librustdesk::record_sink::parse_key(&args[1])?;
+    let complete =
+        librustdesk::record_sink::decrypt_file(Path::new(&args[0]), Path::new(&args[2]), &key)?;
+    println!("Wrote {}", args[2]);
+    if !complete {
+        println!("The recording was not finished, its end may be missing");
+    }
+    Ok(())
+}
+
+fn open_webm(path: &Path) -> ResultType<WebmReader> {
+    if path.extension().and_then(|e| e.to_str()) != Some("webm") {
+        bail!("only WebM recordings can be cut or decoded");
+    }
+    WebmReader::open(path)
+}
+
+fn encoded_frame(
+    format: CodecFormat,
+    packet: &playback::VideoPacket,
+) -> hbb_common::message_proto::video_frame::Union {
+    use hbb_common::message_proto::{video_frame::Union, EncodedVideoFrame, EncodedVideoFrames};
+    let frames = EncodedVideoFrames {
+        frames: vec![EncodedVideoFrame {
+            data: packet.data.clone().into(),
+            key: packet.key,
+            pts: packet.pts,
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+    match format {
+        CodecFormat::VP8 => Union::Vp8s(frames),
+        CodecFormat::AV1 => Union::Av1s(frames),
+        _ => Union::Vp9s(frames),
+    }
+}
+
+fn parse_seconds(s: &str) -> ResultType<i64> {
+    let secs: f64 = s.parse()?;
+    if secs < 0.0 {
+        bail!("negative time {}", s);
+    }
+    Ok((secs * 1000.0) as i64)
+}
+
+fn format_duration(ms: u64) -> String {
+    format!("{:02}:{:02}:{:02}.{:03}", ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+/// `20240102030405678` -> `2024-01-02 03:04:05.678`
+fn format_started(s: &str) -> String {
+    if s.len() != 17 {
+        return s.to_owned();
+    }
+    format!(
+        "{}-{}-{} {}:{}:{}.{}",
+        &s[0..4],
+        &s[4..6],
+        &s[6..8],
+        &s[8..10],
+        &s[10..12],
+        &s[12..14],
+        &s[14..17]
+    )
+}
+
+fn file_json(file: &RecordingFile) -> serde_json::Value {
+    serde_json::json!({
+        "path": file.path.display().to_string(),
+        "direction": if file.server { "incoming" } else { "outgoing" },
+        "id": file.id,
+        "started": format_started(&file.started),
+        "camera": file.camera,
+        "display": file.display_idx,
+        "codec": file.format.to_string(),
+    })
+}
+
+fn info_json(info: &RecordingInfo) -> serde_json::Value {
+    serde_json::json!({
+        "codec": info.format.to_string(),
+        "width": info.width,
+        "height": info.height,
+        "duration_ms": info.duration_ms,
+        "frames": info.frames,
+        "key_frames": info.key_frames,
+    })
+}
@@ -0,0 +1,345 @@
+//! Tunnels multiplexed over one port forward session.
+//!
+//! Tunnels are asked for with a port forward login to [`TUNNEL_HOST`]. Once
+//! authorized, the raw session carries the [`Frame`]s of the channels, both
+//! sides sending data and closing them.
+//!
+//! Forward tunnels, many local listeners and SOCKS5 proxies sharing one login:
+//! the controlling side asks for a channel per local connection with
+//! [`Frame::Connect`] and the controlled side, checking the target as any port
+//! forward, answers with [`Frame::Open`] once connected or [`Frame::Close`].
+//!
+//! Reverse port forwarding, the controlled side listening and tunneling the
+//! connections it accepts back to the controlling side like `ssh -R`: the
+//! controlling side asks for it with [`Frame::Listen`], so nothing is bound
+//! before the peer is authorized. The controlled side answers with the same
+//! frame once listening, opens a channel per accepted connection and the
+//! controlling side connects it to its local target.
+
+use hbb_common::{
+    bail,
+    bytes::{BufMut, Bytes, BytesMut},
+    log,
+    tokio::{
+        self,
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        sync::mpsc::{channel, error::TrySendError, Receiver, Sender},
+    },
+    ResultType,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+};
+
+/// The host of a port forward login asking for tunnels, never a valid host
+/// name.
+pub const TUNNEL_HOST: &str = "<tunnel>";
+
+/// Whether the controlled side accepts reverse tunnels, off by default. The
+/// tunnel permission and the tunnel policy apply too.
+pub const OPTION_ALLOW_REVERSE_TUNNEL: &str = "allow-reverse-tunnel";
+
+const FRAME_OPEN: u8 = 0;
+const FRAME_DATA: u8 = 1;
+const FRAME_CLOSE: u8 = 2;
+const FRAME_CONNECT: u8 = 3;
+const FRAME_LISTEN: u8 = 4;
+const READ_BUF_SIZE: usize = 32 * 1024;
+// Frames queued per direction, reading stops once full
+const QUEUE_SIZE: usize = 64;
+
+/// One message of the tunnel, one per message of the session.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    /// The controlled side accepted a connection of a reverse tunnel, or
+    /// connected the one asked for by [`Frame::Connect`]
+    Open(u32),
+    Data(u32, Bytes),
+    Close(u32),
+    /// The controlling side asking for a connection to `host:port`
+    Connect(u32, String),
+    /// The controlling side asking for a reverse tunnel from the loopback
+    /// `port`, echoed by the controlled side once it listens
+    Listen(u16),
+}
+
+impl Frame {
+    pub fn encode(&self) -> Bytes {
+        let (kind, channel, data) = match self {
+            Self::Open(channel) => (FRAME_OPEN, *channel, &[][..]),
+            Self::Data(channel, data) => (FRAME_DATA, *channel, &data[..]),
+            Self::Close(channel) => (FRAME_CLOSE, *channel, &[][..]),
+            Self::Connect(channel, addr) => (FRAME_CONNECT, *channel, addr.as_bytes()),
+            // The port goes in place of the channel
+            Self::Listen(port) => (FRAME_LISTEN, *port as u32, &[][..]),
+        };
+        let mut bytes = BytesMut::with_capacity(5 + data.len());
+        bytes.put_u8(kind);
+        bytes.put_u32(channel);
+        bytes.put_slice(data);
+        bytes.freeze()
+    }
+
+    pub fn decode(bytes: &[u8]) -> ResultType<Self> {
+        if bytes.len() < 5 {
+            bail!("Reverse tunnel frame too short");
+        }
+        let channel = u32::from_be_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]);
+        Ok(match bytes[0] {
+            FRAME_OPEN => Self::Open(channel),
+            FRAME_DATA => Self::Data(channel, Bytes::copy_from_slice(&bytes[5..])),
+            FRAME_CLOSE => Self::Close(channel),
+            FRAME_CONNECT => Self::Connect(channel, String::from_utf8(bytes[5..].to_vec())?),
+            FRAME_LISTEN => Self::Listen(u16::try_from(channel)?),
+            kind => bail!("Unknown reverse tunnel frame {}", kind),
+        })
+    }
+
+    /// Length of the tunneled data
+    pub fn data_len(&self) -> usize {
+        match self {
+            Self::Data(_, data) => data.len(),
+            _ => 0,
+        }
+    }
+}
+
+/// The local connections of the channels of one tunnel.
+pub struct Channels {
+    tx: Sender<Frame>,
+    rx: Receiver<Frame>,
+    writers: HashMap<u32, Sender<Bytes>>,
+    /// Channels closed on this side, to tell the peer
+    closed: VecDeque<u32>,
+}
+
+impl Default for Channels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Channels {
+    pub fn new() -> Self {
+        let (tx, rx) = channel(QUEUE_SIZE);
+        Self {
+            tx,
+            rx,
+            writers: HashMap::new(),
+            closed: VecDeque::new(),
+        }
+    }
+
+    /// Run `channel` on an accepted connection.
+    pub fn add(&mut self, channel: u32, socket: TcpStream) -> ResultType<()> {
+        self.spawn(channel, async move { Ok(socket) }, false)
+    }
+
+    /// Run `channel` on a new connection to `addr`, closing the channel if
+    /// it fails.
+    pub fn connect(&mut self, channel: u32, addr: String) -> ResultType<()> {
+        self.spawn(
+            channel,
+            async move {
+                match hbb_common::timeout(3000, TcpStream::connect(&addr)).await {
+                    Ok(Ok(socket)) => Ok(socket),
+                    Ok(Err(e)) => bail!("Failed to connect {}: {}", addr, e),
+                    Err(_) => bail!("Timeout connecting {}", addr),
+                }
+            },
+            false,
+        )
+    }
+
+    /// Run `channel` on the connection `socket` resolves to, closing the
+    /// channel if it fails. With `announce`, [`Frame::Open`] is sent first.
+    ///
+    /// Fails if `channel` is running already, its connection is kept.
+    pub fn spawn(
+        &mut self,
+        channel: u32,
+        socket: impl Future<Output = ResultType<TcpStream>> + Send + 'static,
+        announce: bool,
+    ) -> ResultType<()> {
+        if self.writers.contains_key(&channel) {
+            bail!("Duplicate tunnel channel {}", channel);
+        }
+        let (tx_write, mut rx_write) = channel::<Bytes>(QUEUE_SIZE);
+        self.writers.insert(channel, tx_write);
+        let tx = self.tx.clone();
+        tokio::spawn(async move {
+            let socket = match socket.await {
+                Ok(socket) => socket,
+                Err(e) => {
+                    log::error!("Tunnel channel {}: {}", channel, e);
+                    tx.send(Frame::Close(channel)).await.ok();
+                    return;
+                }
+            };
+            if announce {
+                tx.send(Frame::Open(channel)).await.ok();
+            }
+            let (mut reader, mut writer) = socket.into_split();
+            let read = async {
+                let mut buf = vec![0u8; READ_BUF_SIZE];
+                loop {
+                    match reader.read(&mut buf).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => {
+                            let data = Bytes::copy_from_slice(&buf[..n]);
+                            if tx.send(Frame::Data(channel, data)).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            };
+            let write = async {
+                while let Some(data) = rx_write.recv().await {
+                    if writer.write_all(&data).await.is_err() {
+                        break;
+                    }
+                }
+            };
+            // Either direction ending closes the channel, like the port forward does
+            tokio::select! {
+                _ = read => {}
+                _ = write => {}
+            }
+            tx.send(Frame::Close(channel)).await.ok();
+        });
+        Ok(())
+    }
+
+    /// The next frame to send to the peer.
+    pub async fn next(&mut self) -> Frame {
+        if let Some(channel) = self.closed.pop_front() {
+            return Frame::Close(channel);
+        }
+        loop {
+            // Never `None`, `self.tx` is alive
+            if let Some(frame) = self.rx.recv().await {
+                if let Frame::Close(channel) = frame {
+                    // Closed by the peer already
+                    if self.writers.remove(&channel).is_none() {
+                        continue;
+                    }
+                }
+                return frame;
+            }
+        }
+    }
+
+    /// Handle a frame received from the peer, other than [`Frame::Open`],
+    /// [`Frame::Connect`] and [`Frame::Listen`].
+    ///
+    /// Never waits, the channels share the session: a connection whose queue
+    /// is full is too slow to take its data and is closed.
+    pub fn handle(&mut self, frame: Frame) {
+        match frame {
+            Frame::Data(channel, data) => {
+                if let Some(writer) = self.writers.get(&channel) {
+                    if let Err(TrySendError::Full(_)) = writer.try_send(data) {
+                        log::error!("Tunnel channel {} too slow, closing it", channel);
+                        self.writers.remove(&channel);
+                        self.closed.push_back(channel);
+                    }
+                }
+            }
+            Frame::Close(channel) => {
+                // Dropping the writer ends the connection of the channel
+                self.writers.remove(&channel);
+            }
+            Frame::Open(channel) | Frame::Connect(channel, _) => {
+                log::warn!("Unexpected open of tunnel channel {}", channel);
+            }
+            Frame::Listen(port) => {
+                log::warn!("Unexpected reverse tunnel from port {}", port);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hbb_common::tokio::net::TcpListener;
+
+    #[test]
+    fn test_frames() {
+        for frame in [
+            Frame::Open(1),
+            Frame::Data(u32::MAX, Bytes::from_static(b"license")),
+            Frame::Close(7),
+            Frame::Connect(2, "[::1]:22".to_owned()),
+            Frame::Listen(8080),
+        ] {
+            assert_eq!(Frame::decode(&frame.encode()).unwrap(), frame);
+        }
+        assert!(Frame::decode(&[FRAME_DATA, 0, 0]).is_err());
+        assert!(Frame::decode(&[9, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn test_channels() {
+        run_channels();
+    }
+
+    // Both sides of a tunnel on loopback, frames passed by hand
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_channels() {
+        let server = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = server.local_addr().unwrap().to_string();
+        let mut controlling = Channels::new();
+        controlling.connect(3, target).unwrap();
+        let (mut socket, _) = server.accept().await.unwrap();
+        let (client, mut accepted) = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let client = TcpStream::connect(listener.local_addr().unwrap());
+            let (client, accepted) = tokio::join!(client, listener.accept());
+            (client.unwrap(), accepted.unwrap().0)
+        };
+        let mut controlled = Channels::new();
+        controlled.add(3, client).unwrap();
+
+        accepted.write_all(b"ping").await.unwrap();
+        let frame = controlled.next().await;
+        assert_eq!(frame, Frame::Data(3, Bytes::from_static(b"ping")));
+        controlling.handle(frame);
+        let mut buf = [0u8; 4];
+        socket.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        socket.write_all(b"pong").await.unwrap();
+        let frame = controlling.next().await;
+        controlled.handle(frame);
+        accepted.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pong");
+
+        drop(socket);
+        let frame = controlling.next().await;
+        assert_eq!(frame, Frame::Close(3));
+        controlled.handle(frame);
+        assert_eq!(accepted.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_slow_channel() {
+        run_slow_channel();
+    }
+
+    #[tokio::main(flavor = "current_thread")]
+    async fn run_slow_channel() {
+        let mut channels = Channels::new();
+        // Never connected, never takes its data
+        channels.spawn(5, std::future::pending(), false).unwrap();
+        assert!(channels.spawn(5, std::future::pending(), false).is_err());
+        for _ in 0..=QUEUE_SIZE {
+            channels.handle(Frame::Data(5, Bytes::from_static(b"data")));
+        }
+        assert_eq!(channels.next().await, Frame::Close(5));
+        assert!(channels.writers.is_empty());
+    }
+}
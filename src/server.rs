@@ -32,9 +32,17 @@ use video_service::VideoSource;
 
 use crate::ipc::Data;
 
+mod access_policy;
+mod audit;
+mod policy;
+mod tunnel_policy;
 pub mod audio_service;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
 pub mod terminal_service;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_policy;
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+mod terminal_recorder;
 cfg_if::cfg_if! {
 if #[cfg(not(target_os = "ios"))] {
 mod clipboard_service;
@@ -64,6 +72,8 @@ pub mod input_service {
     pub const NAME_WINDOW_FOCUS: &'static str = "";
 }
 
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+pub mod capture_region;
 mod connection;
 pub mod display_service;
 #[cfg(windows)]
@@ -304,6 +314,7 @@ impl Server {
     fn is_video_service_name(name: &str) -> bool {
         name.starts_with(VideoSource::Monitor.service_name_prefix())
             || name.starts_with(VideoSource::Camera.service_name_prefix())
+            || name.starts_with(VideoSource::Region.service_name_prefix())
     }
 
     pub fn try_add_primary_camera_service(&mut self) {
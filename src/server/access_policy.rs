@@ -0,0 +1,379 @@
+//! Per-peer permissions of incoming connections, evaluated at login.
+//!
+//! The `access-policy-file` option points to a JSON file:
+//!
+//! ```json
+//! {
+//!   "groups": { "admin": ["123456789"] },
+//!   "rules": [
+//!     { "peers": ["group:admin"], "permissions": { "*": true } },
+//!     { "peers": ["group:helpdesk"], "days": ["mon", "tue", "wed", "thu", "fri"],
+//!       "hours": "09:00-18:00", "reason": "Helpdesk is view-only",
+//!       "permissions": { "*": false } },
+//!     { "peers": ["group:helpdesk"], "deny": true, "reason": "Outside office hours" },
+//!     { "ips": ["192.168.0.0/16"], "permissions": { "terminal": false } }
+//!   ]
+//! }
+//! ```
+//!
+//! The first rule whose `peers` (ids or `group:<name>`), `ips`, `days` and
+//! `hours` all match applies; a missing condition matches anything. A peer is
+//! in the groups of the policy file and in those named like the tags of its
+//! entries in the local address book. `hours`
+//! is local time and may wrap midnight. The permissions a rule doesn't list,
+//! and every permission without a matching rule, follow the global options.
+//! `reason` is sent to the peer when the rule denies something it asks for.
+//!
+//! For the kind of session a policy can only narrow access: file transfer,
+//! terminal and tunnel sessions are refused before authentication when their
+//! global option is off, so `"*": true` only lifts the permissions within a
+//! session, e.g. keyboard or clipboard.
+//!
+//! The policy is evaluated once the peer is authenticated. `peers` match the ID the peer claims in its login request, which nothing verifies:
+//! any peer knowing the password can claim any ID. Only `ips` matches what
+//! the connection itself shows, so a rule granting more than the global
+//! options should also be bound to `ips`.
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{
+    bail,
+    chrono::{self, Datelike, NaiveTime, Weekday},
+    config::{self, keys, Config},
+    log, ResultType,
+};
+use serde_derive::Deserialize;
+use std::{collections::HashMap, net::IpAddr, str::FromStr};
+
+use super::policy;
+
+pub const OPTION_ACCESS_POLICY_FILE: &str = "access-policy-file";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AccessPermission {
+    Keyboard,
+    Clipboard,
+    File,
+    Audio,
+    Terminal,
+    Tunnel,
+    Restart,
+    Recording,
+}
+
+impl AccessPermission {
+    pub const ALL: [Self; 8] = [
+        Self::Keyboard,
+        Self::Clipboard,
+        Self::File,
+        Self::Audio,
+        Self::Terminal,
+        Self::Tunnel,
+        Self::Restart,
+        Self::Recording,
+    ];
+
+    /// The global option deciding when no rule does.
+    pub fn option(&self) -> &'static str {
+        match self {
+            Self::Keyboard => "enable-keyboard",
+            Self::Clipboard => "enable-clipboard",
+            Self::File => keys::OPTION_ENABLE_FILE_TRANSFER,
+            Self::Audio => "enable-audio",
+            Self::Terminal => keys::OPTION_ENABLE_TERMINAL,
+            Self::Tunnel => "enable-tunnel",
+            Self::Restart => "enable-remote-restart",
+            Self::Recording => "enable-record-session",
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Keyboard => "keyboard",
+            Self::Clipboard => "clipboard",
+            Self::File => "file",
+            Self::Audio => "audio",
+            Self::Terminal => "terminal",
+            Self::Tunnel => "tunnel",
+            Self::Restart => "restart",
+            Self::Recording => "recording",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PolicyConfig {
+    groups: HashMap<String, Vec<String>>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Rule {
+    peers: Vec<String>,
+    ips: Vec<String>,
+    days: Vec<String>,
+    hours: Option<String>,
+    deny: bool,
+    permissions: HashMap<String, bool>,
+    reason: String,
+}
+
+/// The peer logging in.
+#[derive(Debug, Clone, Default)]
+pub struct AccessPeer {
+    /// Claimed by the peer, unauthenticated
+    pub peer_id: String,
+    pub ip: Option<IpAddr>,
+    /// Groups of the peer, its address book tags
+    pub groups: Vec<String>,
+}
+
+impl AccessPeer {
+    pub fn new(peer_id: &str, ip: &str) -> Self {
+        Self {
+            peer_id: peer_id.to_owned(),
+            ip: ip.parse().ok(),
+            groups: address_book_tags(peer_id),
+        }
+    }
+}
+
+/// What the matching rule decided.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessGrant {
+    permissions: HashMap<AccessPermission, bool>,
+    pub reason: String,
+}
+
+impl AccessGrant {
+    /// `None` if the global option decides.
+    pub fn get(&self, permission: AccessPermission) -> Option<bool> {
+        self.permissions.get(&permission).copied()
+    }
+}
+
+/// Resolve the grant of `peer` from the policy file, if any.
+///
+/// Returns an error with the reason sent to the peer if the login is denied.
+pub fn resolve(peer: &AccessPeer) -> ResultType<AccessGrant> {
+    let path = Config::get_option(OPTION_ACCESS_POLICY_FILE);
+    if path.trim().is_empty() {
+        return Ok(AccessGrant::default());
+    }
+    // Fail closed, a broken file must not lift the restrictions
+    let config: PolicyConfig = match std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| serde_json::from_str(&s).map_err(|e| e.to_string()))
+    {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("Invalid access policy {}: {}", path, e);
+            bail!("Access policy of the remote side is invalid");
+        }
+    };
+    let now = chrono::Local::now().naive_local();
+    config.resolve(peer, now.weekday(), now.time())
+}
+
+fn address_book_tags(peer_id: &str) -> Vec<String> {
+    let mut tags: Vec<String> = config::Ab::load()
+        .ab_entries
+        .iter()
+        .flat_map(|ab| ab.peers.iter())
+        .filter(|p| p.id == peer_id)
+        .flat_map(|p| p.tags.iter().cloned())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+impl PolicyConfig {
+    /// Every condition and permission of every rule must be valid.
+    fn check(&self) -> ResultType<()> {
+        for rule in &self.rules {
+            if let Some(ip) = rule.ips.iter().find(|ip| IpCidr::from_str(ip).is_err()) {
+                bail!("invalid ip {}", ip);
+            }
+            if let Some(day) = rule.days.iter().find(|d| Weekday::from_str(d).is_err()) {
+                bail!("invalid day {}", day);
+            }
+            if let Some(hours) = &rule.hours {
+                if parse_hours(hours).is_none() {
+                    bail!("invalid hours {}", hours);
+                }
+            }
+            for name in rule.permissions.keys() {
+                if name != "*" && !AccessPermission::ALL.iter().any(|p| p.name() == name) {
+                    bail!("unknown permission {}", name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn matches(&self, rule: &Rule, peer: &AccessPeer, day: Weekday, time: NaiveTime) -> bool {
+        let peer_matches = policy::peer_matches(&rule.peers, &self.groups, &peer.peer_id)
+            || rule.peers.iter().any(|p| {
+                p.strip_prefix("group:")
+                    .map_or(false, |group| peer.groups.iter().any(|g| g == group))
+            });
+        let ip_matches = rule.ips.is_empty()
+            || peer.ip.map_or(false, |ip| {
+                rule.ips
+                    .iter()
+                    .any(|x| IpCidr::from_str(x).map_or(false, |cidr| cidr.contains(ip)))
+            });
+        let day_matches = rule.days.is_empty()
+            || rule
+                .days
+                .iter()
+                .any(|d| Weekday::from_str(d).map_or(false, |d| d == day));
+        let hour_matches = rule
+            .hours
+            .as_ref()
+            .map_or(true, |hours| in_hours(hours, time).unwrap_or(false));
+        peer_matches && ip_matches && day_matches && hour_matches
+    }
+
+    fn resolve(
+        &self,
+        peer: &AccessPeer,
+        day: Weekday,
+        time: NaiveTime,
+    ) -> ResultType<AccessGrant> {
+        // Fail closed, a rule that can't be evaluated must not lift the restrictions
+        if let Err(e) = self.check() {
+            log::error!("Invalid access policy: {}", e);
+            bail!("Access policy of the remote side is invalid");
+        }
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| self.matches(rule, peer, day, time))
+        else {
+            return Ok(AccessGrant::default());
+        };
+        if rule.deny {
+            if rule.reason.is_empty() {
+                bail!("Access denied by policy");
+            }
+            bail!("{}", rule.reason);
+        }
+        let mut permissions = HashMap::new();
+        if let Some(enabled) = rule.permissions.get("*") {
+            for permission in AccessPermission::ALL {
+                permissions.insert(permission, *enabled);
+            }
+        }
+        for (name, enabled) in &rule.permissions {
+            if name == "*" {
+                continue;
+            }
+            if let Some(permission) = AccessPermission::ALL.iter().find(|p| p.name() == name) {
+                permissions.insert(*permission, *enabled);
+            }
+        }
+        Ok(AccessGrant {
+            permissions,
+            reason: rule.reason.clone(),
+        })
+    }
+}
+
+/// The start and end of `HH:MM-HH:MM`.
+fn parse_hours(hours: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = hours.split_once('-')?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    Some((start, end))
+}
+
+/// Whether `time` is within `hours`, `None` if `hours` is not `HH:MM-HH:MM`.
+fn in_hours(hours: &str, time: NaiveTime) -> Option<bool> {
+    let (start, end) = parse_hours(hours)?;
+    Some(if start <= end {
+        start <= time && time < end
+    } else {
+        start <= time || time < end
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_office_hours_policy() {
+        let config: PolicyConfig = serde_json::from_str(
+            r#"{
+                "groups": { "admin": ["1"] },
+                "rules": [
+                    { "peers": ["group:admin"], "permissions": { "*": true } },
+                    { "peers": ["group:helpdesk"], "days": ["mon", "fri"], "hours": "09:00-18:00",
+                      "reason": "Helpdesk is view-only",
+                      "permissions": { "*": false, "audio": true } },
+                    { "peers": ["group:helpdesk"], "deny": true, "reason": "Outside office hours" },
+                    { "ips": ["10.0.0.0/8"], "hours": "22:00-06:00",
+                      "permissions": { "terminal": false } }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let peer = |peer_id: &str, ip: &str, groups: &[&str]| AccessPeer {
+            peer_id: peer_id.to_owned(),
+            ip: ip.parse().ok(),
+            groups: groups.iter().map(|g| g.to_string()).collect(),
+        };
+        let at = |h: u32, m: u32| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+
+        let admin = config
+            .resolve(&peer("1", "1.2.3.4", &[]), Weekday::Sun, at(3, 0))
+            .unwrap();
+        assert_eq!(admin.get(AccessPermission::Terminal), Some(true));
+
+        let helpdesk = peer("2", "1.2.3.4", &["helpdesk"]);
+        let grant = config.resolve(&helpdesk, Weekday::Mon, at(9, 0)).unwrap();
+        assert_eq!(grant.get(AccessPermission::Keyboard), Some(false));
+        assert_eq!(grant.get(AccessPermission::Audio), Some(true));
+        assert_eq!(grant.reason, "Helpdesk is view-only");
+        let err = config.resolve(&helpdesk, Weekday::Mon, at(18, 0));
+        assert_eq!(err.unwrap_err().to_string(), "Outside office hours");
+        let err = config.resolve(&helpdesk, Weekday::Tue, at(10, 0));
+        assert!(err.is_err());
+
+        // Wrapping midnight
+        let lan = peer("3", "10.1.2.3", &[]);
+        let grant = config.resolve(&lan, Weekday::Tue, at(23, 30)).unwrap();
+        assert_eq!(grant.get(AccessPermission::Terminal), Some(false));
+        assert_eq!(grant.get(AccessPermission::Keyboard), None);
+        let grant = config.resolve(&lan, Weekday::Tue, at(12, 0)).unwrap();
+        assert_eq!(grant, AccessGrant::default());
+
+    }
+
+    #[test]
+    fn test_malformed_rules_fail_closed() {
+        let peer = AccessPeer {
+            peer_id: "1".to_owned(),
+            ip: "10.1.2.3".parse().ok(),
+            groups: vec![],
+        };
+        let noon = NaiveTime::from_hms_opt(12, 0, 0).unwrap();
+        for rule in [
+            r#"{ "permissions": { "mouse": true } }"#,
+            r#"{ "ips": ["10.0.0.0/8", "10.0.0.0/33"], "permissions": { "*": true } }"#,
+            r#"{ "days": ["tue", "someday"], "permissions": { "*": true } }"#,
+            r#"{ "hours": "9-18", "permissions": { "*": true } }"#,
+        ] {
+            // Invalid even when an earlier rule matches
+            let config: PolicyConfig = serde_json::from_str(&format!(
+                r#"{{ "rules": [ {{ "peers": ["1"] }}, {} ] }}"#,
+                rule
+            ))
+            .unwrap();
+            assert!(config.resolve(&peer, Weekday::Tue, noon).is_err(), "{}", rule);
+        }
+    }
+}
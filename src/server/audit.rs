@@ -0,0 +1,280 @@
+//! Audit trail of the controlled side.
+//!
+//! Connections, permission switches, file transfers, alarms, terminals and
+//! port forwards are reported as [`AuditEvent`]s to every sink listed in the
+//! `audit-sinks` option, comma separated:
+//!
+//! - `http`, the default: post to the api server like before, retrying failed
+//!   posts in order. Only the events the api server knows are posted.
+//! - `file`: append JSON lines to `audit-log-file`, `audit.jsonl` in the log
+//!   directory by default.
+//! - `syslog`: log JSON lines to syslog, which journald picks up. Unix only.
+
+use hbb_common::{
+    chrono,
+    config::Config,
+    lazy_static, log,
+    tokio::{
+        self,
+        sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        time::Instant,
+    },
+};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+pub const OPTION_AUDIT_SINKS: &str = "audit-sinks";
+pub const OPTION_AUDIT_LOG_FILE: &str = "audit-log-file";
+
+const MAX_HTTP_QUEUE: usize = 1000;
+const MIN_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static::lazy_static! {
+    static ref SENDER: Mutex<Option<UnboundedSender<AuditEvent>>> = Default::default();
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditKind {
+    Conn,
+    Permission,
+    File,
+    Alarm,
+    Terminal,
+    PortForward,
+}
+
+impl AuditKind {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Conn => "conn",
+            Self::Permission => "permission",
+            Self::File => "file",
+            Self::Alarm => "alarm",
+            Self::Terminal => "terminal",
+            Self::PortForward => "port_forward",
+        }
+    }
+
+    /// The api server audit endpoint, for the kinds it accepts
+    fn endpoint(&self) -> Option<&'static str> {
+        match self {
+            Self::Conn => Some("conn"),
+            Self::File => Some("file"),
+            Self::Alarm => Some("alarm"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub kind: AuditKind,
+    pub action: String,
+    /// Sent as is to the api server
+    pub body: Value,
+    pub time: chrono::DateTime<chrono::Local>,
+}
+
+impl AuditEvent {
+    pub fn new(kind: AuditKind, action: &str, body: Value) -> Self {
+        Self {
+            kind,
+            action: action.to_owned(),
+            body,
+            time: chrono::Local::now(),
+        }
+    }
+
+    /// The line written by the local sinks, the body fields flattened.
+    fn to_line(&self) -> String {
+        let mut line = json!({
+            "time": self.time.to_rfc3339(),
+            "kind": self.kind.name(),
+            "action": self.action,
+        });
+        if let (Some(line), Value::Object(body)) = (line.as_object_mut(), &self.body) {
+            for (key, value) in body {
+                line.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        line.to_string()
+    }
+}
+
+/// Hand `event` to the configured sinks.
+pub fn emit(event: AuditEvent) {
+    let mut sender = SENDER.lock().unwrap();
+    if sender.is_none() {
+        let (tx, rx) = unbounded_channel();
+        std::thread::spawn(move || run(rx));
+        *sender = Some(tx);
+    }
+    if let Some(tx) = sender.as_ref() {
+        tx.send(event).ok();
+    }
+}
+
+fn sinks() -> Vec<String> {
+    let option = Config::get_option(OPTION_AUDIT_SINKS);
+    if option.trim().is_empty() {
+        return vec!["http".to_owned()];
+    }
+    option
+        .split(',')
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn run(mut rx: UnboundedReceiver<AuditEvent>) {
+    let mut file = FileSink::default();
+    let mut queue: VecDeque<(String, Value)> = VecDeque::new();
+    let mut retry_interval = MIN_RETRY_INTERVAL;
+    let mut retry_at: Option<Instant> = None;
+    loop {
+        let deadline = retry_at.unwrap_or_else(|| Instant::now() + MAX_RETRY_INTERVAL);
+        tokio::select! {
+            event = rx.recv() => {
+                let Some(event) = event else {
+                    break;
+                };
+                let sinks = sinks();
+                let line = event.to_line();
+                for sink in &sinks {
+                    match sink.as_str() {
+                        "file" => file.write(&line),
+                        "syslog" => syslog(&line),
+                        "http" => {
+                            if let Some(url) = http_url(&event) {
+                                if queue.len() >= MAX_HTTP_QUEUE {
+                                    log::warn!("Audit queue full, dropping the oldest event");
+                                    queue.pop_front();
+                                }
+                                queue.push_back((url, event.body.clone()));
+                            }
+                        }
+                        _ => log::error!("Unknown audit sink: {}", sink),
+                    }
+                }
+            }
+            _ = tokio::time::sleep_until(deadline), if retry_at.is_some() => {
+                retry_at = None;
+            }
+        }
+        if retry_at.is_some() {
+            continue;
+        }
+        while let Some((url, body)) = queue.front() {
+            match crate::post_request(url.clone(), body.to_string(), "").await {
+                Ok(_) => {
+                    queue.pop_front();
+                    retry_interval = MIN_RETRY_INTERVAL;
+                }
+                Err(e) => {
+                    log::warn!(
+                        "Failed to post audit to {}, retry in {:?}: {}",
+                        url,
+                        retry_interval,
+                        e
+                    );
+                    retry_at = Some(Instant::now() + retry_interval);
+                    retry_interval = (retry_interval * 2).min(MAX_RETRY_INTERVAL);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn http_url(event: &AuditEvent) -> Option<String> {
+    let url = crate::get_audit_server(
+        Config::get_option("api-server"),
+        Config::get_option("custom-rendezvous-server"),
+        event.kind.endpoint()?.to_owned(),
+    );
+    (!url.is_empty()).then_some(url)
+}
+
+#[derive(Default)]
+struct FileSink {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl FileSink {
+    fn write(&mut self, line: &str) {
+        let option = Config::get_option(OPTION_AUDIT_LOG_FILE);
+        let path = if option.is_empty() {
+            Config::log_path().join("audit.jsonl")
+        } else {
+            PathBuf::from(option)
+        };
+        if self.file.is_none() || self.path != path {
+            self.file = None;
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).ok();
+            }
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => self.file = Some(file),
+                Err(e) => log::error!("Failed to open audit log {}: {}", path.display(), e),
+            }
+            self.path = path;
+        }
+        if let Some(file) = self.file.as_mut() {
+            if let Err(e) = writeln!(file, "{}", line) {
+                log::error!("Failed to write audit log {}: {}", self.path.display(), e);
+                self.file = None;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+fn syslog(line: &str) {
+    use hbb_common::libc;
+    static OPEN: std::sync::Once = std::sync::Once::new();
+    OPEN.call_once(|| unsafe {
+        libc::openlog(b"rustdesk\0".as_ptr() as _, libc::LOG_PID, libc::LOG_AUTH);
+    });
+    if let Ok(msg) = std::ffi::CString::new(line) {
+        unsafe {
+            libc::syslog(libc::LOG_INFO, b"%s\0".as_ptr() as _, msg.as_ptr());
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn syslog(_line: &str) {
+    static WARN: std::sync::Once = std::sync::Once::new();
+    WARN.call_once(|| log::warn!("The syslog audit sink is not supported on this platform"));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_audit_line() {
+        let event = AuditEvent::new(
+            AuditKind::Conn,
+            "close",
+            json!({ "action": "close", "conn_id": 3, "kind": "overridden" }),
+        );
+        let line: Value = serde_json::from_str(&event.to_line()).unwrap();
+        assert_eq!(line["kind"], "conn");
+        assert_eq!(line["action"], "close");
+        assert_eq!(line["conn_id"], 3);
+        assert!(line["time"].as_str().unwrap().len() > 10);
+        assert_eq!(AuditKind::Terminal.endpoint(), None);
+        assert_eq!(AuditKind::File.endpoint(), Some("file"));
+    }
+}
@@ -0,0 +1,304 @@
+//! Application windows and rectangles shared as displays of their own.
+//!
+//! The regions are the JSON value of the `capture-regions` option:
+//!
+//! ```json
+//! [
+//!   { "name": "Ticket app", "window": "ticket system" },
+//!   { "name": "Left half", "display": 0, "rect": [0, 0, 960, 1080] },
+//!   { "all_windows": true }
+//! ]
+//! ```
+//!
+//! A window region follows the first visible top level window whose title
+//! contains `window`, case insensitive, on the display holding its center.
+//! `all_windows` adds every visible top level window, named by its title, so
+//! the peer picks one from the display list. A rectangle is relative to its
+//! `display`, the first one by default. All are clipped to their display. The
+//! regions found are listed to the peer after the displays, a missing window
+//! being left out. With `capture-regions-only` set, the monitors are not
+//! listed and can't be captured, only the regions are shared, nothing if none
+//! is found. A moved or resized window is a display change. Windows and X11
+//! only.
+//!
+//! Regions are captured by cropping their display, so the rest of the screen
+//! is never encoded. A window region is the window's rectangle on screen, not
+//! the window itself: whatever overlaps it, another window or a popup, is
+//! captured too.
+
+use crate::platform::WindowInfo;
+use hbb_common::{config::Config, log};
+use scrap::{Frame, PixelBuffer, Pixfmt, TraitCapturer, TraitPixelBuffer};
+use serde_derive::Deserialize;
+use std::io;
+
+pub const OPTION_CAPTURE_REGIONS: &str = "capture-regions";
+pub const OPTION_CAPTURE_REGIONS_ONLY: &str = "capture-regions-only";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct RegionConfig {
+    name: String,
+    window: Option<String>,
+    display: usize,
+    /// `[x, y, width, height]`
+    rect: Option<[i32; 4]>,
+    all_windows: bool,
+}
+
+/// Whether only the regions are shared, not the monitors.
+pub fn regions_only() -> bool {
+    Config::get_bool_option(OPTION_CAPTURE_REGIONS_ONLY)
+}
+
+/// A region found on the displays, in desktop coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureRegion {
+    pub name: String,
+    /// The display it's cropped from
+    pub display: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// The regions found on `displays`, given as `(x, y, width, height)`.
+pub fn find(displays: &[(i32, i32, usize, usize)]) -> Vec<CaptureRegion> {
+    #[cfg(target_os = "linux")]
+    if !crate::platform::linux::is_x11() {
+        return vec![];
+    }
+    let option = Config::get_option(OPTION_CAPTURE_REGIONS);
+    if option.trim().is_empty() {
+        return vec![];
+    }
+    let configs: Vec<RegionConfig> = match serde_json::from_str(&option) {
+        Ok(configs) => configs,
+        Err(e) => {
+            log::error!("Invalid {}: {}", OPTION_CAPTURE_REGIONS, e);
+            return vec![];
+        }
+    };
+    let windows = if configs.iter().any(|c| c.window.is_some() || c.all_windows) {
+        crate::platform::get_windows()
+    } else {
+        vec![]
+    };
+    resolve(&configs, displays, &windows)
+}
+
+fn resolve(
+    configs: &[RegionConfig],
+    displays: &[(i32, i32, usize, usize)],
+    windows: &[WindowInfo],
+) -> Vec<CaptureRegion> {
+    let contains = |(x, y, w, h): (i32, i32, usize, usize), px: i32, py: i32| {
+        px >= x && px < x + w as i32 && py >= y && py < y + h as i32
+    };
+    let on_display = |w: &WindowInfo| {
+        let (cx, cy) = (w.x + w.width / 2, w.y + w.height / 2);
+        displays.iter().position(|d| contains(*d, cx, cy))
+    };
+    let mut found = Vec::new();
+    for config in configs {
+        if config.all_windows {
+            for w in windows {
+                if let Some(display) = on_display(w) {
+                    found.push((w.title.clone(), display, [w.x, w.y, w.width, w.height]));
+                }
+            }
+        } else if let Some(title) = &config.window {
+            let title = title.to_lowercase();
+            let Some(w) = windows
+                .iter()
+                .find(|w| w.title.to_lowercase().contains(&title))
+            else {
+                continue;
+            };
+            let Some(display) = on_display(w) else {
+                continue;
+            };
+            found.push((config.name.clone(), display, [w.x, w.y, w.width, w.height]));
+        } else if let Some([x, y, width, height]) = config.rect {
+            let Some(d) = displays.get(config.display) else {
+                continue;
+            };
+            found.push((config.name.clone(), config.display, [d.0 + x, d.1 + y, width, height]));
+        } else {
+            log::error!("Capture region {} has no window or rect", config.name);
+        }
+    }
+    let mut regions = Vec::new();
+    for (name, display, rect) in found {
+        // Clipped to the display, even sized for the encoders
+        let (dx, dy, dw, dh) = displays[display];
+        let x = rect[0].max(dx);
+        let y = rect[1].max(dy);
+        let right = (rect[0] + rect[2]).min(dx + dw as i32);
+        let bottom = (rect[1] + rect[3]).min(dy + dh as i32);
+        let width = ((right - x).max(0) as usize) & !1;
+        let height = ((bottom - y).max(0) as usize) & !1;
+        if width == 0 || height == 0 {
+            continue;
+        }
+        regions.push(CaptureRegion {
+            name,
+            display,
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+    regions
+}
+
+/// Captures a rectangle of a display.
+pub struct RegionCapturer {
+    inner: Box<dyn TraitCapturer>,
+    /// Relative to the display
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    data: Vec<u8>,
+}
+
+impl RegionCapturer {
+    pub fn new(
+        inner: Box<dyn TraitCapturer>,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+    ) -> Self {
+        #[allow(unused_mut)]
+        let mut c = Self {
+            inner,
+            x,
+            y,
+            width,
+            height,
+            data: Vec::new(),
+        };
+        // Textures can't be cropped
+        #[cfg(feature = "vram")]
+        c.inner.set_output_texture(false);
+        c
+    }
+}
+
+impl TraitCapturer for RegionCapturer {
+    fn frame<'a>(&'a mut self, timeout: std::time::Duration) -> io::Result<Frame<'a>> {
+        let pixfmt = match self.inner.frame(timeout)? {
+            Frame::PixelBuffer(f) => {
+                let pixfmt = f.pixfmt();
+                if !matches!(pixfmt, Pixfmt::BGRA | Pixfmt::RGBA | Pixfmt::RGB565LE) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("Can't crop {:?} frames", pixfmt),
+                    ));
+                }
+                if self.x + self.width > f.width() || self.y + self.height > f.height() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "Capture region out of the display",
+                    ));
+                }
+                let bpp = pixfmt.bytes_per_pixel();
+                let stride = f.stride()[0];
+                let data = f.data();
+                self.data.clear();
+                for y in self.y..self.y + self.height {
+                    let start = y * stride + self.x * bpp;
+                    self.data
+                        .extend_from_slice(&data[start..start + self.width * bpp]);
+                }
+                pixfmt
+            }
+            Frame::Texture(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "Can't crop texture frames",
+                ));
+            }
+        };
+        Ok(Frame::PixelBuffer(PixelBuffer::new(
+            &self.data,
+            pixfmt,
+            self.width,
+            self.height,
+        )))
+    }
+
+    #[cfg(windows)]
+    fn is_gdi(&self) -> bool {
+        self.inner.is_gdi()
+    }
+
+    #[cfg(windows)]
+    fn set_gdi(&mut self) -> bool {
+        self.inner.set_gdi()
+    }
+
+    #[cfg(feature = "vram")]
+    fn device(&self) -> scrap::AdapterDevice {
+        self.inner.device()
+    }
+
+    #[cfg(feature = "vram")]
+    fn set_output_texture(&mut self, _texture: bool) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_regions() {
+        let configs: Vec<RegionConfig> = serde_json::from_str(
+            r#"[
+                { "name": "app", "window": "TICKET" },
+                { "name": "gone", "window": "closed" },
+                { "name": "half", "display": 1, "rect": [0, 0, 961, 2000] },
+                { "name": "nowhere", "display": 2, "rect": [0, 0, 10, 10] },
+                { "all_windows": true }
+            ]"#,
+        )
+        .unwrap();
+        let displays = [(0, 0, 1920, 1080), (1920, 0, 1280, 1024)];
+        let windows = [WindowInfo {
+            id: 1,
+            title: "Ticket system - Browser".to_owned(),
+            x: 1800,
+            y: 100,
+            width: 300,
+            height: 201,
+        }];
+        let regions = resolve(&configs, &displays, &windows);
+        assert_eq!(regions.len(), 3);
+        // Centered on the second display, clipped to it
+        assert_eq!(
+            regions[0],
+            CaptureRegion {
+                name: "app".to_owned(),
+                display: 1,
+                x: 1920,
+                y: 100,
+                width: 180,
+                height: 200,
+            }
+        );
+        assert_eq!(
+            (
+                regions[1].display,
+                regions[1].x,
+                regions[1].width,
+                regions[1].height
+            ),
+            (1, 1920, 960, 1024)
+        );
+        // Every window, named by its title
+        assert_eq!(regions[2].name, "Ticket system - Browser");
+        assert_eq!((regions[2].x, regions[2].width), (regions[0].x, regions[0].width));
+    }
+}
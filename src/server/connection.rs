@@ -1,4 +1,10 @@
-use super::{input_service::*, *};
+use super::{
+    access_policy::{self, AccessGrant, AccessPeer, AccessPermission},
+    audit::{self, AuditEvent, AuditKind},
+    tunnel_policy::TunnelPolicy,
+    input_service::*,
+    *,
+};
 #[cfg(feature = "unix-file-copy-paste")]
 use crate::clipboard::try_empty_clipboard_files;
 #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -14,6 +20,7 @@ use crate::platform::WallPaperRemover;
 #[cfg(windows)]
 use crate::portable_service::client as portable_client;
 use crate::{
+    reverse_tunnel::{self, Channels, Frame},
     client::{
         new_voice_call_request, new_voice_call_response, start_audio_thread, MediaData, MediaSender,
     },
@@ -36,7 +43,7 @@ use hbb_common::{
     sha2::{Digest, Sha256},
     sleep, timeout,
     tokio::{
-        net::TcpStream,
+        net::{lookup_host, TcpListener, TcpStream},
         sync::mpsc,
         time::{self, Duration, Instant},
     },
@@ -213,8 +220,9 @@ pub struct Connection {
     file_transfer: Option<(String, bool)>,
     view_camera: bool,
     terminal: bool,
-    port_forward_socket: Option<Framed<TcpStream, BytesCodec>>,
+    port_forward_socket: Option<PortForwardSocket>,
     port_forward_address: String,
+    tunnel_policy: TunnelPolicy,
     tx_to_cm: mpsc::UnboundedSender<ipc::Data>,
     authorized: bool,
     require_2fa: Option<totp_rs::TOTP>,
@@ -225,6 +233,7 @@ pub struct Connection {
     restart: bool,
     recording: bool,
     block_input: bool,
+    access_grant: AccessGrant,
     last_test_delay: Option<Instant>,
     network_delay: u32,
     lock_after_session_end: bool,
@@ -249,8 +258,6 @@ pub struct Connection {
     tx_input: std_mpsc::Sender<MessageInput>,
     // handle input messages
     video_ack_required: bool,
-    server_audit_conn: String,
-    server_audit_file: String,
     lr: LoginRequest,
     peer_argb: u32,
     session_last_recv_time: Option<Arc<Mutex<Instant>>>,
@@ -282,9 +289,8 @@ pub struct Connection {
     multi_ui_session: bool,
     tx_from_authed: mpsc::UnboundedSender<ipc::Data>,
     printer_data: Vec<(Instant, String, Vec<u8>)>,
-    // For post requests that need to be sent sequentially.
-    // eg. post_conn_audit
-    tx_post_seq: mpsc::UnboundedSender<(String, Value)>,
+    // The "new" conn audit was sent, "close" is only sent after it.
+    conn_audit_opened: bool,
     terminal_service_id: String,
     terminal_persistent: bool,
     // The user token must be set when terminal is enabled.
@@ -367,11 +373,6 @@ impl Connection {
         let linux_headless_handle =
             LinuxHeadlessHandle::new(_rx_cm_stream_ready, _tx_desktop_ready);
 
-        let (tx_post_seq, rx_post_seq) = mpsc::unbounded_channel();
-        tokio::spawn(async move {
-            Self::post_seq_loop(rx_post_seq).await;
-        });
-
         #[cfg(not(any(target_os = "android", target_os = "ios")))]
         let tx_cloned = tx.clone();
         let mut conn = Self {
@@ -393,6 +394,7 @@ impl Connection {
             terminal: false,
             port_forward_socket: None,
             port_forward_address: "".to_owned(),
+            tunnel_policy: Default::default(),
             tx_to_cm,
             authorized: false,
             keyboard: Connection::permission("enable-keyboard"),
@@ -403,6 +405,7 @@ impl Connection {
             restart: Connection::permission("enable-remote-restart"),
             recording: Connection::permission("enable-record-session"),
             block_input: Connection::permission("enable-block-input"),
+            access_grant: Default::default(),
             last_test_delay: None,
             network_delay: 0,
             lock_after_session_end: false,
@@ -420,8 +423,6 @@ impl Connection {
             show_my_cursor: false,
             tx_input,
             video_ack_required: false,
-            server_audit_conn: "".to_owned(),
-            server_audit_file: "".to_owned(),
             lr: Default::default(),
             peer_argb: 0u32,
             session_last_recv_time: None,
@@ -456,7 +457,7 @@ impl Connection {
             retina: Retina::default(),
             tx_from_authed,
             printer_data: Vec::new(),
-            tx_post_seq,
+            conn_audit_opened: false,
             terminal_service_id: "".to_owned(),
             terminal_persistent: false,
             #[cfg(not(any(target_os = "android", target_os = "ios")))]
@@ -543,7 +544,10 @@ impl Connection {
                     match data {
                         ipc::Data::Authorize => {
                             conn.require_2fa.take();
-                            conn.send_logon_response().await;
+                            if !conn.send_logon_response().await {
+                                conn.on_close("access policy", false).await;
+                                break;
+                            }
                             if conn.port_forward_socket.is_some() {
                                 break;
                             }
@@ -575,6 +579,11 @@ impl Connection {
                         }
                         ipc::Data::SwitchPermission{name, enabled} => {
                             log::info!("Change permission {} -> {}", name, enabled);
+                            conn.post_local_audit(
+                                AuditKind::Permission,
+                                "switch",
+                                json!({ "name": name, "enabled": enabled }),
+                            );
                             if &name == "keyboard" {
                                 conn.keyboard = enabled;
                                 conn.send_permission(Permission::Keyboard, enabled).await;
@@ -931,9 +940,11 @@ impl Connection {
             raii::AuthedConnID::check_remove_session(conn.inner.id(), conn.session_key());
         }
 
-        conn.post_conn_audit(json!({
-            "action": "close",
-        }));
+        if conn.conn_audit_opened {
+            conn.post_conn_audit(json!({
+                "action": "close",
+            }));
+        }
         if let Some(s) = conn.server.upgrade() {
             let mut s = s.write().unwrap();
             s.remove_connection(&conn.inner);
@@ -1049,67 +1060,149 @@ impl Connection {
         log::debug!("Input thread exited");
     }
 
-    async fn post_seq_loop(mut rx: mpsc::UnboundedReceiver<(String, Value)>) {
-        while let Some((url, v)) = rx.recv().await {
-            allow_err!(Self::post_audit_async(url, v).await);
+    async fn try_port_forward_loop(
+        &mut self,
+        rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+    ) -> ResultType<()> {
+        if let Some(socket) = self.port_forward_socket.take() {
+            let target = match &socket {
+                PortForwardSocket::Forward(forward) => forward.get_ref().peer_addr().ok(),
+                PortForwardSocket::Tunnels => None,
+            };
+            self.post_local_audit(
+                AuditKind::PortForward,
+                "open",
+                json!({
+                    "address": self.port_forward_address,
+                    "target": target,
+                }),
+            );
+            let start = Instant::now();
+            let (mut bytes_to_peer, mut bytes_from_peer) = (0, 0);
+            let res = self
+                .port_forward_loop(socket, rx_from_cm, &mut bytes_to_peer, &mut bytes_from_peer)
+                .await;
+            self.post_local_audit(
+                AuditKind::PortForward,
+                "close",
+                json!({
+                    "address": self.port_forward_address,
+                    "target": target,
+                    "duration_secs": start.elapsed().as_secs(),
+                    "bytes_to_peer": bytes_to_peer,
+                    "bytes_from_peer": bytes_from_peer,
+                    "reason": res.as_ref().err().map(|e| e.to_string()),
+                }),
+            );
+            res?;
         }
-        log::debug!("post_seq_loop exited");
+        Ok(())
     }
 
-    async fn try_port_forward_loop(
+    async fn port_forward_loop(
         &mut self,
+        socket: PortForwardSocket,
         rx_from_cm: &mut mpsc::UnboundedReceiver<Data>,
+        bytes_to_peer: &mut u64,
+        bytes_from_peer: &mut u64,
     ) -> ResultType<()> {
+        let tunnels = matches!(socket, PortForwardSocket::Tunnels);
+        let mut forward = match socket {
+            PortForwardSocket::Forward(forward) => Some(forward),
+            PortForwardSocket::Tunnels => None,
+        };
+        // Listening for the connections of a reverse tunnel, once asked for
+        let mut listener: Option<TcpListener> = None;
+        // The connections accepted by a reverse tunnel, or asked for by forward tunnels
+        let mut channels = Channels::new();
+        let mut next_channel = 0u32;
         let mut last_recv_time = Instant::now();
-        if let Some(mut forward) = self.port_forward_socket.take() {
-            log::info!("Running port forwarding loop");
-            self.stream.set_raw();
-            let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
-            loop {
-                tokio::select! {
-                    Some(data) = rx_from_cm.recv() => {
-                        match data {
-                            ipc::Data::Close => {
-                                bail!("Close requested from connection manager");
-                            }
-                            ipc::Data::CmErr(e) => {
-                                log::error!("Connection manager error: {e}");
-                                bail!("{e}");
-                            }
-                            _ => {}
+        log::info!("Running port forwarding loop");
+        self.stream.set_raw();
+        let mut hbbs_rx = crate::hbbs_http::sync::signal_receiver();
+        let max_bytes = self.tunnel_policy.max_bytes.unwrap_or(u64::MAX);
+        let limited = self.tunnel_policy.max_duration.is_some();
+        let deadline = Instant::now() + self.tunnel_policy.max_duration.unwrap_or_default();
+        loop {
+            tokio::select! {
+                Some(data) = rx_from_cm.recv() => {
+                    match data {
+                        ipc::Data::Close => {
+                            bail!("Close requested from connection manager");
                         }
-                    }
-                    res = forward.next() => {
-                        if let Some(res) = res {
-                            last_recv_time = Instant::now();
-                            self.stream.send_bytes(res?.into()).await?;
-                        } else {
-                            bail!("Forward reset by the peer");
+                        ipc::Data::CmErr(e) => {
+                            log::error!("Connection manager error: {e}");
+                            bail!("{e}");
                         }
-                    },
-                    res = self.stream.next() => {
-                        if let Some(res) = res {
-                            last_recv_time = Instant::now();
-                            timeout(SEND_TIMEOUT_OTHER, forward.send(res?)).await??;
+                        _ => {}
+                    }
+                }
+                res = async { forward.as_mut().unwrap().next().await }, if forward.is_some() => {
+                    if let Some(res) = res {
+                        last_recv_time = Instant::now();
+                        let bytes = res?;
+                        *bytes_to_peer += bytes.len() as u64;
+                        self.stream.send_bytes(bytes.into()).await?;
+                    } else {
+                        bail!("Forward reset by the peer");
+                    }
+                },
+                res = async { listener.as_ref().unwrap().accept().await }, if listener.is_some() => {
+                    let (socket, addr) = res?;
+                    log::info!("Reverse tunnel channel {} from {:?}", next_channel, addr);
+                    channels.add(next_channel, socket)?;
+                    self.stream.send_bytes(Frame::Open(next_channel).encode()).await?;
+                    next_channel = next_channel.wrapping_add(1);
+                }
+                frame = channels.next() => {
+                    last_recv_time = Instant::now();
+                    *bytes_to_peer += frame.data_len() as u64;
+                    self.stream.send_bytes(frame.encode()).await?;
+                }
+                res = self.stream.next() => {
+                    if let Some(res) = res {
+                        last_recv_time = Instant::now();
+                        let bytes = res?;
+                        if let Some(forward) = forward.as_mut() {
+                            *bytes_from_peer += bytes.len() as u64;
+                            timeout(SEND_TIMEOUT_OTHER, forward.send(bytes)).await??;
                         } else {
-                            bail!("Stream reset by the peer");
-                        }
-                    },
-                    _ = self.timer.tick() => {
-                        if last_recv_time.elapsed() >= H1 {
-                            bail!("Timeout");
+                            let frame = Frame::decode(&bytes)?;
+                            *bytes_from_peer += frame.data_len() as u64;
+                            match frame {
+                                Frame::Connect(channel, addr) if tunnels => {
+                                    self.connect_tunnel(&mut channels, channel, addr)?;
+                                }
+                                Frame::Listen(port) if tunnels && listener.is_none() => {
+                                    listener = Some(self.listen_reverse_tunnel(port).await?);
+                                    self.stream.send_bytes(Frame::Listen(port).encode()).await?;
+                                }
+                                frame => channels.handle(frame),
+                            }
                         }
+                    } else {
+                        bail!("Stream reset by the peer");
                     }
-                    Ok(conns) = hbbs_rx.recv() => {
-                        if conns.contains(&self.inner.id) {
-                            // todo: check reconnect
-                            bail!("Closed manually by the web console");
-                        }
+                },
+                _ = self.timer.tick() => {
+                    if last_recv_time.elapsed() >= H1 {
+                        bail!("Timeout");
                     }
                 }
+                _ = time::sleep_until(deadline), if limited => {
+                    bail!("Tunnel duration limit reached");
+                }
+                Ok(conns) = hbbs_rx.recv() => {
+                    if conns.contains(&self.inner.id) {
+                        // todo: check reconnect
+                        bail!("Closed manually by the web console");
+                    }
+                }
+            }
+            if *bytes_to_peer + *bytes_from_peer > max_bytes {
+                bail!("Tunnel byte limit reached");
             }
         }
-        Ok(())
     }
 
     async fn send_permission(&mut self, permission: Permission, enabled: bool) {
@@ -1124,6 +1217,103 @@ impl Connection {
         self.send(msg_out).await;
     }
 
+    /// Accept the channels of tunnels, checked by [`Self::connect_tunnel`] or,
+    /// for a reverse tunnel, [`Self::listen_reverse_tunnel`].
+    async fn accept_tunnels(&mut self) -> bool {
+        self.port_forward_address = reverse_tunnel::TUNNEL_HOST.to_owned();
+        match TunnelPolicy::resolve(&self.lr.my_id) {
+            Ok(policy) => {
+                self.tunnel_policy = policy;
+                self.port_forward_socket = Some(PortForwardSocket::Tunnels);
+                true
+            }
+            Err(e) => {
+                self.deny_port_forward(e.to_string()).await;
+                false
+            }
+        }
+    }
+
+    /// Connect `channel` of forward tunnels to `addr` if the tunnel policy
+    /// permits it, auditing it as a port forward.
+    fn connect_tunnel(
+        &self,
+        channels: &mut Channels,
+        channel: u32,
+        addr: String,
+    ) -> ResultType<()> {
+        let policy = self.tunnel_policy.clone();
+        let mut event = self.local_audit_body(json!({ "address": addr, "channel": channel }));
+        channels.spawn(
+            channel,
+            async move {
+                // Check the resolved addresses, a host name says nothing
+                let resolved: Vec<SocketAddr> = match timeout(3000, lookup_host(&addr)).await {
+                    Ok(Ok(addrs)) => addrs.collect(),
+                    _ => vec![],
+                };
+                let allowed: Vec<SocketAddr> =
+                    resolved.iter().filter(|a| policy.permits(a)).cloned().collect();
+                if !resolved.is_empty() && allowed.is_empty() {
+                    let reason = format!("Tunneling to {} is not allowed by the remote side", addr);
+                    event["reason"] = json!(reason);
+                    audit::emit(AuditEvent::new(AuditKind::PortForward, "denied", event));
+                    bail!(reason);
+                }
+                match timeout(3000, TcpStream::connect(&allowed[..])).await {
+                    Ok(Ok(socket)) => {
+                        event["target"] = json!(socket.peer_addr().ok());
+                        audit::emit(AuditEvent::new(AuditKind::PortForward, "open", event));
+                        Ok(socket)
+                    }
+                    Ok(Err(e)) => bail!("Failed to connect {}: {}", addr, e),
+                    Err(_) => bail!("Timeout connecting {}", addr),
+                }
+            },
+            true,
+        )
+    }
+
+    /// Listen on the loopback `port` for a reverse tunnel, asked for by the
+    /// authorized peer with [`Frame::Listen`].
+    async fn listen_reverse_tunnel(&self, port: u16) -> ResultType<TcpListener> {
+        let address = format!("{}:{}", std::net::Ipv4Addr::LOCALHOST, port);
+        if !config::option2bool(
+            reverse_tunnel::OPTION_ALLOW_REVERSE_TUNNEL,
+            &Config::get_option(reverse_tunnel::OPTION_ALLOW_REVERSE_TUNNEL),
+        ) {
+            let reason = "Reverse tunneling is not allowed by the remote side";
+            self.post_local_audit(
+                AuditKind::PortForward,
+                "denied",
+                json!({ "address": address, "reverse": true, "reason": reason }),
+            );
+            bail!(reason);
+        }
+        match TcpListener::bind((std::net::Ipv4Addr::LOCALHOST, port)).await {
+            Ok(listener) => {
+                self.post_local_audit(
+                    AuditKind::PortForward,
+                    "listen",
+                    json!({ "address": address, "reverse": true }),
+                );
+                Ok(listener)
+            }
+            Err(e) => bail!("Failed to listen on {} for a reverse tunnel: {}", address, e),
+        }
+    }
+
+    async fn deny_port_forward(&mut self, reason: String) {
+        log::info!("Port forward to {} denied: {}", self.port_forward_address, reason);
+        self.post_local_audit(
+            AuditKind::PortForward,
+            "denied",
+            json!({ "address": self.port_forward_address, "reason": reason }),
+        );
+        self.send_login_error(reason).await;
+        sleep(1.).await;
+    }
+
     async fn check_privacy_mode_on(&mut self) -> bool {
         if privacy_mode::is_in_privacy_mode() {
             self.send_login_error("Someone turns on privacy mode, exit")
@@ -1179,38 +1369,39 @@ impl Connection {
         let mut msg_out = Message::new();
         msg_out.set_hash(self.hash.clone());
         self.send(msg_out).await;
-        self.get_api_server();
         self.post_conn_audit(json!({
             "ip": addr.ip(),
             "action": "new",
         }));
+        self.conn_audit_opened = true;
         true
     }
 
-    fn get_api_server(&mut self) {
-        self.server_audit_conn = crate::get_audit_server(
-            Config::get_option("api-server"),
-            Config::get_option("custom-rendezvous-server"),
-            "conn".to_owned(),
-        );
-        self.server_audit_file = crate::get_audit_server(
-            Config::get_option("api-server"),
-            Config::get_option("custom-rendezvous-server"),
-            "file".to_owned(),
-        );
-    }
-
     fn post_conn_audit(&self, v: Value) {
-        if self.server_audit_conn.is_empty() {
-            return;
-        }
-        let url = self.server_audit_conn.clone();
         let mut v = v;
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["conn_id"] = json!(self.inner.id);
         v["session_id"] = json!(self.lr.session_id);
-        allow_err!(self.tx_post_seq.send((url, v)));
+        // The login audit carries no action
+        let action = v["action"].as_str().unwrap_or("authorized").to_owned();
+        audit::emit(AuditEvent::new(AuditKind::Conn, &action, v));
+    }
+
+    /// Audit events only kept by the local sinks, the api server doesn't know them.
+    fn post_local_audit(&self, kind: AuditKind, action: &str, v: Value) {
+        audit::emit(AuditEvent::new(kind, action, self.local_audit_body(v)));
+    }
+
+    /// `v` with the fields identifying this connection
+    fn local_audit_body(&self, v: Value) -> Value {
+        let mut v = v;
+        v["id"] = json!(Config::get_id());
+        v["conn_id"] = json!(self.inner.id);
+        v["session_id"] = json!(self.lr.session_id);
+        v["peer_id"] = json!(self.lr.my_id);
+        v["ip"] = json!(self.ip);
+        v
     }
 
     fn get_files_for_audit(job_type: fs::JobType, mut files: Vec<FileEntry>) -> Vec<(String, i64)> {
@@ -1236,10 +1427,6 @@ impl Connection {
         files: Vec<(String, i64)>,
         info: Value,
     ) {
-        if self.server_audit_file.is_empty() {
-            return;
-        }
-        let url = self.server_audit_file.clone();
         let file_num = files.len();
         let mut files = files;
         files.sort_by(|a, b| b.1.cmp(&a.1));
@@ -1259,38 +1446,27 @@ impl Connection {
             "is_file":is_file,
             "info":json!(info).to_string(),
         });
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
+        let action = match r#type {
+            FileAuditType::RemoteSend => "send",
+            FileAuditType::RemoteReceive => "receive",
+        };
+        audit::emit(AuditEvent::new(AuditKind::File, action, v));
     }
 
     pub fn post_alarm_audit(typ: AlarmAuditType, info: Value) {
-        let url = crate::get_audit_server(
-            Config::get_option("api-server"),
-            Config::get_option("custom-rendezvous-server"),
-            "alarm".to_owned(),
-        );
-        if url.is_empty() {
-            return;
-        }
         let mut v = Value::default();
         v["id"] = json!(Config::get_id());
         v["uuid"] = json!(crate::encode64(hbb_common::get_uuid()));
         v["typ"] = json!(typ as i8);
         v["info"] = serde_json::Value::String(info.to_string());
-        tokio::spawn(async move {
-            allow_err!(Self::post_audit_async(url, v).await);
-        });
-    }
-
-    #[inline]
-    async fn post_audit_async(url: String, v: Value) -> ResultType<String> {
-        crate::post_request(url, v.to_string(), "").await
+        audit::emit(AuditEvent::new(AuditKind::Alarm, typ.name(), v));
     }
 
-    async fn send_logon_response(&mut self) {
+    /// Returns false if the access policy denies the login, the connection
+    /// must be closed then.
+    async fn send_logon_response(&mut self) -> bool {
         if self.authorized {
-            return;
+            return true;
         }
         if self.require_2fa.is_some() && !self.is_recent_session(true) && !self.from_switch {
             self.require_2fa.as_ref().map(|totp| {
@@ -1321,7 +1497,10 @@ impl Connection {
                 }
             });
             self.send_login_error(crate::client::REQUIRE_2FA).await;
-            return;
+            return true;
+        }
+        if !self.apply_access_policy().await {
+            return false;
         }
         self.authorized = true;
         let (conn_type, auth_conn_type) = if self.file_transfer.is_some() {
@@ -1556,6 +1735,7 @@ impl Connection {
                     }
                     pi.displays = displays;
                     pi.current_display = self.display_idx as _;
+                    super::display_service::add_capture_regions_info(&mut pi.platform_additions);
                     #[cfg(not(any(target_os = "android", target_os = "ios")))]
                     {
                         pi.resolutions = Some(SupportedResolutions {
@@ -1920,6 +2100,88 @@ impl Connection {
         )
     }
 
+    /// Apply the access policy to the permissions of the peer logging in,
+    /// once it is authenticated.
+    ///
+    /// Returns false if the policy denies the login or the kind of session.
+    async fn apply_access_policy(&mut self) -> bool {
+        let peer = AccessPeer::new(&self.lr.my_id, &self.ip);
+        let grant = match access_policy::resolve(&peer) {
+            Ok(grant) => grant,
+            Err(reason) => {
+                log::info!("Login of {} denied by access policy: {}", peer.peer_id, reason);
+                self.post_local_audit(
+                    AuditKind::Permission,
+                    "denied",
+                    json!({ "reason": reason.to_string() }),
+                );
+                self.send_login_error(reason).await;
+                return false;
+            }
+        };
+        // Checked against the global options only before authentication
+        let session = if self.file_transfer.is_some() {
+            Some((AccessPermission::File, "No permission of file transfer"))
+        } else if self.terminal {
+            Some((AccessPermission::Terminal, "No permission of terminal"))
+        } else if self.port_forward_socket.is_some() {
+            Some((AccessPermission::Tunnel, "No permission of IP tunneling"))
+        } else {
+            None
+        };
+        self.access_grant = grant;
+        if let Some((access, err)) = session {
+            if !self.permitted(access) {
+                self.post_local_audit(
+                    AuditKind::Permission,
+                    "denied",
+                    json!({ "reason": err }),
+                );
+                self.send_no_permission_error(access, err).await;
+                return false;
+            }
+        }
+        let grant = self.access_grant.clone();
+        let fields = [
+            (AccessPermission::Keyboard, Permission::Keyboard, &mut self.keyboard),
+            (AccessPermission::Clipboard, Permission::Clipboard, &mut self.clipboard),
+            (AccessPermission::Audio, Permission::Audio, &mut self.audio),
+            (AccessPermission::File, Permission::File, &mut self.file),
+            (AccessPermission::Restart, Permission::Restart, &mut self.restart),
+            (AccessPermission::Recording, Permission::Recording, &mut self.recording),
+        ];
+        let mut changed = vec![];
+        for (access, permission, field) in fields {
+            if let Some(enabled) = grant.get(access) {
+                if *field != enabled {
+                    *field = enabled;
+                    changed.push((permission, enabled));
+                }
+            }
+        }
+        for (permission, enabled) in changed {
+            self.send_permission(permission, enabled).await;
+        }
+        true
+    }
+
+    /// Whether the access policy, or else the global option, allows `permission`.
+    fn permitted(&self, permission: AccessPermission) -> bool {
+        self.access_grant
+            .get(permission)
+            .unwrap_or_else(|| Connection::permission(permission.option()))
+    }
+
+    async fn send_no_permission_error(&mut self, permission: AccessPermission, err: &str) {
+        if self.access_grant.get(permission) == Some(false) && !self.access_grant.reason.is_empty()
+        {
+            let reason = self.access_grant.reason.clone();
+            self.send_login_error(reason).await;
+        } else {
+            self.send_login_error(err).await;
+        }
+    }
+
     fn update_codec_on_login(&self) {
         use scrap::codec::{Encoder, EncodingUpdate::*};
         if let Some(o) = self.lr.clone().option.as_ref() {
@@ -2015,9 +2277,12 @@ impl Connection {
             }
             match lr.union {
                 Some(login_request::Union::FileTransfer(ft)) => {
-                    if !Connection::permission(keys::OPTION_ENABLE_FILE_TRANSFER) {
-                        self.send_login_error("No permission of file transfer")
-                            .await;
+                    if !self.permitted(AccessPermission::File) {
+                        self.send_no_permission_error(
+                            AccessPermission::File,
+                            "No permission of file transfer",
+                        )
+                        .await;
                         sleep(1.).await;
                         return false;
                     }
@@ -2033,8 +2298,12 @@ impl Connection {
                     self.view_camera = true;
                 }
                 Some(login_request::Union::Terminal(terminal)) => {
-                    if !Connection::permission(keys::OPTION_ENABLE_TERMINAL) {
-                        self.send_login_error("No permission of terminal").await;
+                    if !self.permitted(AccessPermission::Terminal) {
+                        self.send_no_permission_error(
+                            AccessPermission::Terminal,
+                            "No permission of terminal",
+                        )
+                        .await;
                         sleep(1.).await;
                         return false;
                     }
@@ -2081,37 +2350,73 @@ impl Connection {
                     }
                 }
                 Some(login_request::Union::PortForward(mut pf)) => {
-                    if !Connection::permission("enable-tunnel") {
-                        self.send_login_error("No permission of IP tunneling").await;
+                    if !self.permitted(AccessPermission::Tunnel) {
+                        self.send_no_permission_error(
+                            AccessPermission::Tunnel,
+                            "No permission of IP tunneling",
+                        )
+                        .await;
                         sleep(1.).await;
                         return false;
                     }
-                    let mut is_rdp = false;
-                    if pf.host == "RDP" && pf.port == 0 {
-                        pf.host = "localhost".to_owned();
-                        pf.port = 3389;
-                        is_rdp = true;
-                    }
-                    if pf.host.is_empty() {
-                        pf.host = "localhost".to_owned();
-                    }
-                    let mut addr = format!("{}:{}", pf.host, pf.port);
-                    self.port_forward_address = addr.clone();
-                    match timeout(3000, TcpStream::connect(&addr)).await {
-                        Ok(Ok(sock)) => {
-                            self.port_forward_socket = Some(Framed::new(sock, BytesCodec::new()));
+                    if pf.host == reverse_tunnel::TUNNEL_HOST {
+                        if !self.accept_tunnels().await {
+                            return false;
+                        }
+                    } else {
+                        let mut is_rdp = false;
+                        if pf.host == "RDP" && pf.port == 0 {
+                            pf.host = "localhost".to_owned();
+                            pf.port = 3389;
+                            is_rdp = true;
                         }
-                        _ => {
-                            if is_rdp {
-                                addr = "RDP".to_owned();
+                        if pf.host.is_empty() {
+                            pf.host = "localhost".to_owned();
+                        }
+                        let mut addr = format!("{}:{}", pf.host, pf.port);
+                        self.port_forward_address = addr.clone();
+                        let policy = match TunnelPolicy::resolve(&self.lr.my_id) {
+                            Ok(policy) => policy,
+                            Err(e) => {
+                                self.deny_port_forward(e.to_string()).await;
+                                return false;
                             }
-                            self.send_login_error(format!(
-                                "Failed to access remote {}, please make sure if it is open",
+                        };
+                        // Check the resolved addresses, a host name says nothing
+                        let resolved: Vec<SocketAddr> =
+                            match timeout(3000, lookup_host(&addr)).await {
+                                Ok(Ok(addrs)) => addrs.collect(),
+                                _ => vec![],
+                            };
+                        let allowed: Vec<SocketAddr> =
+                            resolved.iter().filter(|a| policy.permits(a)).cloned().collect();
+                        if !resolved.is_empty() && allowed.is_empty() {
+                            self.deny_port_forward(format!(
+                                "Tunneling to {} is not allowed by the remote side",
                                 addr
                             ))
                             .await;
                             return false;
                         }
+                        self.tunnel_policy = policy;
+                        match timeout(3000, TcpStream::connect(&allowed[..])).await {
+                            Ok(Ok(sock)) => {
+                                self.port_forward_socket = Some(PortForwardSocket::Forward(
+                                    Framed::new(sock, BytesCodec::new()),
+                                ));
+                            }
+                            _ => {
+                                if is_rdp {
+                                    addr = "RDP".to_owned();
+                                }
+                                self.send_login_error(format!(
+                                    "Failed to access remote {}, please make sure if it is open",
+                                    addr
+                                ))
+                                .await;
+                                return false;
+                            }
+                        }
                     }
                 }
                 _ => {
@@ -2183,7 +2488,10 @@ impl Connection {
                 if err_msg.is_empty() {
                     #[cfg(target_os = "linux")]
                     self.linux_headless_handle.wait_desktop_cm_ready().await;
-                    self.send_logon_response().await;
+                    if !self.send_logon_response().await {
+                        sleep(1.).await;
+                        return false;
+                    }
                     self.try_start_cm(lr.my_id.clone(), lr.my_name.clone(), self.authorized);
                 } else {
                     self.send_login_error(err_msg).await;
@@ -2219,7 +2527,10 @@ impl Connection {
                     if err_msg.is_empty() {
                         #[cfg(target_os = "linux")]
                         self.linux_headless_handle.wait_desktop_cm_ready().await;
-                        self.send_logon_response().await;
+                        if !self.send_logon_response().await {
+                            sleep(1.).await;
+                            return false;
+                        }
                         self.try_start_cm(lr.my_id, lr.my_name, self.authorized);
                     } else {
                         self.send_login_error(err_msg).await;
@@ -2237,7 +2548,10 @@ impl Connection {
                         self.update_failure(failure, true, 1);
                         self.require_2fa.take();
                         raii::AuthedConnID::set_session_2fa(self.session_key());
-                        self.send_logon_response().await;
+                        if !self.send_logon_response().await {
+                            sleep(1.).await;
+                            return false;
+                        }
                         self.try_start_cm(
                             self.lr.my_id.to_owned(),
                             self.lr.my_name.to_owned(),
@@ -2288,7 +2602,10 @@ impl Connection {
                     if let Some((_instant, uuid_old)) = uuid_old {
                         if uuid == uuid_old {
                             self.from_switch = true;
-                            self.send_logon_response().await;
+                            if !self.send_logon_response().await {
+                                sleep(1.).await;
+                                return false;
+                            }
                             self.try_start_cm(
                                 lr.my_id.clone(),
                                 lr.my_name.clone(),
@@ -3414,6 +3731,10 @@ impl Connection {
         if self.keyboard {
             if let Ok(displays) = display_service::try_get_displays() {
                 let display_idx = d.unwrap_or(self.display_idx);
+                // A capture region is sized by its window or rectangle
+                if display_service::is_capture_region(display_idx) {
+                    return;
+                }
                 if let Some(display) = displays.get(display_idx) {
                     let name = display.name();
                     #[cfg(windows)]
@@ -3525,6 +3846,12 @@ impl Connection {
                     .user_image_quality(self.inner.id(), image_quality);
             }
         }
+        if let Some(mode) = scrap::codec::LosslessMode::from_request(o) {
+            scrap::codec::Encoder::update(scrap::codec::EncodingUpdate::Lossless(
+                self.inner.id(),
+                mode,
+            ));
+        }
         if o.custom_fps > 0 {
             video_service::VIDEO_QOS
                 .lock()
@@ -4187,8 +4514,50 @@ impl Connection {
             Some(self.terminal_persistent),
             user_token.to_terminal_service_token(),
         );
+        // Only opening a terminal needs the peer, for the terminal policy
+        if let Some(terminal_action::Union::Open(_)) = &action.union {
+            let os_user = if self.lr.os_login.username.is_empty() {
+                crate::platform::get_active_username()
+            } else {
+                self.lr.os_login.username.clone()
+            };
+            proxy = proxy.with_peer(terminal_service::TerminalPeer {
+                peer_id: self.lr.my_id.clone(),
+                os_user,
+            });
+        }
+        let result = proxy.handle_action(&action);
+        match &action.union {
+            Some(terminal_action::Union::Open(open)) => {
+                // Audited once handled, the policy may deny the terminal
+                let error = match &result {
+                    Ok(Some(response)) => match &response.union {
+                        Some(terminal_response::Union::Error(error)) => Some(error.message.clone()),
+                        _ => None,
+                    },
+                    Ok(None) => None,
+                    Err(err) => Some(err.to_string()),
+                };
+                self.post_local_audit(
+                    AuditKind::Terminal,
+                    "open",
+                    json!({
+                        "terminal_id": open.terminal_id,
+                        "service_id": self.terminal_service_id,
+                        "outcome": if error.is_some() { "denied" } else { "opened" },
+                        "error": error,
+                    }),
+                );
+            }
+            Some(terminal_action::Union::Close(close)) => self.post_local_audit(
+                AuditKind::Terminal,
+                "close",
+                json!({ "terminal_id": close.terminal_id, "service_id": self.terminal_service_id }),
+            ),
+            _ => {}
+        }
 
-        match proxy.handle_action(&action) {
+        match result {
             Ok(Some(response)) => {
                 let mut msg_out = Message::new();
                 msg_out.set_terminal_response(response);
@@ -4373,12 +4742,28 @@ fn try_activate_screen() {
     });
 }
 
+enum PortForwardSocket {
+    Forward(Framed<TcpStream, BytesCodec>),
+    /// The channels of tunnels, connected on demand or accepted by a reverse tunnel
+    Tunnels,
+}
+
 pub enum AlarmAuditType {
     IpWhitelist = 0,
     ExceedThirtyAttempts = 1,
     SixAttemptsWithinOneMinute = 2,
 }
 
+impl AlarmAuditType {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::IpWhitelist => "ip_whitelist",
+            Self::ExceedThirtyAttempts => "exceed_thirty_attempts",
+            Self::SixAttemptsWithinOneMinute => "six_attempts_within_one_minute",
+        }
+    }
+}
+
 pub enum FileAuditType {
     RemoteSend = 0,
     RemoteReceive = 1,
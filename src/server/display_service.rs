@@ -14,6 +14,10 @@ use std::sync::atomic::{AtomicBool, Ordering};
 
 pub const NAME: &'static str = "display";
 
+/// Capture synthetic frames instead of the displays, the value being the spec
+/// of `scrap::synthetic`. For headless CI and load testing.
+pub const OPTION_SYNTHETIC_CAPTURE: &str = "synthetic-capture";
+
 #[cfg(windows)]
 const DUMMY_DISPLAY_SIDE_MAX_SIZE: usize = 1024;
 
@@ -37,11 +41,17 @@ static TEMP_IGNORE_DISPLAYS_CHANGED: AtomicBool = AtomicBool::new(false);
 #[derive(Default)]
 struct SyncDisplaysInfo {
     displays: Vec<DisplayInfo>,
+    // The number of monitors, listed in `displays` unless only regions are shared
+    monitors: usize,
+    // Index of the first capture region in `displays`
+    first_region: usize,
     is_synced: bool,
 }
 
 impl SyncDisplaysInfo {
-    fn check_changed(&mut self, displays: Vec<DisplayInfo>) {
+    fn check_changed(&mut self, displays: Vec<DisplayInfo>, monitors: usize, first_region: usize) {
+        self.monitors = monitors;
+        self.first_region = first_region;
         if self.displays.len() != displays.len() {
             self.displays = displays;
             if !TEMP_IGNORE_DISPLAYS_CHANGED.load(Ordering::Relaxed) {
@@ -107,7 +117,7 @@ pub(super) fn check_display_changed(
     //  2. The client version > 1.2.4, The client side can handle the case because sync peer info message will be sent.
     // But it is acceptable to for the user to reconnect manually, because the monitor is unplugged.
     let d = lock.displays.get(idx)?;
-    if ndisplay != lock.displays.len() {
+    if ndisplay != lock.monitors {
         return Some(d.clone());
     }
     if !(d.x == x && d.y == y && d.width == w as i32 && d.height == h as i32) {
@@ -188,6 +198,7 @@ fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
         let m = crate::virtual_display_manager::get_platform_additions();
         pi.platform_additions = serde_json::to_string(&m).unwrap_or_default();
     }
+    add_capture_regions_info(&mut pi.platform_additions);
 
     // current_display should not be used in server.
     // It is set to 0 for compatibility with old clients.
@@ -198,6 +209,10 @@ fn displays_to_msg(displays: Vec<DisplayInfo>) -> Message {
 }
 
 fn check_get_displays_changed_msg() -> Option<Message> {
+    if let Some(spec) = synthetic_capture() {
+        check_update_synthetic_displays(&spec).ok()?;
+        return get_displays_msg();
+    }
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
@@ -209,6 +224,9 @@ fn check_get_displays_changed_msg() -> Option<Message> {
 }
 
 pub fn check_displays_changed() -> ResultType<()> {
+    if let Some(spec) = synthetic_capture() {
+        return check_update_synthetic_displays(&spec);
+    }
     #[cfg(target_os = "linux")]
     {
         // Currently, wayland need to call wayland::clear() before call Display::all(), otherwise it will cause
@@ -301,10 +319,39 @@ pub(super) fn get_display_info(idx: usize) -> Option<DisplayInfo> {
     SYNC_DISPLAYS.lock().unwrap().displays.get(idx).cloned()
 }
 
+/// Lists the displays which are capture regions in `platform_additions`.
+pub(super) fn add_capture_regions_info(platform_additions: &mut String) {
+    let regions = {
+        let lock = SYNC_DISPLAYS.lock().unwrap();
+        (lock.first_region..lock.displays.len()).collect::<Vec<_>>()
+    };
+    if regions.is_empty() {
+        return;
+    }
+    let mut m: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(platform_additions).unwrap_or_default();
+    m.insert("capture_regions".into(), regions.into());
+    *platform_additions = serde_json::to_string(&m).unwrap_or_default();
+}
+
+pub(super) fn is_capture_region(idx: usize) -> bool {
+    let lock = SYNC_DISPLAYS.lock().unwrap();
+    idx >= lock.first_region && idx < lock.displays.len()
+}
+
+/// Whether the monitors are hidden, only the capture regions being shared.
+pub(super) fn capture_regions_only() -> bool {
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    return synthetic_capture().is_none() && super::capture_region::regions_only();
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    false
+}
+
 // Display to DisplayInfo
 // The DisplayInfo is be sent to the peer.
 pub(super) fn check_update_displays(all: &Vec<Display>) {
-    let displays = all
+    #[allow(unused_mut)]
+    let mut displays = all
         .iter()
         .map(|d| {
             let display_name = d.name();
@@ -334,7 +381,88 @@ pub(super) fn check_update_displays(all: &Vec<Display>) {
             }
         })
         .collect::<Vec<DisplayInfo>>();
-    SYNC_DISPLAYS.lock().unwrap().check_changed(displays);
+    let monitors = displays.len();
+    let mut first_region = monitors;
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    {
+        let rects = displays
+            .iter()
+            .map(|d| (d.x, d.y, d.width as usize, d.height as usize))
+            .collect::<Vec<_>>();
+        for region in super::capture_region::find(&rects) {
+            let scale = displays[region.display].scale;
+            displays.push(DisplayInfo {
+                x: region.x,
+                y: region.y,
+                width: region.width as _,
+                height: region.height as _,
+                name: region.name,
+                online: true,
+                cursor_embedded: false,
+                original_resolution: Some(Resolution {
+                    width: region.width as _,
+                    height: region.height as _,
+                    ..Default::default()
+                })
+                .into(),
+                scale,
+                ..Default::default()
+            });
+        }
+        if capture_regions_only() {
+            displays.drain(..monitors);
+            first_region = 0;
+        }
+    }
+    SYNC_DISPLAYS
+        .lock()
+        .unwrap()
+        .check_changed(displays, monitors, first_region);
+}
+
+/// The spec of the synthetic capturer if the `synthetic-capture` option is set.
+pub(super) fn synthetic_capture() -> Option<String> {
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    {
+        let spec = Config::get_option(OPTION_SYNTHETIC_CAPTURE);
+        if !spec.trim().is_empty() {
+            return Some(spec.trim().to_owned());
+        }
+    }
+    None
+}
+
+// One display, the size of the synthetic frames
+fn check_update_synthetic_displays(spec: &str) -> ResultType<()> {
+    #[cfg(any(target_os = "windows", target_os = "linux"))]
+    let (width, height) = scrap::synthetic::SyntheticCapturer::size(spec)?;
+    #[cfg(not(any(target_os = "windows", target_os = "linux")))]
+    let (width, height): (usize, usize) = bail!(
+        "Synthetic capture is not supported on this platform: {}",
+        spec
+    );
+    let display = DisplayInfo {
+        x: 0,
+        y: 0,
+        name: "synthetic".to_owned(),
+        width: width as _,
+        height: height as _,
+        online: true,
+        cursor_embedded: false,
+        scale: 1.0,
+        original_resolution: Some(Resolution {
+            width: width as _,
+            height: height as _,
+            ..Default::default()
+        })
+        .into(),
+        ..Default::default()
+    };
+    SYNC_DISPLAYS
+        .lock()
+        .unwrap()
+        .check_changed(vec![display], 1, 1);
+    Ok(())
 }
 
 pub fn is_inited_msg() -> Option<Message> {
@@ -346,6 +474,10 @@ pub fn is_inited_msg() -> Option<Message> {
 }
 
 pub async fn update_get_sync_displays_on_login() -> ResultType<Vec<DisplayInfo>> {
+    if let Some(spec) = synthetic_capture() {
+        check_update_synthetic_displays(&spec)?;
+        return Ok(SYNC_DISPLAYS.lock().unwrap().displays.clone());
+    }
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
@@ -362,6 +494,9 @@ pub async fn update_get_sync_displays_on_login() -> ResultType<Vec<DisplayInfo>>
 
 #[inline]
 pub fn get_primary() -> usize {
+    if synthetic_capture().is_some() || capture_regions_only() {
+        return 0;
+    }
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
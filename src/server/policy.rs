@@ -0,0 +1,53 @@
+//! Shared by the JSON policies of the controlled side, see
+//! [`super::tunnel_policy`] and the terminal policy.
+
+use hbb_common::{bail, config::Config, log, ResultType};
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+
+/// Parse the JSON policy in `option`, `None` if the option is not set.
+///
+/// An invalid policy is an error naming `what`. Fail closed, a typo must not
+/// lift the restrictions.
+pub fn load<T: DeserializeOwned>(option: &str, what: &str) -> ResultType<Option<T>> {
+    let value = Config::get_option(option);
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    match serde_json::from_str(&value) {
+        Ok(config) => Ok(Some(config)),
+        Err(e) => {
+            log::error!("Invalid {}: {}", option, e);
+            bail!("{} of the remote side is invalid", what);
+        }
+    }
+}
+
+/// Whether `peer_id` is one of `peers`, ids or `group:<name>` of `groups`. An
+/// empty list matches anything.
+pub fn peer_matches(peers: &[String], groups: &HashMap<String, Vec<String>>, peer_id: &str) -> bool {
+    peers.is_empty()
+        || peers.iter().any(|p| match p.strip_prefix("group:") {
+            Some(group) => groups
+                .get(group)
+                .map_or(false, |ids| ids.iter().any(|id| id == peer_id)),
+            None => p == peer_id,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peer_matches() {
+        let groups = HashMap::from([("ops".to_owned(), vec!["1".to_owned()])]);
+        let peers = |list: &[&str]| list.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert!(peer_matches(&[], &groups, "9"));
+        assert!(peer_matches(&peers(&["group:ops"]), &groups, "1"));
+        assert!(peer_matches(&peers(&["group:dev", "2"]), &groups, "2"));
+        assert!(!peer_matches(&peers(&["group:dev", "group:ops"]), &groups, "2"));
+        // A group named like an id is no id
+        assert!(!peer_matches(&peers(&["group:1"]), &groups, "1"));
+    }
+}
@@ -228,14 +228,23 @@ impl<T: Subscriber + From<ConnInner>> ServiceTmpl<T> {
         }
     }
 
-    pub fn send_video_frame(&self, msg: Message) -> HashSet<i32> {
-        self.send_video_frame_shared(Arc::new(msg))
+    /// Send the frame to the subscribers `to` accepts, a frame of a temporal
+    /// layer too high for some of them. Returns the ids it was sent to.
+    pub fn send_video_frame(&self, msg: Message, to: impl Fn(i32) -> bool) -> HashSet<i32> {
+        self.send_video_frame_shared(Arc::new(msg), to)
     }
 
-    pub fn send_video_frame_shared(&self, msg: Arc<Message>) -> HashSet<i32> {
+    pub fn send_video_frame_shared(
+        &self,
+        msg: Arc<Message>,
+        to: impl Fn(i32) -> bool,
+    ) -> HashSet<i32> {
         let mut conn_ids = HashSet::new();
         let mut lock = self.0.write().unwrap();
         for s in lock.subscribes.values_mut() {
+            if !to(s.id()) {
+                continue;
+            }
             s.send(msg.clone());
             conn_ids.insert(s.id());
         }
@@ -0,0 +1,190 @@
+//! Which shell a terminal runs, and how, for a given peer and OS user.
+//!
+//! The policy is the JSON value of the `terminal-policy` option:
+//!
+//! ```json
+//! {
+//!   "groups": { "ops": ["123456789", "987654321"] },
+//!   "rules": [
+//!     { "peers": ["group:ops"], "shells": ["/bin/bash", "/bin/zsh"],
+//!       "env": { "HISTFILE": "/dev/null" }, "cwd": "/srv", "idle_timeout_secs": 900 },
+//!     { "users": ["support"], "command": ["/usr/local/bin/menu"] },
+//!     { "deny": true }
+//!   ]
+//! }
+//! ```
+//!
+//! The first rule whose `peers` (ids or `group:<name>`) and `users` both match
+//! applies; an empty list matches anything. Without a matching rule the
+//! default shell runs unrestricted. `command` forces a program instead of the
+//! shell, `shells` restricts the shell to the listed ones.
+//!
+//! Neither condition is authenticated: `peers` match the ID the peer claims in
+//! its login request and `users` the OS user name it sends, so a peer knowing
+//! the password can claim any of them. Rules restricting one peer or user can
+//! be dodged by claiming another; restrict the rule matching anything, e.g. a
+//! last `{ "deny": true }`, and don't grant a rule more than the password
+//! alone should allow.
+
+use hbb_common::anyhow::{anyhow, Result};
+use serde_derive::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+use super::policy;
+
+pub const OPTION_TERMINAL_POLICY: &str = "terminal-policy";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PolicyConfig {
+    groups: HashMap<String, Vec<String>>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Rule {
+    peers: Vec<String>,
+    users: Vec<String>,
+    deny: bool,
+    shells: Vec<String>,
+    command: Vec<String>,
+    env: HashMap<String, String>,
+    cwd: Option<String>,
+    idle_timeout_secs: Option<u64>,
+}
+
+/// The peer asking for a terminal.
+#[derive(Debug, Clone, Default)]
+pub struct TerminalPeer {
+    /// Claimed by the peer, unauthenticated
+    pub peer_id: String,
+    /// Sent by the peer, unauthenticated
+    pub os_user: String,
+}
+
+/// How to start the terminal for one peer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerminalPolicy {
+    /// Program and arguments
+    pub argv: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<String>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl TerminalPolicy {
+    /// Resolve the policy for `peer` from the current options, `default_shell`
+    /// being the shell that runs without restrictions.
+    ///
+    /// Returns an error describing why the terminal is denied.
+    pub fn resolve(peer: &TerminalPeer, default_shell: &str) -> Result<Self> {
+        let config: PolicyConfig =
+            policy::load(OPTION_TERMINAL_POLICY, "Terminal policy")?.unwrap_or_default();
+        config.resolve(peer, default_shell, |path| {
+            std::path::Path::new(path).exists()
+        })
+    }
+}
+
+impl PolicyConfig {
+    fn matches(&self, rule: &Rule, peer: &TerminalPeer) -> bool {
+        let peer_matches = policy::peer_matches(&rule.peers, &self.groups, &peer.peer_id);
+        let user_matches = rule.users.is_empty()
+            || rule
+                .users
+                .iter()
+                .any(|u| u.eq_ignore_ascii_case(&peer.os_user));
+        peer_matches && user_matches
+    }
+
+    fn resolve(
+        &self,
+        peer: &TerminalPeer,
+        default_shell: &str,
+        exists: impl Fn(&str) -> bool,
+    ) -> Result<TerminalPolicy> {
+        let Some(rule) = self.rules.iter().find(|rule| self.matches(rule, peer)) else {
+            return Ok(TerminalPolicy {
+                argv: vec![default_shell.to_owned()],
+                env: vec![],
+                cwd: None,
+                idle_timeout: None,
+            });
+        };
+        if rule.deny {
+            return Err(anyhow!("Terminal access denied by policy"));
+        }
+        let argv = if !rule.command.is_empty() {
+            rule.command.clone()
+        } else if rule.shells.is_empty() || rule.shells.iter().any(|s| s == default_shell) {
+            vec![default_shell.to_owned()]
+        } else {
+            match rule.shells.iter().find(|s| exists(s)) {
+                Some(shell) => vec![shell.clone()],
+                None => return Err(anyhow!("No shell allowed by policy is available")),
+            }
+        };
+        let mut env: Vec<_> = rule.env.clone().into_iter().collect();
+        env.sort();
+        Ok(TerminalPolicy {
+            argv,
+            env,
+            cwd: rule.cwd.clone(),
+            idle_timeout: rule
+                .idle_timeout_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_first_matching_rule_applies() {
+        let config: PolicyConfig = serde_json::from_str(
+            r#"{
+                "groups": { "ops": ["1"] },
+                "rules": [
+                    { "peers": ["group:ops"], "shells": ["/bin/zsh", "/bin/bash"],
+                      "env": { "B": "2", "A": "1" }, "cwd": "/srv", "idle_timeout_secs": 60 },
+                    { "users": ["Support"], "command": ["/usr/bin/menu", "--restricted"] },
+                    { "peers": ["3"], "shells": ["/bin/fish"] },
+                    { "deny": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let peer = |peer_id: &str, os_user: &str| TerminalPeer {
+            peer_id: peer_id.to_owned(),
+            os_user: os_user.to_owned(),
+        };
+        let exists = |path: &str| path != "/bin/zsh";
+
+        let ops = config.resolve(&peer("1", "root"), "/bin/sh", exists).unwrap();
+        assert_eq!(ops.argv, vec!["/bin/bash"]);
+        assert_eq!(ops.env, vec![("A".into(), "1".into()), ("B".into(), "2".into())]);
+        assert_eq!(ops.cwd.as_deref(), Some("/srv"));
+        assert_eq!(ops.idle_timeout, Some(Duration::from_secs(60)));
+        // The default shell is kept when it is allowed
+        let ops = config.resolve(&peer("1", "root"), "/bin/zsh", exists).unwrap();
+        assert_eq!(ops.argv, vec!["/bin/zsh"]);
+
+        let support = config.resolve(&peer("2", "support"), "/bin/sh", exists).unwrap();
+        assert_eq!(support.argv, vec!["/usr/bin/menu", "--restricted"]);
+
+        let err = config.resolve(&peer("3", "root"), "/bin/sh", |_| false);
+        assert!(err.unwrap_err().to_string().contains("No shell"));
+        let err = config.resolve(&peer("4", "root"), "/bin/sh", exists);
+        assert!(err.unwrap_err().to_string().contains("denied"));
+
+        let open = PolicyConfig::default()
+            .resolve(&peer("4", "root"), "/bin/sh", exists)
+            .unwrap();
+        assert_eq!(open.argv, vec!["/bin/sh"]);
+        assert_eq!(open.idle_timeout, None);
+    }
+}
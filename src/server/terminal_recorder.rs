@@ -0,0 +1,227 @@
+//! Asciicast v2 recordings of terminal sessions.
+//!
+//! Every terminal opened while `allow-auto-record-terminal` is set writes an
+//! `incoming_{id}_{time}_terminal{terminal_id}.cast` file next to the screen
+//! recordings. Output and resize events are always recorded, keystrokes only
+//! with `allow-record-terminal-input`. The file is reported to the
+//! [`crate::record_sink`]s like any screen recording, and encrypted the same
+//! way.
+//!
+//! See <https://docs.asciinema.org/manual/asciicast/v2/>.
+
+use hbb_common::{
+    chrono,
+    config::{self, Config},
+    log,
+    sodiumoxide::crypto::secretbox::Key,
+    ResultType,
+};
+use scrap::record::{RecordFile, RecordState};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    time::Instant,
+};
+
+pub const OPTION_RECORD_TERMINAL: &str = "allow-auto-record-terminal";
+pub const OPTION_RECORD_TERMINAL_INPUT: &str = "allow-record-terminal-input";
+
+pub struct TerminalRecorder {
+    file: RecordFile,
+    path: PathBuf,
+    start: Instant,
+    record_input: bool,
+    // Trailing bytes of a UTF-8 sequence split across reads
+    pending_output: Vec<u8>,
+    pending_input: Vec<u8>,
+    failed: bool,
+    tx: Option<Sender<RecordState>>,
+}
+
+impl TerminalRecorder {
+    /// Start recording a new terminal if enabled by the options.
+    pub fn start(terminal_id: i32, rows: u16, cols: u16, shell: &str) -> Option<Self> {
+        if !config::option2bool(
+            OPTION_RECORD_TERMINAL,
+            &Config::get_option(OPTION_RECORD_TERMINAL),
+        ) {
+            return None;
+        }
+        let record_input = config::option2bool(
+            OPTION_RECORD_TERMINAL_INPUT,
+            &Config::get_option(OPTION_RECORD_TERMINAL_INPUT),
+        );
+        #[cfg(windows)]
+        let root = crate::platform::is_root();
+        #[cfg(not(windows))]
+        let root = false;
+        let key = match crate::record_sink::encryption_key() {
+            Ok(key) => key,
+            Err(e) => {
+                log::error!("Not recording terminal {}, encryption is on but: {}", terminal_id, e);
+                return None;
+            }
+        };
+        let dir = PathBuf::from(crate::ui_interface::video_save_directory(root));
+        let filename = format!(
+            "incoming_{}{}terminal{}.cast{}",
+            Config::get_id(),
+            chrono::Local::now().format("_%Y%m%d%H%M%S%3f_"),
+            terminal_id,
+            if key.is_some() { ".enc" } else { "" }
+        );
+        match Self::new(
+            &dir.join(filename),
+            rows,
+            cols,
+            shell,
+            record_input,
+            key,
+            crate::record_sink::start(),
+        ) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                log::error!("Failed to record terminal {}: {}", terminal_id, e);
+                None
+            }
+        }
+    }
+
+    fn new(
+        path: &Path,
+        rows: u16,
+        cols: u16,
+        shell: &str,
+        record_input: bool,
+        key: Option<Key>,
+        tx: Option<Sender<RecordState>>,
+    ) -> ResultType<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = RecordFile::create(&path.to_string_lossy(), key)?;
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+            "env": { "SHELL": shell, "TERM": "xterm-256color" },
+        });
+        writeln!(file, "{}", header)?;
+        file.flush()?;
+        if let Some(tx) = &tx {
+            tx.send(RecordState::NewFile(path.to_string_lossy().to_string()))
+                .ok();
+        }
+        log::info!("Recording terminal to {}", path.display());
+        Ok(Self {
+            file,
+            path: path.to_owned(),
+            start: Instant::now(),
+            record_input,
+            pending_output: Vec::new(),
+            pending_input: Vec::new(),
+            failed: false,
+            tx,
+        })
+    }
+
+    pub fn output(&mut self, data: &[u8]) {
+        let text = take_utf8(&mut self.pending_output, data);
+        self.event("o", &text);
+    }
+
+    pub fn input(&mut self, data: &[u8]) {
+        if self.record_input {
+            let text = take_utf8(&mut self.pending_input, data);
+            self.event("i", &text);
+        }
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.event("r", &format!("{}x{}", cols, rows));
+    }
+
+    fn event(&mut self, kind: &str, data: &str) {
+        if self.failed || data.is_empty() {
+            return;
+        }
+        let time = (self.start.elapsed().as_secs_f64() * 1e6).round() / 1e6;
+        let line = serde_json::json!([time, kind, data]);
+        // Flushed per event, the sinks follow the file as it grows
+        if let Err(e) = writeln!(self.file, "{}", line).and_then(|_| self.file.flush()) {
+            log::error!("Failed to write {}: {}", self.path.display(), e);
+            self.failed = true;
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            tx.send(RecordState::NewFrame).ok();
+        }
+    }
+}
+
+impl Drop for TerminalRecorder {
+    fn drop(&mut self) {
+        let pending = std::mem::take(&mut self.pending_output);
+        self.event("o", &String::from_utf8_lossy(&pending));
+        self.file.finish().ok();
+        if let Some(tx) = &self.tx {
+            tx.send(RecordState::WriteTail).ok();
+        }
+        log::info!("Terminal recording {} finished", self.path.display());
+    }
+}
+
+/// Append `data` to `pending` and take the longest prefix that is complete UTF-8.
+fn take_utf8(pending: &mut Vec<u8>, data: &[u8]) -> String {
+    pending.extend_from_slice(data);
+    let complete = match std::str::from_utf8(pending) {
+        Ok(_) => pending.len(),
+        // Only an unfinished sequence at the end is kept for the next read
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        Err(_) => pending.len(),
+    };
+    let text = String::from_utf8_lossy(&pending[..complete]).into_owned();
+    pending.drain(..complete);
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_asciicast_events() {
+        let dir = std::env::temp_dir().join(format!("terminal-recorder-{}", std::process::id()));
+        let path = dir.join("incoming_1_20240101000000000_terminal0.cast");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut recorder =
+            TerminalRecorder::new(&path, 24, 80, "/bin/sh", false, None, Some(tx)).unwrap();
+        let euro = "€".as_bytes();
+        recorder.output(&[b"$ "[..], &euro[..1]].concat());
+        recorder.output(&euro[1..]);
+        recorder.input(b"secret\r");
+        recorder.resize(30, 100);
+        drop(recorder);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[0]["height"], 24);
+        let events: Vec<(&str, &str)> = lines[1..]
+            .iter()
+            .map(|e| (e[1].as_str().unwrap(), e[2].as_str().unwrap()))
+            .collect();
+        assert_eq!(events, vec![("o", "$ "), ("o", "€"), ("r", "100x30")]);
+
+        let states: Vec<_> = rx.try_iter().collect();
+        assert!(matches!(states.first(), Some(RecordState::NewFile(_))));
+        assert!(matches!(states.last(), Some(RecordState::WriteTail)));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
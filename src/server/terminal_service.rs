@@ -1,4 +1,4 @@
-use super::*;
+use super::{terminal_policy::TerminalPolicy, terminal_recorder::TerminalRecorder, *};
 use hbb_common::{
     anyhow::{anyhow, Context, Result},
     compress,
@@ -17,6 +17,8 @@ use std::{
     time::{Duration, Instant},
 };
 
+pub use super::terminal_policy::TerminalPeer;
+
 const MAX_OUTPUT_BUFFER_SIZE: usize = 1024 * 1024; // 1MB per terminal
 const MAX_BUFFER_LINES: usize = 10000;
 const MAX_SERVICES: usize = 100; // Maximum number of persistent terminal services
@@ -247,6 +249,25 @@ fn check_zombie_terminals() {
     }
 }
 
+/// Close the terminals idle for longer than their policy allows.
+///
+/// Runs on the cleanup task, so detached terminals of persistent services idle
+/// out too, nobody polls their output then.
+fn close_idle_terminals() {
+    let services: Vec<_> = TERMINAL_SERVICES.lock().unwrap().values().cloned().collect();
+    for service in services {
+        let sessions: Vec<_> = match service.lock() {
+            Ok(service) => service.sessions.iter().map(|(id, s)| (*id, s.clone())).collect(),
+            Err(_) => continue,
+        };
+        for (terminal_id, session) in sessions {
+            if let Ok(mut session) = session.try_lock() {
+                session.close_if_idle(terminal_id);
+            }
+        }
+    }
+}
+
 /// Ensure the cleanup task is running
 fn ensure_cleanup_task() {
     let mut task_handle = CLEANUP_TASK.lock().unwrap();
@@ -254,10 +275,17 @@ fn ensure_cleanup_task() {
         let handle = std::thread::spawn(|| {
             log::info!("Started cleanup task");
             let mut last_service_cleanup = Instant::now();
+            let mut last_idle_check = Instant::now();
             loop {
                 // Check for zombie processes every 100ms
                 check_zombie_terminals();
 
+                // Check for idle terminals every second
+                if last_idle_check.elapsed() > Duration::from_secs(1) {
+                    close_idle_terminals();
+                    last_idle_check = Instant::now();
+                }
+
                 // Check for inactive services every 5 minutes
                 if last_service_cleanup.elapsed() > Duration::from_secs(300) {
                     cleanup_inactive_services();
@@ -440,6 +468,11 @@ impl OutputBuffer {
 pub struct TerminalSession {
     pub created_at: Instant,
     last_activity: Instant,
+    last_input: Instant,
+    // Close the terminal after this long without input, set by the terminal policy
+    idle_timeout: Option<Duration>,
+    // Closed for being idle, the peer has not been told yet
+    idle_closed: bool,
     pty_pair: Option<portable_pty::PtyPair>,
     child: Option<Box<dyn Child + std::marker::Send + Sync>>,
     // Channel for sending input to the writer thread
@@ -458,6 +491,7 @@ pub struct TerminalSession {
     // Track if we've already sent the closed message
     closed_message_sent: bool,
     is_opened: bool,
+    recorder: Option<TerminalRecorder>,
 }
 
 impl TerminalSession {
@@ -465,6 +499,9 @@ impl TerminalSession {
         Self {
             created_at: Instant::now(),
             last_activity: Instant::now(),
+            last_input: Instant::now(),
+            idle_timeout: None,
+            idle_closed: false,
             pty_pair: None,
             child: None,
             input_tx: None,
@@ -479,6 +516,7 @@ impl TerminalSession {
             cols,
             closed_message_sent: false,
             is_opened: false,
+            recorder: None,
         }
     }
 
@@ -486,6 +524,21 @@ impl TerminalSession {
         self.last_activity = Instant::now();
     }
 
+    fn close_if_idle(&mut self, terminal_id: i32) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        if self.last_input.elapsed() > idle_timeout {
+            log::info!("Terminal {} idle for {:?}, closing", terminal_id, idle_timeout);
+            // Kill once, the closed message follows when the reader thread exits
+            self.idle_timeout = None;
+            self.idle_closed = true;
+            if let Some(child) = self.child.as_mut() {
+                child.kill().ok();
+            }
+        }
+    }
+
     // This helper function is to ensure that the threads are joined before the child process is dropped.
     // Though this is not strictly necessary on macOS.
     fn stop(&mut self) {
@@ -533,6 +586,9 @@ impl TerminalSession {
             let _ = writer_thread.join();
         }
 
+        // Finish the recording once the reader has delivered the last output
+        self.recorder = None;
+
         if let Some(mut child) = self.child.take() {
             // Kill the process
             let _ = child.kill();
@@ -625,6 +681,7 @@ impl PersistentTerminalService {
 pub struct TerminalServiceProxy {
     service_id: String,
     is_persistent: bool,
+    peer: TerminalPeer,
     #[cfg(target_os = "windows")]
     user_token: Option<UserToken>,
 }
@@ -654,6 +711,7 @@ impl TerminalServiceProxy {
         TerminalServiceProxy {
             service_id,
             is_persistent,
+            peer: Default::default(),
             #[cfg(target_os = "windows")]
             user_token: _user_token,
         }
@@ -663,6 +721,12 @@ impl TerminalServiceProxy {
         &self.service_id
     }
 
+    /// Set the peer the terminal policy is resolved for when opening terminals.
+    pub fn with_peer(mut self, peer: TerminalPeer) -> Self {
+        self.peer = peer;
+        self
+    }
+
     pub fn handle_action(&mut self, action: &TerminalAction) -> Result<Option<TerminalResponse>> {
         let service = match get_service(&self.service_id) {
             Some(s) => s,
@@ -711,6 +775,24 @@ impl TerminalServiceProxy {
     ) -> Result<Option<TerminalResponse>> {
         let mut response = TerminalResponse::new();
 
+        // Use default shell for the platform, unless the policy says otherwise
+        let policy = match TerminalPolicy::resolve(&self.peer, &get_default_shell()) {
+            Ok(policy) => policy,
+            Err(e) => {
+                log::warn!(
+                    "Terminal {} denied for peer {} (user {}): {}",
+                    open.terminal_id,
+                    self.peer.peer_id,
+                    self.peer.os_user,
+                    e
+                );
+                let mut error = TerminalError::new();
+                error.message = e.to_string();
+                response.set_error(error);
+                return Ok(Some(response));
+            }
+        };
+
         // Check if terminal already exists
         if let Some(session_arc) = service.sessions.get(&open.terminal_id) {
             // Reconnect to existing terminal
@@ -767,12 +849,17 @@ impl TerminalServiceProxy {
         let pty_system = portable_pty::native_pty_system();
         let pty_pair = pty_system.openpty(pty_size).context("Failed to open PTY")?;
 
-        // Use default shell for the platform
-        let shell = get_default_shell();
-        log::debug!("Using shell: {}", shell);
+        let shell = policy.argv[0].clone();
+        log::debug!("Using shell: {:?}", policy.argv);
 
-        #[allow(unused_mut)]
-        let mut cmd = CommandBuilder::new(&shell);
+        let mut cmd = CommandBuilder::from_argv(policy.argv.iter().map(Into::into).collect());
+        for (key, value) in &policy.env {
+            cmd.env(key, value);
+        }
+        if let Some(cwd) = &policy.cwd {
+            cmd.cwd(cwd);
+        }
+        session.idle_timeout = policy.idle_timeout;
 
         #[cfg(target_os = "windows")]
         if let Some(token) = &self.user_token {
@@ -886,6 +973,8 @@ impl TerminalServiceProxy {
         session.reader_thread = Some(reader_thread);
         session.writer_thread = Some(writer_thread);
         session.is_opened = true;
+        session.recorder =
+            TerminalRecorder::start(open.terminal_id, open.rows as u16, open.cols as u16, &shell);
 
         let mut opened = TerminalOpened::new();
         opened.terminal_id = open.terminal_id;
@@ -925,6 +1014,9 @@ impl TerminalServiceProxy {
             session.update_activity();
             session.rows = resize.rows as u16;
             session.cols = resize.cols as u16;
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.resize(resize.rows as u16, resize.cols as u16);
+            }
 
             if let Some(pty_pair) = &session.pty_pair {
                 pty_pair.master.resize(PtySize {
@@ -946,6 +1038,10 @@ impl TerminalServiceProxy {
         if let Some(session_arc) = session {
             let mut session = session_arc.lock().unwrap();
             session.update_activity();
+            session.last_input = Instant::now();
+            if let Some(recorder) = session.recorder.as_mut() {
+                recorder.input(&data.data);
+            }
             if let Some(input_tx) = &session.input_tx {
                 // Send data to writer thread
                 if let Err(e) = input_tx.send(data.data.to_vec()) {
@@ -1030,6 +1126,15 @@ impl TerminalServiceProxy {
                     closed_terminals.push(terminal_id);
                 }
 
+                session.close_if_idle(terminal_id);
+                if std::mem::take(&mut session.idle_closed) {
+                    let mut response = TerminalResponse::new();
+                    let mut error = TerminalError::new();
+                    error.message = format!("Terminal {} closed after being idle", terminal_id);
+                    response.set_error(error);
+                    responses.push(response);
+                }
+
                 if !session.is_opened {
                     // Skip the session if it is not opened.
                     continue;
@@ -1049,6 +1154,9 @@ impl TerminalServiceProxy {
                 // Update buffer after reading
                 for data in &received_data {
                     session.output_buffer.append(data);
+                    if let Some(recorder) = session.recorder.as_mut() {
+                        recorder.output(data);
+                    }
                 }
 
                 // Process received data for responses
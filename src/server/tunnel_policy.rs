@@ -0,0 +1,189 @@
+//! Which hosts and ports a peer may tunnel to, and for how long.
+//!
+//! The policy is the JSON value of the `tunnel-policy` option:
+//!
+//! ```json
+//! {
+//!   "groups": { "ops": ["123456789", "987654321"] },
+//!   "rules": [
+//!     { "peers": ["group:ops"], "allow": ["10.0.0.0/8:22", "127.0.0.1:8000-8100"],
+//!       "max_duration_secs": 3600, "max_bytes": 1073741824 },
+//!     { "allow": ["127.0.0.1:3389", "[::1]:3389"] }
+//!   ]
+//! }
+//! ```
+//!
+//! The first rule whose `peers` (ids or `group:<name>`) match applies; an empty
+//! list matches anything. `allow` lists `CIDR:ports`, the ports being one port,
+//! a range or `*`. Host names are resolved first, only the allowed addresses
+//! are connected to. Once the option is set, a peer without a matching rule,
+//! or with a `deny` rule, can't tunnel at all. Without the option anything
+//! may be tunneled, as before. Forward tunnels sharing one session check each
+//! of their channels. Reverse tunnels only listen on loopback, the limits of
+//! the rule apply to them but not `allow`.
+//!
+//! `peers` match the ID the peer claims in its login request, which is not
+//! authenticated: a peer knowing the password can claim any ID. Don't grant a
+//! peer rule more than the connection's password alone should allow.
+
+use cidr_utils::cidr::IpCidr;
+use hbb_common::{anyhow::anyhow, bail, log, ResultType};
+use serde_derive::Deserialize;
+use std::{
+    collections::HashMap, net::SocketAddr, ops::RangeInclusive, str::FromStr, time::Duration,
+};
+
+use super::policy;
+
+pub const OPTION_TUNNEL_POLICY: &str = "tunnel-policy";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PolicyConfig {
+    groups: HashMap<String, Vec<String>>,
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Rule {
+    peers: Vec<String>,
+    deny: bool,
+    allow: Vec<String>,
+    max_duration_secs: Option<u64>,
+    max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Target {
+    cidr: IpCidr,
+    ports: RangeInclusive<u16>,
+}
+
+impl FromStr for Target {
+    type Err = hbb_common::anyhow::Error;
+
+    fn from_str(s: &str) -> ResultType<Self> {
+        let Some((cidr, ports)) = s.rsplit_once(':') else {
+            bail!("missing ports in {}", s);
+        };
+        let cidr = cidr.trim_start_matches('[').trim_end_matches(']');
+        let cidr = IpCidr::from_str(cidr).map_err(|e| anyhow!("{:?}", e))?;
+        let ports = if ports == "*" {
+            0..=u16::MAX
+        } else if let Some((start, end)) = ports.split_once('-') {
+            start.parse()?..=end.parse()?
+        } else {
+            let port = ports.parse()?;
+            port..=port
+        };
+        Ok(Self { cidr, ports })
+    }
+}
+
+/// The tunnels one peer may open.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TunnelPolicy {
+    /// `None` if anything may be tunneled
+    targets: Option<Vec<Target>>,
+    pub max_duration: Option<Duration>,
+    pub max_bytes: Option<u64>,
+}
+
+impl TunnelPolicy {
+    /// Resolve the policy of `peer_id` from the current options.
+    ///
+    /// `peer_id` is the unverified ID of the login request.
+    ///
+    /// Returns an error describing why tunneling is denied.
+    pub fn resolve(peer_id: &str) -> ResultType<Self> {
+        match policy::load::<PolicyConfig>(OPTION_TUNNEL_POLICY, "Tunnel policy")? {
+            Some(config) => config.resolve(peer_id),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn permits(&self, addr: &SocketAddr) -> bool {
+        self.targets.as_ref().map_or(true, |targets| {
+            targets
+                .iter()
+                .any(|t| t.cidr.contains(addr.ip()) && t.ports.contains(&addr.port()))
+        })
+    }
+}
+
+impl PolicyConfig {
+    fn resolve(&self, peer_id: &str) -> ResultType<TunnelPolicy> {
+        let Some(rule) = self
+            .rules
+            .iter()
+            .find(|rule| policy::peer_matches(&rule.peers, &self.groups, peer_id))
+        else {
+            bail!("Tunneling is not allowed by the remote side");
+        };
+        if rule.deny {
+            bail!("Tunneling is not allowed by the remote side");
+        }
+        let mut targets = Vec::new();
+        for target in &rule.allow {
+            match target.parse() {
+                Ok(target) => targets.push(target),
+                Err(e) => {
+                    log::error!("Invalid {} target {}: {}", OPTION_TUNNEL_POLICY, target, e);
+                    bail!("Tunnel policy of the remote side is invalid");
+                }
+            }
+        }
+        Ok(TunnelPolicy {
+            targets: Some(targets),
+            max_duration: rule
+                .max_duration_secs
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs),
+            max_bytes: rule.max_bytes.filter(|bytes| *bytes > 0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tunnel_targets() {
+        let config: PolicyConfig = serde_json::from_str(
+            r#"{
+                "groups": { "ops": ["1"] },
+                "rules": [
+                    { "peers": ["group:ops"], "allow": ["10.0.0.0/8:22", "127.0.0.1:8000-8100"],
+                      "max_duration_secs": 60, "max_bytes": 1024 },
+                    { "peers": ["2"], "allow": ["[::1]:*"] },
+                    { "peers": ["3"], "deny": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let addr = |s: &str| SocketAddr::from_str(s).unwrap();
+
+        let ops = config.resolve("1").unwrap();
+        assert!(ops.permits(&addr("10.1.2.3:22")));
+        assert!(!ops.permits(&addr("10.1.2.3:23")));
+        assert!(ops.permits(&addr("127.0.0.1:8100")));
+        assert!(!ops.permits(&addr("127.0.0.2:8000")));
+        assert_eq!(ops.max_duration, Some(Duration::from_secs(60)));
+        assert_eq!(ops.max_bytes, Some(1024));
+
+        let v6 = config.resolve("2").unwrap();
+        assert!(v6.permits(&addr("[::1]:3389")));
+        assert!(!v6.permits(&addr("127.0.0.1:3389")));
+        assert_eq!(v6.max_duration, None);
+
+        assert!(config.resolve("3").is_err());
+        assert!(config.resolve("4").is_err());
+        assert!(TunnelPolicy::default().permits(&addr("8.8.8.8:53")));
+
+        let invalid: PolicyConfig =
+            serde_json::from_str(r#"{ "rules": [ { "allow": ["10.0.0.1"] } ] }"#).unwrap();
+        assert!(invalid.resolve("1").is_err());
+    }
+}
@@ -1,5 +1,8 @@
 use super::*;
-use scrap::codec::{Quality, BR_BALANCED, BR_BEST, BR_SPEED};
+use scrap::{
+    codec::{Quality, BR_BALANCED, BR_BEST, BR_SPEED},
+    layers,
+};
 use std::{
     collections::VecDeque,
     time::{Duration, Instant},
@@ -25,6 +28,11 @@ adjust between FPS and ratio:
 
 delay:
     use delay minus RTT as the actual network delay
+
+temporal layers:
+    With several users, the displays are encoded in temporal layers if their encoders support it.
+    Each user then has its own fps and gets the layers matching it, the real fps being the highest one.
+    The ratio follows the delay of the fastest user, the slower ones get fewer frames instead.
 */
 
 // Constants
@@ -43,6 +51,7 @@ const HISTORY_DELAY_LEN: usize = 2;
 const ADJUST_RATIO_INTERVAL: usize = 3; // Adjust quality ratio every 3 seconds
 const DYNAMIC_SCREEN_THRESHOLD: usize = 2; // Allow increase quality ratio if encode more than 2 times in one second
 const DELAY_THRESHOLD_150MS: u32 = 150; // 150ms is the threshold for good network condition
+const OPTION_TEMPORAL_LAYERS: &str = "enable-temporal-layers";
 
 #[derive(Default, Debug, Clone)]
 struct UserDelay {
@@ -99,6 +108,7 @@ struct UserData {
 struct DisplayData {
     send_counter: usize, // Number of times encode during period
     support_changing_quality: bool,
+    temporal_layers: usize,
 }
 
 // Main QoS controller structure
@@ -110,6 +120,7 @@ pub struct VideoQoS {
     bitrate_store: u32,
     adjust_ratio_instant: Instant,
     abr_config: bool,
+    layers_config: bool,
     new_user_instant: Instant,
 }
 
@@ -123,6 +134,7 @@ impl Default for VideoQoS {
             bitrate_store: 0,
             adjust_ratio_instant: Instant::now(),
             abr_config: true,
+            layers_config: true,
             new_user_instant: Instant::now(),
         }
     }
@@ -178,6 +190,40 @@ impl VideoQoS {
     pub fn in_vbr_state(&self) -> bool {
         self.abr_config && self.displays.iter().all(|e| e.1.support_changing_quality)
     }
+
+    // Whether the displays should be encoded in temporal layers
+    pub fn want_temporal_layers(&self) -> bool {
+        self.layers_config && self.users.len() > 1
+    }
+
+    pub fn set_temporal_layers(&mut self, video_service_name: &str, layers: usize) {
+        if let Some(display) = self.displays.get_mut(video_service_name) {
+            display.temporal_layers = layers;
+        }
+    }
+
+    // Check if each user can have its own fps
+    fn layered(&self) -> bool {
+        self.want_temporal_layers()
+            && !self.displays.is_empty()
+            && self.displays.iter().all(|e| e.1.temporal_layers > 1)
+    }
+
+    // The highest temporal layer to send to each user, of a display encoded in `layers`
+    pub fn max_layers(&self, layers: usize) -> HashMap<i32, usize> {
+        let layered = self.layered();
+        self.users
+            .iter()
+            .map(|(id, u)| {
+                let layer = if layered {
+                    layers::max_layer(Self::user_fps(u), self.fps(), layers)
+                } else {
+                    layers.saturating_sub(1)
+                };
+                (*id, layer)
+            })
+            .collect()
+    }
 }
 
 // User session management
@@ -186,6 +232,7 @@ impl VideoQoS {
     pub fn on_connection_open(&mut self, id: i32) {
         self.users.insert(id, UserData::default());
         self.abr_config = Config::get_option("enable-abr") != "N";
+        self.layers_config = Config::get_option(OPTION_TEMPORAL_LAYERS) != "N";
         self.new_user_instant = Instant::now();
     }
 
@@ -244,6 +291,7 @@ impl VideoQoS {
     }
 
     pub fn user_network_delay(&mut self, id: i32, delay: u32) {
+        let layered = self.layered();
         let highest_fps = self.highest_fps();
         let target_ratio = self.latest_quality().ratio();
 
@@ -266,7 +314,11 @@ impl VideoQoS {
             user.delay.add_delay(delay);
             let mut avg_delay = user.delay.avg_delay();
             avg_delay = avg_delay.max(10);
-            let mut fps = self.fps;
+            let mut fps = if layered {
+                user.delay.fps.unwrap_or(self.fps)
+            } else {
+                self.fps
+            };
 
             // Adaptive FPS adjustment based on network delay:
             if avg_delay < 50 {
@@ -321,6 +373,11 @@ impl VideoQoS {
                 user.delay.quick_increase_fps_count = 0;
             }
 
+            let highest_fps = if layered {
+                Self::user_highest_fps(user).clamp(MIN_FPS, MAX_FPS)
+            } else {
+                highest_fps
+            };
             fps = fps.clamp(MIN_FPS, highest_fps);
             // first network delay message
             adjust_ratio = user.delay.fps.is_none();
@@ -377,22 +434,31 @@ impl VideoQoS {
         }
     }
 
-    #[inline]
-    fn highest_fps(&self) -> u32 {
-        let user_fps = |u: &UserData| {
-            let mut fps = u.custom_fps.unwrap_or(FPS);
-            if let Some(auto_adjust_fps) = u.auto_adjust_fps {
-                if fps == 0 || auto_adjust_fps < fps {
-                    fps = auto_adjust_fps;
-                }
+    fn user_highest_fps(u: &UserData) -> u32 {
+        let mut fps = u.custom_fps.unwrap_or(FPS);
+        if let Some(auto_adjust_fps) = u.auto_adjust_fps {
+            if fps == 0 || auto_adjust_fps < fps {
+                fps = auto_adjust_fps;
             }
-            fps
-        };
+        }
+        fps
+    }
+
+    // The fps of one user according to its own network delay
+    fn user_fps(u: &UserData) -> u32 {
+        let mut fps = u.delay.fps.unwrap_or(INIT_FPS);
+        if u.delay.response_delayed {
+            fps = fps.min(MIN_FPS + 1);
+        }
+        fps.clamp(MIN_FPS, Self::user_highest_fps(u).clamp(MIN_FPS, MAX_FPS))
+    }
 
+    #[inline]
+    fn highest_fps(&self) -> u32 {
         let fps = self
             .users
             .iter()
-            .map(|(_, u)| user_fps(u))
+            .map(|(_, u)| Self::user_highest_fps(u))
             .filter(|u| *u >= MIN_FPS)
             .min()
             .unwrap_or(FPS);
@@ -417,8 +483,13 @@ impl VideoQoS {
         if !self.in_vbr_state() {
             return;
         }
-        // Get maximum delay from all users
-        let max_delay = self.users.iter().map(|u| u.1.delay.avg_delay()).max();
+        // Get maximum delay from all users, the minimum one with temporal layers
+        let delays = self.users.iter().map(|u| u.1.delay.avg_delay());
+        let max_delay = if self.layered() {
+            delays.min()
+        } else {
+            delays.max()
+        };
         let Some(max_delay) = max_delay else {
             return;
         };
@@ -509,6 +580,20 @@ impl VideoQoS {
 
     // Adjust fps based on network delay and user response time
     fn adjust_fps(&mut self) {
+        if self.layered() {
+            // The fastest user, the slower ones dropping layers
+            let mut fps = self
+                .users
+                .iter()
+                .map(|u| Self::user_fps(u.1))
+                .max()
+                .unwrap_or(INIT_FPS);
+            if self.new_user_instant.elapsed().as_secs() < 1 && fps > INIT_FPS {
+                fps = INIT_FPS;
+            }
+            self.fps = fps.clamp(MIN_FPS, MAX_FPS);
+            return;
+        }
         let highest_fps = self.highest_fps();
         // Get minimum fps from all users
         let mut fps = self
@@ -46,7 +46,8 @@ use scrap::vram::{VRamEncoder, VRamEncoderConfig};
 use scrap::Capturer;
 use scrap::{
     aom::AomEncoderConfig,
-    codec::{Encoder, EncoderCfg},
+    codec::{Encoder, EncoderCfg, LosslessMode},
+    damage::{Damage, DamageTracker},
     record::{Recorder, RecorderContext},
     vpxcodec::{VpxEncoderConfig, VpxVideoCodecId},
     CodecFormat, Display, EncodeInput, TraitCapturer, TraitPixelBuffer,
@@ -61,6 +62,10 @@ use std::{
 };
 
 pub const OPTION_REFRESH: &'static str = "refresh";
+/// Skip static frames and hint the encoder with the changed tiles, on by default.
+pub const OPTION_DAMAGE_TRACKING: &'static str = "enable-damage-tracking";
+// How long the screen must not change before it's encoded losslessly, see `LosslessMode::Static`
+const LOSSLESS_STATIC_DELAY: Duration = Duration::from_millis(500);
 
 lazy_static::lazy_static! {
     static ref FRAME_FETCHED_NOTIFIER: (UnboundedSender<(i32, Option<Instant>)>, Arc<TokioMutex<UnboundedReceiver<(i32, Option<Instant>)>>>) = {
@@ -138,6 +143,8 @@ impl VideoFrameController {
 pub enum VideoSource {
     Monitor,
     Camera,
+    /// A window or rectangle of a monitor, see [`super::capture_region`]
+    Region,
 }
 
 impl VideoSource {
@@ -145,6 +152,16 @@ impl VideoSource {
         match self {
             VideoSource::Monitor => "monitor",
             VideoSource::Camera => "camera",
+            VideoSource::Region => "region",
+        }
+    }
+
+    /// The source of display `idx`, the capture regions being listed after the monitors.
+    pub fn of_display(self, idx: usize) -> Self {
+        if self.is_monitor() && display_service::is_capture_region(idx) {
+            VideoSource::Region
+        } else {
+            self
         }
     }
 
@@ -179,14 +196,14 @@ impl DerefMut for VideoService {
 }
 
 pub fn get_service_name(source: VideoSource, idx: usize) -> String {
-    format!("{}{}", source.service_name_prefix(), idx)
+    format!("{}{}", source.of_display(idx).service_name_prefix(), idx)
 }
 
 pub fn new(source: VideoSource, idx: usize) -> GenericService {
     let vs = VideoService {
         sp: GenericService::new(get_service_name(source, idx), true),
         idx,
-        source,
+        source: source.of_display(idx),
     };
     GenericService::run(&vs, run);
     vs.sp
@@ -322,6 +339,9 @@ fn get_capturer_monitor(
     current: usize,
     portable_service_running: bool,
 ) -> ResultType<CapturerInfo> {
+    if let Some(spec) = display_service::synthetic_capture() {
+        return get_capturer_synthetic(&spec, current);
+    }
     #[cfg(target_os = "linux")]
     {
         if !is_x11() {
@@ -452,6 +472,108 @@ fn get_capturer_camera(current: usize) -> ResultType<CapturerInfo> {
         capturer,
     });
 }
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+fn get_capturer_synthetic(spec: &str, current: usize) -> ResultType<CapturerInfo> {
+    if current != 0 {
+        bail!("Failed to get display {}, displays len: 1", current);
+    }
+    let capturer = scrap::synthetic::SyntheticCapturer::new(spec)
+        .with_context(|| format!("Failed to create synthetic capturer {}", spec))?;
+    let (width, height) = (capturer.width(), capturer.height());
+    log::debug!(
+        "synthetic capture {}, width={}, height={}, cpus={}/{}",
+        spec,
+        width,
+        height,
+        num_cpus::get_physical(),
+        num_cpus::get(),
+    );
+    let privacy_mode_id = get_privacy_mode_conn_id().unwrap_or(INVALID_PRIVACY_MODE_CONN_ID);
+    Ok(CapturerInfo {
+        origin: (0, 0),
+        width,
+        height,
+        ndisplay: 1,
+        current,
+        privacy_mode_id,
+        _capturer_privacy_mode_id: privacy_mode_id,
+        capturer: Box::new(capturer),
+    })
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn get_capturer_synthetic(spec: &str, _current: usize) -> ResultType<CapturerInfo> {
+    bail!(
+        "Synthetic capture is not supported on this platform: {}",
+        spec
+    );
+}
+
+// A display of the capture regions, cropped from the monitor it's on
+#[cfg(any(target_os = "windows", target_os = "linux"))]
+fn get_capturer_region(current: usize, portable_service_running: bool) -> ResultType<CapturerInfo> {
+    #[cfg(target_os = "linux")]
+    if !is_x11() {
+        bail!("Capture regions are not supported on Wayland");
+    }
+    let rects = Display::all()?
+        .iter()
+        .map(|d| (d.origin().0, d.origin().1, d.width(), d.height()))
+        .collect::<Vec<_>>();
+    let ndisplay = rects.len();
+    let regions = super::capture_region::find(&rects);
+    let first_region = if display_service::capture_regions_only() {
+        0
+    } else {
+        ndisplay
+    };
+    let Some(region) = current.checked_sub(first_region).and_then(|i| regions.get(i)) else {
+        bail!(
+            "Failed to get capture region {}, displays len: {}, regions len: {}",
+            current,
+            ndisplay,
+            regions.len()
+        );
+    };
+    let info = get_capturer_monitor(region.display, portable_service_running)?;
+    let x = (region.x - info.origin.0) as usize;
+    let y = (region.y - info.origin.1) as usize;
+    log::debug!(
+        "capture region {}, current={}, display={}, origin: {:?}, width={}, height={}",
+        region.name,
+        current,
+        region.display,
+        (region.x, region.y),
+        region.width,
+        region.height,
+    );
+    Ok(CapturerInfo {
+        origin: (region.x, region.y),
+        width: region.width,
+        height: region.height,
+        ndisplay,
+        current,
+        privacy_mode_id: info.privacy_mode_id,
+        _capturer_privacy_mode_id: info._capturer_privacy_mode_id,
+        capturer: Box::new(super::capture_region::RegionCapturer::new(
+            info.capturer,
+            (x, y),
+            (region.width, region.height),
+        )),
+    })
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn get_capturer_region(
+    current: usize,
+    _portable_service_running: bool,
+) -> ResultType<CapturerInfo> {
+    bail!(
+        "Capture regions are not supported on this platform, display {}",
+        current
+    );
+}
+
 fn get_capturer(
     source: VideoSource,
     current: usize,
@@ -460,6 +582,7 @@ fn get_capturer(
     match source {
         VideoSource::Monitor => get_capturer_monitor(current, portable_service_running),
         VideoSource::Camera => get_capturer_camera(current),
+        VideoSource::Region => get_capturer_region(current, portable_service_running),
     }
 }
 
@@ -548,7 +671,18 @@ fn run(vs: VideoService) -> ResultType<()> {
         .lock()
         .unwrap()
         .set_support_changing_quality(&sp.name(), encoder.support_changing_quality());
-    log::info!("initial quality: {quality:?}");
+    // Several users, each one getting the frame rate of its network
+    let want_temporal_layers = VIDEO_QOS.lock().unwrap().want_temporal_layers();
+    let temporal_layers = if want_temporal_layers {
+        encoder.set_temporal_layers(scrap::layers::MAX_LAYERS)
+    } else {
+        1
+    };
+    VIDEO_QOS
+        .lock()
+        .unwrap()
+        .set_temporal_layers(&sp.name(), temporal_layers);
+    log::info!("initial quality: {quality:?}, temporal layers: {temporal_layers}");
 
     if sp.is_option_true(OPTION_REFRESH) {
         sp.set_option_bool(OPTION_REFRESH, false);
@@ -576,6 +710,23 @@ fn run(vs: VideoService) -> ResultType<()> {
     let capture_width = c.width;
     let capture_height = c.height;
     let (mut second_instant, mut send_counter) = (Instant::now(), 0);
+    let mut damage_tracker = config::option2bool(
+        OPTION_DAMAGE_TRACKING,
+        &Config::get_option(OPTION_DAMAGE_TRACKING),
+    )
+    .then(DamageTracker::default);
+    let lossless_mode = LosslessMode::refresh();
+    // Whether the encoder was asked to encode losslessly
+    let mut lossless = false;
+    if lossless_mode == LosslessMode::Always {
+        lossless = true;
+        match encoder.set_lossless(true) {
+            Ok(true) => log::info!("lossless encoding"),
+            Ok(false) => log::info!("no lossless mode in {:?}", codec_format),
+            Err(e) => log::warn!("Failed to set lossless encoding: {e:?}"),
+        }
+    }
+    let mut last_change = Instant::now();
 
     while sp.ok() {
         #[cfg(windows)]
@@ -590,7 +741,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             &sp.name(),
         )?;
         if sp.is_option_true(OPTION_REFRESH) {
-            if vs.source.is_monitor() {
+            if !vs.source.is_camera() {
                 let _ = try_broadcast_display_changed(&sp, display_idx, &c, true);
             }
             log::info!("switch to refresh");
@@ -613,13 +764,22 @@ fn run(vs: VideoService) -> ResultType<()> {
             log::info!("switch due to i444 changed");
             bail!("SWITCH");
         }
+        // Users joined or left, the encoder is only set up for layers at start
+        if VIDEO_QOS.lock().unwrap().want_temporal_layers() != want_temporal_layers {
+            log::info!("switch due to temporal layers changed");
+            bail!("SWITCH");
+        }
+        if LosslessMode::get() != lossless_mode {
+            log::info!("switch due to lossless mode changed");
+            bail!("SWITCH");
+        }
         #[cfg(all(windows, feature = "vram"))]
         if c.is_gdi() && encoder.input_texture() {
             log::info!("changed to gdi when using vram");
             VRamEncoder::set_fallback_gdi(sp.name(), true);
             bail!("SWITCH");
         }
-        if vs.source.is_monitor() {
+        if !vs.source.is_camera() {
             check_privacy_mode_changed(&sp, display_idx, &c)?;
         }
         #[cfg(windows)]
@@ -631,7 +791,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             }
         }
         let now = time::Instant::now();
-        if vs.source.is_monitor() && last_check_displays.elapsed().as_millis() > 1000 {
+        if !vs.source.is_camera() && last_check_displays.elapsed().as_millis() > 1000 {
             last_check_displays = now;
             // This check may be redundant, but it is better to be safe.
             // The previous check in `sp.is_option_true(OPTION_REFRESH)` block may be enough.
@@ -645,6 +805,7 @@ fn run(vs: VideoService) -> ResultType<()> {
         let res = match c.frame(spf) {
             Ok(frame) => {
                 repeat_encode_counter = 0;
+                let mut static_frame = false;
                 if frame.valid() {
                     let screenshot = SCREENSHOTS.lock().unwrap().remove(&display_idx);
                     if let Some(mut screenshot) = screenshot {
@@ -689,21 +850,44 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
 
-                    let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
-                    let send_conn_ids = handle_one_frame(
-                        display_idx,
-                        &sp,
-                        frame,
-                        ms,
-                        &mut encoder,
-                        recorder.clone(),
-                        &mut encode_fail_counter,
-                        &mut first_frame,
-                        capture_width,
-                        capture_height,
-                    )?;
-                    frame_controller.set_send(now, send_conn_ids);
-                    send_counter += 1;
+                    if let (Some(tracker), scrap::Frame::PixelBuffer(f)) =
+                        (damage_tracker.as_mut(), &frame)
+                    {
+                        let damage = tracker.update(f);
+                        static_frame = damage.is_empty();
+                        // The active map is relative to the previous frame, not the referenced one
+                        if !static_frame && temporal_layers == 1 {
+                            if let Err(e) = encoder.set_damage(&damage) {
+                                log::warn!("Failed to set damage, stop tracking it: {e:?}");
+                                damage_tracker = None;
+                            }
+                        }
+                    }
+                    if !static_frame {
+                        last_change = now;
+                        if lossless_mode == LosslessMode::Static && lossless {
+                            lossless = false;
+                            if let Err(e) = encoder.set_lossless(false) {
+                                log::warn!("Failed to unset lossless encoding: {e:?}");
+                            }
+                        }
+                        let frame = frame.to(encoder.yuvfmt(), &mut yuv, &mut mid_data)?;
+                        let send_conn_ids = handle_one_frame(
+                            display_idx,
+                            &sp,
+                            frame,
+                            ms,
+                            &mut encoder,
+                            recorder.clone(),
+                            &mut encode_fail_counter,
+                            &mut first_frame,
+                            capture_width,
+                            capture_height,
+                            temporal_layers,
+                        )?;
+                        frame_controller.set_send(now, send_conn_ids);
+                        send_counter += 1;
+                    }
                 }
                 #[cfg(windows)]
                 {
@@ -713,7 +897,12 @@ fn run(vs: VideoService) -> ResultType<()> {
                     }
                     try_gdi = 0;
                 }
-                Ok(())
+                if static_frame {
+                    // Handled like the capturers returning `WouldBlock` on unchanged frames
+                    Err(WouldBlock.into())
+                } else {
+                    Ok(())
+                }
             }
             Err(err) => Err(err),
         };
@@ -745,7 +934,63 @@ fn run(vs: VideoService) -> ResultType<()> {
                         }
                     }
                 }
-                if !encoder.latency_free() && yuv.len() > 0 {
+                // At most one encode of the last frame per loop, they'd share `ms`
+                let mut reencoded = false;
+                if temporal_layers > 1 && encoder.temporal_layer() > 0 && yuv.len() > 0 {
+                    // The last change may have been dropped for the slower users
+                    reencoded = true;
+                    encoder.set_temporal_layers(temporal_layers);
+                    let send_conn_ids = handle_one_frame(
+                        display_idx,
+                        &sp,
+                        EncodeInput::YUV(&yuv),
+                        ms,
+                        &mut encoder,
+                        recorder.clone(),
+                        &mut encode_fail_counter,
+                        &mut first_frame,
+                        capture_width,
+                        capture_height,
+                        temporal_layers,
+                    )?;
+                    frame_controller.set_send(now, send_conn_ids);
+                    send_counter += 1;
+                }
+                if lossless_mode == LosslessMode::Static
+                    && !reencoded
+                    && !lossless
+                    && yuv.len() > 0
+                    && last_change.elapsed() >= LOSSLESS_STATIC_DELAY
+                {
+                    // Once per still, the last frame again but pixel exact
+                    lossless = true;
+                    reencoded = true;
+                    match encoder.set_lossless(true) {
+                        Ok(true) => {
+                            if damage_tracker.is_some() {
+                                encoder.set_damage(&Damage::full(capture_width, capture_height))?;
+                            }
+                            let send_conn_ids = handle_one_frame(
+                                display_idx,
+                                &sp,
+                                EncodeInput::YUV(&yuv),
+                                ms,
+                                &mut encoder,
+                                recorder.clone(),
+                                &mut encode_fail_counter,
+                                &mut first_frame,
+                                capture_width,
+                                capture_height,
+                                temporal_layers,
+                            )?;
+                            frame_controller.set_send(now, send_conn_ids);
+                            send_counter += 1;
+                        }
+                        Ok(false) => {}
+                        Err(e) => log::warn!("Failed to set lossless encoding: {e:?}"),
+                    }
+                }
+                if !reencoded && !encoder.latency_free() && yuv.len() > 0 {
                     // yun.len() > 0 means the frame is not texture.
                     if repeat_encode_counter < repeat_encode_max {
                         repeat_encode_counter += 1;
@@ -760,6 +1005,7 @@ fn run(vs: VideoService) -> ResultType<()> {
                             &mut first_frame,
                             capture_width,
                             capture_height,
+                            temporal_layers,
                         )?;
                         frame_controller.set_send(now, send_conn_ids);
                         send_counter += 1;
@@ -769,7 +1015,7 @@ fn run(vs: VideoService) -> ResultType<()> {
             Err(err) => {
                 // This check may be redundant, but it is better to be safe.
                 // The previous check in `sp.is_option_true(OPTION_REFRESH)` block may be enough.
-                if vs.source.is_monitor() {
+                if !vs.source.is_camera() {
                     try_broadcast_display_changed(&sp, display_idx, &c, true)?;
                 }
 
@@ -793,7 +1039,7 @@ fn run(vs: VideoService) -> ResultType<()> {
         let timeout_millis = 3_000u64;
         let wait_begin = Instant::now();
         while wait_begin.elapsed().as_millis() < timeout_millis as _ {
-            if vs.source.is_monitor() {
+            if !vs.source.is_camera() {
                 check_privacy_mode_changed(&sp, display_idx, &c)?;
             }
             frame_controller.try_wait_next(&mut fetched_conn_ids, 300);
@@ -884,7 +1130,7 @@ fn get_encoder_config(
     _source: VideoSource,
 ) -> EncoderCfg {
     #[cfg(all(windows, feature = "vram"))]
-    if _portable_service || c.is_gdi() || _source == VideoSource::Camera {
+    if _portable_service || c.is_gdi() || _source != VideoSource::Monitor {
         log::info!("gdi:{}, portable:{}", c.is_gdi(), _portable_service);
         VRamEncoder::set_not_use(_name, true);
     }
@@ -962,24 +1208,25 @@ fn get_recorder(
     #[cfg(not(windows))]
     let root = false;
     let recorder = if record_incoming {
-        use crate::hbbs_http::record_upload;
-
-        let tx = if record_upload::is_enable() {
-            let (tx, rx) = std::sync::mpsc::channel();
-            record_upload::run(rx);
-            Some(tx)
-        } else {
-            None
-        };
-        Recorder::new(RecorderContext {
-            server: true,
-            id: Config::get_id(),
-            dir: crate::ui_interface::video_save_directory(root),
-            display_idx,
-            camera,
-            tx,
-        })
-        .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+        match crate::record_sink::encryption_key() {
+            Ok(key) => {
+                let tx = crate::record_sink::start();
+                Recorder::new(RecorderContext {
+                    server: true,
+                    id: Config::get_id(),
+                    dir: crate::ui_interface::video_save_directory(root),
+                    display_idx,
+                    camera,
+                    tx,
+                    key,
+                })
+                .map_or(Default::default(), |r| Arc::new(Mutex::new(Some(r))))
+            }
+            Err(e) => {
+                log::error!("Not recording, encryption is on but: {}", e);
+                Default::default()
+            }
+        }
     } else {
         Default::default()
     };
@@ -1058,6 +1305,7 @@ fn handle_one_frame(
     first_frame: &mut bool,
     width: usize,
     height: usize,
+    temporal_layers: usize,
 ) -> ResultType<HashSet<i32>> {
     sp.snapshot(|sps| {
         // so that new sub and old sub share the same encoder after switch
@@ -1082,7 +1330,15 @@ fn handle_one_frame(
                 .unwrap()
                 .as_mut()
                 .map(|r| r.write_message(&msg, width, height));
-            send_conn_ids = sp.send_video_frame(msg);
+            let layer = encoder.temporal_layer();
+            send_conn_ids = if layer == 0 {
+                sp.send_video_frame(msg, |_| true)
+            } else {
+                let max_layers = VIDEO_QOS.lock().unwrap().max_layers(temporal_layers);
+                sp.send_video_frame(msg, |id| {
+                    max_layers.get(&id).map_or(true, |max| layer <= *max)
+                })
+            };
         }
         Err(e) => {
             *encode_fail_counter += 1;
@@ -1182,10 +1438,13 @@ pub fn make_display_changed_msg(
     opt_display: Option<DisplayInfo>,
     source: VideoSource,
 ) -> Option<Message> {
+    let source = source.of_display(display_idx);
     let display = match opt_display {
         Some(d) => d,
         None => match source {
-            VideoSource::Monitor => display_service::get_display_info(display_idx)?,
+            VideoSource::Monitor | VideoSource::Region => {
+                display_service::get_display_info(display_idx)?
+            }
             VideoSource::Camera => camera::Cameras::get_sync_cameras()
                 .get(display_idx)?
                 .clone(),
@@ -1199,7 +1458,9 @@ pub fn make_display_changed_msg(
         width: display.width,
         height: display.height,
         cursor_embedded: match source {
-            VideoSource::Monitor => display_service::capture_cursor_embedded(),
+            VideoSource::Monitor | VideoSource::Region => {
+                display_service::capture_cursor_embedded()
+            }
             VideoSource::Camera => false,
         },
         #[cfg(not(target_os = "android"))]
@@ -1216,6 +1477,8 @@ pub fn make_display_changed_msg(
                     .ok()
                     .into_iter()
                     .collect(),
+                // Sized by the window or the rectangle
+                VideoSource::Region => vec![],
             },
             ..SupportedResolutions::default()
         })
@@ -38,7 +38,7 @@ pub struct CodeUChainSession<T: InvokeUiSession> {
 impl<T: InvokeUiSession> CodeUChainSession<T> {
     /// Create a new CodeUChain session with default middleware stack
     pub fn new(ui_handler: T, lc: Arc<RwLock<LoginConfigHandler>>) -> Self {
-        let mut chain = ClientChain::new();
+        let mut chain = ClientChain::new(crate::VERSION);
 
         // Add comprehensive middleware stack
         chain = chain
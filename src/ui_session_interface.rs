@@ -466,6 +466,11 @@ impl<T: InvokeUiSession> Session<T> {
         }
     }
 
+    pub fn save_lossless_encoding(&self, value: String) {
+        let msg = self.lc.write().unwrap().save_lossless_encoding(value);
+        self.send(Data::Message(msg));
+    }
+
     pub fn save_trackpad_speed(&self, trackpad_speed: i32) {
         self.lc.write().unwrap().save_trackpad_speed(trackpad_speed);
     }